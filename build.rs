@@ -3,14 +3,16 @@ fn main() {
         Windows::Win32::Etw::*,
         Windows::Win32::Debug::WIN32_ERROR,
         Windows::Win32::SystemServices::{
-            PSTR, MAX_PATH, VER_GREATER_EQUAL, LocalFree
+            PSTR, MAX_PATH, VER_GREATER_EQUAL, LocalFree, GetModuleHandleA, GetProcAddress,
+            GetCurrentThread
         },
         Windows::Win32::Automation::{
             SysStringLen, BSTR
         },
         Windows::Win32::WindowsProgramming::{
             FILETIME, GetSystemTimeAsFileTime, OSVERSIONINFOEXA,
-            VerifyVersionInfoA, VerSetConditionMask
+            VerifyVersionInfoA, VerSetConditionMask, QueryPerformanceCounter,
+            QueryPerformanceFrequency
         },
         Windows::Win32::Security::{ConvertSidToStringSidA, PSID},
     );
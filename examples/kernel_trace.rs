@@ -13,7 +13,7 @@ fn main() {
             Ok(schema) => {
                 let opcode = schema.opcode();
                 if opcode == 10 {
-                    let name = schema.provider_name();
+                    let name = schema.provider_name().unwrap_or_default();
                     println!("ProviderName: {}", name);
                     let mut parser = Parser::create(&schema);
                     // Fully Qualified Syntax for Disambiguation
@@ -38,5 +38,5 @@ fn main() {
         .unwrap();
 
     std::thread::sleep(Duration::new(20, 0));
-    trace.stop();
+    trace.stop().unwrap();
 }
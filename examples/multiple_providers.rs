@@ -69,5 +69,5 @@ fn main() {
         .unwrap();
 
     std::thread::sleep(Duration::new(10, 0));
-    trace.stop();
+    trace.stop().unwrap();
 }
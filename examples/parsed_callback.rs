@@ -0,0 +1,40 @@
+use ferrisetw::native::etw_types::EventRecord;
+use ferrisetw::parser::{Parser, TryParse};
+use ferrisetw::provider::*;
+use ferrisetw::schema::{EventMetadata, SchemaError};
+use ferrisetw::trace::*;
+use std::time::Duration;
+
+fn main() {
+    let process_provider = Provider::new()
+        .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716") // Microsoft-Windows-Kernel-Process
+        .on_parsed(|meta: &EventMetadata, parser: &mut Parser| {
+            if meta.event_id == 1 {
+                let process_id: u32 = parser.try_parse("ProcessID").unwrap_or(0);
+                let image_name: String = parser.try_parse("ImageName").unwrap_or_default();
+                println!(
+                    "[{}] {} PID: {}, ImageName: {}",
+                    meta.provider_name.as_deref().unwrap_or(""),
+                    meta.task_name.as_deref().unwrap_or(""),
+                    process_id,
+                    image_name
+                );
+            }
+        })
+        .on_missing_schema(|record: &EventRecord, err: &SchemaError| {
+            println!(
+                "No schema for event {}: {:?}",
+                record.EventHeader.EventDescriptor.Id, err
+            );
+        })
+        .build()
+        .unwrap();
+
+    let stats = UserTrace::new()
+        .named(String::from("MyParsedProvider"))
+        .enable(process_provider)
+        .run_for(Duration::new(20, 0))
+        .unwrap();
+
+    println!("Events handled: {}", stats.events_handled);
+}
@@ -13,7 +13,7 @@ fn main() {
             Ok(schema) => {
                 let event_id = schema.event_id();
                 if event_id == 2 {
-                    let name = schema.provider_name();
+                    let name = schema.provider_name().unwrap_or_default();
                     println!("Name: {}", name);
                     let mut parser = Parser::create(&schema);
                     let process_id: u32 = parser.try_parse("ProcessID").unwrap();
@@ -34,12 +34,11 @@ fn main() {
         .build()
         .unwrap();
 
-    let mut trace = UserTrace::new()
+    let stats = UserTrace::new()
         .named(String::from("MyProvider"))
         .enable(process_provider)
-        .start()
+        .run_for(Duration::new(20, 0))
         .unwrap();
 
-    std::thread::sleep(Duration::new(20, 0));
-    trace.stop();
+    println!("Events handled: {}", stats.events_handled);
 }
@@ -0,0 +1,265 @@
+//! ETW Activity tree builder
+//!
+//! The `activity` module rebuilds the parent/child relationships between the ETW activities of a
+//! Trace. Activities are identified by the ActivityId of the event header and linked together by
+//! the RelatedActivityId logged by the Start and transfer (Send/Receive) events
+//!
+//! See: [About Event Activity IDs](https://docs.microsoft.com/en-us/windows/win32/api/evntprov/nf-evntprov-eventactivityidcontrol)
+use crate::native::etw_types::{EventRecord, EventRecordExt};
+use crate::utils;
+use std::collections::HashMap;
+use windows::Guid;
+
+/// win:Start opcode
+pub const OPCODE_START: u8 = 1;
+/// win:Stop opcode
+pub const OPCODE_STOP: u8 = 2;
+/// win:Send opcode, the activity transfers to the RelatedActivityId
+pub const OPCODE_SEND: u8 = 9;
+/// win:Receive opcode, the activity is transferred from the RelatedActivityId
+pub const OPCODE_RECEIVE: u8 = 240;
+
+/// Represents an activity seen by the [ActivityTree]
+#[derive(Debug, Clone)]
+pub struct Activity {
+    /// ActivityId of the activity
+    pub id: Guid,
+    /// Provider of the first event seen for the activity
+    pub provider_id: Guid,
+    /// TimeStamp of the Start event, if seen
+    pub start: Option<i64>,
+    /// TimeStamp of the Stop event, if seen
+    pub stop: Option<i64>,
+    /// Number of events logged within the activity
+    pub event_count: usize,
+}
+
+impl Activity {
+    fn new(id: Guid, provider_id: Guid) -> Self {
+        Activity {
+            id,
+            provider_id,
+            start: None,
+            stop: None,
+            event_count: 0,
+        }
+    }
+
+    /// Use the `is_complete` function to check if both the Start and Stop events were seen
+    pub fn is_complete(&self) -> bool {
+        self.start.is_some() && self.stop.is_some()
+    }
+}
+
+/// Activity tree builder
+///
+/// Events are fed with the `push` function, in any order: a child can be seen before its parent,
+/// the link is kept and the parent shows up in the tree once any of its events arrives. Children
+/// whose parent is never seen are reported as orphans, and listed among the roots
+///
+/// # Example
+/// ```rust
+/// let tree = Arc::new(Mutex::new(ActivityTree::new()));
+/// let callback_tree = Arc::clone(&tree);
+/// let provider = Provider::new()
+///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+///     .add_callback(move |record, _| callback_tree.lock().unwrap().push(&record))
+///     .build()?;
+/// // ... start and stop the trace
+/// for root in tree.lock().unwrap().roots() {
+///     println!("{:?}", root.id);
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct ActivityTree {
+    activities: HashMap<[u8; 16], Activity>,
+    // child -> parent, the first link seen for a child wins
+    parents: HashMap<[u8; 16], Guid>,
+}
+
+impl ActivityTree {
+    /// Use the `new` function to create an empty ActivityTree
+    pub fn new() -> Self {
+        ActivityTree {
+            activities: HashMap::new(),
+            parents: HashMap::new(),
+        }
+    }
+
+    /// Use the `push` function to add an event to the tree
+    ///
+    /// Events without ActivityId are ignored
+    ///
+    /// # Arguments
+    /// * `record` - The [EventRecord] to add
+    pub fn push(&mut self, record: &EventRecord) {
+        let header = &record.EventHeader;
+        let id = header.ActivityId;
+        if id == Guid::zeroed() {
+            return;
+        }
+
+        let activity = self
+            .activities
+            .entry(utils::guid_bytes(&id))
+            .or_insert_with(|| Activity::new(id, header.ProviderId));
+        activity.event_count += 1;
+
+        let opcode = header.EventDescriptor.Opcode;
+        match opcode {
+            OPCODE_START => activity.start = Some(header.TimeStamp),
+            OPCODE_STOP => activity.stop = Some(header.TimeStamp),
+            _ => {}
+        }
+
+        let related = match record.related_activity_id() {
+            Some(related) if related != Guid::zeroed() && related != id => related,
+            _ => return,
+        };
+        match opcode {
+            // The sender is the parent of the activity it transfers to
+            OPCODE_SEND => self.link(related, id),
+            OPCODE_START | OPCODE_RECEIVE => self.link(id, related),
+            _ => {}
+        }
+    }
+
+    fn link(&mut self, child: Guid, parent: Guid) {
+        // Refuse links that would turn the tree into a cycle
+        let mut ancestor = Some(parent);
+        while let Some(current) = ancestor {
+            if current == child {
+                return;
+            }
+            ancestor = self.parent(&current);
+        }
+        self.parents
+            .entry(utils::guid_bytes(&child))
+            .or_insert(parent);
+    }
+
+    /// Use the `activity` function to obtain an [Activity] by its ActivityId
+    pub fn activity(&self, id: &Guid) -> Option<&Activity> {
+        self.activities.get(&utils::guid_bytes(id))
+    }
+
+    /// Use the `parent` function to obtain the ActivityId of the parent of an activity
+    ///
+    /// The parent might not be in the tree if none of its events were seen, see
+    /// [ActivityTree::orphans]
+    pub fn parent(&self, id: &Guid) -> Option<Guid> {
+        self.parents.get(&utils::guid_bytes(id)).copied()
+    }
+
+    /// Use the `children` function to obtain the ActivityIds of the children of an activity
+    pub fn children(&self, id: &Guid) -> Vec<Guid> {
+        self.parents
+            .iter()
+            .filter(|(_, parent)| *parent == id)
+            .filter_map(|(child, _)| self.activities.get(child).map(|a| a.id))
+            .collect()
+    }
+
+    /// Use the `roots` function to obtain the activities without a known parent
+    ///
+    /// Orphans are included, their parent was never seen
+    pub fn roots(&self) -> Vec<&Activity> {
+        self.activities
+            .values()
+            .filter(|activity| match self.parent(&activity.id) {
+                Some(parent) => self.activity(&parent).is_none(),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Use the `orphans` function to obtain the activities linked to a parent that was never seen
+    pub fn orphans(&self) -> Vec<&Activity> {
+        self.activities
+            .values()
+            .filter(|activity| match self.parent(&activity.id) {
+                Some(parent) => self.activity(&parent).is_none(),
+                None => false,
+            })
+            .collect()
+    }
+
+    /// Use the `len` function to obtain the number of activities in the tree
+    pub fn len(&self) -> usize {
+        self.activities.len()
+    }
+
+    /// Use the `is_empty` function to check if no activity has been seen yet
+    pub fn is_empty(&self) -> bool {
+        self.activities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::native::test_utils::{TestEvent, TestSchema};
+
+    const REQUEST: &str = "11111111-0000-0000-0000-000000000001";
+    const QUERY: &str = "11111111-0000-0000-0000-000000000002";
+    const WORKER: &str = "11111111-0000-0000-0000-000000000003";
+    const LOST: &str = "11111111-0000-0000-0000-000000000004";
+    const ORPHAN: &str = "11111111-0000-0000-0000-000000000005";
+
+    fn event(opcode: u8, timestamp: i64, id: &str, related: Option<&str>) -> TestEvent {
+        let mut schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        schema.opcode = opcode;
+        let mut event =
+            TestEvent::new(&schema, Vec::new()).activity(Guid::from(id), related.map(Guid::from));
+        event.record.EventHeader.TimeStamp = timestamp;
+        event
+    }
+
+    #[test]
+    fn test_activity_tree() {
+        let events = [
+            event(OPCODE_START, 1, REQUEST, None),
+            // Late: the child starts are delivered before the parent transfer
+            event(OPCODE_START, 3, QUERY, Some(REQUEST)),
+            event(OPCODE_SEND, 2, REQUEST, Some(WORKER)),
+            event(OPCODE_RECEIVE, 4, WORKER, Some(REQUEST)),
+            event(OPCODE_STOP, 5, QUERY, None),
+            event(OPCODE_START, 6, ORPHAN, Some(LOST)),
+            event(OPCODE_STOP, 7, REQUEST, None),
+        ];
+        let mut tree = ActivityTree::new();
+        for event in events.iter() {
+            tree.push(&event.record);
+        }
+
+        assert_eq!(tree.len(), 4);
+        let request = tree.activity(&Guid::from(REQUEST)).unwrap();
+        assert_eq!(request.start, Some(1));
+        assert_eq!(request.stop, Some(7));
+        assert_eq!(request.event_count, 3);
+        assert!(request.is_complete());
+
+        let mut children = tree.children(&Guid::from(REQUEST));
+        children.sort_by_key(utils::guid_bytes);
+        assert_eq!(children, vec![Guid::from(QUERY), Guid::from(WORKER)]);
+        assert_eq!(tree.parent(&Guid::from(QUERY)), Some(Guid::from(REQUEST)));
+        assert!(!tree.activity(&Guid::from(WORKER)).unwrap().is_complete());
+
+        let orphans: Vec<Guid> = tree.orphans().iter().map(|a| a.id).collect();
+        assert_eq!(orphans, vec![Guid::from(ORPHAN)]);
+        let mut roots: Vec<Guid> = tree.roots().iter().map(|a| a.id).collect();
+        roots.sort_by_key(utils::guid_bytes);
+        assert_eq!(roots, vec![Guid::from(REQUEST), Guid::from(ORPHAN)]);
+    }
+
+    #[test]
+    fn test_activity_tree_ignores_cycles() {
+        let mut tree = ActivityTree::new();
+        tree.push(&event(OPCODE_START, 1, QUERY, Some(REQUEST)).record);
+        tree.push(&event(OPCODE_START, 2, REQUEST, Some(QUERY)).record);
+        tree.push(&event(OPCODE_START, 3, REQUEST, Some(REQUEST)).record);
+
+        assert_eq!(tree.parent(&Guid::from(QUERY)), Some(Guid::from(REQUEST)));
+        assert_eq!(tree.parent(&Guid::from(REQUEST)), None);
+    }
+}
@@ -0,0 +1,433 @@
+//! Columnar export of ETW events
+//!
+//! The `columnar` module, available with the `arrow` feature, collects the properties of the events
+//! into Apache Arrow [RecordBatch]es so they can be loaded into DataFrame tooling like Polars or
+//! DataFusion. Batches are built per event, identified by its Provider, EventId and Version, so
+//! every column holds a single type
+use crate::native::tdh_types::{Property, TdhInType};
+use crate::parser::{self, Parser, ParserError, PropertyValue};
+use crate::schema::Schema;
+use crate::utils;
+use arrow::array::{
+    ArrayRef, BinaryBuilder, BooleanBuilder, Float32Builder, Float64Builder, Int16Builder,
+    Int32Builder, Int64Builder, Int8Builder, StringBuilder, TimestampMicrosecondBuilder,
+    UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder,
+};
+use arrow::datatypes::{DataType, Field, SchemaRef, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use windows::Guid;
+
+/// Columnar module errors
+#[derive(Debug)]
+pub enum ColumnarError {
+    /// Wrapper over a [ParserError] raised while parsing the event properties
+    ParserError(ParserError),
+    /// Wrapper over an [ArrowError] raised while building the [RecordBatch]
+    ArrowError(ArrowError),
+    /// Returned when the string passed to [ColumnarCollector::new] isn't a GUID
+    InvalidGuid(String),
+}
+
+impl From<ParserError> for ColumnarError {
+    fn from(err: ParserError) -> Self {
+        ColumnarError::ParserError(err)
+    }
+}
+
+impl From<ArrowError> for ColumnarError {
+    fn from(err: ArrowError) -> Self {
+        ColumnarError::ArrowError(err)
+    }
+}
+
+type ColumnarResult<T> = Result<T, ColumnarError>;
+
+// Properties are logged with a fixed in-type per EventId, values that don't match the column type
+// (e.g. a truncated buffer parsed as Binary) are stored as nulls
+enum Column {
+    Int8(Int8Builder),
+    UInt8(UInt8Builder),
+    Int16(Int16Builder),
+    UInt16(UInt16Builder),
+    Int32(Int32Builder),
+    UInt32(UInt32Builder),
+    Int64(Int64Builder),
+    UInt64(UInt64Builder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    Boolean(BooleanBuilder),
+    Utf8(StringBuilder),
+    Timestamp(TimestampMicrosecondBuilder),
+    Binary(BinaryBuilder),
+}
+
+impl Column {
+    fn for_property(property: &Property) -> Self {
+        if parser::is_time_property(property) {
+            return Column::Timestamp(TimestampMicrosecondBuilder::new());
+        }
+
+        match property.in_type() {
+            TdhInType::InTypeInt8 => Column::Int8(Int8Builder::new()),
+            TdhInType::InTypeUInt8 => Column::UInt8(UInt8Builder::new()),
+            TdhInType::InTypeInt16 => Column::Int16(Int16Builder::new()),
+            TdhInType::InTypeUInt16 => Column::UInt16(UInt16Builder::new()),
+            TdhInType::InTypeInt32 => Column::Int32(Int32Builder::new()),
+            TdhInType::InTypeUInt32 | TdhInType::InTypeHexInt32 => {
+                Column::UInt32(UInt32Builder::new())
+            }
+            TdhInType::InTypeInt64 => Column::Int64(Int64Builder::new()),
+            TdhInType::InTypeUInt64
+            | TdhInType::InTypeHexInt64
+            | TdhInType::InTypePointer
+            | TdhInType::InTypeSizeT => Column::UInt64(UInt64Builder::new()),
+            TdhInType::InTypeFloat => Column::Float32(Float32Builder::new()),
+            TdhInType::InTypeDouble => Column::Float64(Float64Builder::new()),
+            TdhInType::InTypeBoolean => Column::Boolean(BooleanBuilder::new()),
+            TdhInType::InTypeUnicodeString
+            | TdhInType::InTypeAnsiString
+            | TdhInType::InTypeManifestCountedString
+            | TdhInType::InTypeManifestCountedAnsiString
+            | TdhInType::InTypeCountedString
+            | TdhInType::InTypeCountedAnsiString
+            | TdhInType::InTypeGuid => Column::Utf8(StringBuilder::new()),
+            _ => Column::Binary(BinaryBuilder::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Column::Int8(_) => DataType::Int8,
+            Column::UInt8(_) => DataType::UInt8,
+            Column::Int16(_) => DataType::Int16,
+            Column::UInt16(_) => DataType::UInt16,
+            Column::Int32(_) => DataType::Int32,
+            Column::UInt32(_) => DataType::UInt32,
+            Column::Int64(_) => DataType::Int64,
+            Column::UInt64(_) => DataType::UInt64,
+            Column::Float32(_) => DataType::Float32,
+            Column::Float64(_) => DataType::Float64,
+            Column::Boolean(_) => DataType::Boolean,
+            Column::Utf8(_) => DataType::Utf8,
+            Column::Timestamp(_) => DataType::Timestamp(TimeUnit::Microsecond, None),
+            Column::Binary(_) => DataType::Binary,
+        }
+    }
+
+    fn append(&mut self, value: Option<PropertyValue>) {
+        match (self, value) {
+            (Column::Int8(b), Some(PropertyValue::I8(v))) => b.append_value(v),
+            (Column::UInt8(b), Some(PropertyValue::U8(v))) => b.append_value(v),
+            (Column::Int16(b), Some(PropertyValue::I16(v))) => b.append_value(v),
+            (Column::UInt16(b), Some(PropertyValue::U16(v))) => b.append_value(v),
+            (Column::Int32(b), Some(PropertyValue::I32(v))) => b.append_value(v),
+            (Column::UInt32(b), Some(PropertyValue::U32(v))) => b.append_value(v),
+            (Column::Int64(b), Some(PropertyValue::I64(v))) => b.append_value(v),
+            (Column::UInt64(b), Some(PropertyValue::U64(v))) => b.append_value(v),
+            (Column::UInt64(b), Some(PropertyValue::Pointer(v))) => b.append_value(v as u64),
+            (Column::Float32(b), Some(PropertyValue::F32(v))) => b.append_value(v),
+            (Column::Float64(b), Some(PropertyValue::F64(v))) => b.append_value(v),
+            (Column::Boolean(b), Some(PropertyValue::Bool(v))) => b.append_value(v),
+            (Column::Utf8(b), Some(PropertyValue::String(v))) => b.append_value(v),
+            (Column::Utf8(b), Some(PropertyValue::Guid(v))) => {
+                b.append_value(crate::fmt::format_guid(&v))
+            }
+            (Column::Timestamp(b), Some(PropertyValue::Time(v))) => {
+                match v.duration_since(UNIX_EPOCH) {
+                    Ok(since_epoch) => b.append_value(since_epoch.as_micros() as i64),
+                    Err(_) => b.append_null(),
+                }
+            }
+            (Column::Binary(b), Some(PropertyValue::Binary(v))) => b.append_value(v),
+            (column, _) => column.append_null(),
+        }
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            Column::Int8(b) => b.append_null(),
+            Column::UInt8(b) => b.append_null(),
+            Column::Int16(b) => b.append_null(),
+            Column::UInt16(b) => b.append_null(),
+            Column::Int32(b) => b.append_null(),
+            Column::UInt32(b) => b.append_null(),
+            Column::Int64(b) => b.append_null(),
+            Column::UInt64(b) => b.append_null(),
+            Column::Float32(b) => b.append_null(),
+            Column::Float64(b) => b.append_null(),
+            Column::Boolean(b) => b.append_null(),
+            Column::Utf8(b) => b.append_null(),
+            Column::Timestamp(b) => b.append_null(),
+            Column::Binary(b) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Column::Int8(b) => Arc::new(b.finish()),
+            Column::UInt8(b) => Arc::new(b.finish()),
+            Column::Int16(b) => Arc::new(b.finish()),
+            Column::UInt16(b) => Arc::new(b.finish()),
+            Column::Int32(b) => Arc::new(b.finish()),
+            Column::UInt32(b) => Arc::new(b.finish()),
+            Column::Int64(b) => Arc::new(b.finish()),
+            Column::UInt64(b) => Arc::new(b.finish()),
+            Column::Float32(b) => Arc::new(b.finish()),
+            Column::Float64(b) => Arc::new(b.finish()),
+            Column::Boolean(b) => Arc::new(b.finish()),
+            Column::Utf8(b) => Arc::new(b.finish()),
+            Column::Timestamp(b) => Arc::new(b.finish()),
+            Column::Binary(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Collects the events of a single Provider, EventId and Version into Arrow [RecordBatch]es
+///
+/// The columns are created from the top level properties of the first [Schema] pushed, one row is
+/// appended per event and a [RecordBatch] is produced every `batch_size` rows. Events of another
+/// Provider, EventId or Version are ignored, EventIds are only unique within a Provider and a new
+/// Version can change the properties. Use a collector per event to export several events
+///
+/// # Example
+/// ```rust
+/// let mut collector = ColumnarCollector::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1, 0, 1024)?;
+/// let my_callback = move |record: EventRecord, schema_locator: &mut SchemaLocator| {
+///     if let Ok(schema) = schema_locator.event_schema(record) {
+///         collector.push(&schema).unwrap();
+///     }
+/// };
+/// ```
+pub struct ColumnarCollector {
+    provider_guid: Guid,
+    event_id: u16,
+    version: u8,
+    batch_size: usize,
+    schema: Option<SchemaRef>,
+    names: Vec<String>,
+    columns: Vec<Column>,
+    rows: usize,
+    batches: Vec<RecordBatch>,
+}
+
+impl ColumnarCollector {
+    /// Use the `new` function to create a ColumnarCollector
+    ///
+    /// # Arguments
+    /// * `provider_guid` - A string representation of the Provider GUID, without curly braces
+    /// * `event_id` - EventId of the events collected
+    /// * `version` - Version of the events collected
+    /// * `batch_size` - Number of rows of each [RecordBatch], a `batch_size` of 0 is treated as 1
+    ///
+    /// # Remarks
+    /// This function can fail, if the string isn't made of 32 hex digits in the 8-4-4-4-12 format
+    /// a [ColumnarError::InvalidGuid] is returned
+    pub fn new(
+        provider_guid: &str,
+        event_id: u16,
+        version: u8,
+        batch_size: usize,
+    ) -> ColumnarResult<Self> {
+        if !utils::is_guid_str(provider_guid) {
+            return Err(ColumnarError::InvalidGuid(provider_guid.to_string()));
+        }
+        Ok(ColumnarCollector {
+            provider_guid: Guid::from(provider_guid),
+            event_id,
+            version,
+            batch_size: batch_size.max(1),
+            schema: None,
+            names: Vec::new(),
+            columns: Vec::new(),
+            rows: 0,
+            batches: Vec::new(),
+        })
+    }
+
+    /// Use the `push` function to append the properties of an event as a new row
+    ///
+    /// Returns `false` if the event was ignored because its Provider, EventId or Version don't
+    /// match the collector
+    ///
+    /// # Arguments
+    /// * `schema` - The [Schema] of the event
+    ///
+    /// # Remarks
+    /// This function can fail, if the properties can't be parsed or the [RecordBatch] can't be
+    /// built the [ColumnarError] will be returned accordingly
+    pub fn push(&mut self, schema: &Schema) -> ColumnarResult<bool> {
+        if schema.provider_guid() != self.provider_guid
+            || schema.event_id() != self.event_id
+            || schema.event_version() != self.version
+        {
+            return Ok(false);
+        }
+        if self.schema.is_none() {
+            self.init_columns(schema);
+        }
+
+        let mut values = Parser::create(schema).try_parse_all()?;
+        for (name, column) in self.names.iter().zip(self.columns.iter_mut()) {
+            let value = values
+                .iter()
+                .position(|(prop, _)| prop == name)
+                .map(|i| values.swap_remove(i).1);
+            column.append(value);
+        }
+
+        self.rows += 1;
+        if self.rows >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(true)
+    }
+
+    /// Use the `flush` function to turn the pending rows into a [RecordBatch]
+    ///
+    /// Nothing is done if there are no pending rows
+    pub fn flush(&mut self) -> ColumnarResult<()> {
+        let schema = match &self.schema {
+            Some(schema) if self.rows > 0 => Arc::clone(schema),
+            _ => return Ok(()),
+        };
+
+        let columns = self.columns.iter_mut().map(|c| c.finish()).collect();
+        self.batches.push(RecordBatch::try_new(schema, columns)?);
+        self.rows = 0;
+        Ok(())
+    }
+
+    /// Use the `take_batches` function to obtain the [RecordBatch]es produced so far
+    ///
+    /// Pending rows are not included, call [ColumnarCollector::flush] first to get them as well
+    pub fn take_batches(&mut self) -> Vec<RecordBatch> {
+        std::mem::take(&mut self.batches)
+    }
+
+    /// Use the `arrow_schema` function to obtain the Arrow schema of the batches
+    ///
+    /// Returns `None` until the first event has been pushed
+    pub fn arrow_schema(&self) -> Option<SchemaRef> {
+        self.schema.clone()
+    }
+
+    fn init_columns(&mut self, schema: &Schema) {
+        let mut fields = Vec::new();
+        for i in 0..schema.top_level_property_count() {
+            let property = schema.property(i);
+            let column = Column::for_property(&property);
+            fields.push(Field::new(&property.name, column.data_type(), true));
+            self.names.push(property.name);
+            self.columns.push(column);
+        }
+        self.schema = Some(Arc::new(arrow::datatypes::Schema::new(fields)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+    use arrow::array::{UInt32Array, UInt64Array};
+
+    #[test]
+    fn test_collect_record_batches() {
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1)
+            .property(TestProperty::new(
+                "ProcessID",
+                TdhInType::InTypeUInt32 as u16,
+                4,
+            ))
+            .property(TestProperty::new(
+                "Flags",
+                TdhInType::InTypeHexInt64 as u16,
+                8,
+            ));
+        let mut collector =
+            ColumnarCollector::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1, 0, 2).unwrap();
+
+        for (pid, flags) in [(4u32, 0x1u64), (100, 0x10), (200, 0x100)].iter() {
+            let mut user_data = pid.to_ne_bytes().to_vec();
+            user_data.extend_from_slice(&flags.to_ne_bytes());
+            let event = TestEvent::new(&test_schema, user_data);
+            assert!(collector.push(&event.schema(&test_schema)).unwrap());
+        }
+        let other = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 2);
+        let event = TestEvent::new(&other, Vec::new());
+        assert!(!collector.push(&event.schema(&other)).unwrap());
+
+        let mut batches = collector.take_batches();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 2);
+        collector.flush().unwrap();
+        batches.extend(collector.take_batches());
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1].num_rows(), 1);
+
+        let schema = collector.arrow_schema().unwrap();
+        assert_eq!(schema.field(0).name(), "ProcessID");
+        assert_eq!(schema.field(0).data_type(), &DataType::UInt32);
+        assert_eq!(schema.field(1).data_type(), &DataType::UInt64);
+
+        let mut pids = Vec::new();
+        let mut flags = Vec::new();
+        for batch in batches.iter() {
+            let pid_column = batch.column(0);
+            let pid_column = pid_column.as_any().downcast_ref::<UInt32Array>().unwrap();
+            let flag_column = batch.column(1);
+            let flag_column = flag_column.as_any().downcast_ref::<UInt64Array>().unwrap();
+            for i in 0..batch.num_rows() {
+                pids.push(pid_column.value(i));
+                flags.push(flag_column.value(i));
+            }
+        }
+        assert_eq!(pids, vec![4, 100, 200]);
+        assert_eq!(flags, vec![0x1, 0x10, 0x100]);
+    }
+
+    #[test]
+    fn test_collect_ignores_other_providers_and_versions() {
+        let pid = TestProperty::new("ProcessID", TdhInType::InTypeUInt32 as u16, 4);
+        let test_schema =
+            TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1).property(pid.clone());
+        // Same EventId in another Provider, with a property of another type
+        let other_provider = TestSchema::new("A0C1853B-5C40-4B15-8766-3CF1C58F985A", 1).property(
+            TestProperty::new("ProcessID", TdhInType::InTypeUInt64 as u16, 8),
+        );
+        let mut other_version =
+            TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1).property(pid);
+        other_version.version = 1;
+        let mut collector =
+            ColumnarCollector::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1, 0, 16).unwrap();
+
+        let event = TestEvent::new(&other_provider, 7u64.to_ne_bytes().to_vec());
+        assert!(!collector.push(&event.schema(&other_provider)).unwrap());
+        let event = TestEvent::new(&other_version, 8u32.to_ne_bytes().to_vec());
+        assert!(!collector.push(&event.schema(&other_version)).unwrap());
+        let event = TestEvent::new(&test_schema, 4u32.to_ne_bytes().to_vec());
+        assert!(collector.push(&event.schema(&test_schema)).unwrap());
+        collector.flush().unwrap();
+
+        let schema = collector.arrow_schema().unwrap();
+        assert_eq!(schema.field(0).data_type(), &DataType::UInt32);
+        let batches = collector.take_batches();
+        assert_eq!(batches.len(), 1);
+        let pid_column = batches[0].column(0);
+        let pid_column = pid_column.as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(batches[0].num_rows(), 1);
+        assert_eq!(pid_column.value(0), 4);
+    }
+
+    #[test]
+    fn test_collector_invalid_guid() {
+        for malformed in ["", "{22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716}", "not-a-guid"] {
+            assert!(matches!(
+                ColumnarCollector::new(malformed, 1, 0, 16),
+                Err(ColumnarError::InvalidGuid(value)) if value == malformed
+            ));
+        }
+    }
+}
@@ -0,0 +1,697 @@
+//! Kernel Event decoders
+//!
+//! Decoders for the classic (MOF) events logged by the [kernel_providers]. The layouts follow the
+//! [MSNT_SystemTrace](https://docs.microsoft.com/en-us/windows/win32/etw/msnt-systemtrace) classes
+//!
+//! [kernel_providers]: crate::provider::kernel_providers
+use crate::native::etw_types::{self, EventRecord, EVENT_HEADER_FLAG_32_BIT_HEADER};
+use crate::provider::kernel_providers::kernel_guids;
+use std::collections::{HashMap, VecDeque};
+use windows::Guid;
+
+/// DiskIo Read event opcode
+pub const DISK_IO_READ_OPCODE: u8 = 10;
+/// DiskIo Write event opcode
+pub const DISK_IO_WRITE_OPCODE: u8 = 11;
+
+/// StackWalk Stack event opcode
+pub const STACK_WALK_OPCODE: u8 = 32;
+
+/// Registry CreateKey event opcode
+pub const REGISTRY_CREATE_OPCODE: u8 = 10;
+/// Registry OpenKey event opcode
+pub const REGISTRY_OPEN_OPCODE: u8 = 11;
+/// Registry DeleteKey event opcode
+pub const REGISTRY_DELETE_OPCODE: u8 = 12;
+/// Registry QueryKey event opcode
+pub const REGISTRY_QUERY_OPCODE: u8 = 13;
+/// Registry SetValueKey event opcode
+pub const REGISTRY_SET_VALUE_OPCODE: u8 = 14;
+/// Registry DeleteValueKey event opcode
+pub const REGISTRY_DELETE_VALUE_OPCODE: u8 = 15;
+/// Registry QueryValueKey event opcode
+pub const REGISTRY_QUERY_VALUE_OPCODE: u8 = 16;
+/// Registry KCBCreate event opcode, logged when a key control block is created
+pub const REGISTRY_KCB_CREATE_OPCODE: u8 = 22;
+/// Registry KCBDelete event opcode, logged when a key control block is freed
+pub const REGISTRY_KCB_DELETE_OPCODE: u8 = 23;
+/// Registry KCBRundownBegin event opcode, logged for the key control blocks alive when the trace starts
+pub const REGISTRY_KCB_RUNDOWN_BEGIN_OPCODE: u8 = 24;
+/// Registry KCBRundownEnd event opcode
+pub const REGISTRY_KCB_RUNDOWN_END_OPCODE: u8 = 25;
+/// Registry Close event opcode
+pub const REGISTRY_CLOSE_OPCODE: u8 = 27;
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?;
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes);
+    Some(u64::from_ne_bytes(value))
+}
+
+fn read_pointer(data: &[u8], offset: usize, pointer_size: usize) -> Option<u64> {
+    match pointer_size {
+        4 => read_u32(data, offset).map(u64::from),
+        8 => read_u64(data, offset),
+        _ => None,
+    }
+}
+
+// Null terminated UTF-16 string, a missing terminator ends the string at the end of the buffer
+fn read_utf16(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let chars: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    Some(String::from_utf16_lossy(&chars))
+}
+
+fn record_pointer_size(record: &EventRecord) -> usize {
+    if record.EventHeader.Flags & EVENT_HEADER_FLAG_32_BIT_HEADER != 0 {
+        4
+    } else {
+        8
+    }
+}
+
+fn record_user_data(record: &EventRecord) -> Option<&[u8]> {
+    if record.UserData.is_null() {
+        return None;
+    }
+    Some(etw_types::user_data(record))
+}
+
+/// Represents a DiskIo Read or Write event
+///
+/// See: [DiskIo_TypeGroup1](https://docs.microsoft.com/en-us/windows/win32/etw/diskio-typegroup1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskIoReadWrite {
+    /// Index of the disk that contains the data
+    pub disk_number: u32,
+    /// Flags of the I/O request packet
+    pub irp_flags: u32,
+    /// Size of the data transfer, in bytes
+    pub transfer_size: u32,
+    /// Byte offset from the beginning of the disk
+    pub byte_offset: u64,
+    /// File object pointer, widened to 64 bits for 32-bit events
+    pub file_object: u64,
+    /// I/O request packet pointer, widened to 64 bits for 32-bit events
+    pub irp: u64,
+    /// Time taken to complete the I/O request, in CPU ticks
+    pub high_res_response_time: u64,
+}
+
+impl DiskIoReadWrite {
+    /// Use the `decode` function to decode the user data of a DiskIo Read or Write event
+    ///
+    /// Returns `None` when the buffer is too small to hold the event
+    ///
+    /// # Arguments
+    /// * `data` - User data of the event
+    /// * `pointer_size` - Size of a pointer, in bytes, on the machine that logged the event (4 or 8)
+    pub fn decode(data: &[u8], pointer_size: usize) -> Option<Self> {
+        let pointers = 24;
+        Some(DiskIoReadWrite {
+            disk_number: read_u32(data, 0)?,
+            irp_flags: read_u32(data, 4)?,
+            transfer_size: read_u32(data, 8)?,
+            // 4 reserved bytes keep the ByteOffset aligned
+            byte_offset: read_u64(data, 16)?,
+            file_object: read_pointer(data, pointers, pointer_size)?,
+            irp: read_pointer(data, pointers + pointer_size, pointer_size)?,
+            high_res_response_time: read_u64(data, pointers + 2 * pointer_size)?,
+        })
+    }
+
+    /// Use the `from_record` function to decode an [EventRecord] as a DiskIo Read or Write event
+    ///
+    /// Returns `None` when the Event is not a DiskIo Read or Write or its data is truncated. The
+    /// pointer size is taken from the Event header flags
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if let Some(io) = DiskIoReadWrite::from_record(&record) {
+    ///         println!("{} bytes at {}", io.transfer_size, io.byte_offset);
+    ///     }
+    /// };
+    /// ```
+    pub fn from_record(record: &EventRecord) -> Option<Self> {
+        let header = &record.EventHeader;
+        if header.ProviderId != Guid::from(kernel_guids::DISK_IO_GUID) {
+            return None;
+        }
+        let opcode = header.EventDescriptor.Opcode;
+        if opcode != DISK_IO_READ_OPCODE && opcode != DISK_IO_WRITE_OPCODE {
+            return None;
+        }
+
+        DiskIoReadWrite::decode(record_user_data(record)?, record_pointer_size(record))
+    }
+}
+
+/// Represents a Registry event
+///
+/// Registry events reference the key through the address of its key control block (KCB), see
+/// [RegistryPathResolver] to turn it into a key path
+///
+/// See: [Registry_TypeGroup1](https://docs.microsoft.com/en-us/windows/win32/etw/registry-typegroup1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryEvent {
+    /// Opcode of the event, see the `REGISTRY_*_OPCODE` constants
+    pub opcode: u8,
+    /// Initial time of the registry operation
+    pub initial_time: i64,
+    /// NTSTATUS value of the registry operation
+    pub status: u32,
+    /// Subkey index of the registry operation, for enumerations
+    pub index: u32,
+    /// Address of the key control block, widened to 64 bits for 32-bit events
+    pub key_handle: u64,
+    /// Key name, relative to `key_handle` unless `key_handle` is zero. Holds the value name for
+    /// the value operations
+    pub key_name: String,
+}
+
+impl RegistryEvent {
+    /// Use the `decode` function to decode the user data of a Registry event
+    ///
+    /// Returns `None` when the buffer is too small to hold the event
+    ///
+    /// # Arguments
+    /// * `opcode` - Opcode of the event
+    /// * `data` - User data of the event
+    /// * `pointer_size` - Size of a pointer, in bytes, on the machine that logged the event (4 or 8)
+    pub fn decode(opcode: u8, data: &[u8], pointer_size: usize) -> Option<Self> {
+        Some(RegistryEvent {
+            opcode,
+            initial_time: read_u64(data, 0)? as i64,
+            status: read_u32(data, 8)?,
+            index: read_u32(data, 12)?,
+            key_handle: read_pointer(data, 16, pointer_size)?,
+            key_name: read_utf16(data, 16 + pointer_size).unwrap_or_default(),
+        })
+    }
+
+    /// Use the `from_record` function to decode an [EventRecord] as a Registry event
+    ///
+    /// Returns `None` when the Event is not a Registry event or its data is truncated. The pointer
+    /// size is taken from the Event header flags
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if let Some(event) = RegistryEvent::from_record(&record) {
+    ///         println!("{:#x} {}", event.key_handle, event.key_name);
+    ///     }
+    /// };
+    /// ```
+    pub fn from_record(record: &EventRecord) -> Option<Self> {
+        let header = &record.EventHeader;
+        if header.ProviderId != Guid::from(kernel_guids::REGISTRY_GUID) {
+            return None;
+        }
+        RegistryEvent::decode(
+            header.EventDescriptor.Opcode,
+            record_user_data(record)?,
+            record_pointer_size(record),
+        )
+    }
+
+    fn is_kcb_name(&self) -> bool {
+        self.opcode == REGISTRY_KCB_CREATE_OPCODE
+            || self.opcode == REGISTRY_KCB_RUNDOWN_BEGIN_OPCODE
+    }
+
+    // The value operations log the value name instead of a key name
+    fn is_value_operation(&self) -> bool {
+        matches!(
+            self.opcode,
+            REGISTRY_SET_VALUE_OPCODE | REGISTRY_DELETE_VALUE_OPCODE | REGISTRY_QUERY_VALUE_OPCODE
+        )
+    }
+}
+
+/// Resolves the key paths of the [RegistryEvent]s
+///
+/// Registry events only carry the address of the key control block (KCB) they operate on, the
+/// resolver keeps the KCB to path map built from the KCBCreate and KCBRundownBegin events and
+/// drops the entries on KCBDelete, so a reused KCB address never resolves to the path of the
+/// freed key
+///
+/// # Remarks
+/// The rundown events are only logged when the trace starts, keys opened before the trace started
+/// can't be resolved if the rundown is missed
+///
+/// # Example
+/// ```rust
+/// let mut resolver = RegistryPathResolver::new();
+/// let my_callback = move |record: EventRecord, schema_locator: &mut SchemaLocator| {
+///     if let Some(event) = RegistryEvent::from_record(&record) {
+///         if let Some(path) = resolver.observe(&event) {
+///             println!("{}", path);
+///         }
+///     }
+/// };
+/// ```
+#[derive(Debug, Default)]
+pub struct RegistryPathResolver {
+    paths: HashMap<u64, String>,
+}
+
+impl RegistryPathResolver {
+    /// Use the `new` function to create an empty RegistryPathResolver
+    pub fn new() -> Self {
+        RegistryPathResolver {
+            paths: HashMap::new(),
+        }
+    }
+
+    /// Use the `observe` function to feed a [RegistryEvent] to the resolver
+    ///
+    /// Updates the KCB map with the KCB events and returns the path of the key the event refers
+    /// to, if it can be resolved
+    ///
+    /// # Arguments
+    /// * `event` - The [RegistryEvent] to track and resolve
+    pub fn observe(&mut self, event: &RegistryEvent) -> Option<String> {
+        if event.is_kcb_name() {
+            self.paths.insert(event.key_handle, event.key_name.clone());
+            return Some(event.key_name.clone());
+        }
+
+        let path = self.resolve(event);
+        if event.opcode == REGISTRY_KCB_DELETE_OPCODE {
+            self.paths.remove(&event.key_handle);
+        }
+        path
+    }
+
+    /// Use the `resolve` function to obtain the path of the key a [RegistryEvent] refers to
+    ///
+    /// The key name of the event is appended to the path of its KCB, events without KCB already
+    /// hold the full path. Value operations resolve to the path of the key holding the value
+    ///
+    /// # Arguments
+    /// * `event` - The [RegistryEvent] to resolve
+    pub fn resolve(&self, event: &RegistryEvent) -> Option<String> {
+        if event.key_handle == 0 {
+            if event.key_name.is_empty() {
+                return None;
+            }
+            return Some(event.key_name.clone());
+        }
+
+        let base = self.paths.get(&event.key_handle)?;
+        if event.key_name.is_empty() || event.is_kcb_name() || event.is_value_operation() {
+            Some(base.clone())
+        } else {
+            Some(format!("{}\\{}", base, event.key_name))
+        }
+    }
+}
+
+/// Represents a StackWalk event
+///
+/// Logged right after the event whose stack was walked, which is identified by its timestamp and
+/// thread, see [StackWalkCorrelator]
+///
+/// See: [StackWalk_Event](https://docs.microsoft.com/en-us/windows/win32/etw/stackwalk-event)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackWalkEvent {
+    /// TimeStamp of the event the stack belongs to
+    pub event_timestamp: i64,
+    /// Id of the process the stack belongs to
+    pub process_id: u32,
+    /// Id of the thread the stack belongs to
+    pub thread_id: u32,
+    /// Return addresses, innermost frame first, widened to 64 bits for 32-bit events
+    pub stack: Vec<u64>,
+}
+
+impl StackWalkEvent {
+    /// Use the `decode` function to decode the user data of a StackWalk event
+    ///
+    /// Returns `None` when the buffer is too small to hold the event
+    ///
+    /// # Arguments
+    /// * `data` - User data of the event
+    /// * `pointer_size` - Size of a pointer, in bytes, on the machine that logged the event (4 or 8)
+    pub fn decode(data: &[u8], pointer_size: usize) -> Option<Self> {
+        if pointer_size == 0 {
+            return None;
+        }
+        let frames = data.len().checked_sub(16)? / pointer_size;
+        Some(StackWalkEvent {
+            event_timestamp: read_u64(data, 0)? as i64,
+            process_id: read_u32(data, 8)?,
+            thread_id: read_u32(data, 12)?,
+            stack: (0..frames)
+                .map(|i| read_pointer(data, 16 + i * pointer_size, pointer_size))
+                .collect::<Option<Vec<u64>>>()?,
+        })
+    }
+
+    /// Use the `from_record` function to decode an [EventRecord] as a StackWalk event
+    ///
+    /// Returns `None` when the Event is not a StackWalk event or its data is truncated
+    pub fn from_record(record: &EventRecord) -> Option<Self> {
+        let header = &record.EventHeader;
+        if header.ProviderId != Guid::from(kernel_guids::STACK_WALK_GUID)
+            || header.EventDescriptor.Opcode != STACK_WALK_OPCODE
+        {
+            return None;
+        }
+        StackWalkEvent::decode(record_user_data(record)?, record_pointer_size(record))
+    }
+}
+
+/// An event along with the call stack logged for it, see [StackWalkCorrelator]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackedEvent<T> {
+    /// The event, as decoded by the user
+    pub event: T,
+    /// Return addresses, innermost frame first
+    pub stack: Vec<u64>,
+}
+
+/// Attaches the StackWalk events to the events they were logged for
+///
+/// Stack walking is enabled per event with [KernelTrace::enable_stack_walk], the StackWalk event
+/// comes after its target event and references it by timestamp and thread. The correlator keeps
+/// the last `capacity` target events until their stack arrives, older events are evicted
+///
+/// # Example
+/// ```rust
+/// let mut correlator = StackWalkCorrelator::new(1024);
+/// let my_callback = move |record: EventRecord, schema_locator: &mut SchemaLocator| {
+///     if let Some(stack) = StackWalkEvent::from_record(&record) {
+///         if let Some(stacked) = correlator.push_stack(stack) {
+///             println!("{:?} {:x?}", stacked.event, stacked.stack);
+///         }
+///     } else if let Some(io) = DiskIoReadWrite::from_record(&record) {
+///         correlator.push_event(&record, io);
+///     }
+/// };
+/// ```
+///
+/// [KernelTrace::enable_stack_walk]: crate::trace::KernelTrace::enable_stack_walk
+#[derive(Debug)]
+pub struct StackWalkCorrelator<T> {
+    capacity: usize,
+    pending: HashMap<(u32, i64), T>,
+    order: VecDeque<(u32, i64)>,
+}
+
+impl<T> StackWalkCorrelator<T> {
+    /// Use the `new` function to create a StackWalkCorrelator
+    ///
+    /// # Arguments
+    /// * `capacity` - Maximum number of events waiting for their stack, a `capacity` of 0 is
+    ///   treated as 1
+    pub fn new(capacity: usize) -> Self {
+        StackWalkCorrelator {
+            capacity: capacity.max(1),
+            pending: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Use the `push_event` function to keep an event until its stack arrives
+    ///
+    /// Returns the oldest pending event if it had to be evicted to make room
+    ///
+    /// # Arguments
+    /// * `record` - The [EventRecord] of the event, its header identifies the stack
+    /// * `event` - The decoded event
+    pub fn push_event(&mut self, record: &EventRecord, event: T) -> Option<T> {
+        let key = (record.EventHeader.ThreadId, record.EventHeader.TimeStamp);
+        if self.pending.insert(key, event).is_none() {
+            self.order.push_back(key);
+        }
+
+        while self.pending.len() > self.capacity {
+            let oldest = self.order.pop_front()?;
+            if let Some(evicted) = self.pending.remove(&oldest) {
+                return Some(evicted);
+            }
+        }
+        None
+    }
+
+    /// Use the `push_stack` function to attach a [StackWalkEvent] to its event
+    ///
+    /// Returns `None` when the event of the stack is not pending, e.g. it wasn't pushed or it was
+    /// already evicted
+    pub fn push_stack(&mut self, stack: StackWalkEvent) -> Option<StackedEvent<T>> {
+        let key = (stack.thread_id, stack.event_timestamp);
+        let event = self.pending.remove(&key)?;
+        self.order.retain(|pending| *pending != key);
+        Some(StackedEvent {
+            event,
+            stack: stack.stack,
+        })
+    }
+
+    /// Use the `drain` function to take the events still waiting for their stack
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut pending = std::mem::take(&mut self.pending);
+        self.order
+            .drain(..)
+            .filter_map(|key| pending.remove(&key))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // DiskNumber 1, IrpFlags 0x60043, TransferSize 0x1000, ByteOffset 0x1_2345_6000
+    fn disk_io_header() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u32.to_ne_bytes());
+        data.extend_from_slice(&0x60043u32.to_ne_bytes());
+        data.extend_from_slice(&0x1000u32.to_ne_bytes());
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        data.extend_from_slice(&0x1_2345_6000u64.to_ne_bytes());
+        data
+    }
+
+    #[test]
+    fn test_decode_disk_io_64bit() {
+        let mut data = disk_io_header();
+        data.extend_from_slice(&0xffff_a000_0000_1000u64.to_ne_bytes());
+        data.extend_from_slice(&0xffff_a000_0000_2000u64.to_ne_bytes());
+        data.extend_from_slice(&1500u64.to_ne_bytes());
+
+        let io = DiskIoReadWrite::decode(&data, 8).unwrap();
+        assert_eq!(io.disk_number, 1);
+        assert_eq!(io.irp_flags, 0x60043);
+        assert_eq!(io.transfer_size, 0x1000);
+        assert_eq!(io.byte_offset, 0x1_2345_6000);
+        assert_eq!(io.file_object, 0xffff_a000_0000_1000);
+        assert_eq!(io.irp, 0xffff_a000_0000_2000);
+        assert_eq!(io.high_res_response_time, 1500);
+    }
+
+    #[test]
+    fn test_decode_disk_io_32bit() {
+        let mut data = disk_io_header();
+        data.extend_from_slice(&0x8000_1000u32.to_ne_bytes());
+        data.extend_from_slice(&0x8000_2000u32.to_ne_bytes());
+        data.extend_from_slice(&1500u64.to_ne_bytes());
+
+        let io = DiskIoReadWrite::decode(&data, 4).unwrap();
+        assert_eq!(io.byte_offset, 0x1_2345_6000);
+        assert_eq!(io.file_object, 0x8000_1000);
+        assert_eq!(io.irp, 0x8000_2000);
+        assert_eq!(io.high_res_response_time, 1500);
+    }
+
+    #[test]
+    fn test_decode_disk_io_truncated() {
+        let mut data = disk_io_header();
+        data.extend_from_slice(&0x8000_1000u32.to_ne_bytes());
+
+        assert_eq!(DiskIoReadWrite::decode(&data, 8), None);
+    }
+
+    #[test]
+    fn test_from_record_32bit_header() {
+        let mut data = disk_io_header();
+        data.extend_from_slice(&0x8000_1000u32.to_ne_bytes());
+        data.extend_from_slice(&0x8000_2000u32.to_ne_bytes());
+        data.extend_from_slice(&1500u64.to_ne_bytes());
+
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.ProviderId = Guid::from(kernel_guids::DISK_IO_GUID);
+        record.EventHeader.EventDescriptor.Opcode = DISK_IO_WRITE_OPCODE;
+        record.EventHeader.Flags = EVENT_HEADER_FLAG_32_BIT_HEADER;
+        record.UserDataLength = data.len() as u16;
+        record.UserData = data.as_mut_ptr() as *mut _;
+
+        let io = DiskIoReadWrite::from_record(&record).unwrap();
+        assert_eq!(io.file_object, 0x8000_1000);
+
+        record.EventHeader.EventDescriptor.Opcode = 12;
+        assert_eq!(DiskIoReadWrite::from_record(&record), None);
+    }
+
+    #[test]
+    fn test_stack_walk_correlation() {
+        let mut io_data = disk_io_header();
+        io_data.extend_from_slice(&0xffff_a000_0000_1000u64.to_ne_bytes());
+        io_data.extend_from_slice(&0xffff_a000_0000_2000u64.to_ne_bytes());
+        io_data.extend_from_slice(&1500u64.to_ne_bytes());
+        let mut io_record: EventRecord = unsafe { std::mem::zeroed() };
+        io_record.EventHeader.ProviderId = Guid::from(kernel_guids::DISK_IO_GUID);
+        io_record.EventHeader.EventDescriptor.Opcode = DISK_IO_READ_OPCODE;
+        io_record.EventHeader.ThreadId = 42;
+        io_record.EventHeader.TimeStamp = 1000;
+        io_record.UserDataLength = io_data.len() as u16;
+        io_record.UserData = io_data.as_mut_ptr() as *mut _;
+
+        let mut stack_data = Vec::new();
+        stack_data.extend_from_slice(&1000u64.to_ne_bytes());
+        stack_data.extend_from_slice(&4u32.to_ne_bytes());
+        stack_data.extend_from_slice(&42u32.to_ne_bytes());
+        stack_data.extend_from_slice(&0xffff_f800_0000_1234u64.to_ne_bytes());
+        stack_data.extend_from_slice(&0x7ff6_0000_5678u64.to_ne_bytes());
+        let mut stack_record: EventRecord = unsafe { std::mem::zeroed() };
+        stack_record.EventHeader.ProviderId = Guid::from(kernel_guids::STACK_WALK_GUID);
+        stack_record.EventHeader.EventDescriptor.Opcode = STACK_WALK_OPCODE;
+        stack_record.UserDataLength = stack_data.len() as u16;
+        stack_record.UserData = stack_data.as_mut_ptr() as *mut _;
+
+        let mut correlator = StackWalkCorrelator::new(4);
+        let io = DiskIoReadWrite::from_record(&io_record).unwrap();
+        assert_eq!(correlator.push_event(&io_record, io), None);
+        assert_eq!(StackWalkEvent::from_record(&io_record), None);
+
+        let stack = StackWalkEvent::from_record(&stack_record).unwrap();
+        assert_eq!(stack.process_id, 4);
+        let stacked = correlator.push_stack(stack.clone()).unwrap();
+        assert_eq!(stacked.event, io);
+        assert_eq!(stacked.stack, vec![0xffff_f800_0000_1234, 0x7ff6_0000_5678]);
+
+        // Already matched
+        assert_eq!(correlator.push_stack(stack), None);
+        assert!(correlator.drain().is_empty());
+    }
+
+    #[test]
+    fn test_stack_walk_correlator_eviction() {
+        let mut correlator = StackWalkCorrelator::new(2);
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        for timestamp in 1..=3 {
+            record.EventHeader.TimeStamp = timestamp;
+            let evicted = correlator.push_event(&record, timestamp);
+            assert_eq!(evicted, if timestamp == 3 { Some(1) } else { None });
+        }
+
+        assert_eq!(correlator.drain(), vec![2, 3]);
+    }
+
+    fn registry_data(key_handle: u64, key_name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&0i64.to_ne_bytes());
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        data.extend_from_slice(&key_handle.to_ne_bytes());
+        key_name
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .for_each(|c| data.extend_from_slice(&c.to_ne_bytes()));
+        data
+    }
+
+    fn registry_event(opcode: u8, key_handle: u64, key_name: &str) -> RegistryEvent {
+        RegistryEvent::decode(opcode, &registry_data(key_handle, key_name), 8).unwrap()
+    }
+
+    #[test]
+    fn test_decode_registry_event() {
+        let mut data = registry_data(0xffff_c000_0000_1000, "Software\\Test");
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.ProviderId = Guid::from(kernel_guids::REGISTRY_GUID);
+        record.EventHeader.EventDescriptor.Opcode = REGISTRY_OPEN_OPCODE;
+        record.UserDataLength = data.len() as u16;
+        record.UserData = data.as_mut_ptr() as *mut _;
+
+        let event = RegistryEvent::from_record(&record).unwrap();
+        assert_eq!(event.opcode, REGISTRY_OPEN_OPCODE);
+        assert_eq!(event.key_handle, 0xffff_c000_0000_1000);
+        assert_eq!(event.key_name, "Software\\Test");
+    }
+
+    #[test]
+    fn test_registry_path_resolution() {
+        let machine = 0xffff_c000_0000_1000;
+        let mut resolver = RegistryPathResolver::new();
+
+        resolver.observe(&registry_event(
+            REGISTRY_KCB_RUNDOWN_BEGIN_OPCODE,
+            machine,
+            "\\REGISTRY\\MACHINE",
+        ));
+        assert_eq!(
+            resolver.observe(&registry_event(
+                REGISTRY_OPEN_OPCODE,
+                machine,
+                "SOFTWARE\\Test"
+            )),
+            Some("\\REGISTRY\\MACHINE\\SOFTWARE\\Test".to_owned())
+        );
+        assert_eq!(
+            resolver.observe(&registry_event(
+                REGISTRY_QUERY_VALUE_OPCODE,
+                machine,
+                "Value"
+            )),
+            Some("\\REGISTRY\\MACHINE".to_owned())
+        );
+        assert_eq!(
+            resolver.observe(&registry_event(REGISTRY_QUERY_OPCODE, 0xdead, "")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_registry_path_handle_reuse() {
+        let kcb = 0xffff_c000_0000_2000;
+        let mut resolver = RegistryPathResolver::new();
+
+        resolver.observe(&registry_event(
+            REGISTRY_KCB_CREATE_OPCODE,
+            kcb,
+            "\\REGISTRY\\USER\\Old",
+        ));
+        assert_eq!(
+            resolver.observe(&registry_event(REGISTRY_KCB_DELETE_OPCODE, kcb, "")),
+            Some("\\REGISTRY\\USER\\Old".to_owned())
+        );
+        assert_eq!(
+            resolver.observe(&registry_event(REGISTRY_SET_VALUE_OPCODE, kcb, "Value")),
+            None
+        );
+
+        resolver.observe(&registry_event(
+            REGISTRY_KCB_CREATE_OPCODE,
+            kcb,
+            "\\REGISTRY\\USER\\New",
+        ));
+        assert_eq!(
+            resolver.observe(&registry_event(REGISTRY_SET_VALUE_OPCODE, kcb, "")),
+            Some("\\REGISTRY\\USER\\New".to_owned())
+        );
+    }
+}
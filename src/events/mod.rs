@@ -0,0 +1,5 @@
+//! Typed ETW Event decoders
+//!
+//! The `events` module provides decoders for well known events whose layout is fixed, so their
+//! data can be read without going through the [Schema](crate::schema::Schema) lookup
+pub mod kernel;
@@ -0,0 +1,92 @@
+//! Formatting helpers
+//!
+//! The `fmt` module holds the functions used across the crate to turn GUIDs, raw buffers and
+//! pointers into strings, so every feature, and the viewers built on top of the crate, display
+//! them the same way
+use windows::Guid;
+
+/// Use the `format_guid` function to format a GUID the way it's passed to this crate
+///
+/// Lowercase and without curly braces, e.g. `22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716`, which is the
+/// format accepted by [Provider::by_guid]
+///
+/// # Example
+/// ```rust
+/// let guid = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+/// assert_eq!(format_guid(&guid), "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+/// ```
+///
+/// [Provider::by_guid]: crate::provider::Provider::by_guid
+pub fn format_guid(guid: &Guid) -> String {
+    // The Debug impl of the bindings is the uppercase format without braces
+    format!("{:?}", guid).to_lowercase()
+}
+
+/// Use the `format_guid_braced` function to format a GUID the way Windows displays it
+///
+/// Uppercase and within curly braces, e.g. `{22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716}`, which is the
+/// format of [StringFromGUID2] and the one used by the Windows tools and the registry
+///
+/// [StringFromGUID2]: https://docs.microsoft.com/en-us/windows/win32/api/combaseapi/nf-combaseapi-stringfromguid2
+pub fn format_guid_braced(guid: &Guid) -> String {
+    format!("{{{}}}", format_guid(guid).to_uppercase())
+}
+
+/// Use the `format_hex` function to format a buffer as a string of lowercase hex digits
+///
+/// Two digits per byte, without prefix nor separators, e.g. `[0xde, 0xad]` is `dead`. An empty
+/// buffer results in an empty string
+pub fn format_hex(buffer: &[u8]) -> String {
+    buffer.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Use the `format_ptr` function to format a pointer of the machine that logged an Event
+///
+/// The value is prefixed with `0x` and zero padded to the width of the pointer, e.g.
+/// `0x00001000` for 32-bit pointers and `0x0000000000001000` for 64-bit pointers
+///
+/// # Arguments
+/// * `pointer` - The pointer, widened to 64 bits
+/// * `pointer_size` - Size of a pointer, in bytes, on the machine that logged the Event. Any size
+///   other than 4 is formatted as a 64-bit pointer
+pub fn format_ptr(pointer: u64, pointer_size: usize) -> String {
+    match pointer_size {
+        4 => format!("{:#010x}", pointer as u32),
+        _ => format!("{:#018x}", pointer),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GUID: &str = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+
+    #[test]
+    fn test_format_guid() {
+        let guid = Guid::from(GUID);
+        assert_eq!(format_guid(&guid), GUID);
+        assert_eq!(
+            format_guid_braced(&guid),
+            "{22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716}"
+        );
+        assert_eq!(
+            format_guid(&Guid::zeroed()),
+            "00000000-0000-0000-0000-000000000000"
+        );
+    }
+
+    #[test]
+    fn test_format_hex() {
+        assert_eq!(format_hex(&[0xde, 0xad, 0x00, 0x0f]), "dead000f");
+        assert_eq!(format_hex(&[]), "");
+    }
+
+    #[test]
+    fn test_format_ptr() {
+        assert_eq!(format_ptr(0x1000, 4), "0x00001000");
+        assert_eq!(format_ptr(0xffff_ffff_8000_1000, 4), "0x80001000");
+        assert_eq!(format_ptr(0x1000, 8), "0x0000000000001000");
+        assert_eq!(format_ptr(0xffff_f800_0000_1234, 8), "0xfffff80000001234");
+    }
+}
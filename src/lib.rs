@@ -89,7 +89,7 @@
 //!     std::thread::sleep(Duration::new(20, 0));
 //!   
 //!     // We stop the trace
-//!     trace.stop();
+//!     trace.stop().unwrap();
 //! }
 //! ```
 //!
@@ -109,11 +109,19 @@ extern crate num_traits;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod activity;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+pub mod events;
+pub mod fmt;
 pub mod native;
 pub mod parser;
 pub mod property;
 pub mod provider;
 pub mod schema;
+#[cfg(feature = "sink")]
+pub mod sink;
+pub mod summary;
 pub mod trace;
 mod traits;
 mod utils;
@@ -10,9 +10,10 @@ use super::bindings::Windows::Win32::{
     Etw,
     SystemServices::{MAX_PATH, PSTR},
 };
-use crate::native::tdh_types::Property;
-use crate::provider::Provider;
-use crate::trace::{TraceData, TraceProperties, TraceTrait};
+use crate::native::tdh_types::{Property, PropertyFlags};
+use crate::native::version_helper::is_win8_or_greater;
+use crate::provider::{kernel_providers, Provider};
+use crate::trace::{LogFile, TraceData, TraceProperties, TraceTrait};
 use crate::utils;
 use std::fmt::Formatter;
 use std::sync::RwLock;
@@ -22,12 +23,498 @@ use windows::Guid;
 pub(crate) type TraceHandle = u64;
 pub(crate) type EvenTraceControl = Etw::EVENT_TRACE_CONTROL;
 
-/// Renaming type [EVENT_RECORD] type to match rust Naming Convention
-///
-/// [EVENT_RECORD]: https://microsoft.github.io/windows-docs-rs/doc/bindings/Windows/Win32/Etw/struct.EVENT_RECORD.html
 pub type EventRecord = Etw::EVENT_RECORD;
 pub(crate) type PEventRecord = *mut EventRecord;
 
+/// Accessors over the header and buffers of an [EventRecord]
+///
+/// The trait has to be in scope to call them, e.g. `use ferrisetw::native::etw_types::EventRecordExt`
+pub trait EventRecordExt {
+    /// Use the `kernel_user_time` function to obtain the (KernelTime, UserTime) of the Event
+    ///
+    /// The times represent the elapsed execution time in CPU time units. This is `None` when the
+    /// event doesn't carry CPU times (`EVENT_HEADER_FLAG_NO_CPUTIME`) or when it comes from a private
+    /// session, which records the [EventRecordExt::processor_time] instead
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if let Some((kernel_time, user_time)) = record.kernel_user_time() {
+    ///         println!("{} {}", kernel_time, user_time);
+    ///     }
+    /// };
+    /// ```
+    fn kernel_user_time(&self) -> Option<(u32, u32)>;
+
+    /// Use the `processor_time` function to obtain the ProcessorTime of the Event
+    ///
+    /// Only events from private sessions (`EVENT_HEADER_FLAG_PRIVATE_SESSION`) record the processor
+    /// time, this is `None` otherwise or when the event doesn't carry CPU times
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let processor_time = record.processor_time();
+    /// };
+    /// ```
+    fn processor_time(&self) -> Option<u64>;
+
+    /// Use the `is_kernel_event` function to check if the Event was logged by the kernel
+    ///
+    /// This is the case for events whose provider is one of the [kernel_providers] GUIDs and for
+    /// events carrying a classic (MOF) header (`EVENT_HEADER_FLAG_CLASSIC_HEADER`). It can be used
+    /// to route the decoding of the Event to the MOF or the manifest path
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if record.is_kernel_event() {
+    ///         println!("kernel event");
+    ///     }
+    /// };
+    /// ```
+    ///
+    /// [kernel_providers]: crate::provider::kernel_providers
+    fn is_kernel_event(&self) -> bool;
+
+    /// Use the `is_classic` function to check if the Event carries a classic (MOF) header
+    /// (`EVENT_HEADER_FLAG_CLASSIC_HEADER`)
+    ///
+    /// Classic events are described by a MOF class instead of a manifest, this is the flag to
+    /// branch on when picking the decoding path
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if record.is_classic() {
+    ///         println!("MOF event");
+    ///     }
+    /// };
+    /// ```
+    fn is_classic(&self) -> bool;
+
+    /// Use the `as_string_event` function to obtain the message of an Event logged with
+    /// [EventWriteString]
+    ///
+    /// Those Events have no properties and no Schema, their whole payload is a single UTF-16
+    /// string. They are recognized by the `EVENT_HEADER_FLAG_STRING_ONLY` flag of the header
+    ///
+    /// # Remarks
+    /// Returns `None` for structured Events, use a [Parser] to read their properties
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if let Some(message) = record.as_string_event() {
+    ///         println!("{}", message);
+    ///     }
+    /// };
+    /// ```
+    ///
+    /// [EventWriteString]: https://docs.microsoft.com/en-us/windows/win32/api/evntprov/nf-evntprov-eventwritestring
+    /// [Parser]: crate::parser::Parser
+    fn as_string_event(&self) -> Option<String>;
+
+    /// Use the `timestamp` function to obtain the TimeStamp of the Event as a [RawTimestamp]
+    ///
+    /// The value is a FILETIME unless the Trace was built with `raw_timestamps`, in which case it
+    /// is the raw QPC value. Either way it can be converted with [RawTimestamp::to_system_time]
+    /// and the [TraceClock] of the Trace
+    ///
+    /// # Example
+    /// ```rust
+    /// let clock = trace.clock();
+    /// let my_callback = move |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let time = record.timestamp().to_system_time(&clock);
+    /// };
+    /// ```
+    fn timestamp(&self) -> RawTimestamp;
+
+    /// Use the `system_time` function to obtain the TimeStamp of the Event as a [SystemTime]
+    ///
+    /// Shorthand for [EventRecordExt::timestamp] followed by [RawTimestamp::to_system_time], the
+    /// TimeStamp is converted from the 100ns intervals since January 1, 1601 using the
+    /// [TraceClock] of the Trace, so it also applies to the raw QPC timestamps
+    ///
+    /// # Arguments
+    /// * `clock` - [TraceClock] of the Trace the event comes from
+    ///
+    /// # Remarks
+    /// Times before 1970 are kept, a negative TimeStamp is clamped to January 1, 1601
+    ///
+    /// # Example
+    /// ```rust
+    /// let clock = trace.clock();
+    /// let my_callback = move |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let time = record.system_time(&clock);
+    /// };
+    /// ```
+    ///
+    /// [SystemTime]: std::time::SystemTime
+    fn system_time(&self, clock: &TraceClock) -> std::time::SystemTime;
+
+    /// Use the `is_private_session` function to check if the Event comes from a private session
+    /// (`EVENT_HEADER_FLAG_PRIVATE_SESSION`)
+    fn is_private_session(&self) -> bool;
+
+    /// Use the `process_id` function to obtain the ProcessId of the Event
+    ///
+    /// Unlike [Schema::process_id] this doesn't require locating the Schema, which makes it
+    /// suitable to filter the events before decoding them
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if record.process_id() != target_pid {
+    ///         return;
+    ///     }
+    ///     let schema = schema_locator.event_schema(record)?;
+    /// };
+    /// ```
+    ///
+    /// [Schema::process_id]: crate::schema::Schema::process_id
+    fn process_id(&self) -> u32;
+
+    /// Use the `thread_id` function to obtain the ThreadId of the Event
+    fn thread_id(&self) -> u32;
+
+    /// Use the `processor_index` function to obtain the index of the CPU that logged the Event
+    ///
+    /// The index is read from the `ProcessorIndex` of the BufferContext when the header has the
+    /// `EVENT_HEADER_FLAG_PROCESSOR_INDEX` flag, which is needed on machines with more than 256
+    /// CPUs, and from the 8-bit `ProcessorNumber` otherwise
+    fn processor_index(&self) -> u16;
+
+    /// Use the `activity_id` function to obtain the ActivityId of the Event
+    ///
+    /// Events logged within the same activity share the ActivityId, which allows pairing the
+    /// Start and Stop events of an operation. It's zeroed when the Provider logged no activity
+    fn activity_id(&self) -> Guid;
+
+    /// Use the `related_activity_id` function to obtain the RelatedActivityId of the Event
+    ///
+    /// The RelatedActivityId is logged as an extended data item by the Start and transfer events,
+    /// it identifies the parent activity of the ActivityId in the header
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if let Some(parent) = record.related_activity_id() {
+    ///         println!("{:?} -> {:?}", parent, record.activity_id());
+    ///     }
+    /// };
+    /// ```
+    fn related_activity_id(&self) -> Option<Guid>;
+
+    /// Use the `extended_data_stack` function to obtain the call stack captured with the Event
+    ///
+    /// The stack is logged as an extended data item when the Provider is enabled with
+    /// [Provider::with_stack_trace], the return addresses are listed from the innermost frame.
+    /// Stacks of 32-bit processes (`EVENT_HEADER_EXT_TYPE_STACK_TRACE32`) are widened to 64 bits
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     for address in record.extended_data_stack().unwrap_or_default() {
+    ///         println!("{:#x}", address);
+    ///     }
+    /// };
+    /// ```
+    ///
+    /// [Provider::with_stack_trace]: crate::provider::Provider::with_stack_trace
+    fn extended_data_stack(&self) -> Option<Vec<u64>>;
+
+    /// Use the `extended_data_raw` function to obtain the extended data items of the Event
+    ///
+    /// Each item is returned as its type (`EVENT_HEADER_EXT_TYPE_*`) and its raw data, which allows
+    /// handling the item types not modeled by this crate. The Vec is empty unless the header is
+    /// flagged with `EVENT_HEADER_FLAG_EXTENDED_INFO`
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     for (ext_type, data) in record.extended_data_raw() {
+    ///         println!("{}: {} bytes", ext_type, data.len());
+    ///     }
+    /// };
+    /// ```
+    ///
+    /// # Remarks
+    /// The data is owned by ETW, it's only valid for the duration of the callback
+    fn extended_data_raw(&self) -> Vec<(u16, &[u8])>;
+
+    /// Use the `as_raw` function to obtain a pointer to the native [EVENT_RECORD]
+    ///
+    /// This is an escape hatch to call the ETW and TDH functions not wrapped by this crate, the
+    /// pointer can be cast to the `EVENT_RECORD` of the bindings used to call them since the
+    /// layout is the same
+    ///
+    /// # Safety Note
+    /// Obtaining the pointer is safe but dereferencing it is not. The pointer is only valid while
+    /// this EventRecord is alive and the buffers it references (UserData, ExtendedData) are owned
+    /// by ETW, they are only valid for the duration of the callback that received the Event. The
+    /// record must not be modified through the pointer
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let raw = record.as_raw() as *const my_bindings::EVENT_RECORD;
+    ///     let status = unsafe { TdhGetEventInformation(raw, 0, null_mut(), buffer, &mut size) };
+    /// };
+    /// ```
+    ///
+    /// [EVENT_RECORD]: https://docs.microsoft.com/en-us/windows/win32/api/evntcons/ns-evntcons-event_record
+    fn as_raw(&self) -> *const Etw::EVENT_RECORD;
+}
+
+impl EventRecordExt for EventRecord {
+    fn kernel_user_time(&self) -> Option<(u32, u32)> {
+        let flags = u32::from(self.EventHeader.Flags);
+        if flags & (Etw::EVENT_HEADER_FLAG_NO_CPUTIME | Etw::EVENT_HEADER_FLAG_PRIVATE_SESSION) != 0
+        {
+            return None;
+        }
+
+        let times = unsafe { self.EventHeader.Anonymous.Anonymous };
+        Some((times.KernelTime, times.UserTime))
+    }
+
+    fn processor_time(&self) -> Option<u64> {
+        let flags = u32::from(self.EventHeader.Flags);
+        if flags & Etw::EVENT_HEADER_FLAG_NO_CPUTIME != 0
+            || flags & Etw::EVENT_HEADER_FLAG_PRIVATE_SESSION == 0
+        {
+            return None;
+        }
+
+        Some(unsafe { self.EventHeader.Anonymous.ProcessorTime })
+    }
+
+    fn is_kernel_event(&self) -> bool {
+        self.is_classic() || kernel_providers::by_guid(&self.EventHeader.ProviderId).is_some()
+    }
+
+    fn is_classic(&self) -> bool {
+        u32::from(self.EventHeader.Flags) & Etw::EVENT_HEADER_FLAG_CLASSIC_HEADER != 0
+    }
+
+    fn as_string_event(&self) -> Option<String> {
+        if self.EventHeader.Flags & EVENT_HEADER_FLAG_STRING_ONLY == 0 {
+            return None;
+        }
+        if self.UserData.is_null() {
+            return Some(String::new());
+        }
+        let data = unsafe {
+            std::slice::from_raw_parts(self.UserData as *const u8, self.UserDataLength.into())
+        };
+        Some(utils::parse_null_utf16_string(data))
+    }
+
+    fn timestamp(&self) -> RawTimestamp {
+        RawTimestamp(self.EventHeader.TimeStamp)
+    }
+
+    fn system_time(&self, clock: &TraceClock) -> std::time::SystemTime {
+        self.timestamp().to_system_time(clock)
+    }
+
+    fn is_private_session(&self) -> bool {
+        u32::from(self.EventHeader.Flags) & Etw::EVENT_HEADER_FLAG_PRIVATE_SESSION != 0
+    }
+
+    fn process_id(&self) -> u32 {
+        self.EventHeader.ProcessId
+    }
+
+    fn thread_id(&self) -> u32 {
+        self.EventHeader.ThreadId
+    }
+
+    fn processor_index(&self) -> u16 {
+        let context = &self.BufferContext.Anonymous;
+        if u32::from(self.EventHeader.Flags) & Etw::EVENT_HEADER_FLAG_PROCESSOR_INDEX != 0 {
+            unsafe { context.ProcessorIndex }
+        } else {
+            u16::from(unsafe { context.Anonymous.ProcessorNumber })
+        }
+    }
+
+    fn activity_id(&self) -> Guid {
+        self.EventHeader.ActivityId
+    }
+
+    fn related_activity_id(&self) -> Option<Guid> {
+        self.extended_data_raw()
+            .into_iter()
+            .find(|(ext_type, data)| {
+                *ext_type == EVENT_HEADER_EXT_TYPE_RELATEDACTIVITYID
+                    && data.len() >= std::mem::size_of::<Guid>()
+            })
+            .map(|(_, data)| unsafe { std::ptr::read_unaligned(data.as_ptr() as *const Guid) })
+    }
+
+    fn extended_data_stack(&self) -> Option<Vec<u64>> {
+        self.extended_data_raw()
+            .into_iter()
+            .find_map(|(ext_type, data)| match u32::from(ext_type) {
+                Etw::EVENT_HEADER_EXT_TYPE_STACK_TRACE32 => Some(stack_addresses(data, 4)),
+                Etw::EVENT_HEADER_EXT_TYPE_STACK_TRACE64 => Some(stack_addresses(data, 8)),
+                _ => None,
+            })
+    }
+
+    fn extended_data_raw(&self) -> Vec<(u16, &[u8])> {
+        let flags = u32::from(self.EventHeader.Flags);
+        let items = if flags & Etw::EVENT_HEADER_FLAG_EXTENDED_INFO == 0
+            || self.ExtendedData.is_null()
+        {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.ExtendedData, self.ExtendedDataCount.into()) }
+        };
+
+        items
+            .iter()
+            .map(|item| {
+                let data: &[u8] = if item.DataPtr == 0 {
+                    &[]
+                } else {
+                    unsafe {
+                        std::slice::from_raw_parts(item.DataPtr as *const u8, item.DataSize.into())
+                    }
+                };
+                (item.ExtType, data)
+            })
+            .collect()
+    }
+
+    fn as_raw(&self) -> *const Etw::EVENT_RECORD {
+        self
+    }
+}
+
+/// Payload of the Event, without the classic header that may precede it
+///
+/// Some classic (MOF) events are delivered with their [EVENT_TRACE_HEADER] still at the start
+/// of the UserData, parsing the payload from offset 0 would misalign every property
+///
+/// [EVENT_TRACE_HEADER]: https://docs.microsoft.com/en-us/windows/win32/api/evntrace/ns-evntrace-event_trace_header
+pub(crate) fn user_data(record: &EventRecord) -> &[u8] {
+    if record.UserData.is_null() {
+        return &[];
+    }
+    let data = unsafe {
+        std::slice::from_raw_parts(record.UserData as *const u8, record.UserDataLength.into())
+    };
+    &data[classic_header_len(record, data)..]
+}
+
+fn classic_header_len(record: &EventRecord, data: &[u8]) -> usize {
+    if !record.is_classic() || data.len() < EVENT_TRACE_HEADER_SIZE {
+        return 0;
+    }
+
+    // The header is only there if it describes this very event: its Size covers the whole
+    // UserData, its Class.Type is the Opcode and its Guid the ProviderId
+    let size = usize::from(u16::from_ne_bytes([data[0], data[1]]));
+    let class_type = data[4];
+    let guid = unsafe { std::ptr::read_unaligned(data[24..].as_ptr() as *const Guid) };
+    if size == data.len()
+        && class_type == record.EventHeader.EventDescriptor.Opcode
+        && guid == record.EventHeader.ProviderId
+    {
+        EVENT_TRACE_HEADER_SIZE
+    } else {
+        0
+    }
+}
+
+// Not part of the generated bindings
+const EVENT_HEADER_EXT_TYPE_RELATEDACTIVITYID: u16 = 0x0001;
+
+// EVENT_EXTENDED_ITEM_STACK_TRACE32/64: the MatchId followed by the addresses
+fn stack_addresses(data: &[u8], address_size: usize) -> Vec<u64> {
+    let match_id_size = std::mem::size_of::<u64>();
+    if data.len() < match_id_size {
+        return Vec::new();
+    }
+
+    data[match_id_size..]
+        .chunks_exact(address_size)
+        .map(|address| match address_size {
+            4 => u64::from(u32::from_ne_bytes([
+                address[0], address[1], address[2], address[3],
+            ])),
+            _ => u64::from_ne_bytes([
+                address[0], address[1], address[2], address[3], address[4], address[5], address[6],
+                address[7],
+            ]),
+        })
+        .collect()
+}
+
+// sizeof(EVENT_TRACE_HEADER): Size, FieldTypeFlags, Class, ThreadId, ProcessId, TimeStamp, Guid
+// and the Kernel/User times
+pub(crate) const EVENT_TRACE_HEADER_SIZE: usize = 48;
+
+/// Clock information needed to convert a [RawTimestamp]
+///
+/// Obtained from the Trace once it's open, see [TraceBaseTrait::clock]
+///
+/// [TraceBaseTrait::clock]: crate::trace::TraceBaseTrait::clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TraceClock {
+    /// Timestamps are already converted to FILETIME by ETW
+    #[default]
+    FileTime,
+    /// Timestamps are raw QPC values
+    Qpc {
+        /// QPC frequency, in counts per second
+        frequency: i64,
+        /// QPC value at the synchronization point
+        sync_qpc: i64,
+        /// FILETIME at the synchronization point
+        sync_filetime: i64,
+    },
+}
+
+/// Unconverted TimeStamp of an [EventRecord], see [EventRecordExt::timestamp]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RawTimestamp(pub i64);
+
+impl RawTimestamp {
+    /// Use the `to_filetime` function to convert the timestamp to a FILETIME (100ns intervals
+    /// since January 1, 1601)
+    ///
+    /// # Arguments
+    /// * `clock` - [TraceClock] of the Trace the event comes from
+    pub fn to_filetime(&self, clock: &TraceClock) -> i64 {
+        match *clock {
+            TraceClock::FileTime => self.0,
+            TraceClock::Qpc {
+                frequency,
+                sync_qpc,
+                sync_filetime,
+            } => {
+                let elapsed = i128::from(self.0 - sync_qpc) * 10_000_000 / i128::from(frequency);
+                sync_filetime + elapsed as i64
+            }
+        }
+    }
+
+    /// Use the `to_system_time` function to convert the timestamp to a [SystemTime]
+    ///
+    /// # Arguments
+    /// * `clock` - [TraceClock] of the Trace the event comes from
+    ///
+    /// [SystemTime]: std::time::SystemTime
+    pub fn to_system_time(&self, clock: &TraceClock) -> std::time::SystemTime {
+        utils::filetime_to_system_time(self.to_filetime(clock).max(0) as u64)
+    }
+}
+
 pub const INVALID_TRACE_HANDLE: TraceHandle = u64::MAX;
 
 #[allow(dead_code)]
@@ -52,6 +539,7 @@ enum LoggingMode {
     DelayOpenFile,
     PrivateLogger,
     NoPerProcBuffering,
+    Preallocate,
 }
 
 impl From<LoggingMode> for u32 {
@@ -71,6 +559,7 @@ impl From<LoggingMode> for u32 {
             LoggingMode::PrivateLogger => Etw::EVENT_TRACE_PRIVATE_LOGGER_MODE,
             LoggingMode::SystemLogger => Etw::EVENT_TRACE_SYSTEM_LOGGER_MODE,
             LoggingMode::NoPerProcBuffering => Etw::EVENT_TRACE_NO_PER_PROCESSOR_BUFFERING,
+            LoggingMode::Preallocate => Etw::EVENT_TRACE_FILE_MODE_PREALLOCATE,
         }
     }
 }
@@ -85,8 +574,8 @@ enum ProcessTraceMode {
 impl From<ProcessTraceMode> for u32 {
     fn from(val: ProcessTraceMode) -> Self {
         match val {
-            ProcessTraceMode::RealTime => Etw::PROCESS_TRACE_MODE_EVENT_RECORD,
-            ProcessTraceMode::EventRecord => Etw::PROCESS_TRACE_MODE_REAL_TIME,
+            ProcessTraceMode::RealTime => Etw::PROCESS_TRACE_MODE_REAL_TIME,
+            ProcessTraceMode::EventRecord => Etw::PROCESS_TRACE_MODE_EVENT_RECORD,
             ProcessTraceMode::RawTimestamp => Etw::PROCESS_TRACE_MODE_RAW_TIMESTAMP,
         }
     }
@@ -145,7 +634,9 @@ impl TraceInfo {
         &mut self,
         trace_name: &str,
         trace_properties: &TraceProperties,
+        log_file: Option<&LogFile>,
         providers: &RwLock<Vec<Provider>>,
+        memory_mode: u32,
     ) where
         T: TraceTrait,
     {
@@ -157,6 +648,8 @@ impl TraceInfo {
         self.properties.0.MinimumBuffers = trace_properties.min_buffer;
         self.properties.0.MaximumBuffers = trace_properties.max_buffer;
         self.properties.0.FlushTimer = trace_properties.flush_timer;
+        self.properties.0.Anonymous.AgeLimit =
+            age_limit_for_os(trace_properties.age_limit, is_win8_or_greater());
 
         if trace_properties.log_file_mode != 0 {
             self.properties.0.LogFileMode = trace_properties.log_file_mode;
@@ -165,12 +658,59 @@ impl TraceInfo {
                 u32::from(LoggingMode::RealTime) | u32::from(LoggingMode::NoPerProcBuffering);
         }
 
-        self.properties.0.LogFileMode |= T::augmented_file_mode();
+        self.properties.0.LogFileMode |= T::augmented_file_mode() | memory_mode;
         self.properties.0.EnableFlags = Etw::EVENT_TRACE_FLAG::from(T::enable_flags(providers));
 
-        self.properties.0.LoggerNameOffset = offset_of!(TraceInfo, log_file_name) as u32;
+        if let Some(log_file) = log_file {
+            self.properties.0.LogFileMode |= log_file_mode(log_file);
+            self.properties.0.MaximumFileSize = log_file.max_file_size;
+            self.properties.0.LogFileNameOffset = offset_of!(TraceInfo, log_file_name) as u32;
+            self.log_file_name[..log_file.path.len()].copy_from_slice(log_file.path.as_bytes());
+        }
+
+        self.properties.0.LoggerNameOffset = offset_of!(TraceInfo, trace_name) as u32;
         self.trace_name[..trace_name.len()].copy_from_slice(trace_name.as_bytes())
     }
+
+    /// Buffer for an `EVENT_TRACE_CONTROL_QUERY` of the session named `trace_name`
+    pub(crate) fn query(trace_name: &str) -> Self {
+        let mut info = TraceInfo::default();
+        info.properties.0.Wnode.BufferSize = std::mem::size_of::<TraceInfo>() as u32;
+        info.properties.0.LoggerNameOffset = offset_of!(TraceInfo, trace_name) as u32;
+        info.properties.0.LogFileNameOffset = offset_of!(TraceInfo, log_file_name) as u32;
+        info.trace_name[..trace_name.len()].copy_from_slice(trace_name.as_bytes());
+        info
+    }
+
+    // Filled by the system when the session is started, 0 until then
+    pub(crate) fn logger_thread_id(&self) -> Option<u32> {
+        match self.properties.0.LoggerThreadId.0 {
+            0 => None,
+            thread_id => Some(thread_id as u32),
+        }
+    }
+}
+
+// A log_file_mode of 0 is replaced by the real time default when the session is started
+pub(crate) fn is_real_time_mode(log_file_mode: u32) -> bool {
+    log_file_mode == 0 || log_file_mode & u32::from(LoggingMode::RealTime) != 0
+}
+
+fn log_file_mode(log_file: &LogFile) -> u32 {
+    let mut mode = u32::from(LoggingMode::Sequential);
+    if log_file.preallocate {
+        mode |= u32::from(LoggingMode::Preallocate);
+    }
+    mode
+}
+
+// AgeLimit is only honoured before Win8, afterwards the union is reused as FlushThreshold
+fn age_limit_for_os(age_limit: u32, win8_or_greater: bool) -> i32 {
+    if win8_or_greater {
+        0
+    } else {
+        age_limit as i32
+    }
 }
 
 impl Default for TraceInfo {
@@ -198,12 +738,33 @@ impl EventTraceLogfile {
         log_file.0.LoggerName = PSTR::from(trace_data.name.clone());
         log_file.0.Anonymous1.ProcessTraceMode =
             u32::from(ProcessTraceMode::RealTime) | u32::from(ProcessTraceMode::EventRecord);
+        if trace_data.raw_timestamps() {
+            unsafe {
+                log_file.0.Anonymous1.ProcessTraceMode |= u32::from(ProcessTraceMode::RawTimestamp);
+            }
+        }
 
         log_file.0.Anonymous2.EventRecordCallback = callback as *mut _;
         log_file.0.Context = unsafe { std::mem::transmute(trace_data as *const _) };
 
         log_file
     }
+
+    pub fn create_from_file<T>(
+        trace_data: &mut TraceData,
+        log_file_name: PSTR,
+        callback: unsafe fn(T),
+    ) -> Self {
+        let mut log_file = EventTraceLogfile::default();
+
+        log_file.0.LogFileName = log_file_name;
+        log_file.0.Anonymous1.ProcessTraceMode = u32::from(ProcessTraceMode::EventRecord);
+
+        log_file.0.Anonymous2.EventRecordCallback = callback as *mut _;
+        log_file.0.Context = trace_data as *mut TraceData as *mut _;
+
+        log_file
+    }
 }
 
 impl From<String> for PSTR {
@@ -258,6 +819,72 @@ impl EnableTraceParameters {
 
         params
     }
+
+    /// Attach `filters` to the parameters, the filters have to outlive the parameters
+    pub(crate) fn set_filters(&mut self, filters: &mut [EventFilterDescriptor]) {
+        self.0.EnableFilterDesc = filters.as_mut_ptr() as *mut _;
+        self.0.FilterDescCount = filters.len() as u32;
+    }
+}
+
+pub(crate) const EVENT_ENABLE_PROPERTY_EXCLUDE_INPRIVATE: u32 =
+    Etw::EVENT_ENABLE_PROPERTY_EXCLUDE_INPRIVATE;
+pub(crate) const EVENT_ENABLE_PROPERTY_STACK_TRACE: u32 = Etw::EVENT_ENABLE_PROPERTY_STACK_TRACE;
+
+// Not part of the generated bindings
+const EVENT_FILTER_TYPE_PID: u32 = 0x80000004;
+const EVENT_FILTER_TYPE_EVENT_ID: u32 = 0x80000200;
+/// Maximum number of EventIds of an `EVENT_FILTER_TYPE_EVENT_ID` filter
+pub(crate) const MAX_EVENT_FILTER_EVENT_ID_COUNT: usize = 64;
+
+/// Builds the EVENT_FILTER_EVENT_ID structure matching the events with an Id in `ids`
+///
+/// Stored as u16 to keep the alignment of the `Events` array: `FilterIn` and `Reserved` share the
+/// first element, followed by the `Count` and the Ids
+pub(crate) fn event_id_filter(ids: &[u16]) -> Vec<u16> {
+    let mut filter = Vec::with_capacity(ids.len() + 2);
+    // FilterIn = TRUE, the events not listed are dropped
+    filter.push(u16::from_ne_bytes([1, 0]));
+    filter.push(ids.len() as u16);
+    filter.extend_from_slice(ids);
+    filter
+}
+
+/// Newtype wrapper over an [EVENT_FILTER_DESCRIPTOR]
+///
+/// [EVENT_FILTER_DESCRIPTOR]: https://microsoft.github.io/windows-docs-rs/doc/bindings/Windows/Win32/Etw/struct.EVENT_FILTER_DESCRIPTOR.html
+#[repr(transparent)]
+#[derive(Clone, Copy, Default)]
+pub struct EventFilterDescriptor(Etw::EVENT_FILTER_DESCRIPTOR);
+
+impl EventFilterDescriptor {
+    /// Filter descriptor matching the events of the processes in `pids`, the `pids` have to outlive
+    /// the descriptor
+    pub(crate) fn pid(pids: &[u32]) -> Self {
+        let mut desc = EventFilterDescriptor::default();
+        desc.0.Ptr = pids.as_ptr() as u64;
+        desc.0.Size = std::mem::size_of_val(pids) as u32;
+        desc.0.Type = EVENT_FILTER_TYPE_PID;
+        desc
+    }
+
+    /// Filter descriptor matching the events with the Ids of `filter`, built by [event_id_filter].
+    /// The `filter` has to outlive the descriptor
+    pub(crate) fn event_ids(filter: &[u16]) -> Self {
+        let mut desc = EventFilterDescriptor::default();
+        desc.0.Ptr = filter.as_ptr() as u64;
+        desc.0.Size = std::mem::size_of_val(filter) as u32;
+        desc.0.Type = EVENT_FILTER_TYPE_EVENT_ID;
+        desc
+    }
+}
+
+impl std::ops::Deref for EventFilterDescriptor {
+    type Target = Etw::EVENT_FILTER_DESCRIPTOR;
+
+    fn deref(&self) -> &self::Etw::EVENT_FILTER_DESCRIPTOR {
+        &self.0
+    }
 }
 
 impl std::ops::Deref for EnableTraceParameters {
@@ -360,6 +987,12 @@ impl From<Etw::DECODING_SOURCE> for DecodingSource {
 // Safe cast (EVENT_HEADER_FLAG_32_BIT_HEADER = 32)
 #[doc(hidden)]
 pub const EVENT_HEADER_FLAG_32_BIT_HEADER: u16 = Etw::EVENT_HEADER_FLAG_32_BIT_HEADER as u16;
+// Safe cast (EVENT_HEADER_FLAG_CLASSIC_HEADER = 256)
+#[doc(hidden)]
+pub const EVENT_HEADER_FLAG_CLASSIC_HEADER: u16 = Etw::EVENT_HEADER_FLAG_CLASSIC_HEADER as u16;
+// Safe cast (EVENT_HEADER_FLAG_STRING_ONLY = 4)
+#[doc(hidden)]
+pub const EVENT_HEADER_FLAG_STRING_ONLY: u16 = Etw::EVENT_HEADER_FLAG_STRING_ONLY as u16;
 
 #[repr(C)]
 #[derive(Debug, Clone, Default)]
@@ -394,29 +1027,44 @@ impl TraceEventInfoRaw {
         DecodingSource::from(TraceEventInfo::from(self).DecodingSource)
     }
 
-    pub(crate) fn provider_name(&self) -> String {
-        let provider_name_offset = TraceEventInfo::from(self).ProviderNameOffset as usize;
-        // TODO: Evaluate performance, but this sounds better than creating a whole Vec<u16> and getting the string from the offset/2
-        utils::parse_unk_size_null_utf16_string(&self.info[provider_name_offset..])
+    pub(crate) fn provider_name(&self) -> Option<String> {
+        self.name_at(TraceEventInfo::from(self).ProviderNameOffset)
     }
 
-    pub(crate) fn task_name(&self) -> String {
-        let task_name_offset = TraceEventInfo::from(self).TaskNameOffset as usize;
-        utils::parse_unk_size_null_utf16_string(&self.info[task_name_offset..])
+    pub(crate) fn task_name(&self) -> Option<String> {
+        self.name_at(TraceEventInfo::from(self).TaskNameOffset)
     }
 
     pub(crate) fn opcode_name(&self) -> String {
-        let opcode_name_offset = TraceEventInfo::from(self).OpcodeNameOffset as usize;
-        if opcode_name_offset == 0 {
-            return String::from("");
+        self.name_at(TraceEventInfo::from(self).OpcodeNameOffset)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn level_name(&self) -> String {
+        self.name_at(TraceEventInfo::from(self).LevelNameOffset)
+            .unwrap_or_default()
+    }
+
+    // An offset of 0 means the event has no such name
+    fn name_at(&self, offset: u32) -> Option<String> {
+        let offset = offset as usize;
+        if offset == 0 || offset >= self.info.len() {
+            return None;
         }
-        utils::parse_unk_size_null_utf16_string(&self.info[opcode_name_offset..])
+        // TODO: Evaluate performance, but this sounds better than creating a whole Vec<u16> and getting the string from the offset/2
+        Some(utils::parse_unk_size_null_utf16_string(
+            &self.info[offset..],
+        ))
     }
 
     pub(crate) fn property_count(&self) -> u32 {
         TraceEventInfo::from(self).PropertyCount
     }
 
+    pub(crate) fn top_level_property_count(&self) -> u32 {
+        TraceEventInfo::from(self).TopLevelPropertyCount
+    }
+
     pub(crate) fn property(&self, index: u32) -> Property {
         // let's make sure index is not bigger thant the PropertyCount
         assert!(index <= self.property_count());
@@ -431,6 +1079,639 @@ impl TraceEventInfoRaw {
         let curr_prop = EventPropertyInfo::from(&self.info[curr_prop_offset..]);
         let name =
             utils::parse_unk_size_null_utf16_string(&self.info[curr_prop.NameOffset as usize..]);
-        Property::new(name, &curr_prop)
+        let property = Property::new(name, &curr_prop);
+
+        let map_name_offset = unsafe { curr_prop.Anonymous1.nonStructType.MapNameOffset } as usize;
+        if property.flags.contains(PropertyFlags::PROPERTY_STRUCT) || map_name_offset == 0 {
+            return property;
+        }
+
+        let map_name = utils::parse_unk_size_null_utf16_string(&self.info[map_name_offset..]);
+        property.with_map_name(Some(map_name))
+    }
+
+    pub(crate) fn info_as_event_info_ptr(&self) -> *mut Etw::TRACE_EVENT_INFO {
+        // TDH only reads from the info, the mutable pointer is required by the bindings
+        self.info.as_ptr() as *mut _
+    }
+}
+
+/// Value map of a Property
+///
+/// Holds an [EVENT_MAP_INFO] as returned by `TdhGetEventMapInformation`, which translates the
+/// values of a Property into their names
+///
+/// [EVENT_MAP_INFO]: https://docs.microsoft.com/en-us/windows/win32/api/tdh/ns-tdh-event_map_info
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventMapInfo {
+    info: Vec<u8>,
+}
+
+impl EventMapInfo {
+    pub(crate) fn new(info: Vec<u8>) -> Self {
+        EventMapInfo { info }
+    }
+
+    fn entries(&self) -> Vec<(u32, String)> {
+        let header_size = std::mem::size_of::<Etw::EVENT_MAP_INFO>()
+            - std::mem::size_of::<Etw::EVENT_MAP_ENTRY>();
+        if self.info.len() < header_size {
+            return Vec::new();
+        }
+
+        let map_info = unsafe { *(self.info.as_ptr() as *const Etw::EVENT_MAP_INFO) };
+        (0..map_info.EntryCount as usize)
+            .map(|i| header_size + i * std::mem::size_of::<Etw::EVENT_MAP_ENTRY>())
+            .take_while(|offset| {
+                offset + std::mem::size_of::<Etw::EVENT_MAP_ENTRY>() <= self.info.len()
+            })
+            .map(|offset| {
+                let entry =
+                    unsafe { *(self.info[offset..].as_ptr() as *const Etw::EVENT_MAP_ENTRY) };
+                let name = utils::parse_unk_size_null_utf16_string(
+                    &self.info[entry.OutputOffset as usize..],
+                );
+                // Manifest map names are usually padded with a trailing space
+                (
+                    unsafe { entry.Anonymous.Value },
+                    name.trim_end().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn is_bitmap(&self) -> bool {
+        if self.info.len() < std::mem::size_of::<Etw::EVENT_MAP_INFO>() {
+            return false;
+        }
+        let map_info = unsafe { *(self.info.as_ptr() as *const Etw::EVENT_MAP_INFO) };
+        map_info.Flag.0
+            & (Etw::MAP_FLAGS::EVENTMAP_INFO_FLAG_MANIFEST_BITMAP.0
+                | Etw::MAP_FLAGS::EVENTMAP_INFO_FLAG_WBEM_BITMAP.0)
+            != 0
+    }
+
+    /// Translates a value into its name, bitmaps are translated into the names of every bit set
+    /// joined by " | ", the bits without a name are appended in hex
+    pub(crate) fn resolve(&self, value: u32) -> Option<String> {
+        let entries = self.entries();
+        if self.is_bitmap() {
+            let mut leftover = value;
+            let mut names: Vec<String> = entries
+                .into_iter()
+                .filter(|(mask, _)| *mask != 0 && value & mask == *mask)
+                .map(|(mask, name)| {
+                    leftover &= !mask;
+                    name
+                })
+                .collect();
+            if leftover != 0 {
+                names.push(format!("{:#x}", leftover));
+            }
+            if names.is_empty() {
+                return None;
+            }
+            return Some(names.join(" | "));
+        }
+
+        entries
+            .into_iter()
+            .find(|(entry, _)| *entry == value)
+            .map(|(_, name)| name)
+    }
+}
+
+/// Checks whether a `TraceGuidQueryInfo` buffer reports the provider as enabled on a session
+///
+/// The buffer holds a [TRACE_GUID_INFO] followed by one [TRACE_PROVIDER_INSTANCE_INFO] per
+/// registration of the provider, each followed by a [TRACE_ENABLE_INFO] per session enabling it
+///
+/// [TRACE_GUID_INFO]: https://docs.microsoft.com/en-us/windows/win32/api/evntrace/ns-evntrace-trace_guid_info
+/// [TRACE_PROVIDER_INSTANCE_INFO]: https://docs.microsoft.com/en-us/windows/win32/api/evntrace/ns-evntrace-trace_provider_instance_info
+/// [TRACE_ENABLE_INFO]: https://docs.microsoft.com/en-us/windows/win32/api/evntrace/ns-evntrace-trace_enable_info
+pub(crate) fn guid_info_enabled_on(buffer: &[u8], logger_id: u16) -> bool {
+    let guid_info_size = std::mem::size_of::<Etw::TRACE_GUID_INFO>();
+    let instance_size = std::mem::size_of::<Etw::TRACE_PROVIDER_INSTANCE_INFO>();
+    let enable_size = std::mem::size_of::<Etw::TRACE_ENABLE_INFO>();
+    if buffer.len() < guid_info_size {
+        return false;
+    }
+
+    let guid_info: Etw::TRACE_GUID_INFO =
+        unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const _) };
+    let mut offset = guid_info_size;
+    for _ in 0..guid_info.InstanceCount {
+        if buffer.len() < offset + instance_size {
+            return false;
+        }
+        let instance: Etw::TRACE_PROVIDER_INSTANCE_INFO =
+            unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const _) };
+
+        for i in 0..instance.EnableCount as usize {
+            let enable_offset = offset + instance_size + i * enable_size;
+            if buffer.len() < enable_offset + enable_size {
+                return false;
+            }
+            let enable_info: Etw::TRACE_ENABLE_INFO =
+                unsafe { std::ptr::read_unaligned(buffer[enable_offset..].as_ptr() as *const _) };
+            if enable_info.IsEnabled != 0 && enable_info.LoggerId == logger_id {
+                return true;
+            }
+        }
+
+        if instance.NextOffset == 0 {
+            break;
+        }
+        offset += instance.NextOffset as usize;
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push_struct<T>(buffer: &mut Vec<u8>, value: &T) {
+        buffer.extend_from_slice(unsafe {
+            std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>())
+        });
+    }
+
+    fn guid_info_buffer(instances: &[&[(u16, u32)]]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        push_struct(
+            &mut buffer,
+            &Etw::TRACE_GUID_INFO {
+                InstanceCount: instances.len() as u32,
+                Reserved: 0,
+            },
+        );
+        for (i, sessions) in instances.iter().enumerate() {
+            let next_offset = if i + 1 == instances.len() {
+                0
+            } else {
+                std::mem::size_of::<Etw::TRACE_PROVIDER_INSTANCE_INFO>()
+                    + sessions.len() * std::mem::size_of::<Etw::TRACE_ENABLE_INFO>()
+            };
+            push_struct(
+                &mut buffer,
+                &Etw::TRACE_PROVIDER_INSTANCE_INFO {
+                    NextOffset: next_offset as u32,
+                    EnableCount: sessions.len() as u32,
+                    Pid: 4,
+                    Flags: 0,
+                },
+            );
+            for (logger_id, is_enabled) in sessions.iter() {
+                push_struct(
+                    &mut buffer,
+                    &Etw::TRACE_ENABLE_INFO {
+                        IsEnabled: *is_enabled,
+                        LoggerId: *logger_id,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_guid_info_enabled_on() {
+        let buffer = guid_info_buffer(&[&[(3, 1)], &[(7, 0), (12, 1)]]);
+
+        assert!(guid_info_enabled_on(&buffer, 3));
+        assert!(guid_info_enabled_on(&buffer, 12));
+        assert!(!guid_info_enabled_on(&buffer, 7));
+        assert!(!guid_info_enabled_on(&buffer, 40));
+        assert!(!guid_info_enabled_on(&buffer[..20], 3));
+        assert!(!guid_info_enabled_on(&[], 3));
+    }
+
+    fn record_with_flags(flags: u32) -> EventRecord {
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.Flags = flags as u16;
+        record.EventHeader.Anonymous.Anonymous.KernelTime = 10;
+        record.EventHeader.Anonymous.Anonymous.UserTime = 20;
+        record
+    }
+
+    #[test]
+    fn test_kernel_user_time() {
+        let record = record_with_flags(0);
+
+        assert_eq!(record.kernel_user_time(), Some((10, 20)));
+        assert_eq!(record.processor_time(), None);
+    }
+
+    #[test]
+    fn test_processor_time_private_session() {
+        let record = record_with_flags(Etw::EVENT_HEADER_FLAG_PRIVATE_SESSION);
+
+        assert_eq!(record.kernel_user_time(), None);
+        assert_eq!(record.processor_time(), Some(20 << 32 | 10));
+    }
+
+    #[test]
+    fn test_no_cpu_time() {
+        let record = record_with_flags(Etw::EVENT_HEADER_FLAG_NO_CPUTIME);
+        assert_eq!(record.kernel_user_time(), None);
+        assert_eq!(record.processor_time(), None);
+
+        let record = record_with_flags(
+            Etw::EVENT_HEADER_FLAG_NO_CPUTIME | Etw::EVENT_HEADER_FLAG_PRIVATE_SESSION,
+        );
+        assert_eq!(record.kernel_user_time(), None);
+        assert_eq!(record.processor_time(), None);
+    }
+
+    #[test]
+    fn test_is_kernel_event_classic_header() {
+        let record = record_with_flags(Etw::EVENT_HEADER_FLAG_CLASSIC_HEADER);
+
+        assert!(record.is_kernel_event());
+        assert!(!record.is_private_session());
+    }
+
+    #[test]
+    fn test_raw_timestamp_matches_eager_conversion() {
+        // 2021-01-01T00:00:00Z
+        let sync_filetime = 132_539_328_000_000_000;
+        let clock = TraceClock::Qpc {
+            frequency: 3_000_000,
+            sync_qpc: 1_000_000,
+            sync_filetime,
+        };
+        // 1.5 seconds after the synchronization point
+        let raw = RawTimestamp(1_000_000 + 4_500_000);
+        let eager = RawTimestamp(sync_filetime + 15_000_000);
+
+        assert_eq!(raw.to_filetime(&clock), eager.0);
+        assert_eq!(
+            raw.to_system_time(&clock),
+            eager.to_system_time(&TraceClock::FileTime)
+        );
+    }
+
+    #[test]
+    fn test_system_time_known_filetimes() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        let mut system_time = |filetime: i64| {
+            record.EventHeader.TimeStamp = filetime;
+            record.system_time(&TraceClock::FileTime)
+        };
+
+        assert_eq!(system_time(116_444_736_000_000_000), UNIX_EPOCH);
+        // 2021-01-01T00:00:00.1234567Z
+        assert_eq!(
+            system_time(132_539_328_001_234_567),
+            UNIX_EPOCH + Duration::new(1_609_459_200, 123_456_700)
+        );
+        // 1969-12-31T23:59:59Z
+        assert_eq!(
+            system_time(116_444_735_990_000_000),
+            UNIX_EPOCH - Duration::from_secs(1)
+        );
+        // 1601-01-01T00:00:00Z, negative values are clamped to it
+        let filetime_epoch = UNIX_EPOCH - Duration::from_secs(11_644_473_600);
+        assert_eq!(system_time(0), filetime_epoch);
+        assert_eq!(system_time(-1), filetime_epoch);
+    }
+
+    #[test]
+    fn test_filetime_clock_is_identity() {
+        let mut record = record_with_flags(0);
+        record.EventHeader.TimeStamp = 132_539_328_000_000_000;
+
+        assert_eq!(
+            record.timestamp().to_filetime(&TraceClock::FileTime),
+            132_539_328_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_as_raw_is_native_record() {
+        let record = record_with_flags(Etw::EVENT_HEADER_FLAG_CLASSIC_HEADER);
+        let raw: *const Etw::EVENT_RECORD = record.as_raw();
+
+        assert_eq!(raw, &record as *const Etw::EVENT_RECORD);
+        assert_eq!(
+            std::mem::size_of::<EventRecord>(),
+            std::mem::size_of::<Etw::EVENT_RECORD>()
+        );
+        assert_eq!(
+            unsafe { (*raw).EventHeader.Flags },
+            record.EventHeader.Flags
+        );
+    }
+
+    #[test]
+    fn test_event_id_filter_descriptor() {
+        let filter = event_id_filter(&[4, 0x1234]);
+        let bytes: Vec<u8> = filter
+            .iter()
+            .flat_map(|w| w.to_ne_bytes().to_vec())
+            .collect();
+        let mut expected = vec![1, 0];
+        expected.extend_from_slice(&2u16.to_ne_bytes());
+        expected.extend_from_slice(&4u16.to_ne_bytes());
+        expected.extend_from_slice(&0x1234u16.to_ne_bytes());
+        assert_eq!(bytes, expected);
+
+        let desc = EventFilterDescriptor::event_ids(&filter);
+        assert_eq!(desc.Type, 0x80000200);
+        assert_eq!(desc.Size, 8);
+        assert_eq!(desc.Ptr, filter.as_ptr() as u64);
+    }
+
+    #[test]
+    fn test_logger_thread_id() {
+        use crate::native::bindings::Windows::Win32::SystemServices::HANDLE;
+
+        let mut info = TraceInfo::default();
+        assert_eq!(info.logger_thread_id(), None);
+
+        // As filled by StartTrace
+        info.properties.LoggerThreadId = HANDLE(0x1a2c);
+        assert_eq!(info.logger_thread_id(), Some(0x1a2c));
+    }
+
+    #[test]
+    fn test_extended_data_raw() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        let event = TestEvent::new(&schema, Vec::new())
+            .extended_item(0x0003, 1234u32.to_ne_bytes().to_vec()) // EVENT_HEADER_EXT_TYPE_TS_ID
+            .extended_item(0x000b, vec![0xde, 0xad, 0xbe, 0xef, 0x00]);
+
+        let items: Vec<(u16, Vec<u8>)> = event
+            .record
+            .extended_data_raw()
+            .into_iter()
+            .map(|(ext_type, data)| (ext_type, data.to_vec()))
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                (0x0003, 1234u32.to_ne_bytes().to_vec()),
+                (0x000b, vec![0xde, 0xad, 0xbe, 0xef, 0x00]),
+            ]
+        );
+        assert_eq!(event.record.related_activity_id(), None);
+
+        // Without EVENT_HEADER_FLAG_EXTENDED_INFO the items are ignored
+        let mut record = event.record;
+        record.EventHeader.Flags = 0;
+        assert!(record.extended_data_raw().is_empty());
+    }
+
+    #[test]
+    fn test_activity_ids() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        let activity = Guid::from("6a6e7e3a-3cc1-4ee5-9d3e-0d5c6e1d5c01");
+        let related = Guid::from("0f2a1b3c-4d5e-4f60-8172-839405a6b7c8");
+
+        let event = TestEvent::new(&schema, Vec::new())
+            .extended_item(0x0003, 1234u32.to_ne_bytes().to_vec())
+            .activity(activity, Some(related));
+        assert_eq!(event.record.activity_id(), activity);
+        assert_eq!(event.record.related_activity_id(), Some(related));
+
+        let event = TestEvent::new(&schema, Vec::new()).activity(activity, None);
+        assert_eq!(event.record.activity_id(), activity);
+        assert_eq!(event.record.related_activity_id(), None);
+    }
+
+    #[test]
+    fn test_header_ids() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        let mut event = TestEvent::new(&schema, Vec::new());
+        event.record.EventHeader.ProcessId = 4242;
+        event.record.EventHeader.ThreadId = 17;
+        event
+            .record
+            .BufferContext
+            .Anonymous
+            .Anonymous
+            .ProcessorNumber = 3;
+        event.record.BufferContext.Anonymous.Anonymous.Alignment = 0xff;
+        assert_eq!(event.record.process_id(), 4242);
+        assert_eq!(event.record.thread_id(), 17);
+        assert_eq!(event.record.processor_index(), 3);
+
+        // CPU 300 of a machine with more than 256 CPUs
+        event.record.EventHeader.Flags |= Etw::EVENT_HEADER_FLAG_PROCESSOR_INDEX as u16;
+        event.record.BufferContext.Anonymous.ProcessorIndex = 300;
+        assert_eq!(event.record.processor_index(), 300);
+    }
+
+    #[test]
+    fn test_trace_event_info_names() {
+        use crate::native::test_utils::TestSchema;
+
+        let mut test_schema = TestSchema::new("1c95126e-7eea-49a9-a3fe-a378b03ddb4d", 3000);
+        test_schema.provider_name = Some(String::from("Microsoft-Windows-DNS-Client"));
+        test_schema.task_name = Some(String::from("DnsQuery"));
+        let info = test_schema.build();
+        assert_eq!(
+            info.provider_name().as_deref(),
+            Some("Microsoft-Windows-DNS-Client")
+        );
+        assert_eq!(info.task_name().as_deref(), Some("DnsQuery"));
+        assert_eq!(info.opcode_name(), "");
+        assert_eq!(info.level_name(), "");
+
+        let info = TestSchema::new("1c95126e-7eea-49a9-a3fe-a378b03ddb4d", 3000).build();
+        assert_eq!(info.provider_name(), None);
+        assert_eq!(info.task_name(), None);
+    }
+
+    #[test]
+    fn test_trace_info_fill_name() {
+        let providers = RwLock::new(Vec::new());
+        let mut info = TraceInfo::default();
+        info.fill::<crate::trace::UserTrace>(
+            "RoundTripTrace",
+            &TraceProperties::default(),
+            None,
+            &providers,
+            0,
+        );
+
+        let offset = info.properties.LoggerNameOffset as usize;
+        let buffer = unsafe {
+            std::slice::from_raw_parts(
+                &info as *const TraceInfo as *const u8,
+                std::mem::size_of::<TraceInfo>(),
+            )
+        };
+        assert_eq!(&buffer[offset..offset + 15], b"RoundTripTrace\0");
+    }
+
+    #[test]
+    fn test_trace_info_query() {
+        let info = TraceInfo::query("QueryTrace");
+        let properties = &info.properties;
+
+        assert_eq!(
+            properties.Wnode.BufferSize as usize,
+            std::mem::size_of::<TraceInfo>()
+        );
+        assert_eq!(
+            properties.LoggerNameOffset as usize,
+            offset_of!(TraceInfo, trace_name)
+        );
+        assert_eq!(
+            properties.LogFileNameOffset as usize,
+            offset_of!(TraceInfo, log_file_name)
+        );
+        assert_eq!(&info.trace_name[..11], b"QueryTrace\0");
+        assert!(info.log_file_name.iter().all(|byte| *byte == 0));
+        // The counters are filled by the query
+        assert_eq!(properties.LogFileMode, 0);
+        assert_eq!(properties.EventsLost, 0);
+        assert_eq!(properties.BuffersWritten, 0);
+        assert_eq!(properties.RealTimeBuffersLost, 0);
+        assert_eq!(properties.LogBuffersLost, 0);
+    }
+
+    #[test]
+    fn test_extended_data_stack() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        assert_eq!(
+            TestEvent::new(&schema, Vec::new())
+                .record
+                .extended_data_stack(),
+            None
+        );
+
+        let mut stack64 = 42u64.to_ne_bytes().to_vec(); // MatchId
+        stack64.extend_from_slice(&0xffff_f800_0000_1234u64.to_ne_bytes());
+        stack64.extend_from_slice(&0x7ff6_0000_5678u64.to_ne_bytes());
+        let event = TestEvent::new(&schema, Vec::new())
+            .extended_item(0x0003, 1234u32.to_ne_bytes().to_vec()) // EVENT_HEADER_EXT_TYPE_TS_ID
+            .extended_item(0x0006, stack64); // EVENT_HEADER_EXT_TYPE_STACK_TRACE64
+        assert_eq!(
+            event.record.extended_data_stack(),
+            Some(vec![0xffff_f800_0000_1234, 0x7ff6_0000_5678])
+        );
+
+        let mut stack32 = 42u64.to_ne_bytes().to_vec();
+        stack32.extend_from_slice(&0x7700_1234u32.to_ne_bytes());
+        stack32.extend_from_slice(&0x0040_5678u32.to_ne_bytes());
+        // Truncated trailing address
+        stack32.extend_from_slice(&[0xff, 0xff]);
+        let event = TestEvent::new(&schema, Vec::new()).extended_item(0x0005, stack32); // EVENT_HEADER_EXT_TYPE_STACK_TRACE32
+        assert_eq!(
+            event.record.extended_data_stack(),
+            Some(vec![0x7700_1234, 0x0040_5678])
+        );
+    }
+
+    #[test]
+    fn test_is_classic() {
+        assert!(record_with_flags(Etw::EVENT_HEADER_FLAG_CLASSIC_HEADER).is_classic());
+        assert!(record_with_flags(
+            Etw::EVENT_HEADER_FLAG_CLASSIC_HEADER | Etw::EVENT_HEADER_FLAG_32_BIT_HEADER
+        )
+        .is_classic());
+        assert!(!record_with_flags(0).is_classic());
+        assert!(!record_with_flags(Etw::EVENT_HEADER_FLAG_64_BIT_HEADER).is_classic());
+    }
+
+    #[test]
+    fn test_as_string_event_without_schema() {
+        // EventWriteString events have no Schema TDH could locate, only the header and the payload
+        let message: Vec<u8> = "Hello from EventWriteString\0"
+            .encode_utf16()
+            .flat_map(|c| c.to_ne_bytes().to_vec())
+            .collect();
+        let mut record = record_with_flags(u32::from(EVENT_HEADER_FLAG_STRING_ONLY));
+        record.UserData = message.as_ptr() as *mut _;
+        record.UserDataLength = message.len() as u16;
+        assert_eq!(
+            record.as_string_event(),
+            Some(String::from("Hello from EventWriteString"))
+        );
+
+        record.EventHeader.Flags = 0;
+        assert_eq!(record.as_string_event(), None);
+    }
+
+    #[test]
+    fn test_is_kernel_event_kernel_guid() {
+        let mut record = record_with_flags(0);
+        record.EventHeader.ProviderId = Guid::from(kernel_providers::kernel_guids::REGISTRY_GUID);
+
+        assert!(record.is_kernel_event());
+    }
+
+    #[test]
+    fn test_is_not_kernel_event_manifest() {
+        let mut record = record_with_flags(Etw::EVENT_HEADER_FLAG_PRIVATE_SESSION);
+        record.EventHeader.ProviderId = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+
+        assert!(!record.is_kernel_event());
+        assert!(record.is_private_session());
+    }
+
+    #[test]
+    fn test_log_file_mode_preallocate() {
+        let log_file = LogFile::new("trace.etl")
+            .max_file_size(512)
+            .preallocate(true);
+        let mode = log_file_mode(&log_file);
+
+        assert_ne!(mode & Etw::EVENT_TRACE_FILE_MODE_PREALLOCATE, 0);
+        assert_ne!(mode & Etw::EVENT_TRACE_FILE_MODE_SEQUENTIAL, 0);
+    }
+
+    #[test]
+    fn test_log_file_mode_no_preallocate() {
+        let log_file = LogFile::new("trace.etl");
+        let mode = log_file_mode(&log_file);
+
+        assert_eq!(mode & Etw::EVENT_TRACE_FILE_MODE_PREALLOCATE, 0);
+    }
+
+    #[test]
+    fn test_age_limit_pre_win8() {
+        assert_eq!(age_limit_for_os(15, false), 15);
+    }
+
+    #[test]
+    fn test_age_limit_win8_or_greater() {
+        assert_eq!(age_limit_for_os(15, true), 0);
+    }
+
+    #[test]
+    fn test_trace_info_logger_name_offset() {
+        let providers = RwLock::new(Vec::new());
+        let mut info = TraceInfo::default();
+        info.fill::<crate::trace::UserTrace>(
+            "OffsetTrace",
+            &TraceProperties::default(),
+            None,
+            &providers,
+            0,
+        );
+
+        assert_eq!(
+            info.properties.LoggerNameOffset as usize,
+            offset_of!(TraceInfo, trace_name)
+        );
+        assert_ne!(
+            info.properties.LoggerNameOffset as usize,
+            offset_of!(TraceInfo, log_file_name)
+        );
     }
 }
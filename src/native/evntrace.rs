@@ -5,12 +5,21 @@
 //!
 //! This module shouldn't be accessed directly. Modules from the crate level provide a safe API to interact
 //! with the crate
-use super::bindings::Windows::Win32::{Debug::WIN32_ERROR, Etw, WindowsProgramming};
+use super::bindings::Windows::Win32::{
+    Debug::WIN32_ERROR,
+    Etw,
+    SystemServices::{self, PSTR},
+    WindowsProgramming,
+};
 use super::etw_types::*;
+use super::version_helper;
 use crate::provider::Provider;
-use crate::trace::{TraceData, TraceProperties, TraceTrait};
+use crate::trace::{
+    LogFile, ProcessErrorAction, ProcessErrorHook, TraceData, TraceProperties, TraceTrait,
+};
 use crate::traits::*;
 use std::sync::RwLock;
+use std::thread::JoinHandle;
 use windows::Guid;
 
 /// Evntrace native module errors
@@ -20,6 +29,8 @@ pub enum EvntraceNativeError {
     InvalidHandle,
     /// Represents an ERROR_ALREADY_EXISTS
     AlreadyExist,
+    /// Represents an ERROR_NO_SYSTEM_RESOURCES
+    NoSystemResources,
     /// Represents an standard IO Error
     IoError(std::io::Error),
 }
@@ -32,18 +43,51 @@ impl From<std::io::Error> for EvntraceNativeError {
     }
 }
 
+impl std::fmt::Display for EvntraceNativeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvntraceNativeError::InvalidHandle => f.write_str("invalid trace handle"),
+            EvntraceNativeError::AlreadyExist => f.write_str("trace session already exists"),
+            EvntraceNativeError::NoSystemResources => f.write_str("not enough system resources"),
+            EvntraceNativeError::IoError(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for EvntraceNativeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EvntraceNativeError::IoError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl EvntraceNativeError {
+    pub(crate) fn io_error_kind(&self) -> std::io::ErrorKind {
+        match self {
+            EvntraceNativeError::InvalidHandle => std::io::ErrorKind::InvalidInput,
+            EvntraceNativeError::AlreadyExist => std::io::ErrorKind::AlreadyExists,
+            EvntraceNativeError::NoSystemResources => std::io::ErrorKind::Other,
+            // ERROR_ACCESS_DENIED is already mapped to PermissionDenied by std
+            EvntraceNativeError::IoError(err) => err.kind(),
+        }
+    }
+}
+
 pub(crate) type EvntraceNativeResult<T> = Result<T, EvntraceNativeError>;
 
 unsafe fn trace_callback_thunk(event_record: PEventRecord) {
-    let ctx: &mut TraceData = TraceData::unsafe_get_callback_ctx((*event_record).UserContext);
+    let ctx: &mut TraceData = TraceData::unsafe_get_callback_ctx((&*event_record).UserContext);
     ctx.on_event(*event_record);
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub(crate) struct NativeEtw {
     info: TraceInfo,
     session_handle: TraceHandle,
     registration_handle: TraceHandle,
+    processing_thread: Option<JoinHandle<()>>,
 }
 
 impl NativeEtw {
@@ -52,30 +96,34 @@ impl NativeEtw {
             info: TraceInfo::default(),
             session_handle: INVALID_TRACE_HANDLE,
             registration_handle: INVALID_TRACE_HANDLE,
+            processing_thread: None,
         }
     }
 
-    pub(crate) fn session_handle(&self) -> TraceHandle {
-        self.session_handle.clone()
-    }
-
     // Not a big fan of this...
     pub(crate) fn fill_info<T>(
         &mut self,
         name: &str,
         properties: &TraceProperties,
+        log_file: Option<&LogFile>,
         providers: &RwLock<Vec<Provider>>,
+        memory_mode: u32,
     ) where
         T: TraceTrait,
     {
-        self.info.fill::<T>(name, properties, providers);
+        self.info
+            .fill::<T>(name, properties, log_file, providers, memory_mode);
     }
 
-    pub(crate) fn start(&mut self) -> EvntraceNativeResult<()> {
+    pub(crate) fn start(
+        &mut self,
+        thread_name: &str,
+        on_error: Option<ProcessErrorHook>,
+    ) -> EvntraceNativeResult<()> {
         if self.session_handle == INVALID_TRACE_HANDLE {
             return Err(EvntraceNativeError::InvalidHandle);
         }
-        Ok(self.process()?)
+        Ok(self.process(thread_name, on_error)?)
     }
 
     pub(crate) fn open(
@@ -85,30 +133,143 @@ impl NativeEtw {
         Ok(self.open_trace(trace_data)?)
     }
 
+    /// Stops the session and closes the consumer handle, doing nothing for the handles that are
+    /// not held so calling it on a stopped, or never started, trace succeeds
+    ///
+    /// Both are attempted and both handles are released even if one of them fails, the first
+    /// error is returned
     pub(crate) fn stop(&mut self, trace_data: &TraceData) -> EvntraceNativeResult<()> {
-        self.stop_trace(trace_data)?;
-        self.close_trace()?;
-        Ok(())
+        let mut result = Ok(());
+        if self.registration_handle != INVALID_TRACE_HANDLE {
+            result = self.stop_trace(trace_data);
+            self.registration_handle = INVALID_TRACE_HANDLE;
+        }
+        if self.session_handle != INVALID_TRACE_HANDLE {
+            let closed = self.close_trace();
+            self.session_handle = INVALID_TRACE_HANDLE;
+            result = result.and(closed);
+        }
+        result
     }
 
-    pub(crate) fn process(&mut self) -> EvntraceNativeResult<()> {
+    /// Spawns the thread calling ProcessTrace, named both for std and with SetThreadDescription on
+    /// the versions that support it (Windows 10 1607 and later)
+    pub(crate) fn process(
+        &mut self,
+        thread_name: &str,
+        on_error: Option<ProcessErrorHook>,
+    ) -> EvntraceNativeResult<()> {
         if self.session_handle == INVALID_TRACE_HANDLE {
             return Err(EvntraceNativeError::InvalidHandle);
         }
 
         let mut clone_handle = self.session_handle.clone();
-        std::thread::spawn(move || {
+        let description = thread_name.to_string();
+        let thread = std::thread::Builder::new().name(thread_name.to_string());
+        self.processing_thread = Some(thread.spawn(move || {
+            set_thread_description(&description);
             let mut now = WindowsProgramming::FILETIME::default();
             unsafe {
                 WindowsProgramming::GetSystemTimeAsFileTime(&mut now);
-
-                Etw::ProcessTrace(&mut clone_handle, 1, &mut now, std::ptr::null_mut());
-                // if Etw::ProcessTrace(&mut clone_handle, 1, &mut now, std::ptr::null_mut()) != 0 {
-                //     return Err(EvntraceNativeError::IoError(std::io::Error::last_os_error()));
-                // }
             }
-        });
+            process_until_done(
+                || unsafe {
+                    Etw::ProcessTrace(&mut clone_handle, 1, &mut now, std::ptr::null_mut())
+                },
+                on_error.as_ref(),
+            );
+        })?);
+
+        Ok(())
+    }
 
+    /// Waits for the thread spawned by `process` to finish, ProcessTrace only returns once the
+    /// session has been closed
+    pub(crate) fn join(&mut self) {
+        if let Some(processing_thread) = self.processing_thread.take() {
+            let _ = processing_thread.join();
+        }
+    }
+
+    /// Sets the PERFINFO_GROUPMASK of a kernel session, this replaces the legacy EnableFlags
+    pub(crate) fn set_group_mask(&self, group_mask: &mut [u32; 8]) -> EvntraceNativeResult<()> {
+        let status = unsafe {
+            Etw::TraceSetInformation(
+                self.registration_handle,
+                Etw::TRACE_QUERY_INFO_CLASS::TraceSystemTraceEnableFlagsInfo,
+                group_mask.as_mut_ptr() as *mut _,
+                std::mem::size_of_val(group_mask) as u32,
+            )
+        };
+        if status != 0 {
+            return Err(EvntraceNativeError::IoError(
+                std::io::Error::from_raw_os_error(status as i32),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sets the interval of the sampled profile of a kernel session, in 100ns units
+    pub(crate) fn set_profile_interval(&self, interval: u32) -> EvntraceNativeResult<()> {
+        let mut profile_interval = profile_interval(interval);
+        let status = unsafe {
+            Etw::TraceSetInformation(
+                self.registration_handle,
+                Etw::TRACE_QUERY_INFO_CLASS::TraceSampledProfileIntervalInfo,
+                &mut profile_interval as *mut _ as *mut _,
+                std::mem::size_of::<Etw::TRACE_PROFILE_INTERVAL>() as u32,
+            )
+        };
+        if status != 0 {
+            return Err(EvntraceNativeError::IoError(
+                std::io::Error::from_raw_os_error(status as i32),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Id of the thread the logger of the session runs on, once the session is started
+    pub(crate) fn logger_thread_id(&self) -> Option<u32> {
+        if self.registration_handle == INVALID_TRACE_HANDLE {
+            return None;
+        }
+        self.info.logger_thread_id()
+    }
+
+    /// Checks whether the provider is enabled on this session, using the LoggerId held in the low
+    /// 16 bits of the session handle
+    pub(crate) fn is_provider_enabled(&self, guid: &Guid) -> EvntraceNativeResult<bool> {
+        if self.registration_handle == INVALID_TRACE_HANDLE {
+            return Err(EvntraceNativeError::InvalidHandle);
+        }
+        let logger_id = (self.registration_handle & 0xFFFF) as u16;
+        Ok(guid_info_enabled_on(&query_guid_info(guid)?, logger_id))
+    }
+
+    /// Enables the stack walking of the given classic events (provider GUID, opcode) on a kernel
+    /// session
+    pub(crate) fn set_stack_tracing(&self, events: &[(Guid, u8)]) -> EvntraceNativeResult<()> {
+        let mut event_ids: Vec<Etw::CLASSIC_EVENT_ID> = events
+            .iter()
+            .map(|(guid, opcode)| Etw::CLASSIC_EVENT_ID {
+                EventGuid: *guid,
+                Type: *opcode,
+                Reserved: [0; 7],
+            })
+            .collect();
+        let status = unsafe {
+            Etw::TraceSetInformation(
+                self.registration_handle,
+                Etw::TRACE_QUERY_INFO_CLASS::TraceStackTracingInfo,
+                event_ids.as_mut_ptr() as *mut _,
+                (event_ids.len() * std::mem::size_of::<Etw::CLASSIC_EVENT_ID>()) as u32,
+            )
+        };
+        if status != 0 {
+            return Err(EvntraceNativeError::IoError(
+                std::io::Error::from_raw_os_error(status as i32),
+            ));
+        }
         Ok(())
     }
 
@@ -210,23 +371,343 @@ impl NativeEtw {
         any: u64,
         all: u64,
         level: u8,
+        timeout: u32,
         mut paramaters: EnableTraceParameters,
     ) -> EvntraceNativeResult<()> {
-        unsafe {
-            if Etw::EnableTraceEx2(
+        let status = unsafe {
+            Etw::EnableTraceEx2(
                 self.registration_handle,
                 &mut guid,
                 1, // Fixme: EVENT_CONTROL_CODE_ENABLE_PROVIDER
                 level,
                 any,
                 all,
-                0,
+                timeout,
                 &mut *paramaters,
-            ) != 0
-            {
-                return Err(EvntraceNativeError::IoError(std::io::Error::last_os_error()));
+            )
+        };
+        enable_status(status)
+    }
+
+    /// Asks an enabled provider to log its current state (`EVENT_CONTROL_CODE_CAPTURE_STATE`)
+    pub(crate) fn capture_state(
+        &self,
+        mut guid: Guid,
+        any: u64,
+        all: u64,
+        level: u8,
+    ) -> EvntraceNativeResult<()> {
+        let status = unsafe {
+            Etw::EnableTraceEx2(
+                self.registration_handle,
+                &mut guid,
+                Etw::ENABLECALLBACK_ENABLED_STATE::EVENT_CONTROL_CODE_CAPTURE_STATE.0,
+                level,
+                any,
+                all,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        enable_status(status)
+    }
+}
+
+/// Queries the properties and counters of the running session named `trace_name`
+pub(crate) fn query_trace(trace_name: &str) -> EvntraceNativeResult<TraceInfo> {
+    let mut info = TraceInfo::query(trace_name);
+    let status = control_trace_by_name(trace_name, &mut info, ControlValues::ControlQuery);
+    if status != 0 {
+        return Err(EvntraceNativeError::IoError(
+            std::io::Error::from_raw_os_error(status as i32),
+        ));
+    }
+
+    Ok(info)
+}
+
+/// Stops the running session named `trace_name`, a session that doesn't exist is not an error
+pub(crate) fn stop_trace_by_name(trace_name: &str) -> EvntraceNativeResult<()> {
+    let mut info = TraceInfo::query(trace_name);
+    let status = control_trace_by_name(trace_name, &mut info, ControlValues::ControlStop);
+    if status != 0 && status != WIN32_ERROR::ERROR_WMI_INSTANCE_NOT_FOUND.0 {
+        return Err(EvntraceNativeError::IoError(
+            std::io::Error::from_raw_os_error(status as i32),
+        ));
+    }
+
+    Ok(())
+}
+
+fn control_trace_by_name(trace_name: &str, info: &mut TraceInfo, control: ControlValues) -> u32 {
+    unsafe {
+        Etw::ControlTraceA(
+            0,
+            trace_name,
+            &mut *info.properties,
+            EvenTraceControl::from(control as u32),
+        )
+    }
+}
+
+// SetThreadDescription is looked up at runtime, importing it would keep the crate from loading on
+// the versions that lack it. Errors are ignored, the thread is still named for std
+fn set_thread_description(name: &str) {
+    if !version_helper::is_win10_1607_or_greater() {
+        return;
+    }
+
+    type SetThreadDescription =
+        unsafe extern "system" fn(SystemServices::HANDLE, *const u16) -> i32;
+    unsafe {
+        let kernel32 =
+            SystemServices::GetModuleHandleA(PSTR(b"kernel32.dll\0".as_ptr() as *mut u8));
+        if kernel32 == 0 {
+            return;
+        }
+        let proc = SystemServices::GetProcAddress(
+            kernel32,
+            PSTR(b"SetThreadDescription\0".as_ptr() as *mut u8),
+        );
+        if let Some(proc) = proc {
+            let set_description: SetThreadDescription = std::mem::transmute(proc);
+            let name: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+            set_description(SystemServices::GetCurrentThread(), name.as_ptr());
+        }
+    }
+}
+
+/// Captures the [TraceClock] of a session, raw QPC timestamps are converted relative to the
+/// current QPC value and system time
+pub(crate) fn trace_clock(raw_timestamps: bool) -> TraceClock {
+    if !raw_timestamps {
+        return TraceClock::FileTime;
+    }
+
+    let mut frequency = 0;
+    let mut sync_qpc = 0;
+    let mut now = WindowsProgramming::FILETIME::default();
+    unsafe {
+        WindowsProgramming::QueryPerformanceFrequency(&mut frequency);
+        WindowsProgramming::QueryPerformanceCounter(&mut sync_qpc);
+        WindowsProgramming::GetSystemTimeAsFileTime(&mut now);
+    }
+
+    TraceClock::Qpc {
+        frequency,
+        sync_qpc,
+        sync_filetime: (i64::from(now.dwHighDateTime) << 32) | i64::from(now.dwLowDateTime),
+    }
+}
+
+/// `ProcessTrace` returns ERROR_SUCCESS once a real-time session is stopped and ERROR_CANCELLED
+/// when the processing is cancelled (CloseTrace or a BufferCallback returning FALSE)
+fn is_normal_termination(status: u32) -> bool {
+    status == 0 || status == WIN32_ERROR::ERROR_CANCELLED.0
+}
+
+/// Calls `process_trace` until it ends normally or the hook, if any, aborts on an error. Returns
+/// the last status
+fn process_until_done<P>(mut process_trace: P, on_error: Option<&ProcessErrorHook>) -> u32
+where
+    P: FnMut() -> u32,
+{
+    let mut attempt = 0;
+    loop {
+        let status = process_trace();
+        if is_normal_termination(status) {
+            return status;
+        }
+
+        attempt += 1;
+        let action = match on_error {
+            Some(hook) => hook.action(status, attempt),
+            None => ProcessErrorAction::Abort,
+        };
+        if action == ProcessErrorAction::Abort {
+            return status;
+        }
+    }
+}
+
+/// Replays the events of an `.etl` file, blocks until the end of the file is reached
+///
+/// Events logged before `start_time` (FILETIME) are not delivered, a `start_time` of 0 replays
+/// the whole file
+pub(crate) fn process_file(
+    trace_data: &mut TraceData,
+    path: &str,
+    start_time: i64,
+) -> EvntraceNativeResult<()> {
+    // The buffer has to outlive the call to OpenTrace
+    let mut path: Vec<u8> = path.bytes().chain(std::iter::once(0)).collect();
+    let mut log_file = EventTraceLogfile::create_from_file(
+        trace_data,
+        PSTR(path.as_mut_ptr()),
+        trace_callback_thunk,
+    );
+
+    unsafe {
+        let mut handle = Etw::OpenTraceA(&mut *log_file);
+        if handle == INVALID_TRACE_HANDLE {
+            return Err(EvntraceNativeError::IoError(std::io::Error::last_os_error()));
+        }
+
+        let mut start = WindowsProgramming::FILETIME {
+            dwLowDateTime: start_time as u32,
+            dwHighDateTime: (start_time >> 32) as u32,
+        };
+        let start_ptr = if start_time == 0 {
+            std::ptr::null_mut()
+        } else {
+            &mut start as *mut _
+        };
+
+        let status = Etw::ProcessTrace(&mut handle, 1, start_ptr, std::ptr::null_mut());
+        Etw::CloseTrace(handle);
+        if status != 0 {
+            return Err(EvntraceNativeError::IoError(
+                std::io::Error::from_raw_os_error(status as i32),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Queries the `TraceGuidQueryInfo` of a provider, an empty buffer is returned when the provider
+/// isn't registered nor enabled anywhere
+fn query_guid_info(guid: &Guid) -> EvntraceNativeResult<Vec<u8>> {
+    let mut guid = *guid;
+    let mut buffer: Vec<u8> = Vec::new();
+    loop {
+        let mut needed = 0;
+        let status = unsafe {
+            Etw::EnumerateTraceGuidsEx(
+                Etw::TRACE_QUERY_INFO_CLASS::TraceGuidQueryInfo,
+                &mut guid as *mut Guid as *mut _,
+                std::mem::size_of::<Guid>() as u32,
+                buffer.as_mut_ptr() as *mut _,
+                buffer.len() as u32,
+                &mut needed,
+            )
+        };
+
+        match status {
+            0 => {
+                buffer.truncate(needed as usize);
+                return Ok(buffer);
+            }
+            _ if status == WIN32_ERROR::ERROR_INSUFFICIENT_BUFFER.0 => {
+                buffer.resize(needed as usize, 0);
+            }
+            _ if status == WIN32_ERROR::ERROR_WMI_GUID_NOT_FOUND.0 => return Ok(Vec::new()),
+            _ => {
+                return Err(EvntraceNativeError::IoError(
+                    std::io::Error::from_raw_os_error(status as i32),
+                ))
             }
         }
-        Ok(())
+    }
+}
+
+pub(crate) fn enable_status(status: u32) -> EvntraceNativeResult<()> {
+    match status {
+        0 => Ok(()),
+        _ if status == WIN32_ERROR::ERROR_NO_SYSTEM_RESOURCES.0 => {
+            Err(EvntraceNativeError::NoSystemResources)
+        }
+        _ => Err(EvntraceNativeError::IoError(
+            std::io::Error::from_raw_os_error(status as i32),
+        )),
+    }
+}
+
+// Source 0 is the ProfileTime source, the timer interrupt sampled by EVENT_TRACE_FLAG_PROFILE
+fn profile_interval(interval: u32) -> Etw::TRACE_PROFILE_INTERVAL {
+    Etw::TRACE_PROFILE_INTERVAL {
+        Source: 0,
+        Interval: interval,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profile_interval() {
+        // 1ms, the default interval
+        let profile_interval = profile_interval(10_000);
+        assert_eq!(profile_interval.Source, 0);
+        assert_eq!(profile_interval.Interval, 10_000);
+        assert_eq!(std::mem::size_of_val(&profile_interval), 8);
+    }
+
+    #[test]
+    fn test_processing_thread_is_named() {
+        let mut etw = NativeEtw::new();
+        assert!(matches!(
+            etw.process("etw-consumer:TestName", None),
+            Err(EvntraceNativeError::InvalidHandle)
+        ));
+
+        // Not a live session, ProcessTrace fails right away
+        etw.session_handle = 0;
+        etw.process("etw-consumer:TestName", None).unwrap();
+        let thread = etw.processing_thread.as_ref().unwrap().thread();
+        assert_eq!(thread.name(), Some("etw-consumer:TestName"));
+        etw.join();
+    }
+
+    #[test]
+    fn test_process_until_done_policy() {
+        const ERROR_NOT_ENOUGH_MEMORY: u32 = 8;
+        let run = |statuses: Vec<u32>, hook: Option<&ProcessErrorHook>| {
+            let mut statuses = statuses.into_iter();
+            let mut calls = 0;
+            let status = process_until_done(
+                || {
+                    calls += 1;
+                    statuses.next().unwrap()
+                },
+                hook,
+            );
+            (status, calls)
+        };
+
+        // Normal termination never reaches the hook
+        let attempts = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook_attempts = std::sync::Arc::clone(&attempts);
+        let retry_twice = ProcessErrorHook::new(move |status, attempt| {
+            hook_attempts.lock().unwrap().push((status, attempt));
+            if attempt < 2 {
+                ProcessErrorAction::Retry
+            } else {
+                ProcessErrorAction::Abort
+            }
+        });
+        assert_eq!(run(vec![0], Some(&retry_twice)), (0, 1));
+        assert_eq!(run(vec![1223], Some(&retry_twice)), (1223, 1));
+        assert!(attempts.lock().unwrap().is_empty());
+
+        // Default is abort
+        assert_eq!(
+            run(vec![ERROR_NOT_ENOUGH_MEMORY, 0], None),
+            (ERROR_NOT_ENOUGH_MEMORY, 1)
+        );
+
+        // Retried until the session ends normally
+        assert_eq!(
+            run(vec![ERROR_NOT_ENOUGH_MEMORY, 0], Some(&retry_twice)),
+            (0, 2)
+        );
+        attempts.lock().unwrap().clear();
+        // Aborted by the hook
+        assert_eq!(
+            run(vec![4201, 4201, 4201, 0], Some(&retry_twice)),
+            (4201, 2)
+        );
+        assert_eq!(*attempts.lock().unwrap(), vec![(4201, 1), (4201, 2)]);
     }
 }
@@ -12,3 +12,6 @@ pub mod sddl;
 pub mod tdh;
 pub mod tdh_types;
 pub mod version_helper;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -57,6 +57,38 @@ impl From<i32> for PlaError {
     }
 }
 
+impl std::fmt::Display for PlaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlaError::NotFound => f.write_str("provider not found"),
+            PlaError::ComHResultError(hr) => write!(f, "COM error {:?}", hr),
+        }
+    }
+}
+
+impl std::error::Error for PlaError {}
+
+impl PlaError {
+    pub(crate) fn io_error_kind(&self) -> std::io::ErrorKind {
+        match self {
+            PlaError::NotFound => std::io::ErrorKind::NotFound,
+            PlaError::ComHResultError(HResult::HrAccessDenied) => {
+                std::io::ErrorKind::PermissionDenied
+            }
+            PlaError::ComHResultError(HResult::HrInvalidArg) => std::io::ErrorKind::InvalidInput,
+            PlaError::ComHResultError(HResult::HrOutOfMemory) => std::io::ErrorKind::OutOfMemory,
+            PlaError::ComHResultError(_) => std::io::ErrorKind::Other,
+        }
+    }
+}
+
+/// Maps the error to its [std::io::ErrorKind], the [PlaError] is kept as the inner error
+impl From<PlaError> for std::io::Error {
+    fn from(err: PlaError) -> Self {
+        std::io::Error::new(err.io_error_kind(), err)
+    }
+}
+
 pub(crate) type ProvidersComResult<T> = Result<T, PlaError>;
 
 const VT_UI4: u16 = 0x13;
@@ -107,7 +139,6 @@ pub(crate) unsafe fn get_provider_guid(name: &str) -> ProvidersComResult<Guid> {
         pla_interfaces::ITraceDataProviderCollection,
     >(&pla_interfaces::CLSID_TRACE_DATA_PROV_COLLECTION)?;
 
-    let mut guid: MaybeUninit<Guid> = MaybeUninit::uninit();
     let mut hr = all_providers.get_trace_data_providers(BSTR::from(""));
     check_hr(hr)?;
 
@@ -126,7 +157,7 @@ pub(crate) unsafe fn get_provider_guid(name: &str) -> ProvidersComResult<Guid> {
         // We can safely unwrap after check_hr
         let mut raw_name: MaybeUninit<BSTR> = MaybeUninit::uninit();
         let provider = provider.unwrap();
-        provider.get_display_name(raw_name.as_mut_ptr());
+        hr = provider.get_display_name(raw_name.as_mut_ptr());
         check_hr(hr)?;
 
         let raw_name = raw_name.assume_init();
@@ -135,22 +166,26 @@ pub(crate) unsafe fn get_provider_guid(name: &str) -> ProvidersComResult<Guid> {
             SysStringLen(raw_name) as usize,
         ));
 
-        index.increment_val();
-        // check if matches, if it does get guid and break
-        if prov_name.eq(name) {
+        // Stop as soon as the provider is found, the providers from the partial iteration are
+        // released when dropped
+        if provider_name_matches(&prov_name, name) {
+            let mut guid: MaybeUninit<Guid> = MaybeUninit::uninit();
             hr = provider.get_guid(guid.as_mut_ptr());
             check_hr(hr)?;
-            println!("{}", prov_name);
-            break;
+
+            // we can assume the guid is init if we reached this point eoc would return Error
+            return Ok(guid.assume_init());
         }
-    }
 
-    if index.get_val() == count as u32 {
-        return Err(PlaError::NotFound);
+        index.increment_val();
     }
 
-    // we can assume the guid is init if we reached this point eoc would return Error
-    Ok(guid.assume_init())
+    Err(PlaError::NotFound)
+}
+
+// Provider names are matched case-insensitively, as the PLA collection does
+fn provider_name_matches(prov_name: &str, name: &str) -> bool {
+    prov_name.to_lowercase() == name.to_lowercase()
 }
 
 mod pla_interfaces {
@@ -246,6 +281,30 @@ mod test {
         }
     }
 
+    #[test]
+    pub fn test_get_provider_case_insensitive() {
+        unsafe {
+            let start = std::time::Instant::now();
+            let guid =
+                get_provider_guid("microsoft-windows-kernel-process").expect("Error Getting GUID");
+
+            assert_eq!(Guid::from("22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716"), guid);
+            assert!(start.elapsed() < std::time::Duration::from_secs(30));
+        }
+    }
+
+    #[test]
+    pub fn test_provider_name_matches() {
+        assert!(provider_name_matches(
+            "Microsoft-Windows-Kernel-Process",
+            "MICROSOFT-windows-kernel-process"
+        ));
+        assert!(!provider_name_matches(
+            "Microsoft-Windows-Kernel-Process",
+            "Microsoft-Windows-Kernel-Proc"
+        ));
+    }
+
     #[test]
     pub fn test_provider_not_found() {
         unsafe {
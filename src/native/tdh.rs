@@ -5,8 +5,9 @@
 //!
 //! This module shouldn't be accessed directly. Modules from the the crate level provide a safe API to interact
 //! with the crate
-use super::bindings::Windows::Win32::{Debug::WIN32_ERROR, Etw};
+use super::bindings::Windows::Win32::{Debug::WIN32_ERROR, Etw, SystemServices::PWSTR};
 use super::etw_types::*;
+use super::tdh_types::{Property, TdhContext};
 use crate::traits::*;
 
 /// Tdh native module errors
@@ -26,13 +27,45 @@ impl From<std::io::Error> for TdhNativeError {
 
 pub(crate) type TdhNativeResult<T> = Result<T, TdhNativeError>;
 
-pub(crate) fn schema_from_tdh(mut event: EventRecord) -> TdhNativeResult<TraceEventInfoRaw> {
+pub(crate) fn schema_from_tdh(
+    mut event: EventRecord,
+    contexts: &[TdhContext],
+) -> TdhNativeResult<TraceEventInfoRaw> {
+    // The strings have to outlive the calls to TdhGetEventInformation
+    let strings: Vec<Vec<u16>> = contexts
+        .iter()
+        .map(|context| match context {
+            TdhContext::WppTmfFile(path)
+            | TdhContext::WppTmfSearchPath(path)
+            | TdhContext::PdbPath(path) => path.as_str().as_utf16(),
+            _ => Vec::new(),
+        })
+        .collect();
+    let mut raw_contexts: Vec<Etw::TDH_CONTEXT> = contexts
+        .iter()
+        .zip(strings.iter())
+        .map(|(context, string)| Etw::TDH_CONTEXT {
+            ParameterType: context.context_type(),
+            ParameterValue: match context {
+                TdhContext::WppGmt(gmt) => *gmt as u64,
+                TdhContext::PointerSize(size) => u64::from(*size),
+                _ => string.as_ptr() as u64,
+            },
+            ..Default::default()
+        })
+        .collect();
+    let context_ptr = if raw_contexts.is_empty() {
+        std::ptr::null_mut()
+    } else {
+        raw_contexts.as_mut_ptr()
+    };
+
     let mut buffer_size = 0;
     unsafe {
         if Etw::TdhGetEventInformation(
             &mut event,
-            0,
-            std::ptr::null_mut(),
+            raw_contexts.len() as u32,
+            context_ptr,
             std::ptr::null_mut(),
             &mut buffer_size,
         ) != WIN32_ERROR::ERROR_INSUFFICIENT_BUFFER.0
@@ -43,8 +76,8 @@ pub(crate) fn schema_from_tdh(mut event: EventRecord) -> TdhNativeResult<TraceEv
         let mut buffer = TraceEventInfoRaw::alloc(buffer_size);
         if Etw::TdhGetEventInformation(
             &mut event,
-            0,
-            std::ptr::null_mut(),
+            raw_contexts.len() as u32,
+            context_ptr,
             buffer.info_as_ptr() as *mut _,
             &mut buffer_size,
         ) != 0
@@ -81,3 +114,97 @@ pub(crate) fn property_size(mut event: EventRecord, name: &str) -> TdhNativeResu
 
     Ok(property_size)
 }
+
+pub(crate) fn event_map_info(mut event: EventRecord, map_name: &str) -> TdhNativeResult<Vec<u8>> {
+    let mut map_name = map_name.as_utf16();
+    let mut buffer_size = 0;
+    unsafe {
+        let status = Etw::TdhGetEventMapInformation(
+            &mut event,
+            PWSTR(map_name.as_mut_ptr()),
+            std::ptr::null_mut(),
+            &mut buffer_size,
+        );
+        if status != WIN32_ERROR::ERROR_INSUFFICIENT_BUFFER.0 {
+            return Err(TdhNativeError::IoError(std::io::Error::from_raw_os_error(
+                status as i32,
+            )));
+        }
+
+        let mut buffer = vec![0u8; buffer_size as usize];
+        let status = Etw::TdhGetEventMapInformation(
+            &mut event,
+            PWSTR(map_name.as_mut_ptr()),
+            buffer.as_mut_ptr() as *mut _,
+            &mut buffer_size,
+        );
+        if status != 0 {
+            return Err(TdhNativeError::IoError(std::io::Error::from_raw_os_error(
+                status as i32,
+            )));
+        }
+
+        Ok(buffer)
+    }
+}
+
+/// Formats the `count` elements of `property` held in `buffer`, `length` is the PropertyLength
+/// expected by TDH: the value of the length property for `PROPERTY_PARAM_LENGTH` properties
+pub(crate) fn format_property(
+    info: &TraceEventInfoRaw,
+    map_info: Option<&mut [u8]>,
+    pointer_size: u32,
+    property: &Property,
+    length: u16,
+    count: usize,
+    buffer: &[u8],
+) -> TdhNativeResult<String> {
+    let map_info = match map_info {
+        Some(map_info) => map_info.as_mut_ptr() as *mut Etw::EVENT_MAP_INFO,
+        None => std::ptr::null_mut(),
+    };
+
+    let mut formatted = Vec::new();
+    let mut offset = 0;
+    let mut output: Vec<u16> = vec![0; 64];
+    // Arrays are formatted one element at a time, TDH tells us how much of the buffer each one took
+    while formatted.len() < count && offset < buffer.len() {
+        let remaining = &buffer[offset..];
+        let mut consumed = 0;
+        let mut output_size = (output.len() * std::mem::size_of::<u16>()) as u32;
+        let status = unsafe {
+            Etw::TdhFormatProperty(
+                info.info_as_event_info_ptr(),
+                map_info,
+                pointer_size,
+                property.in_type() as u16,
+                property.out_type() as u16,
+                length,
+                remaining.len() as u16,
+                remaining.as_ptr() as *mut u8,
+                &mut output_size,
+                PWSTR(output.as_mut_ptr()),
+                &mut consumed,
+            )
+        };
+
+        if status == WIN32_ERROR::ERROR_INSUFFICIENT_BUFFER.0 {
+            output.resize(output_size as usize / std::mem::size_of::<u16>(), 0);
+            continue;
+        }
+        if status != 0 {
+            return Err(TdhNativeError::IoError(std::io::Error::from_raw_os_error(
+                status as i32,
+            )));
+        }
+
+        let end = output.iter().position(|&c| c == 0).unwrap_or(output.len());
+        formatted.push(String::from_utf16_lossy(&output[..end]));
+        if consumed == 0 {
+            break;
+        }
+        offset += consumed as usize;
+    }
+
+    Ok(formatted.join(", "))
+}
@@ -26,6 +26,11 @@ pub struct Property {
     in_type: TdhInType,
     /// TDH Out type of the property
     out_type: TdhOutType,
+    map_name: Option<String>,
+    struct_start_index: u16,
+    struct_members: u16,
+    // Element count, or index of the count property with PROPERTY_PARAM_COUNT
+    count: u16,
 }
 
 #[doc(hidden)]
@@ -33,6 +38,19 @@ impl Property {
     pub fn new(name: String, property: &EventPropertyInfo) -> Self {
         // Fixme: Check flags to see which values to get for the in_type
         unsafe {
+            let flags = PropertyFlags::from(property.Flags);
+            if flags.contains(PropertyFlags::PROPERTY_STRUCT) {
+                return Property {
+                    name,
+                    flags,
+                    length: property.Anonymous3.length,
+                    struct_start_index: property.Anonymous1.structType.StructStartIndex,
+                    struct_members: property.Anonymous1.structType.NumOfStructMembers,
+                    count: property.Anonymous2.count,
+                    ..Default::default()
+                };
+            }
+
             let out_type = FromPrimitive::from_u16(property.Anonymous1.nonStructType.OutType)
                 .unwrap_or(TdhOutType::OutTypeNull);
             let in_type = FromPrimitive::from_u16(property.Anonymous1.nonStructType.InType)
@@ -40,13 +58,23 @@ impl Property {
 
             Property {
                 name,
-                flags: PropertyFlags::from(property.Flags),
+                flags,
                 length: property.Anonymous3.length,
                 in_type,
                 out_type,
+                map_name: None,
+                struct_start_index: 0,
+                struct_members: 0,
+                count: property.Anonymous2.count,
             }
         }
     }
+
+    pub(crate) fn with_map_name(mut self, map_name: Option<String>) -> Self {
+        self.map_name = map_name;
+        self
+    }
+
     pub fn in_type(&self) -> TdhInType {
         self.in_type
     }
@@ -55,9 +83,61 @@ impl Property {
         self.out_type
     }
 
+    /// Name of the value map associated to the property, if any
+    pub fn map_name(&self) -> Option<&str> {
+        self.map_name.as_deref()
+    }
+
+    /// Indexes of the members of a struct Property, empty if the Property is not a struct
+    pub fn struct_members(&self) -> std::ops::Range<u32> {
+        let start = u32::from(self.struct_start_index);
+        start..start + u32::from(self.struct_members)
+    }
+
     pub fn len(&self) -> usize {
         self.length.clone() as usize
     }
+
+    /// Index of the property holding the number of elements of an array Property
+    /// (`PROPERTY_PARAM_COUNT`), `None` if the count is fixed, see [Property::count]
+    pub fn count_property_index(&self) -> Option<u32> {
+        if self.flags.contains(PropertyFlags::PROPERTY_PARAM_COUNT) {
+            return Some(u32::from(self.count));
+        }
+        None
+    }
+
+    /// Index of the property holding the length of the Property (`PROPERTY_PARAM_LENGTH`), `None`
+    /// if the length is fixed, see [Property::len]
+    pub fn length_property_index(&self) -> Option<u32> {
+        if self.flags.contains(PropertyFlags::PROPERTY_PARAM_LENGTH) {
+            return Some(u32::from(self.length));
+        }
+        None
+    }
+
+    /// Number of elements of the Property, 1 for non-array properties. Meaningless when the
+    /// count is held by another property, see [Property::count_property_index]
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    /// Single element of an array Property, same types without the count
+    pub(crate) fn element(&self) -> Self {
+        let mut element = self.clone();
+        element.flags.remove(
+            PropertyFlags::PROPERTY_PARAM_COUNT | PropertyFlags::PROPERTY_PARAM_FIXED_COUNT,
+        );
+        element.count = 1;
+        element
+    }
+
+    /// Whether the Property is an array, either of fixed size or sized by another property
+    pub fn is_array(&self) -> bool {
+        self.flags.intersects(
+            PropertyFlags::PROPERTY_PARAM_COUNT | PropertyFlags::PROPERTY_PARAM_FIXED_COUNT,
+        ) || self.count > 1
+    }
 }
 
 /// Represent a TDH_IN_TYPE
@@ -87,7 +167,32 @@ pub enum TdhInType {
     InTypeSid,        // Field size determined by the first few bytes of the field
     InTypeHexInt32,
     InTypeHexInt64,
-    InTypeCountedString = 300,
+    InTypeManifestCountedString, // Field size is 2 bytes for the length in bytes + length
+    InTypeManifestCountedAnsiString, // Field size is 2 bytes for the length in bytes + length
+    InTypeManifestCountedBinary = 25, // Field size is 2 bytes for the length in bytes + length
+    InTypeCountedString = 300,   // Field size is 2 bytes for the length in bytes + length
+    InTypeCountedAnsiString,     // Field size is 2 bytes for the length in bytes + length
+    InTypeSizeT = 308,           // Field size is the pointer size of the trace
+    InTypeWbemSid = 310,         // TOKEN_USER (two pointers) followed by a SID
+}
+
+impl TdhInType {
+    /// Types whose size is the pointer size of the machine that logged the event
+    pub(crate) fn is_pointer_sized(self) -> bool {
+        matches!(self, TdhInType::InTypePointer | TdhInType::InTypeSizeT)
+    }
+
+    /// Types whose data is preceded by a 2 byte prefix holding its length in bytes
+    pub(crate) fn is_counted(self) -> bool {
+        matches!(
+            self,
+            TdhInType::InTypeManifestCountedString
+                | TdhInType::InTypeManifestCountedAnsiString
+                | TdhInType::InTypeManifestCountedBinary
+                | TdhInType::InTypeCountedString
+                | TdhInType::InTypeCountedAnsiString
+        )
+    }
 }
 
 impl Default for TdhInType {
@@ -165,3 +270,33 @@ impl From<Etw::PROPERTY_FLAGS> for PropertyFlags {
         PropertyFlags::from_bits_truncate(flags as u32)
     }
 }
+
+/// Represents a TDH_CONTEXT
+///
+/// Extra information TDH needs to decode the events of some Providers, like WPP. See
+/// [TDH_CONTEXT_TYPE](https://docs.microsoft.com/en-us/windows/win32/api/tdh/ne-tdh-tdh_context_type)
+#[derive(Debug, Clone, PartialEq)]
+pub enum TdhContext {
+    /// Path to the TMF file used to decode WPP events
+    WppTmfFile(String),
+    /// Semicolon separated list of paths to search for TMF files
+    WppTmfSearchPath(String),
+    /// Whether the WPP event timestamps are reported in UTC
+    WppGmt(bool),
+    /// Size of a pointer, in bytes, on the machine that logged the events (4 or 8)
+    PointerSize(u32),
+    /// Semicolon separated list of paths to search for PDB files
+    PdbPath(String),
+}
+
+impl TdhContext {
+    pub(crate) fn context_type(&self) -> Etw::TDH_CONTEXT_TYPE {
+        match self {
+            TdhContext::WppTmfFile(_) => Etw::TDH_CONTEXT_TYPE::TDH_CONTEXT_WPP_TMFFILE,
+            TdhContext::WppTmfSearchPath(_) => Etw::TDH_CONTEXT_TYPE::TDH_CONTEXT_WPP_TMFSEARCHPATH,
+            TdhContext::WppGmt(_) => Etw::TDH_CONTEXT_TYPE::TDH_CONTEXT_WPP_GMT,
+            TdhContext::PointerSize(_) => Etw::TDH_CONTEXT_TYPE::TDH_CONTEXT_POINTERSIZE,
+            TdhContext::PdbPath(_) => Etw::TDH_CONTEXT_TYPE::TDH_CONTEXT_PDB_PATH,
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Test helpers to build synthetic ETW data
+//!
+//! Tests can't rely on TDH to obtain a [TRACE_EVENT_INFO], this module allows building both the
+//! schema info and the [EventRecord] by hand so Schema and Parser can be exercised offline
+//!
+//! [TRACE_EVENT_INFO]: https://docs.microsoft.com/en-us/windows/win32/api/tdh/ns-tdh-trace_event_info
+use super::bindings::Windows::Win32::Etw;
+use super::etw_types::{EventMapInfo, EventRecord, TraceEventInfoRaw};
+use super::tdh_types::PropertyFlags;
+use crate::schema::Schema;
+use std::sync::Arc;
+use windows::Guid;
+
+/// Description of a property in a synthetic schema
+#[derive(Clone, Default)]
+pub(crate) struct TestProperty {
+    pub name: String,
+    pub flags: u32,
+    pub in_type: u16,
+    pub out_type: u16,
+    pub length: u16,
+    pub count: u16,
+    pub map_name: Option<String>,
+    pub struct_start: u16,
+    pub struct_members: u16,
+    /// Struct members don't count as top level properties
+    pub member: bool,
+}
+
+impl TestProperty {
+    pub(crate) fn new(name: &str, in_type: u16, length: u16) -> Self {
+        TestProperty {
+            name: name.to_string(),
+            flags: 0,
+            in_type,
+            out_type: 0,
+            length,
+            count: 1,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn structure(name: &str, struct_start: u16, struct_members: u16) -> Self {
+        TestProperty {
+            name: name.to_string(),
+            flags: PropertyFlags::PROPERTY_STRUCT.bits(),
+            count: 1,
+            struct_start,
+            struct_members,
+            ..Default::default()
+        }
+    }
+}
+
+/// Builder for a synthetic [TraceEventInfoRaw]
+#[derive(Clone, Default)]
+pub(crate) struct TestSchema {
+    pub provider_guid: Guid,
+    pub event_id: u16,
+    pub version: u8,
+    pub opcode: u8,
+    pub level: u8,
+    pub task: u16,
+    pub keyword: u64,
+    pub provider_name: Option<String>,
+    pub task_name: Option<String>,
+    pub opcode_name: Option<String>,
+    pub level_name: Option<String>,
+    pub properties: Vec<TestProperty>,
+}
+
+fn push_utf16(buffer: &mut Vec<u8>, value: &str) -> u32 {
+    let offset = buffer.len() as u32;
+    value
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .for_each(|c| buffer.extend_from_slice(&c.to_ne_bytes()));
+    offset
+}
+
+fn write_struct<T>(buffer: &mut [u8], offset: usize, value: &T) {
+    let bytes = unsafe {
+        std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>())
+    };
+    buffer[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+impl TestSchema {
+    pub(crate) fn new(provider_guid: &str, event_id: u16) -> Self {
+        TestSchema {
+            provider_guid: Guid::from(provider_guid),
+            event_id,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn property(mut self, property: TestProperty) -> Self {
+        self.properties.push(property);
+        self
+    }
+
+    pub(crate) fn build(&self) -> TraceEventInfoRaw {
+        let prop_size = std::mem::size_of::<Etw::EVENT_PROPERTY_INFO>();
+        let header_size = std::mem::size_of::<Etw::TRACE_EVENT_INFO>() - prop_size;
+        let mut buffer = vec![0u8; header_size + prop_size * self.properties.len().max(1)];
+
+        let mut info: Etw::TRACE_EVENT_INFO = unsafe { std::mem::zeroed() };
+        info.ProviderGuid = self.provider_guid;
+        info.EventDescriptor.Id = self.event_id;
+        info.EventDescriptor.Version = self.version;
+        info.EventDescriptor.Opcode = self.opcode;
+        info.EventDescriptor.Level = self.level;
+        info.EventDescriptor.Task = self.task;
+        info.EventDescriptor.Keyword = self.keyword;
+        info.PropertyCount = self.properties.len() as u32;
+        info.TopLevelPropertyCount = self.properties.iter().filter(|p| !p.member).count() as u32;
+        if let Some(name) = &self.provider_name {
+            info.ProviderNameOffset = push_utf16(&mut buffer, name);
+        }
+        if let Some(name) = &self.task_name {
+            info.TaskNameOffset = push_utf16(&mut buffer, name);
+        }
+        if let Some(name) = &self.opcode_name {
+            info.OpcodeNameOffset = push_utf16(&mut buffer, name);
+        }
+        if let Some(name) = &self.level_name {
+            info.LevelNameOffset = push_utf16(&mut buffer, name);
+        }
+
+        for (i, prop) in self.properties.iter().enumerate() {
+            let mut prop_info: Etw::EVENT_PROPERTY_INFO = unsafe { std::mem::zeroed() };
+            prop_info.Flags = Etw::PROPERTY_FLAGS(prop.flags as i32);
+            prop_info.NameOffset = push_utf16(&mut buffer, &prop.name);
+            if prop.flags & PropertyFlags::PROPERTY_STRUCT.bits() != 0 {
+                prop_info.Anonymous1.structType.StructStartIndex = prop.struct_start;
+                prop_info.Anonymous1.structType.NumOfStructMembers = prop.struct_members;
+            } else {
+                prop_info.Anonymous1.nonStructType.InType = prop.in_type;
+                prop_info.Anonymous1.nonStructType.OutType = prop.out_type;
+                if let Some(map_name) = &prop.map_name {
+                    prop_info.Anonymous1.nonStructType.MapNameOffset =
+                        push_utf16(&mut buffer, map_name);
+                }
+            }
+            prop_info.Anonymous2.count = prop.count;
+            prop_info.Anonymous3.length = prop.length;
+            write_struct(&mut buffer, header_size + i * prop_size, &prop_info);
+        }
+
+        // The header has to be written last, the EventPropertyInfoArray overlaps the first property
+        let first_prop = buffer[header_size..header_size + prop_size].to_vec();
+        write_struct(&mut buffer, 0, &info);
+        buffer[header_size..header_size + prop_size].copy_from_slice(&first_prop);
+
+        let mut raw = TraceEventInfoRaw::alloc(buffer.len() as u32);
+        unsafe {
+            std::ptr::copy_nonoverlapping(buffer.as_ptr(), raw.info_as_ptr(), buffer.len());
+        }
+        raw
+    }
+}
+
+/// A synthetic [EventRecord] alongside the buffers it points to
+///
+/// The record holds raw pointers to the user data, so the `TestEvent` must outlive any use of it
+pub(crate) struct TestEvent {
+    pub record: EventRecord,
+    _user_data: Box<[u8]>,
+    extended_data: Vec<Etw::EVENT_HEADER_EXTENDED_DATA_ITEM>,
+    extended_buffers: Vec<Box<[u8]>>,
+}
+
+impl TestEvent {
+    pub(crate) fn new(schema: &TestSchema, user_data: Vec<u8>) -> Self {
+        let mut user_data = user_data.into_boxed_slice();
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.ProviderId = schema.provider_guid;
+        record.EventHeader.EventDescriptor.Id = schema.event_id;
+        record.EventHeader.EventDescriptor.Version = schema.version;
+        record.EventHeader.EventDescriptor.Opcode = schema.opcode;
+        record.EventHeader.EventDescriptor.Level = schema.level;
+        record.EventHeader.EventDescriptor.Task = schema.task;
+        record.EventHeader.EventDescriptor.Keyword = schema.keyword;
+        record.UserDataLength = user_data.len() as u16;
+        record.UserData = user_data.as_mut_ptr() as *mut _;
+
+        TestEvent {
+            record,
+            _user_data: user_data,
+            extended_data: Vec::new(),
+            extended_buffers: Vec::new(),
+        }
+    }
+
+    /// Appends an extended data item and flags the header with EVENT_HEADER_FLAG_EXTENDED_INFO
+    pub(crate) fn extended_item(mut self, ext_type: u16, data: Vec<u8>) -> Self {
+        let data = data.into_boxed_slice();
+        self.extended_data
+            .push(Etw::EVENT_HEADER_EXTENDED_DATA_ITEM {
+                ExtType: ext_type,
+                DataSize: data.len() as u16,
+                DataPtr: data.as_ptr() as u64,
+                ..Default::default()
+            });
+        self.extended_buffers.push(data);
+        self.record.EventHeader.Flags |= Etw::EVENT_HEADER_FLAG_EXTENDED_INFO as u16;
+        self.record.ExtendedDataCount = self.extended_data.len() as u16;
+        self.record.ExtendedData = self.extended_data.as_mut_ptr();
+        self
+    }
+
+    /// Sets the ActivityId of the header and the RelatedActivityId extended data item
+    pub(crate) fn activity(mut self, activity_id: Guid, related: Option<Guid>) -> Self {
+        self.record.EventHeader.ActivityId = activity_id;
+        match related {
+            // EVENT_HEADER_EXT_TYPE_RELATEDACTIVITYID
+            Some(related) => {
+                self.extended_item(0x0001, crate::utils::guid_bytes(&related).to_vec())
+            }
+            None => self,
+        }
+    }
+
+    pub(crate) fn schema(&self, schema: &TestSchema) -> Schema {
+        Schema::new(self.record, Arc::new(schema.build()))
+    }
+}
+
+/// Builds a synthetic [EventMapInfo] out of (value, name) entries
+///
+/// `flags` are the `MAP_FLAGS` of the map, i.e. valuemap or bitmap
+pub(crate) fn test_map(flags: i32, entries: &[(u32, &str)]) -> EventMapInfo {
+    let entry_size = std::mem::size_of::<Etw::EVENT_MAP_ENTRY>();
+    let header_size = std::mem::size_of::<Etw::EVENT_MAP_INFO>() - entry_size;
+    let mut buffer = vec![0u8; header_size + entry_size * entries.len().max(1)];
+
+    let mut map_entries = Vec::new();
+    for (value, name) in entries {
+        let mut entry: Etw::EVENT_MAP_ENTRY = unsafe { std::mem::zeroed() };
+        entry.OutputOffset = push_utf16(&mut buffer, name);
+        entry.Anonymous.Value = *value;
+        map_entries.push(entry);
+    }
+    for (i, entry) in map_entries.iter().enumerate() {
+        write_struct(&mut buffer, header_size + i * entry_size, entry);
+    }
+
+    let mut info: Etw::EVENT_MAP_INFO = unsafe { std::mem::zeroed() };
+    info.Flag = Etw::MAP_FLAGS(flags);
+    info.EntryCount = entries.len() as u32;
+    // The header has to be written last, the MapEntryArray overlaps the first entry
+    let first_entry = buffer[header_size..header_size + entry_size].to_vec();
+    write_struct(&mut buffer, 0, &info);
+    buffer[header_size..header_size + entry_size].copy_from_slice(&first_entry);
+
+    EventMapInfo::new(buffer)
+}
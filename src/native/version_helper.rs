@@ -3,8 +3,9 @@
 //! The `version_helper` module is an abstraction layer over the Version Helper API/Macro which allow
 //! us to determine the Windows OS system version
 //!
-//! At the moment the only option available is to check if the actual System Version is greater than
-//! Win8, is the only check we need for the crate to work as expected
+//! At the moment the only options available are to check if the actual System Version is greater
+//! than Win8, Win8.1 or Win10 1607, those are the only checks we need for the crate to work as
+//! expected
 use super::bindings::Windows::Win32::{SystemServices, WindowsProgramming};
 use crate::traits::*;
 
@@ -29,12 +30,50 @@ type OsVersionInfo = WindowsProgramming::OSVERSIONINFOEXA;
 // Safe cast, we now the value fits in a u8 (VER_GREATER_EQUAL == 3)
 const VER_GREATER_OR_EQUAL: u8 = SystemServices::VER_GREATER_EQUAL as u8;
 
-fn verify_system_version(major: u8, minor: u8, sp_major: u16) -> VersionHelperResult<bool> {
+fn os_version_info(major: u8, minor: u8, sp_major: u16) -> OsVersionInfo {
     let mut os_version = OsVersionInfo::default();
     os_version.dwOSVersionInfoSize = std::mem::size_of::<OsVersionInfo>() as u32;
     os_version.dwMajorVersion = major as u32;
-    os_version.dwMajorVersion = minor as u32;
+    os_version.dwMinorVersion = minor as u32;
     os_version.wServicePackMajor = sp_major;
+    os_version
+}
+
+// Win10 releases share the 10.0 version and are told apart by their build number
+fn verify_system_build(major: u8, minor: u8, build: u32) -> VersionHelperResult<bool> {
+    let mut os_version = os_version_info(major, minor, 0);
+    os_version.dwBuildNumber = build;
+
+    let mut condition_mask = 0;
+    unsafe {
+        condition_mask = WindowsProgramming::VerSetConditionMask(
+            condition_mask,
+            WindowsProgramming::VER_FLAGS::VER_MAJORVERSION,
+            VER_GREATER_OR_EQUAL,
+        );
+        condition_mask = WindowsProgramming::VerSetConditionMask(
+            condition_mask,
+            WindowsProgramming::VER_FLAGS::VER_MINORVERSION,
+            VER_GREATER_OR_EQUAL,
+        );
+        condition_mask = WindowsProgramming::VerSetConditionMask(
+            condition_mask,
+            WindowsProgramming::VER_FLAGS::VER_BUILDNUMBER,
+            VER_GREATER_OR_EQUAL,
+        );
+
+        Ok(WindowsProgramming::VerifyVersionInfoA(
+            &mut os_version,
+            WindowsProgramming::VER_FLAGS::VER_MAJORVERSION
+                | WindowsProgramming::VER_FLAGS::VER_MINORVERSION
+                | WindowsProgramming::VER_FLAGS::VER_BUILDNUMBER,
+            condition_mask,
+        ) != false)
+    }
+}
+
+fn verify_system_version(major: u8, minor: u8, sp_major: u16) -> VersionHelperResult<bool> {
+    let mut os_version = os_version_info(major, minor, sp_major);
 
     let mut condition_mask = 0;
     unsafe {
@@ -80,6 +119,26 @@ pub fn is_win8_or_greater() -> bool {
     res
 }
 
+/// Use the `is_win8_1_or_greater` function to check if the System Version is Win8.1 or greater
+///
+/// # Remarks
+/// Some ETW features, like the PID filter of [Provider::self_only], need Win8.1
+///
+/// [Provider::self_only]: crate::provider::Provider::self_only
+pub fn is_win8_1_or_greater() -> bool {
+    // Assume an older version when it can't be verified, the feature is just left unused
+    verify_system_version(6, 3, 0).unwrap_or(false)
+}
+
+/// Use the `is_win10_1607_or_greater` function to check if the System Version is Win10 1607
+/// (build 14393) or greater
+///
+/// # Remarks
+/// `SetThreadDescription`, used to name the thread processing the events, needs Win10 1607
+pub fn is_win10_1607_or_greater() -> bool {
+    verify_system_build(10, 0, 14393).unwrap_or(false)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -91,5 +150,22 @@ mod test {
             Ok(res) => assert_eq!(true, res),
             Err(err) => panic!("VersionHelper error: {:?}", err),
         };
+        // Win7
+        assert!(verify_system_version(6, 1, 0).unwrap());
+        // A later minor version implies the earlier one, Win8.1 or greater is Win8 or greater
+        assert!(!is_win8_1_or_greater() || is_win8_or_greater());
+        assert!(!is_win10_1607_or_greater() || is_win8_1_or_greater());
+    }
+
+    #[test]
+    fn test_os_version_info() {
+        let os_version = os_version_info(6, 3, 1);
+        assert_eq!(os_version.dwMajorVersion, 6);
+        assert_eq!(os_version.dwMinorVersion, 3);
+        assert_eq!(os_version.wServicePackMajor, 1);
+        assert_eq!(
+            os_version.dwOSVersionInfoSize as usize,
+            std::mem::size_of::<OsVersionInfo>()
+        );
     }
 }
@@ -1,7 +1,7 @@
 //! ETW Types Parser
 //!
 //! This module act as a helper to parse the Buffer from an ETW Event
-use crate::native::etw_types::EVENT_HEADER_FLAG_32_BIT_HEADER;
+use crate::native::etw_types::EventMapInfo;
 use crate::native::sddl;
 use crate::native::tdh;
 use crate::native::tdh_types::{Property, PropertyFlags, TdhInType, TdhOutType};
@@ -13,17 +13,29 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::rc::Rc;
+use std::time::SystemTime;
 use windows::Guid;
 
 /// Parser module errors
 #[derive(Debug)]
 pub enum ParserError {
-    /// An invalid type...
+    /// The property isn't of the kind the function handles, e.g. a struct, an array or a mapped
+    /// property
     InvalidType,
     /// Error parsing
     ParseError,
-    /// Length mismatch when parsing a type
+    /// The property can't be parsed as the requested type, e.g. an integer parsed as a `String`
+    TypeMismatch {
+        /// Name of the requested type
+        expected: &'static str,
+        /// InType of the property
+        found: TdhInType,
+    },
+    /// Length mismatch when parsing a type, e.g. a 2 bytes property parsed as an `u32`
     LengthMismatch,
+    /// The Schema of the Event has no top level property with this name
+    PropertyNotFound(String),
+    /// The property data can't be located within the Event
     PropertyError(String),
     /// An error while transforming an Utf-8 buffer into String
     Utf8Error(std::string::FromUtf8Error),
@@ -33,15 +45,15 @@ pub enum ParserError {
     ///
     /// [SddlNativeError]: sddl::SddlNativeError
     SddlNativeError(sddl::SddlNativeError),
-    /// Represents an internal [TdhNativeError]
-    ///
-    /// [TdhNativeError]: tdh::TdhNativeError
-    TdhNativeError(tdh::TdhNativeError),
+    /// A TDH function failed with this status, e.g. `ERROR_NOT_FOUND` (1168) when the property
+    /// has no map
+    TdhError(u32),
 }
 
 impl From<tdh::TdhNativeError> for ParserError {
     fn from(err: tdh::TdhNativeError) -> Self {
-        ParserError::TdhNativeError(err)
+        let tdh::TdhNativeError::IoError(err) = err;
+        ParserError::TdhError(err.raw_os_error().unwrap_or_default() as u32)
     }
 }
 
@@ -83,6 +95,179 @@ pub trait TryParse<T> {
     fn try_parse(&mut self, name: &str) -> Result<T, ParserError>;
 }
 
+// Decodes `T` from a property already located in the Event, the TryParse impls of the crate and
+// Parser::try_parse_array share it. Public within a private module so it can bound a public fn
+mod private {
+    use super::{ParserResult, PropertyInfo};
+
+    pub trait TryParseProperty<T> {
+        fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<T>;
+    }
+}
+
+use private::TryParseProperty;
+
+// The property is located by its name and decoded by the TryParseProperty impl of the type
+macro_rules! impl_try_parse {
+    ($(#[$attr:meta])* $T:ty) => {
+        $(#[$attr])*
+        impl TryParse<$T> for Parser<'_> {
+            fn try_parse(&mut self, name: &str) -> ParserResult<$T> {
+                let prop_info = self.find_property(name)?;
+                self.try_parse_property(&prop_info)
+            }
+        }
+    };
+}
+
+/// Represents the value of a Property, see [Parser::try_parse_all]
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    /// Conditionally present property which is not present
+    Null,
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    Guid(Guid),
+    Pointer(usize),
+    /// FILETIME, SYSTEMTIME, or 64-bit integer with a DateTime OutType
+    Time(SystemTime),
+    /// Any value that can't be represented by the other variants, e.g. arrays
+    Binary(Vec<u8>),
+}
+
+// Timestamps are often logged as plain 64-bit integers with a DateTime OutType
+pub(crate) fn is_time_property(property: &Property) -> bool {
+    match property.in_type() {
+        TdhInType::InTypeFileTime | TdhInType::InTypeSystemTime => true,
+        TdhInType::InTypeInt64 | TdhInType::InTypeUInt64 => matches!(
+            property.out_type(),
+            TdhOutType::OutTypeDateTime | TdhOutType::OutTypeDatetimeUtc
+        ),
+        _ => false,
+    }
+}
+
+/// Represents a member of a struct Property, see [Parser::try_parse_struct]
+#[derive(Clone, Default)]
+pub struct StructMember {
+    /// Member attributes and data
+    pub info: PropertyInfo,
+    /// Name the member value translates to, if the member has a value map
+    pub map_value: Option<String>,
+}
+
+impl StructMember {
+    /// Use the `name` function to obtain the name of the member
+    pub fn name(&self) -> &str {
+        &self.info.property.name
+    }
+}
+
+/// Decodes the value of a Property read by a [PropertyCursor]
+pub type LazyValue<'c> = Box<dyn FnOnce() -> Result<PropertyValue, ParserError> + 'c>;
+
+/// Pull based Property reader, see [Schema::cursor]
+///
+/// The cursor walks the top level properties in order, every call to
+/// [PropertyCursor::next_property] moves past one property and returns its [PropertyInfo] along
+/// with a function that decodes its value. Only the properties whose value function is called are
+/// decoded, the cursor can be dropped at any point without touching the rest of the buffer
+///
+/// [Schema::cursor]: crate::schema::Schema::cursor
+pub struct PropertyCursor<'a> {
+    parser: Parser<'a>,
+    index: u32,
+    size: usize,
+}
+
+impl<'a> PropertyCursor<'a> {
+    pub(crate) fn new(schema: &'a Schema) -> Self {
+        let parser = Parser::create(schema);
+        let size = parser.buffer.len();
+        PropertyCursor {
+            parser,
+            index: 0,
+            size,
+        }
+    }
+
+    /// Use the `next_property` function to move to the next Property
+    ///
+    /// Returns `None` once every top level property has been read. After an error the cursor
+    /// doesn't move anymore, since the position of the following properties is unknown
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut cursor = schema.cursor();
+    ///     while let Some(property) = cursor.next_property() {
+    ///         let (info, value) = property?;
+    ///         if info.property.name == "ProcessID" {
+    ///             println!("{:?}", value()?);
+    ///             break;
+    ///         }
+    ///     }
+    /// };
+    /// ```
+    pub fn next_property(&mut self) -> Option<ParserResult<(PropertyInfo, LazyValue<'_>)>> {
+        let count = self.parser.schema.top_level_property_count();
+        if self.index >= count {
+            return None;
+        }
+
+        let name = match self.parser.properties.property(self.index) {
+            Some(prop) => prop.name.clone(),
+            None => {
+                self.index = count;
+                return Some(Err(ParserError::PropertyError(
+                    "Index out of bounds".to_owned(),
+                )));
+            }
+        };
+        // Sizing the property is enough to move past it, decoding is left to the caller
+        let info = match self.parser.find_property(&name) {
+            Ok(info) => info,
+            Err(err) => {
+                self.index = count;
+                return Some(Err(err));
+            }
+        };
+        self.index += 1;
+
+        let parser = &mut self.parser;
+        let value: LazyValue<'_> = Box::new(move || parser.property_value(&name));
+        Some(Ok((PropertyInfo::clone(&info), value)))
+    }
+
+    /// Use the `offset` function to obtain the offset, within the user data, of the next Property
+    pub fn offset(&self) -> usize {
+        self.size - self.parser.buffer.len()
+    }
+}
+
+// Value maps only apply to integer properties of up to 32 bits
+fn map_key(buffer: &[u8]) -> Option<u32> {
+    match buffer.len() {
+        1 => Some(u32::from(buffer[0])),
+        2 => Some(u32::from(u16::from_ne_bytes([buffer[0], buffer[1]]))),
+        4 => Some(u32::from_ne_bytes([
+            buffer[0], buffer[1], buffer[2], buffer[3],
+        ])),
+        _ => None,
+    }
+}
+
 /// Represents a Parser
 ///
 /// This structure holds the necessary data to parse the ETW event and retrieve the data from the
@@ -94,6 +279,7 @@ pub struct Parser<'a> {
     buffer: Vec<u8>,
     last_property: u32,
     cache: HashMap<String, Rc<PropertyInfo>>,
+    maps: HashMap<String, Rc<EventMapInfo>>,
 }
 
 impl<'a> Parser<'a> {
@@ -116,6 +302,7 @@ impl<'a> Parser<'a> {
             properties: PropertyIter::new(schema),
             last_property: 0,
             cache: HashMap::new(), // We could fill the cache on creation
+            maps: HashMap::new(),
         }
     }
 
@@ -150,25 +337,79 @@ impl<'a> Parser<'a> {
     }
 
     // TODO: Find a cleaner way to do this, not very happy with it rn
-    fn find_property_size(&self, property: &Property) -> ParserResult<usize> {
-        if property
-            .flags
-            .intersects(PropertyFlags::PROPERTY_PARAM_LENGTH)
-            && property.len() > 0
+    // `buffer` starts at the property, the variable length ones are sized from their data
+    fn find_property_size(&self, property: &Property, buffer: &[u8]) -> ParserResult<usize> {
+        // Structs take the space of all their members, laid out one after the other
+        if property.flags.contains(PropertyFlags::PROPERTY_STRUCT) {
+            return property.struct_members().try_fold(0, |size, i| {
+                let member = self
+                    .properties
+                    .property(i)
+                    .ok_or_else(|| ParserError::PropertyError("Index out of bounds".to_owned()))?;
+                let member_buffer = buffer.get(size..).unwrap_or_default();
+                Ok(size + self.find_member_size(member, member_buffer)?)
+            });
+        }
+
+        // Conditionally present properties are declared as NULL, they don't take any space
+        if property.in_type() == TdhInType::InTypeNull {
+            return Ok(0);
+        }
+
+        // Arrays of fixed size elements, the length of the property is the one of an element
+        if property.is_array()
+            && !is_string_type(property.in_type())
+            && !property
+                .flags
+                .intersects(PropertyFlags::PROPERTY_PARAM_LENGTH)
         {
-            let size;
-            if property.in_type() == TdhInType::InTypePointer {
-                size = if (self.schema.event_flags() & EVENT_HEADER_FLAG_32_BIT_HEADER) != 0 {
-                    4
-                } else {
-                    8
-                };
+            let element_size = if property.in_type().is_pointer_sized() {
+                self.schema.pointer_size() as usize
             } else {
-                size = property.len() as usize;
+                property.len()
+            };
+            if element_size > 0 {
+                return Ok(element_size * self.array_count(property)?);
             }
+        }
+
+        // A single pointer or SIZE_T, arrays are flagged with their count
+        if property.in_type().is_pointer_sized() && property.flags.is_empty() {
+            return Ok(self.schema.pointer_size() as usize);
+        }
+
+        // Each element of an array of strings has its own length, walk them to find the end
+        if is_string_type(property.in_type()) && property.is_array() {
+            let count = self.array_count(property)?;
+            let (_, size) = split_strings(property.in_type(), buffer, count)?;
             return Ok(size);
         }
 
+        // The SID length is given by its SubAuthorityCount
+        if is_sid_type(property.in_type()) && property.flags.is_empty() {
+            let sid = sid_data(property.in_type(), buffer, self.schema.pointer_size())?;
+            return Ok(buffer.len() - sid.len() + sid_length(sid)?);
+        }
+
+        // Counted types carry their length in a prefix, in front of the data
+        if property.in_type().is_counted() && property.flags.is_empty() {
+            return match buffer.get(..2) {
+                Some(prefix) => Ok(2 + usize::from(u16::from_ne_bytes([prefix[0], prefix[1]]))),
+                None => Err(ParserError::PropertyError(
+                    "Property length out of buffer bounds".to_owned(),
+                )),
+            };
+        }
+
+        // The length is held by another property, in characters for UTF-16 strings
+        if property.length_property_index().is_some() {
+            let length = usize::from(self.property_length(property)?);
+            return match property.in_type() {
+                TdhInType::InTypeUnicodeString => Ok(length * 2),
+                _ => Ok(length),
+            };
+        }
+
         // TODO: Study heuristic method used in krabsetw :)
         if property.flags.is_empty() && property.len() > 0 {
             return Ok(property.len());
@@ -177,22 +418,79 @@ impl<'a> Parser<'a> {
         Ok(tdh::property_size(self.schema.record(), &property.name)? as usize)
     }
 
-    fn find_property(&mut self, name: &str) -> ParserResult<Rc<PropertyInfo>> {
+    // TDH only sizes top level properties by name, the strings of a struct are walked instead
+    fn find_member_size(&self, member: &Property, buffer: &[u8]) -> ParserResult<usize> {
+        if is_string_type(member.in_type()) && member.flags.is_empty() && member.len() == 0 {
+            let (_, size) = split_strings(member.in_type(), buffer, 1)?;
+            return Ok(size);
+        }
+        self.find_property_size(member, buffer)
+    }
+
+    // Number of elements of an array property, the count property is always parsed before
+    fn array_count(&self, property: &Property) -> ParserResult<usize> {
+        match property.count_property_index() {
+            Some(index) => self.param_value(index),
+            None => Ok(usize::from(property.count())),
+        }
+    }
+
+    /// Number of elements of the property, as passed to TDH. 1 for properties that aren't arrays
+    pub(crate) fn element_count(&self, property: &Property) -> ParserResult<usize> {
+        if !property.is_array() {
+            return Ok(1);
+        }
+        self.array_count(property)
+    }
+
+    /// Length of the property, as passed to TDH. When the length is held by another property its
+    /// value is read, the length property is always parsed before
+    pub(crate) fn property_length(&self, property: &Property) -> ParserResult<u16> {
+        match property.length_property_index() {
+            Some(index) => self
+                .param_value(index)?
+                .try_into()
+                .map_err(|_| ParserError::LengthMismatch),
+            None => Ok(property.len() as u16),
+        }
+    }
+
+    // Value of the integer property a length or count refers to
+    fn param_value(&self, index: u32) -> ParserResult<usize> {
+        let param_name = self
+            .properties
+            .property(index)
+            .map(|param| param.name.clone())
+            .ok_or_else(|| ParserError::PropertyError("Index out of bounds".to_owned()))?;
+        let param = self.cache.get(&param_name).ok_or_else(|| {
+            ParserError::PropertyError(format!("Property {} not parsed", param_name))
+        })?;
+
+        let buffer = param.buffer.as_slice();
+        let value = match buffer.len() {
+            1 => u64::from(buffer[0]),
+            2 => u64::from(u16::from_ne_bytes(buffer.try_into()?)),
+            4 => u64::from(u32::from_ne_bytes(buffer.try_into()?)),
+            8 => u64::from_ne_bytes(buffer.try_into()?),
+            _ => return Err(ParserError::LengthMismatch),
+        };
+        Ok(value as usize)
+    }
+
+    pub(crate) fn find_property(&mut self, name: &str) -> ParserResult<Rc<PropertyInfo>> {
         if self.cache.contains_key(name) {
             return Ok(Rc::clone(self.cache.get(name).unwrap()));
         }
 
-        let mut prop_info = Rc::new(PropertyInfo::default());
-
         // TODO: Find a way to do this with an iter, try_find looks promising but is not stable yet
         // TODO: Clean this a bit, not a big fan of this loop
-        for i in self.last_property..self.schema.property_count() {
+        for i in self.last_property..self.schema.top_level_property_count() {
             let curr_prop = match self.properties.property(i) {
                 Some(prop) => prop,
                 None => return Err(ParserError::PropertyError("Index out of bounds".to_owned())),
             };
 
-            let prop_size = self.find_property_size(&curr_prop)?;
+            let prop_size = self.find_property_size(&curr_prop, &self.buffer)?;
 
             if self.buffer.len() < prop_size {
                 return Err(ParserError::PropertyError(
@@ -204,153 +502,637 @@ impl<'a> Parser<'a> {
             // We drain the buffer, if everything works correctly in the end the buffer will be empty
             // and we should have all properties in the cache
             let prop_buffer = self.buffer.drain(..prop_size).collect();
-            prop_info = Rc::from(PropertyInfo::create(curr_prop.clone(), prop_buffer));
+            let prop_info = Rc::from(PropertyInfo::create(curr_prop.clone(), prop_buffer));
             self.cache
                 .insert(String::from(&curr_prop.name), Rc::clone(&prop_info));
 
             if name == curr_prop.name {
                 self.last_property = i + 1;
-                break;
+                return Ok(prop_info);
             }
         }
 
-        Ok(prop_info)
+        // Every remaining property has been cached while looking for it
+        self.last_property = self.schema.top_level_property_count();
+        Err(ParserError::PropertyNotFound(name.to_owned()))
     }
-}
-
-macro_rules! impl_try_parse_primitive {
-    ($T:ident) => {
-        impl TryParse<$T> for Parser<'_> {
-            fn try_parse(&mut self, name: &str) -> ParserResult<$T> {
-                let prop_info = self.find_property(name)?;
-                let prop_info: &PropertyInfo = prop_info.borrow();
 
-                // TODO: Check In and Out type and do a better type checking
-                if std::mem::size_of::<$T>() != prop_info.buffer.len() {
-                    return Err(ParserError::LengthMismatch);
-                }
-                Ok($T::from_ne_bytes(prop_info.buffer.as_slice().try_into()?))
-            }
+    fn map_info(&mut self, map_name: &str) -> ParserResult<Rc<EventMapInfo>> {
+        if let Some(map_info) = self.maps.get(map_name) {
+            return Ok(Rc::clone(map_info));
         }
-    };
-}
 
-impl_try_parse_primitive!(u8);
-impl_try_parse_primitive!(i8);
-impl_try_parse_primitive!(u16);
-impl_try_parse_primitive!(i16);
-impl_try_parse_primitive!(u32);
-impl_try_parse_primitive!(i32);
-impl_try_parse_primitive!(u64);
-impl_try_parse_primitive!(i64);
-impl_try_parse_primitive!(usize);
-impl_try_parse_primitive!(isize);
+        let map_info = Rc::new(EventMapInfo::new(tdh::event_map_info(
+            self.schema.record(),
+            map_name,
+        )?));
+        self.maps
+            .insert(String::from(map_name), Rc::clone(&map_info));
+        Ok(map_info)
+    }
 
-/// The `String` impl of the `TryParse` trait should be used to retrieve the following [TdhInTypes]:
-///
-/// * InTypeUnicodeString
-/// * InTypeAnsiString
-/// * InTypeCountedString
-/// * InTypeGuid
-///
-/// On success a `String` with the with the data from the `name` property will be returned
-///
-/// # Arguments
-/// * `name` - Name of the property to be found in the Schema
-
-/// # Example
-/// ```rust
-/// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
-///     let schema = schema_locator.event_schema(record)?;
-///     let parser = Parse::create(&schema);
-///     let image_name: String = parser.try_parse("ImageName")?;
-/// };
-/// ```
-///
-/// [TdhInTypes]: TdhInType
-impl TryParse<String> for Parser<'_> {
-    fn try_parse(&mut self, name: &str) -> ParserResult<String> {
+    /// Use the `try_parse_struct` function to parse the members of a struct property
+    ///
+    /// The members are returned in the order they are declared in the Schema, members with an
+    /// associated value map (enums and bitmaps) are translated into their names. The value maps are
+    /// cached by the Parser so each one is only requested once
+    ///
+    /// # Arguments
+    /// * `name` - Name of the struct property to be found in the Schema
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     for member in parser.try_parse_struct("Header")? {
+    ///         println!("{}: {:?}", member.name(), member.map_value);
+    ///     }
+    /// };
+    /// ```
+    pub fn try_parse_struct(&mut self, name: &str) -> ParserResult<Vec<StructMember>> {
         let prop_info = self.find_property(name)?;
-        let prop_info: &PropertyInfo = prop_info.borrow();
+        if !prop_info
+            .property
+            .flags
+            .contains(PropertyFlags::PROPERTY_STRUCT)
+        {
+            return Err(ParserError::InvalidType);
+        }
 
-        // TODO: Handle errors and type checking better
-        let res = match prop_info.property.in_type() {
-            TdhInType::InTypeUnicodeString => {
-                utils::parse_null_utf16_string(prop_info.buffer.as_slice())
-            }
-            TdhInType::InTypeAnsiString => String::from_utf8(prop_info.buffer.clone())?
-                .trim_matches(char::default())
-                .to_string(),
-            TdhInType::InTypeSid => {
-                sddl::convert_sid_to_string(prop_info.buffer.as_ptr() as isize)?
+        let mut members = Vec::new();
+        let mut offset = 0;
+        for i in prop_info.property.struct_members() {
+            let member = match self.properties.property(i) {
+                Some(member) => member.clone(),
+                None => return Err(ParserError::PropertyError("Index out of bounds".to_owned())),
+            };
+
+            // Sized from the struct's own data, the buffer of the Parser is already past it
+            let member_size = self.find_member_size(&member, &prop_info.buffer[offset..])?;
+            if prop_info.buffer.len() < offset + member_size {
+                return Err(ParserError::PropertyError(
+                    "Property length out of buffer bounds".to_owned(),
+                ));
             }
-            TdhInType::InTypeCountedString => unimplemented!(),
-            _ => return Err(ParserError::InvalidType),
-        };
+            let buffer = prop_info.buffer[offset..offset + member_size].to_vec();
+            offset += member_size;
 
-        Ok(res)
+            let map_value = match member.map_name() {
+                Some(map_name) => {
+                    let map_info = self.map_info(map_name)?;
+                    map_key(&buffer).and_then(|key| map_info.resolve(key))
+                }
+                None => None,
+            };
+            members.push(StructMember {
+                info: PropertyInfo::create(member, buffer),
+                map_value,
+            });
+        }
+
+        Ok(members)
+    }
+
+    /// Use the `try_parse_time` function to parse a timestamp property
+    ///
+    /// FILETIME, SYSTEMTIME and 64-bit integer properties with a `DateTime` OutType are accepted,
+    /// see the `SystemTime` impl of the `TryParse` trait
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let create_time = parser.try_parse_time("CreateTime")?;
+    /// };
+    /// ```
+    pub fn try_parse_time(&mut self, name: &str) -> ParserResult<SystemTime> {
+        self.try_parse(name)
     }
-}
 
-impl TryParse<Guid> for Parser<'_> {
-    fn try_parse(&mut self, name: &str) -> Result<Guid, ParserError> {
+    /// Use the `try_parse_string_array` function to parse an array of variable length strings
+    ///
+    /// The number of elements is read from the property holding the count, or from the Schema for
+    /// fixed size arrays, then each element is parsed in turn. Null terminated and counted strings
+    /// are supported, both UTF-16 and ANSI
+    ///
+    /// # Arguments
+    /// * `name` - Name of the array property to be found in the Schema
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     for module in parser.try_parse_string_array("Modules")? {
+    ///         println!("{}", module);
+    ///     }
+    /// };
+    /// ```
+    pub fn try_parse_string_array(&mut self, name: &str) -> ParserResult<Vec<String>> {
         let prop_info = self.find_property(name)?;
-        let prop_info: &PropertyInfo = prop_info.borrow();
+        let in_type = prop_info.property.in_type();
+        if !is_string_type(in_type) {
+            return Err(ParserError::InvalidType);
+        }
+
+        let count = self.array_count(&prop_info.property)?;
+        let (elements, _) = split_strings(in_type, &prop_info.buffer, count)?;
+        elements
+            .into_iter()
+            .map(|element| match in_type {
+                TdhInType::InTypeUnicodeString
+                | TdhInType::InTypeCountedString
+                | TdhInType::InTypeManifestCountedString => {
+                    Ok(utils::parse_null_utf16_string(element))
+                }
+                _ => Ok(String::from_utf8(element.to_vec())?),
+            })
+            .collect()
+    }
 
-        let guid_string = utils::parse_utf16_guid(prop_info.buffer.as_slice());
+    /// Use the `try_parse_array` function to parse the elements of an array property
+    ///
+    /// The number of elements is read from the property holding the count, or from the Schema for
+    /// fixed size arrays, then each element is parsed from its slice of the property data the same
+    /// way the `TryParse` impl of `T` parses a property
+    ///
+    /// # Arguments
+    /// * `name` - Name of the array property to be found in the Schema
+    ///
+    /// # Remarks
+    /// Only arrays of fixed size elements are supported, use [Parser::try_parse_string_array] for
+    /// arrays of strings. A [ParserError::LengthMismatch] is returned if the property data can't
+    /// be split into the elements
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let pids: Vec<u32> = parser.try_parse_array("ProcessIds")?;
+    /// };
+    /// ```
+    pub fn try_parse_array<T>(&mut self, name: &str) -> ParserResult<Vec<T>>
+    where
+        Self: TryParseProperty<T>,
+    {
+        let prop_info = self.find_property(name)?;
+        if is_string_type(prop_info.property.in_type()) {
+            return Err(ParserError::InvalidType);
+        }
 
-        if guid_string.len() != 36 {
+        let count = self.array_count(&prop_info.property)?;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        if prop_info.buffer.len() % count != 0 {
             return Err(ParserError::LengthMismatch);
         }
 
-        Ok(Guid::from(guid_string.as_str()))
+        // Each element is decoded from its own slice of the property data
+        let element = prop_info.property.element();
+        let element_size = prop_info.buffer.len() / count;
+        prop_info
+            .buffer
+            .chunks_exact(element_size.max(1))
+            .map(|data| {
+                self.try_parse_property(&PropertyInfo::create(element.clone(), data.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Use the `try_parse_all` function to parse every top level property of the Event
+    ///
+    /// The properties are returned in the order they are declared in the Schema alongside their
+    /// name. The [PropertyValue] is picked from the InType of the property, honoring the OutType
+    /// for timestamps stored as 64-bit integers
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     for (name, value) in parser.try_parse_all()? {
+    ///         println!("{}: {:?}", name, value);
+    ///     }
+    /// };
+    /// ```
+    pub fn try_parse_all(&mut self) -> ParserResult<Vec<(String, PropertyValue)>> {
+        let mut values = Vec::new();
+        for i in 0..self.schema.top_level_property_count() {
+            let name = match self.properties.property(i) {
+                Some(prop) => prop.name.clone(),
+                None => return Err(ParserError::PropertyError("Index out of bounds".to_owned())),
+            };
+            let value = self.property_value(&name)?;
+            values.push((name, value));
+        }
+
+        Ok(values)
     }
-}
 
-impl TryParse<IpAddr> for Parser<'_> {
-    fn try_parse(&mut self, name: &str) -> ParserResult<IpAddr> {
+    fn property_value(&mut self, name: &str) -> ParserResult<PropertyValue> {
         let prop_info = self.find_property(name)?;
-        let prop_info: &PropertyInfo = prop_info.borrow();
+        let property = &prop_info.property;
+        let buffer = prop_info.buffer.as_slice();
 
-        if prop_info.property.out_type() != TdhOutType::OutTypeIpv4
-            && prop_info.property.out_type() != TdhOutType::OutTypeIpv6
-        {
-            return Err(ParserError::InvalidType);
+        if property.in_type() == TdhInType::InTypeNull || buffer.is_empty() {
+            return Ok(PropertyValue::Null);
+        }
+        let time_size = match property.in_type() {
+            TdhInType::InTypeSystemTime => 16,
+            _ => 8,
+        };
+        if is_time_property(property) && buffer.len() == time_size {
+            return Ok(PropertyValue::Time(self.try_parse_time(name)?));
         }
 
-        // Hardcoded values for now
-        let res = match prop_info.property.len() {
-            16 => {
-                let tmp: [u8; 16] = prop_info.buffer.as_slice().try_into()?;
-                IpAddr::V6(Ipv6Addr::from(tmp))
+        let value = match (property.in_type(), buffer.len()) {
+            (TdhInType::InTypeInt8, 1) => PropertyValue::I8(self.try_parse(name)?),
+            (TdhInType::InTypeUInt8, 1) => PropertyValue::U8(self.try_parse(name)?),
+            (TdhInType::InTypeInt16, 2) => PropertyValue::I16(self.try_parse(name)?),
+            (TdhInType::InTypeUInt16, 2) => PropertyValue::U16(self.try_parse(name)?),
+            (TdhInType::InTypeInt32, 4) => PropertyValue::I32(self.try_parse(name)?),
+            (TdhInType::InTypeUInt32, 4) | (TdhInType::InTypeHexInt32, 4) => {
+                PropertyValue::U32(self.try_parse(name)?)
             }
-            4 => {
-                let tmp: [u8; 4] = prop_info.buffer.as_slice().try_into()?;
-                IpAddr::V4(Ipv4Addr::from(tmp))
+            (TdhInType::InTypeInt64, 8) => PropertyValue::I64(self.try_parse(name)?),
+            (TdhInType::InTypeUInt64, 8) | (TdhInType::InTypeHexInt64, 8) => {
+                PropertyValue::U64(self.try_parse(name)?)
             }
-            _ => return Err(ParserError::LengthMismatch),
+            (TdhInType::InTypeFloat, 4) => {
+                PropertyValue::F32(f32::from_ne_bytes(buffer.try_into()?))
+            }
+            (TdhInType::InTypeDouble, 8) => {
+                PropertyValue::F64(f64::from_ne_bytes(buffer.try_into()?))
+            }
+            (TdhInType::InTypeBoolean, 4) => {
+                PropertyValue::Bool(u32::from_ne_bytes(buffer.try_into()?) != 0)
+            }
+            (TdhInType::InTypePointer, _) => {
+                let pointer: Pointer = self.try_parse(name)?;
+                PropertyValue::Pointer(*pointer)
+            }
+            (TdhInType::InTypeSizeT, _) => PropertyValue::U64(self.try_parse_sizet(name)?),
+            (TdhInType::InTypeGuid, 16) => PropertyValue::Guid(guid_from_bytes(buffer)?),
+            (TdhInType::InTypeUnicodeString, _)
+            | (TdhInType::InTypeAnsiString, _)
+            | (TdhInType::InTypeManifestCountedString, _)
+            | (TdhInType::InTypeManifestCountedAnsiString, _)
+            | (TdhInType::InTypeCountedString, _)
+            | (TdhInType::InTypeCountedAnsiString, _)
+            | (TdhInType::InTypeSid, _)
+            | (TdhInType::InTypeWbemSid, _) => PropertyValue::String(self.try_parse(name)?),
+            (TdhInType::InTypeManifestCountedBinary, _) => {
+                PropertyValue::Binary(self.try_parse(name)?)
+            }
+            _ => PropertyValue::Binary(buffer.to_vec()),
         };
 
-        Ok(res)
+        Ok(value)
     }
-}
-
-#[derive(Clone, Default, Debug)]
-pub struct Pointer(usize);
 
-impl std::ops::Deref for Pointer {
-    type Target = usize;
+    /// Use the `try_parse_sizet` function to parse a `SIZE_T` property (`TDH_INTYPE_SIZET`)
+    ///
+    /// The property takes 4 or 8 bytes depending on the pointer size of the machine that logged
+    /// the Event, the value is zero-extended to a u64 in both cases
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let region_size = parser.try_parse_sizet("RegionSize")?;
+    /// };
+    /// ```
+    pub fn try_parse_sizet(&mut self, name: &str) -> ParserResult<u64> {
+        let prop_info = self.find_property(name)?;
+        let buffer = prop_info.buffer.as_slice();
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+        match buffer.len() {
+            4 => Ok(u64::from(u32::from_ne_bytes(buffer.try_into()?))),
+            8 => Ok(u64::from_ne_bytes(buffer.try_into()?)),
+            _ => Err(ParserError::LengthMismatch),
+        }
     }
-}
 
-impl std::ops::DerefMut for Pointer {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
+    /// Use the `try_parse_bytes` function to retrieve the raw data of a property
+    ///
+    /// The data is returned as laid out in the Event, with the length found for the property and
+    /// no interpretation of its InType. Unlike the `Vec<u8>` impl of the `TryParse` trait the length
+    /// prefix of counted types is kept, this is meant for InTypes the crate doesn't understand yet
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let raw = parser.try_parse_bytes("ExtendedInfo")?;
+    /// };
+    /// ```
+    pub fn try_parse_bytes(&mut self, name: &str) -> ParserResult<Vec<u8>> {
+        let prop_info = self.find_property(name)?;
+        Ok(prop_info.buffer.clone())
+    }
+
+    /// Use the `try_parse_map` function to parse a property translated by a value map
+    ///
+    /// The value is translated with the map of the property (`TdhGetEventMapInformation`). Values
+    /// of a bitmap are decomposed into the names of every flag set, joined by ` | `, the bits
+    /// without a name are appended in hex, e.g. `Read | Write | 0x8`. Values without a name are
+    /// returned as a number
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Remarks
+    /// Fails with [ParserError::InvalidType] if the property has no map
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let access = parser.try_parse_map("DesiredAccess")?;
+    /// };
+    /// ```
+    pub fn try_parse_map(&mut self, name: &str) -> ParserResult<String> {
+        let prop_info = self.find_property(name)?;
+        let map_name = match prop_info.property.map_name() {
+            Some(map_name) => map_name.to_owned(),
+            None => return Err(ParserError::InvalidType),
+        };
+        let key = map_key(&prop_info.buffer).ok_or(ParserError::LengthMismatch)?;
+
+        let map_info = self.map_info(&map_name)?;
+        Ok(map_info.resolve(key).unwrap_or_else(|| key.to_string()))
+    }
+
+    /// Use the `try_parse_optional` function to parse a property that might not be present
+    ///
+    /// Some events declare properties that are only conditionally present, those are stored with a
+    /// length of 0 (or a `TDH_INTYPE_NULL` type). This function returns `None` for such properties
+    /// instead of failing, otherwise it behaves like [TryParse::try_parse]
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let parent_id: Option<u32> = parser.try_parse_optional("ParentProcessID")?;
+    /// };
+    /// ```
+    pub fn try_parse_optional<T>(&mut self, name: &str) -> ParserResult<Option<T>>
+    where
+        Self: TryParse<T>,
+    {
+        let prop_info = self.find_property(name)?;
+        if prop_info.buffer.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.try_parse(name)?))
+    }
+}
+
+macro_rules! impl_try_parse_primitive {
+    ($T:ident) => {
+        impl TryParseProperty<$T> for Parser<'_> {
+            fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<$T> {
+                // TODO: Check In and Out type and do a better type checking
+                if std::mem::size_of::<$T>() != prop_info.buffer.len() {
+                    return Err(ParserError::LengthMismatch);
+                }
+                Ok($T::from_ne_bytes(prop_info.buffer.as_slice().try_into()?))
+            }
+        }
+
+        impl_try_parse!($T);
+    };
+}
+
+impl_try_parse_primitive!(u8);
+impl_try_parse_primitive!(i8);
+impl_try_parse_primitive!(u16);
+impl_try_parse_primitive!(i16);
+impl_try_parse_primitive!(u32);
+impl_try_parse_primitive!(i32);
+impl_try_parse_primitive!(u64);
+impl_try_parse_primitive!(i64);
+impl_try_parse_primitive!(usize);
+impl_try_parse_primitive!(isize);
+
+impl_try_parse!(
+    /// The `String` impl of the `TryParse` trait should be used to retrieve the following [TdhInTypes]:
+    ///
+    /// * InTypeNull (an empty `String` is returned)
+    /// * InTypeUnicodeString
+    /// * InTypeAnsiString
+    /// * InTypeCountedString and InTypeManifestCountedString
+    /// * InTypeCountedAnsiString and InTypeManifestCountedAnsiString
+    /// * InTypeGuid
+    ///
+    /// On success a `String` with the with the data from the `name` property will be returned
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let parser = Parse::create(&schema);
+    ///     let image_name: String = parser.try_parse("ImageName")?;
+    /// };
+    /// ```
+    ///
+    /// [TdhInTypes]: TdhInType
+    String
+);
+
+impl TryParseProperty<String> for Parser<'_> {
+    fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<String> {
+        // TODO: Handle errors and type checking better
+        let res = match prop_info.property.in_type() {
+            TdhInType::InTypeNull => String::new(),
+            TdhInType::InTypeUnicodeString => {
+                utils::parse_null_utf16_string(prop_info.buffer.as_slice())
+            }
+            TdhInType::InTypeAnsiString => String::from_utf8(prop_info.buffer.clone())?
+                .trim_matches(char::default())
+                .to_string(),
+            TdhInType::InTypeSid | TdhInType::InTypeWbemSid => {
+                let sid = sid_data(
+                    prop_info.property.in_type(),
+                    &prop_info.buffer,
+                    self.schema.pointer_size(),
+                )?;
+                // Never hand a truncated SID to ConvertSidToStringSid
+                sid_length(sid)?;
+                sddl::convert_sid_to_string(sid.as_ptr() as isize)?
+            }
+            TdhInType::InTypeCountedString | TdhInType::InTypeManifestCountedString => {
+                utils::parse_null_utf16_string(counted_data(&prop_info.buffer))
+            }
+            TdhInType::InTypeCountedAnsiString | TdhInType::InTypeManifestCountedAnsiString => {
+                String::from_utf8(counted_data(&prop_info.buffer).to_vec())?
+                    .trim_matches(char::default())
+                    .to_string()
+            }
+            in_type => {
+                return Err(ParserError::TypeMismatch {
+                    expected: "String",
+                    found: in_type,
+                })
+            }
+        };
+
+        Ok(res)
+    }
+}
+
+// GUIDs are logged in their binary form, the GUID struct layout
+fn guid_from_bytes(buffer: &[u8]) -> ParserResult<Guid> {
+    if buffer.len() != std::mem::size_of::<Guid>() {
+        return Err(ParserError::LengthMismatch);
+    }
+    Ok(unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const Guid) })
+}
+
+impl_try_parse!(
+    /// The `Guid` impl of the `TryParse` trait should be used to retrieve the following [TdhInTypes]:
+    ///
+    /// * InTypeGuid, the 16 bytes GUID structure
+    /// * InTypeUnicodeString holding the string form of a GUID, with or without curly braces
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Remarks
+    /// A [ParserError::LengthMismatch] is returned if the GUID structure isn't 16 bytes long and a
+    /// [ParserError::TypeMismatch] if the string isn't a GUID or for any other InType
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let activity_id: Guid = parser.try_parse("ActivityId")?;
+    /// };
+    /// ```
+    ///
+    /// [TdhInTypes]: TdhInType
+    Guid
+);
+
+impl TryParseProperty<Guid> for Parser<'_> {
+    fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<Guid> {
+        match prop_info.property.in_type() {
+            TdhInType::InTypeGuid => guid_from_bytes(&prop_info.buffer),
+            TdhInType::InTypeUnicodeString => {
+                let guid_string = utils::parse_utf16_guid(prop_info.buffer.as_slice());
+                if !utils::is_guid_str(&guid_string) {
+                    return Err(ParserError::TypeMismatch {
+                        expected: "Guid",
+                        found: TdhInType::InTypeUnicodeString,
+                    });
+                }
+                Ok(Guid::from(guid_string.as_str()))
+            }
+            in_type => Err(ParserError::TypeMismatch {
+                expected: "Guid",
+                found: in_type,
+            }),
+        }
+    }
+}
+
+impl_try_parse!(
+    /// The `IpAddr` impl of the `TryParse` trait should be used to retrieve the properties with the
+    /// following [TdhOutTypes]:
+    ///
+    /// * OutTypeIpv4, 4 bytes in network order, usually logged as an InTypeUInt32
+    /// * OutTypeIpv6, 16 bytes in network order, usually logged as an InTypeBinary
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Remarks
+    /// A [ParserError::TypeMismatch] is returned for any other OutType and a
+    /// [ParserError::LengthMismatch] if the property length doesn't match its address family
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let source: IpAddr = parser.try_parse("saddr")?;
+    /// };
+    /// ```
+    ///
+    /// [TdhOutTypes]: TdhOutType
+    IpAddr
+);
+
+impl TryParseProperty<IpAddr> for Parser<'_> {
+    fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<IpAddr> {
+        let buffer = prop_info.buffer.as_slice();
+
+        let res = match prop_info.property.out_type() {
+            TdhOutType::OutTypeIpv4 => {
+                let tmp: [u8; 4] = buffer.try_into().map_err(|_| ParserError::LengthMismatch)?;
+                IpAddr::V4(Ipv4Addr::from(tmp))
+            }
+            TdhOutType::OutTypeIpv6 => {
+                let tmp: [u8; 16] = buffer.try_into().map_err(|_| ParserError::LengthMismatch)?;
+                IpAddr::V6(Ipv6Addr::from(tmp))
+            }
+            _ => {
+                return Err(ParserError::TypeMismatch {
+                    expected: "IpAddr",
+                    found: prop_info.property.in_type(),
+                })
+            }
+        };
+
+        Ok(res)
+    }
+}
+
+/// Value of an InTypePointer property, widened to `usize`
+///
+/// The property is 4 or 8 bytes long depending on the machine that logged the Event, which is
+/// given by the `EVENT_HEADER_FLAG_32_BIT_HEADER` flag of the header
+#[derive(Clone, Default, Debug)]
+pub struct Pointer(usize);
+
+impl std::ops::Deref for Pointer {
+    type Target = usize;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Pointer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
 
 impl std::fmt::LowerHex for Pointer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -376,30 +1158,1052 @@ impl std::fmt::Display for Pointer {
     }
 }
 
-impl TryParse<Pointer> for Parser<'_> {
-    fn try_parse(&mut self, name: &str) -> ParserResult<Pointer> {
-        let prop_info = self.find_property(name)?;
-        let prop_info: &PropertyInfo = prop_info.borrow();
+impl_try_parse!(
+    /// The `Pointer` impl of the `TryParse` trait reads the 4 or 8 bytes of an InTypePointer
+    /// property, see [Pointer]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let base: Pointer = parser.try_parse("ImageBase")?;
+    ///     println!("{:#x}", base);
+    /// };
+    /// ```
+    Pointer
+);
 
+impl TryParseProperty<Pointer> for Parser<'_> {
+    fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<Pointer> {
         let mut res = Pointer::default();
         if prop_info.buffer.len() == std::mem::size_of::<u32>() {
-            res.0 = TryParse::<u32>::try_parse(self, name)? as usize;
+            res.0 = TryParseProperty::<u32>::try_parse_property(self, prop_info)? as usize;
         } else {
-            res.0 = TryParse::<u64>::try_parse(self, name)? as usize;
+            res.0 = TryParseProperty::<u64>::try_parse_property(self, prop_info)? as usize;
         }
 
         Ok(res)
     }
 }
 
-impl TryParse<Vec<u8>> for Parser<'_> {
-    fn try_parse(&mut self, name: &str) -> Result<Vec<u8>, ParserError> {
-        let prop_info = self.find_property(name)?;
-        let prop_info: &PropertyInfo = prop_info.borrow();
+impl_try_parse!(
+    /// The `SystemTime` impl of the `TryParse` trait should be used to retrieve the following
+    /// [TdhInTypes]:
+    ///
+    /// * InTypeFileTime, 100ns intervals since January 1, 1601 (UTC)
+    /// * InTypeSystemTime, a SYSTEMTIME struct converted field by field
+    /// * InTypeInt64 and InTypeUInt64 with a `DateTime` OutType, interpreted as a FILETIME
+    ///
+    /// # Arguments
+    /// * `name` - Name of the property to be found in the Schema
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     let last_write: SystemTime = parser.try_parse("LastWriteTime")?;
+    /// };
+    /// ```
+    ///
+    /// [TdhInTypes]: TdhInType
+    SystemTime
+);
+
+impl TryParseProperty<SystemTime> for Parser<'_> {
+    fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<SystemTime> {
+        if !is_time_property(&prop_info.property) {
+            return Err(ParserError::TypeMismatch {
+                expected: "SystemTime",
+                found: prop_info.property.in_type(),
+            });
+        }
+
+        if prop_info.property.in_type() == TdhInType::InTypeSystemTime {
+            if prop_info.buffer.len() != 16 {
+                return Err(ParserError::LengthMismatch);
+            }
+            let mut fields = [0u16; 8];
+            for (field, bytes) in fields.iter_mut().zip(prop_info.buffer.chunks_exact(2)) {
+                *field = u16::from_ne_bytes([bytes[0], bytes[1]]);
+            }
+            return utils::systemtime_to_system_time(fields).ok_or(ParserError::ParseError);
+        }
+
+        let filetime: u64 = self.try_parse_property(prop_info)?;
+        Ok(utils::filetime_to_system_time(filetime))
+    }
+}
 
+impl_try_parse!(
+    /// The `Vec<u8>` impl of the `TryParse` trait returns the raw data of any property, the length
+    /// prefix of the counted types (e.g. InTypeManifestCountedBinary) is not included
+    Vec<u8>
+);
+
+impl TryParseProperty<Vec<u8>> for Parser<'_> {
+    fn try_parse_property(&mut self, prop_info: &PropertyInfo) -> ParserResult<Vec<u8>> {
+        if prop_info.property.in_type().is_counted() {
+            return Ok(counted_data(&prop_info.buffer).to_vec());
+        }
         Ok(prop_info.buffer.clone())
     }
 }
 
+fn is_string_type(in_type: TdhInType) -> bool {
+    matches!(
+        in_type,
+        TdhInType::InTypeUnicodeString
+            | TdhInType::InTypeAnsiString
+            | TdhInType::InTypeCountedString
+            | TdhInType::InTypeCountedAnsiString
+            | TdhInType::InTypeManifestCountedString
+            | TdhInType::InTypeManifestCountedAnsiString
+    )
+}
+
+// Splits `count` consecutive strings, returns the data of each one, without terminator nor length
+// prefix, and the number of bytes taken by all of them
+fn split_strings(
+    in_type: TdhInType,
+    buffer: &[u8],
+    count: usize,
+) -> ParserResult<(Vec<&[u8]>, usize)> {
+    let out_of_bounds =
+        || ParserError::PropertyError("String array out of buffer bounds".to_owned());
+
+    let mut elements = Vec::with_capacity(count);
+    let mut offset = 0;
+    for _ in 0..count {
+        let remaining = &buffer[offset..];
+        let (element, size) = match in_type {
+            TdhInType::InTypeUnicodeString => {
+                let end = remaining
+                    .chunks_exact(2)
+                    .position(|c| c == [0, 0])
+                    .ok_or_else(out_of_bounds)?
+                    * 2;
+                (&remaining[..end], end + 2)
+            }
+            TdhInType::InTypeAnsiString => {
+                let end = remaining
+                    .iter()
+                    .position(|c| *c == 0)
+                    .ok_or_else(out_of_bounds)?;
+                (&remaining[..end], end + 1)
+            }
+            _ => {
+                let prefix = remaining.get(..2).ok_or_else(out_of_bounds)?;
+                let size = 2 + usize::from(u16::from_ne_bytes([prefix[0], prefix[1]]));
+                (remaining.get(2..size).ok_or_else(out_of_bounds)?, size)
+            }
+        };
+        elements.push(element);
+        offset += size;
+    }
+
+    Ok((elements, offset))
+}
+
+// Data of a counted property, past its 2 byte length prefix
+fn counted_data(buffer: &[u8]) -> &[u8] {
+    buffer.get(2..).unwrap_or_default()
+}
+
+fn is_sid_type(in_type: TdhInType) -> bool {
+    matches!(in_type, TdhInType::InTypeSid | TdhInType::InTypeWbemSid)
+}
+
+// A WBEMSID is a SID preceded by a TOKEN_USER, two pointers of the machine that logged the event
+fn sid_data(in_type: TdhInType, buffer: &[u8], pointer_size: u32) -> ParserResult<&[u8]> {
+    let token_user_size = match in_type {
+        TdhInType::InTypeWbemSid => 2 * pointer_size as usize,
+        _ => 0,
+    };
+    buffer
+        .get(token_user_size..)
+        .ok_or(ParserError::LengthMismatch)
+}
+
+// Revision, SubAuthorityCount and the 6 bytes IdentifierAuthority, then 4 bytes per SubAuthority
+fn sid_length(sid: &[u8]) -> ParserResult<usize> {
+    let length = match sid.get(1) {
+        Some(sub_authority_count) => 8 + 4 * usize::from(*sub_authority_count),
+        None => return Err(ParserError::LengthMismatch),
+    };
+    if length > sid.len() {
+        return Err(ParserError::LengthMismatch);
+    }
+    Ok(length)
+}
+
 // TODO: Implement SocketAddress
 // TODO: Study if we can use primitive types for HexInt64, HexInt32 and Pointer
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::native::test_utils::{test_map, TestEvent, TestProperty, TestSchema};
+
+    const PROVIDER_GUID: &str = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+
+    fn u32_prop(name: &str) -> TestProperty {
+        TestProperty::new(name, TdhInType::InTypeUInt32 as u16, 4)
+    }
+
+    fn sizet_event(test_schema: &TestSchema, pointer_size: usize) -> TestEvent {
+        let mut data = if pointer_size == 4 {
+            0x1000u32.to_ne_bytes().to_vec()
+        } else {
+            0x1_0000_1000u64.to_ne_bytes().to_vec()
+        };
+        data.extend_from_slice(&7u32.to_ne_bytes());
+        let mut event = TestEvent::new(test_schema, data);
+        if pointer_size == 4 {
+            event.record.EventHeader.Flags |=
+                crate::native::etw_types::EVENT_HEADER_FLAG_32_BIT_HEADER;
+        }
+        event
+    }
+
+    #[test]
+    fn test_try_parse_errors() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(u32_prop("ProcessID"))
+            .property(TestProperty::new(
+                "Flags",
+                TdhInType::InTypeUInt16 as u16,
+                2,
+            ));
+        let mut data = 1234u32.to_ne_bytes().to_vec();
+        data.extend_from_slice(&7u16.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let missing: ParserResult<u32> = parser.try_parse("QueryName");
+        assert!(matches!(missing, Err(ParserError::PropertyNotFound(name)) if name == "QueryName"));
+        let wrong_width: ParserResult<u32> = parser.try_parse("Flags");
+        assert!(matches!(wrong_width, Err(ParserError::LengthMismatch)));
+        let wrong_type: ParserResult<String> = parser.try_parse("ProcessID");
+        assert!(matches!(
+            wrong_type,
+            Err(ParserError::TypeMismatch {
+                expected: "String",
+                found: TdhInType::InTypeUInt32
+            })
+        ));
+
+        let tdh_error = ParserError::from(tdh::TdhNativeError::IoError(
+            std::io::Error::from_raw_os_error(1168),
+        ));
+        assert!(matches!(tdh_error, ParserError::TdhError(1168)));
+
+        // The properties found while looking for the missing one are still readable
+        let pid: u32 = parser.try_parse("ProcessID").unwrap();
+        let flags: u16 = parser.try_parse("Flags").unwrap();
+        assert_eq!((pid, flags), (1234, 7));
+    }
+
+    #[test]
+    fn test_try_parse_guid() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "ActivityId",
+                TdhInType::InTypeGuid as u16,
+                16,
+            ))
+            .property(TestProperty::new(
+                "Truncated",
+                TdhInType::InTypeGuid as u16,
+                8,
+            ));
+        // 22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716, Data1 to Data3 are little endian
+        let mut data = vec![
+            0xd6, 0x2c, 0xfb, 0x22, 0x7b, 0x0e, 0x2b, 0x42, 0xa0, 0xc7, 0x2f, 0xad, 0x1f, 0xd0,
+            0xe7, 0x16,
+        ];
+        data.extend_from_slice(&[0; 8]);
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let activity_id: Guid = parser.try_parse("ActivityId").unwrap();
+        assert_eq!(activity_id, Guid::from(PROVIDER_GUID));
+        let truncated: ParserResult<Guid> = parser.try_parse("Truncated");
+        assert!(matches!(truncated, Err(ParserError::LengthMismatch)));
+    }
+
+    #[test]
+    fn test_try_parse_guid_string() {
+        // Fixed length, the null terminated strings are otherwise sized by TDH
+        let string_prop =
+            |name, len| TestProperty::new(name, TdhInType::InTypeUnicodeString as u16, len);
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(string_prop("Braces", 78))
+            .property(string_prop("Malformed", 74))
+            .property(u32_prop("Value"));
+        let mut data = utf16z("{22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716}");
+        data.extend_from_slice(&utf16z("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e71z"));
+        data.extend_from_slice(&0x1993u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let braces: Guid = parser.try_parse("Braces").unwrap();
+        assert_eq!(braces, Guid::from(PROVIDER_GUID));
+        let malformed: ParserResult<Guid> = parser.try_parse("Malformed");
+        assert!(matches!(
+            malformed,
+            Err(ParserError::TypeMismatch {
+                expected: "Guid",
+                found: TdhInType::InTypeUnicodeString
+            })
+        ));
+        let value: ParserResult<Guid> = parser.try_parse("Value");
+        assert!(matches!(
+            value,
+            Err(ParserError::TypeMismatch {
+                found: TdhInType::InTypeUInt32,
+                ..
+            })
+        ));
+    }
+
+    // S-1-5-18 (LocalSystem)
+    const LOCAL_SYSTEM_SID: [u8; 12] = [1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+
+    fn sid_event(test_schema: &TestSchema) -> TestEvent {
+        let mut data = LOCAL_SYSTEM_SID.to_vec();
+        data.extend_from_slice(&1u32.to_ne_bytes());
+        // TOKEN_USER of a 64-bit machine, the pointer values are meaningless
+        data.extend_from_slice(&[0xaa; 16]);
+        data.extend_from_slice(&LOCAL_SYSTEM_SID);
+        data.extend_from_slice(&2u32.to_ne_bytes());
+        TestEvent::new(test_schema, data)
+    }
+
+    fn sid_schema() -> TestSchema {
+        TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new("UserSid", TdhInType::InTypeSid as u16, 0))
+            .property(u32_prop("First"))
+            .property(TestProperty::new(
+                "WbemSid",
+                TdhInType::InTypeWbemSid as u16,
+                0,
+            ))
+            .property(u32_prop("Second"))
+    }
+
+    #[test]
+    fn test_sid_property_size() {
+        let test_schema = sid_schema();
+        let event = sid_event(&test_schema);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let second: u32 = parser.try_parse("Second").unwrap();
+        let first: u32 = parser.try_parse("First").unwrap();
+        assert_eq!((first, second), (1, 2));
+
+        assert_eq!(sid_length(&LOCAL_SYSTEM_SID).unwrap(), 12);
+        assert!(matches!(
+            sid_length(&LOCAL_SYSTEM_SID[..11]),
+            Err(ParserError::LengthMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_try_parse_sid() {
+        let test_schema = sid_schema();
+        let event = sid_event(&test_schema);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let user_sid: String = parser.try_parse("UserSid").unwrap();
+        let wbem_sid: String = parser.try_parse("WbemSid").unwrap();
+        assert_eq!(user_sid, "S-1-5-18");
+        assert_eq!(wbem_sid, "S-1-5-18");
+    }
+
+    #[test]
+    fn test_try_parse_ip_addr() {
+        let mut saddr = TestProperty::new("saddr", TdhInType::InTypeUInt32 as u16, 4);
+        saddr.out_type = TdhOutType::OutTypeIpv4 as u16;
+        let mut daddr = TestProperty::new("daddr", TdhInType::InTypeBinary as u16, 16);
+        daddr.out_type = TdhOutType::OutTypeIpv6 as u16;
+        let mut truncated = TestProperty::new("truncated", TdhInType::InTypeBinary as u16, 8);
+        truncated.out_type = TdhOutType::OutTypeIpv6 as u16;
+        let test_schema = TestSchema::new(PROVIDER_GUID, 10)
+            .property(saddr)
+            .property(daddr)
+            .property(truncated)
+            .property(u32_prop("size"));
+
+        let mut data = vec![192, 168, 1, 20];
+        data.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&1500u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let saddr: IpAddr = parser.try_parse("saddr").unwrap();
+        assert_eq!(saddr, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)));
+        let daddr: IpAddr = parser.try_parse("daddr").unwrap();
+        assert_eq!(daddr, "2001:db8::1".parse::<IpAddr>().unwrap());
+        let truncated: ParserResult<IpAddr> = parser.try_parse("truncated");
+        assert!(matches!(truncated, Err(ParserError::LengthMismatch)));
+        let size: ParserResult<IpAddr> = parser.try_parse("size");
+        assert!(matches!(
+            size,
+            Err(ParserError::TypeMismatch {
+                expected: "IpAddr",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_try_parse_pointer_width() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "ImageBase",
+                TdhInType::InTypePointer as u16,
+                0,
+            ))
+            .property(u32_prop("Trailing"));
+
+        for (pointer_size, expected) in [(4, 0x1000), (8, 0x1_0000_1000)] {
+            let event = sizet_event(&test_schema, pointer_size);
+            let schema = event.schema(&test_schema);
+            let mut parser = Parser::create(&schema);
+            let base: Pointer = parser.try_parse("ImageBase").unwrap();
+            assert_eq!(*base, expected);
+            let trailing: u32 = parser.try_parse("Trailing").unwrap();
+            assert_eq!(trailing, 7);
+        }
+    }
+
+    #[test]
+    fn test_try_parse_sizet() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "RegionSize",
+                TdhInType::InTypeSizeT as u16,
+                0,
+            ))
+            .property(u32_prop("Trailing"));
+
+        let event = sizet_event(&test_schema, 4);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+        assert_eq!(parser.try_parse_sizet("RegionSize").unwrap(), 0x1000);
+        let trailing: u32 = parser.try_parse("Trailing").unwrap();
+        assert_eq!(trailing, 7);
+
+        let event = sizet_event(&test_schema, 8);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+        assert_eq!(
+            parser.try_parse_all().unwrap(),
+            vec![
+                (
+                    String::from("RegionSize"),
+                    PropertyValue::U64(0x1_0000_1000)
+                ),
+                (String::from("Trailing"), PropertyValue::U32(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cursor_decodes_lazily() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(u32_prop("First"))
+            .property(u32_prop("Second"))
+            .property(u32_prop("Third"))
+            .property(u32_prop("Fourth"));
+        let data: Vec<u8> = (1u32..=4).flat_map(|i| i.to_ne_bytes().to_vec()).collect();
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut cursor = schema.cursor();
+        for expected in 1u32..=2 {
+            let (info, value) = cursor.next_property().unwrap().unwrap();
+            assert_eq!(info.buffer, expected.to_ne_bytes());
+            assert_eq!(value().unwrap(), PropertyValue::U32(expected));
+        }
+
+        // The remaining properties haven't been read
+        assert_eq!(cursor.offset(), 8);
+        assert!(!cursor.parser.cache.contains_key("Third"));
+        assert_eq!(cursor.parser.buffer[..4], 3u32.to_ne_bytes());
+
+        // Skipping a property without decoding it still moves the cursor past it
+        let (info, _) = cursor.next_property().unwrap().unwrap();
+        assert_eq!(info.property.name, "Third");
+        assert_eq!(cursor.offset(), 12);
+        let (_, value) = cursor.next_property().unwrap().unwrap();
+        assert_eq!(value().unwrap(), PropertyValue::U32(4));
+        assert!(cursor.next_property().is_none());
+    }
+
+    #[test]
+    fn test_zero_length_property_between_properties() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(u32_prop("First"))
+            .property(TestProperty::new(
+                "Optional",
+                TdhInType::InTypeNull as u16,
+                0,
+            ))
+            .property(u32_prop("Last"));
+        let mut data = 1u32.to_ne_bytes().to_vec();
+        data.extend_from_slice(&2u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let first: u32 = parser.try_parse("First").unwrap();
+        let optional: Option<u32> = parser.try_parse_optional("Optional").unwrap();
+        let optional_string: String = parser.try_parse("Optional").unwrap();
+        let last: u32 = parser.try_parse("Last").unwrap();
+
+        assert_eq!(first, 1);
+        assert_eq!(optional, None);
+        assert_eq!(optional_string, "");
+        assert_eq!(last, 2);
+    }
+
+    fn classic_event(test_schema: &TestSchema, header: bool) -> TestEvent {
+        use crate::native::etw_types::{EVENT_HEADER_FLAG_CLASSIC_HEADER, EVENT_TRACE_HEADER_SIZE};
+
+        let mut data = Vec::new();
+        if header {
+            let size = (EVENT_TRACE_HEADER_SIZE + 8) as u16;
+            data.extend_from_slice(&size.to_ne_bytes());
+            data.extend_from_slice(&[0, 0, test_schema.opcode, 0, 0, 0]);
+            data.extend_from_slice(&[0; 16]); // ThreadId, ProcessId and TimeStamp
+            data.extend_from_slice(&crate::utils::guid_bytes(&test_schema.provider_guid));
+            data.extend_from_slice(&[0; 8]);
+        }
+        data.extend_from_slice(&0x1993u32.to_ne_bytes());
+        data.extend_from_slice(&7u32.to_ne_bytes());
+
+        let mut event = TestEvent::new(test_schema, data);
+        event.record.EventHeader.Flags = EVENT_HEADER_FLAG_CLASSIC_HEADER;
+        event
+    }
+
+    #[test]
+    fn test_classic_header_is_skipped() {
+        let mut test_schema = TestSchema::new(PROVIDER_GUID, 0)
+            .property(u32_prop("ProcessId"))
+            .property(u32_prop("Count"));
+        test_schema.opcode = 10;
+
+        for header in [true, false].iter() {
+            let event = classic_event(&test_schema, *header);
+            let schema = event.schema(&test_schema);
+
+            let mut parser = Parser::create(&schema);
+            let process_id: u32 = parser.try_parse("ProcessId").unwrap();
+            let count: u32 = parser.try_parse("Count").unwrap();
+
+            assert_eq!(process_id, 0x1993);
+            assert_eq!(count, 7);
+        }
+    }
+
+    fn counted(data: &[u8]) -> Vec<u8> {
+        let mut counted = (data.len() as u16).to_ne_bytes().to_vec();
+        counted.extend_from_slice(data);
+        counted
+    }
+
+    #[test]
+    fn test_counted_string_length_prefix() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "Name",
+                TdhInType::InTypeManifestCountedString as u16,
+                0,
+            ))
+            .property(TestProperty::new(
+                "Module",
+                TdhInType::InTypeCountedAnsiString as u16,
+                0,
+            ))
+            .property(u32_prop("Last"));
+        let name: Vec<u8> = "svchost"
+            .encode_utf16()
+            .flat_map(|c| c.to_ne_bytes().to_vec())
+            .collect();
+        let mut data = counted(&name);
+        data.extend_from_slice(&counted(b"ntdll.dll"));
+        data.extend_from_slice(&0x1993u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let name: String = parser.try_parse("Name").unwrap();
+        let module: String = parser.try_parse("Module").unwrap();
+        let last: u32 = parser.try_parse("Last").unwrap();
+
+        assert_eq!(name, "svchost");
+        assert_eq!(module, "ntdll.dll");
+        assert_eq!(last, 0x1993);
+    }
+
+    #[test]
+    fn test_param_length_string() {
+        let mut name = TestProperty::new("Name", TdhInType::InTypeUnicodeString as u16, 0);
+        // Index of the property holding the length, in characters
+        name.flags = PropertyFlags::PROPERTY_PARAM_LENGTH.bits();
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "NameLength",
+                TdhInType::InTypeUInt16 as u16,
+                2,
+            ))
+            .property(name)
+            .property(u32_prop("Last"));
+        let mut data = 3u16.to_ne_bytes().to_vec();
+        "abc"
+            .encode_utf16()
+            .for_each(|c| data.extend_from_slice(&c.to_ne_bytes()));
+        data.extend_from_slice(&0x1993u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let name: String = parser.try_parse("Name").unwrap();
+        let last: u32 = parser.try_parse("Last").unwrap();
+        let name_info = parser.find_property("Name").unwrap();
+
+        assert_eq!(name, "abc");
+        assert_eq!(last, 0x1993);
+        assert_eq!(parser.property_length(&name_info.property).unwrap(), 3);
+        assert_eq!(parser.element_count(&name_info.property).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_try_parse_bytes() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "Payload",
+                TdhInType::InTypeBinary as u16,
+                6,
+            ))
+            .property(TestProperty::new(
+                "Blob",
+                TdhInType::InTypeManifestCountedBinary as u16,
+                0,
+            ))
+            .property(u32_prop("Last"));
+        let mut data = vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+        data.extend_from_slice(&counted(&[0xde, 0xad]));
+        data.extend_from_slice(&7u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        assert_eq!(
+            parser.try_parse_bytes("Payload").unwrap(),
+            vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06]
+        );
+        // The length prefix of counted types is part of the raw data
+        assert_eq!(
+            parser.try_parse_bytes("Blob").unwrap(),
+            counted(&[0xde, 0xad])
+        );
+        assert_eq!(parser.try_parse_bytes("Last").unwrap(), 7u32.to_ne_bytes());
+        assert!(matches!(
+            parser.try_parse_bytes("Missing"),
+            Err(ParserError::PropertyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_counted_binary_length_prefix() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "Blob",
+                TdhInType::InTypeManifestCountedBinary as u16,
+                0,
+            ))
+            .property(TestProperty::new(
+                "Empty",
+                TdhInType::InTypeManifestCountedBinary as u16,
+                0,
+            ))
+            .property(u32_prop("Last"));
+        let mut data = counted(&[0xde, 0xad, 0xbe]);
+        data.extend_from_slice(&counted(&[]));
+        data.extend_from_slice(&7u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let blob: Vec<u8> = parser.try_parse("Blob").unwrap();
+        let empty: Vec<u8> = parser.try_parse("Empty").unwrap();
+        let last: u32 = parser.try_parse("Last").unwrap();
+
+        assert_eq!(blob, vec![0xde, 0xad, 0xbe]);
+        assert!(empty.is_empty());
+        assert_eq!(last, 7);
+
+        let mut parser = Parser::create(&schema);
+        let values = parser.try_parse_all().unwrap();
+        assert_eq!(values[0].1, PropertyValue::Binary(vec![0xde, 0xad, 0xbe]));
+    }
+
+    fn utf16z(value: &str) -> Vec<u8> {
+        value
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(|c| c.to_ne_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_try_parse_string_array() {
+        let mut modules = TestProperty::new("Modules", TdhInType::InTypeUnicodeString as u16, 0);
+        modules.flags = PropertyFlags::PROPERTY_PARAM_COUNT.bits();
+        modules.count = 0; // Index of ModuleCount
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(u32_prop("ModuleCount"))
+            .property(modules)
+            .property(u32_prop("Last"));
+        let mut data = 3u32.to_ne_bytes().to_vec();
+        for module in ["ntdll.dll", "", "kernel32.dll"].iter() {
+            data.extend_from_slice(&utf16z(module));
+        }
+        data.extend_from_slice(&0x1993u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let modules = parser.try_parse_string_array("Modules").unwrap();
+        let last: u32 = parser.try_parse("Last").unwrap();
+
+        assert_eq!(modules, vec!["ntdll.dll", "", "kernel32.dll"]);
+        assert_eq!(last, 0x1993);
+    }
+
+    #[test]
+    fn test_try_parse_array() {
+        let mut fixed = u32_prop("FixedPids");
+        fixed.flags = PropertyFlags::PROPERTY_PARAM_FIXED_COUNT.bits();
+        fixed.count = 3;
+        let mut handles = TestProperty::new("Handles", TdhInType::InTypePointer as u16, 0);
+        handles.flags = PropertyFlags::PROPERTY_PARAM_COUNT.bits();
+        handles.count = 1; // Index of HandleCount
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(fixed)
+            .property(u32_prop("HandleCount"))
+            .property(handles)
+            .property(u32_prop("Last"));
+
+        let mut data = Vec::new();
+        for pid in [4u32, 88, 1234].iter() {
+            data.extend_from_slice(&pid.to_ne_bytes());
+        }
+        data.extend_from_slice(&2u32.to_ne_bytes());
+        data.extend_from_slice(&0x1a4u64.to_ne_bytes());
+        data.extend_from_slice(&0x2b8u64.to_ne_bytes());
+        data.extend_from_slice(&0x1993u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let last: u32 = parser.try_parse("Last").unwrap();
+        let pids: Vec<u32> = parser.try_parse_array("FixedPids").unwrap();
+        let handles: Vec<Pointer> = parser.try_parse_array("Handles").unwrap();
+
+        assert_eq!(last, 0x1993);
+        assert_eq!(pids, vec![4, 88, 1234]);
+        assert_eq!(
+            handles
+                .iter()
+                .map(|handle| **handle)
+                .collect::<Vec<usize>>(),
+            vec![0x1a4, 0x2b8]
+        );
+        let mismatch: ParserResult<Vec<u64>> = parser.try_parse_array("FixedPids");
+        assert!(matches!(mismatch, Err(ParserError::LengthMismatch)));
+        // Only the properties of the Schema are cached, not the elements
+        assert!(parser.cache.keys().all(|name| !name.contains('[')));
+    }
+
+    #[test]
+    fn test_try_parse_fixed_ansi_string_array() {
+        let mut names = TestProperty::new("Names", TdhInType::InTypeAnsiString as u16, 0);
+        names.flags = PropertyFlags::PROPERTY_PARAM_FIXED_COUNT.bits();
+        names.count = 2;
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(names)
+            .property(u32_prop("Last"));
+        let mut data = b"foo\0barbaz\0".to_vec();
+        data.extend_from_slice(&7u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let last: u32 = parser.try_parse("Last").unwrap();
+        let names = parser.try_parse_string_array("Names").unwrap();
+
+        assert_eq!(last, 7);
+        assert_eq!(names, vec!["foo", "barbaz"]);
+        assert!(matches!(
+            parser.try_parse_string_array("Last"),
+            Err(ParserError::InvalidType)
+        ));
+    }
+
+    #[test]
+    fn test_try_parse_optional_present() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1).property(u32_prop("Present"));
+        let event = TestEvent::new(&test_schema, 0x1993u32.to_ne_bytes().to_vec());
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let present: Option<u32> = parser.try_parse_optional("Present").unwrap();
+
+        assert_eq!(present, Some(0x1993));
+    }
+
+    #[test]
+    fn test_try_parse_map_decomposes_bitmap() {
+        let mut access = u32_prop("Access");
+        access.map_name = Some("AccessMap".to_string());
+        let mut kind = u32_prop("Kind");
+        kind.map_name = Some("KindMap".to_string());
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(access)
+            .property(kind)
+            .property(u32_prop("Size"));
+        // Read | Write plus an unnamed bit
+        let mut data = (0x1u32 | 0x2 | 0x8).to_ne_bytes().to_vec();
+        data.extend_from_slice(&3u32.to_ne_bytes());
+        data.extend_from_slice(&100u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        parser.maps.insert(
+            "AccessMap".to_string(),
+            // EVENTMAP_INFO_FLAG_MANIFEST_BITMAP
+            Rc::new(test_map(2, &[(1, "Read "), (2, "Write "), (4, "Delete ")])),
+        );
+        parser.maps.insert(
+            "KindMap".to_string(),
+            Rc::new(test_map(1, &[(1, "Open "), (2, "Close ")])),
+        );
+
+        assert_eq!(
+            parser.try_parse_map("Access").unwrap(),
+            "Read | Write | 0x8"
+        );
+        // Single value maps don't decompose the value
+        assert_eq!(parser.try_parse_map("Kind").unwrap(), "3");
+        assert!(matches!(
+            parser.try_parse_map("Size"),
+            Err(ParserError::InvalidType)
+        ));
+    }
+
+    #[test]
+    fn test_try_parse_struct_resolves_value_map() {
+        let mut kind = u32_prop("Kind");
+        kind.map_name = Some("KindMap".to_string());
+        kind.member = true;
+        let mut size = u32_prop("Size");
+        size.member = true;
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::structure("Header", 1, 2))
+            .property(kind)
+            .property(size);
+        let mut data = 2u32.to_ne_bytes().to_vec();
+        data.extend_from_slice(&100u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        parser.maps.insert(
+            "KindMap".to_string(),
+            Rc::new(test_map(1, &[(1, "Open "), (2, "Close ")])),
+        );
+        let members = parser.try_parse_struct("Header").unwrap();
+
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].name(), "Kind");
+        assert_eq!(members[0].map_value.as_deref(), Some("Close"));
+        assert_eq!(members[1].name(), "Size");
+        assert_eq!(members[1].map_value, None);
+        assert_eq!(members[1].info.buffer, 100u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_try_parse_struct_string_member() {
+        let mut kind = u32_prop("Kind");
+        kind.member = true;
+        let mut name = TestProperty::new("Name", TdhInType::InTypeUnicodeString as u16, 0);
+        name.member = true;
+        let mut size = u32_prop("Size");
+        size.member = true;
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::structure("Header", 2, 3))
+            .property(u32_prop("Last"))
+            .property(kind)
+            .property(name)
+            .property(size);
+        let mut data = 2u32.to_ne_bytes().to_vec();
+        data.extend_from_slice(&utf16z("ntdll.dll"));
+        data.extend_from_slice(&100u32.to_ne_bytes());
+        data.extend_from_slice(&0x1993u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        let members = parser.try_parse_struct("Header").unwrap();
+
+        assert_eq!(members.len(), 3);
+        assert_eq!(members[0].info.buffer, 2u32.to_ne_bytes());
+        assert_eq!(members[1].name(), "Name");
+        assert_eq!(members[1].info.buffer, utf16z("ntdll.dll"));
+        assert_eq!(members[2].info.buffer, 100u32.to_ne_bytes());
+        // The struct takes the size of the string
+        let last: u32 = parser.try_parse("Last").unwrap();
+        assert_eq!(last, 0x1993);
+    }
+
+    #[test]
+    fn test_try_parse_struct_invalid_type() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1).property(u32_prop("Plain"));
+        let event = TestEvent::new(&test_schema, 1u32.to_ne_bytes().to_vec());
+        let schema = event.schema(&test_schema);
+
+        let mut parser = Parser::create(&schema);
+        assert!(matches!(
+            parser.try_parse_struct("Plain"),
+            Err(ParserError::InvalidType)
+        ));
+    }
+
+    #[test]
+    fn test_try_parse_filetime_and_systemtime() {
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(TestProperty::new(
+                "LastWriteTime",
+                TdhInType::InTypeFileTime as u16,
+                8,
+            ))
+            .property(TestProperty::new(
+                "CreationTime",
+                TdhInType::InTypeSystemTime as u16,
+                16,
+            ))
+            .property(TestProperty::new(
+                "Epoch",
+                TdhInType::InTypeSystemTime as u16,
+                16,
+            ))
+            .property(TestProperty::new(
+                "Corrupt",
+                TdhInType::InTypeSystemTime as u16,
+                16,
+            ))
+            .property(u32_prop("Size"));
+
+        // 2021-01-01T00:00:00Z as FILETIME
+        let mut data = 132_539_328_000_000_000u64.to_ne_bytes().to_vec();
+        // 2024-02-29T13:45:30.250Z, a Thursday
+        let systemtimes: [[u16; 8]; 3] = [
+            [2024, 2, 4, 29, 13, 45, 30, 250],
+            [1970, 1, 4, 1, 0, 0, 0, 0],
+            [2024, 13, 0, 1, 0, 0, 0, 0],
+        ];
+        for fields in systemtimes.iter() {
+            data.extend(fields.iter().flat_map(|field| field.to_ne_bytes().to_vec()));
+        }
+        data.extend_from_slice(&4096u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let last_write: SystemTime = parser.try_parse("LastWriteTime").unwrap();
+        assert_eq!(
+            last_write,
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_609_459_200)
+        );
+        let creation: SystemTime = parser.try_parse("CreationTime").unwrap();
+        assert_eq!(
+            creation,
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_709_214_330_250)
+        );
+        assert_eq!(
+            parser.try_parse_time("Epoch").unwrap(),
+            std::time::UNIX_EPOCH
+        );
+        assert!(matches!(
+            parser.try_parse_time("Corrupt"),
+            Err(ParserError::ParseError)
+        ));
+        let size: ParserResult<SystemTime> = parser.try_parse("Size");
+        assert!(matches!(
+            size,
+            Err(ParserError::TypeMismatch {
+                expected: "SystemTime",
+                ..
+            })
+        ));
+
+        assert_eq!(
+            parser.property_value("CreationTime").unwrap(),
+            PropertyValue::Time(creation)
+        );
+    }
+
+    #[test]
+    fn test_uint64_datetime_is_time() {
+        // 2021-01-01T00:00:00Z as FILETIME
+        let filetime = 132_539_328_000_000_000u64;
+        let mut created = TestProperty::new("Created", TdhInType::InTypeUInt64 as u16, 8);
+        created.out_type = TdhOutType::OutTypeDateTime as u16;
+        let test_schema = TestSchema::new(PROVIDER_GUID, 1)
+            .property(created)
+            .property(TestProperty::new(
+                "Count",
+                TdhInType::InTypeUInt64 as u16,
+                8,
+            ));
+        let mut data = filetime.to_ne_bytes().to_vec();
+        data.extend_from_slice(&7u64.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, data);
+        let schema = event.schema(&test_schema);
+        let expected = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_609_459_200);
+
+        let mut parser = Parser::create(&schema);
+        assert_eq!(parser.try_parse_time("Created").unwrap(), expected);
+        assert!(matches!(
+            parser.try_parse_time("Count"),
+            Err(ParserError::TypeMismatch {
+                expected: "SystemTime",
+                ..
+            })
+        ));
+
+        let mut parser = Parser::create(&schema);
+        let values = parser.try_parse_all().unwrap();
+        assert_eq!(
+            values,
+            vec![
+                ("Created".to_string(), PropertyValue::Time(expected)),
+                ("Count".to_string(), PropertyValue::U64(7)),
+            ]
+        );
+    }
+}
@@ -2,10 +2,19 @@
 //!
 //! Provides an abstraction over an [ETW Provider](https://docs.microsoft.com/en-us/windows/win32/etw/about-event-tracing#providers)
 use super::traits::*;
-use crate::native::etw_types::EventRecord;
+use crate::native::etw_types::{
+    EnableTraceParameters, EventRecord, EVENT_ENABLE_PROPERTY_EXCLUDE_INPRIVATE,
+    EVENT_ENABLE_PROPERTY_STACK_TRACE, MAX_EVENT_FILTER_EVENT_ID_COUNT,
+};
 use crate::native::pla;
+use crate::parser::{Parser, TryParse};
 use crate::schema;
+use crate::utils;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use windows::Guid;
 
 /// Provider module errors
@@ -19,6 +28,17 @@ pub enum ProviderError {
     ComProvider(pla::PlaError),
     /// Wrapper over an standard IO Error
     IoError(std::io::Error),
+    /// Returned when more EventIds than the filter supports are set, see [Provider::with_event_ids]
+    TooManyEventIds(usize),
+    /// Returned when the string passed to [Provider::by_guid] or [Provider::try_by_guid] isn't a
+    /// GUID
+    InvalidGuid(String),
+    /// Returned when the string passed to [Provider::source_id] or [Provider::try_source_id]
+    /// isn't a GUID
+    InvalidSourceId(String),
+    /// Returned when Kernel Providers bound to different GUIDs are combined, see
+    /// [KernelProvider::combine](kernel_providers::KernelProvider::combine)
+    KernelGuidMismatch(Guid),
 }
 
 impl LastOsError<ProviderError> for ProviderError {}
@@ -35,6 +55,57 @@ impl From<pla::PlaError> for ProviderError {
     }
 }
 
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::NoGuid => f.write_str("provider has no GUID"),
+            ProviderError::ComProvider(err) => write!(f, "provider lookup failed: {}", err),
+            ProviderError::IoError(err) => err.fmt(f),
+            ProviderError::TooManyEventIds(count) => write!(
+                f,
+                "{} event ids set, at most {} are supported",
+                count, MAX_EVENT_FILTER_EVENT_ID_COUNT
+            ),
+            ProviderError::InvalidGuid(guid) => write!(f, "invalid provider GUID {:?}", guid),
+            ProviderError::InvalidSourceId(guid) => write!(f, "invalid source id {:?}", guid),
+            ProviderError::KernelGuidMismatch(guid) => {
+                write!(f, "kernel provider {:?} is bound to a different GUID", guid)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProviderError::ComProvider(err) => Some(err),
+            ProviderError::IoError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ProviderError {
+    pub(crate) fn io_error_kind(&self) -> std::io::ErrorKind {
+        match self {
+            ProviderError::NoGuid
+            | ProviderError::TooManyEventIds(_)
+            | ProviderError::InvalidGuid(_)
+            | ProviderError::InvalidSourceId(_)
+            | ProviderError::KernelGuidMismatch(_) => std::io::ErrorKind::InvalidInput,
+            ProviderError::ComProvider(err) => err.io_error_kind(),
+            ProviderError::IoError(err) => err.kind(),
+        }
+    }
+}
+
+/// Maps the error to its [std::io::ErrorKind], the [ProviderError] is kept as the inner error
+impl From<ProviderError> for std::io::Error {
+    fn from(err: ProviderError) -> Self {
+        std::io::Error::new(err.io_error_kind(), err)
+    }
+}
+
 type ProviderResult<T> = Result<T, ProviderError>;
 
 /// Kernel Providers module
@@ -123,8 +194,92 @@ pub mod kernel_providers {
                 flags,
             }
         }
+
+        /// Use the `all` function to create a Kernel Provider with every flag in [kernel_flags]
+        /// enabled, except `EVENT_TRACE_FLAG_PROFILE`
+        ///
+        /// The provider is bound to the `SYSTEM_TRACE_GUID`, callbacks from this provider receive
+        /// the kernel events of every event class GUID, except the ones another Provider enabled
+        /// in the same Trace is bound to
+        ///
+        /// # Remarks
+        /// This is extremely high volume and is meant for short exploratory captures, expect lost
+        /// events unless the session buffers are tuned accordingly.
+        ///
+        /// None of the legacy flags are mutually exclusive, but `EVENT_TRACE_FLAG_PROFILE` requires
+        /// the `SeSystemProfilePrivilege` and makes the session fail to start without it, so it's
+        /// left out. Use the [PROFILE_PROVIDER] alongside this one if sampled profiling is needed
+        ///
+        /// # Example
+        /// ```rust
+        /// let my_provider = Provider::kernel(&KernelProvider::all());
+        /// ```
+        pub fn all() -> KernelProvider {
+            KernelProvider::new(kernel_guids::SYSTEM_TRACE_GUID, ALL_FLAGS)
+        }
+
+        /// Use the `combine` function to merge Kernel Providers bound to the same GUID, the flags
+        /// of the result are the flags of every Provider OR'd together
+        ///
+        /// # Arguments
+        /// * `providers` - Kernel Providers to merge
+        ///
+        /// # Remarks
+        /// The callbacks of a Provider only receive the events of its GUID, so Kernel Providers
+        /// bound to different GUIDs can't be merged and a [ProviderError::KernelGuidMismatch] is
+        /// returned. Use [Provider::kernel_flags] to enable the flags of other event classes on the
+        /// same session, or [KernelProvider::all] to receive every event class
+        ///
+        /// # Example
+        /// ```rust
+        /// let disk = KernelProvider::combine(&[&DISK_IO_PROVIDER, &DISK_IO_INIT_PROVIDER])?;
+        /// let my_provider = Provider::kernel(&disk);
+        /// ```
+        ///
+        /// [ProviderError::KernelGuidMismatch]: super::ProviderError::KernelGuidMismatch
+        /// [Provider::kernel_flags]: super::Provider::kernel_flags
+        pub fn combine(providers: &[&KernelProvider]) -> super::ProviderResult<KernelProvider> {
+            let guid = match providers.first() {
+                Some(provider) => provider.guid,
+                None => return Err(super::ProviderError::NoGuid),
+            };
+            let mut flags = 0;
+            for provider in providers {
+                if provider.guid != guid {
+                    return Err(super::ProviderError::KernelGuidMismatch(provider.guid));
+                }
+                flags |= provider.flags;
+            }
+            Ok(KernelProvider { guid, flags })
+        }
     }
 
+    /// Every flag in [kernel_flags] except `EVENT_TRACE_FLAG_PROFILE`, see [KernelProvider::all]
+    pub const ALL_FLAGS: u32 = kernel_flags::EVENT_TRACE_FLAG_PROCESS
+        | kernel_flags::EVENT_TRACE_FLAG_THREAD
+        | kernel_flags::EVENT_TRACE_FLAG_IMAGE_LOAD
+        | kernel_flags::EVENT_TRACE_FLAG_PROCESS_COUNTERS
+        | kernel_flags::EVENT_TRACE_FLAG_CSWITCH
+        | kernel_flags::EVENT_TRACE_FLAG_DPC
+        | kernel_flags::EVENT_TRACE_FLAG_INTERRUPT
+        | kernel_flags::EVENT_TRACE_FLAG_SYSTEMCALL
+        | kernel_flags::EVENT_TRACE_FLAG_DISK_IO
+        | kernel_flags::EVENT_TRACE_FLAG_DISK_FILE_IO
+        | kernel_flags::EVENT_TRACE_FLAG_DISK_IO_INIT
+        | kernel_flags::EVENT_TRACE_FLAG_DISPATCHER
+        | kernel_flags::EVENT_TRACE_FLAG_MEMORY_PAGE_FAULTS
+        | kernel_flags::EVENT_TRACE_FLAG_MEMORY_HARD_FAULTS
+        | kernel_flags::EVENT_TRACE_FLAG_VIRTUAL_ALLOC
+        | kernel_flags::EVENT_TRACE_FLAG_VAMAP
+        | kernel_flags::EVENT_TRACE_FLAG_NETWORK_TCPIP
+        | kernel_flags::EVENT_TRACE_FLAG_REGISTRY
+        | kernel_flags::EVENT_TRACE_FLAG_DBGPRINT
+        | kernel_flags::EVENT_TRACE_FLAG_ALPC
+        | kernel_flags::EVENT_TRACE_FLAG_SPLIT_IO
+        | kernel_flags::EVENT_TRACE_FLAG_DRIVER
+        | kernel_flags::EVENT_TRACE_FLAG_FILE_IO
+        | kernel_flags::EVENT_TRACE_FLAG_FILE_IO_INIT;
+
     lazy_static! {
         /// Represents the VirtualAlloc Kernel Provider
         pub static ref VIRTUAL_ALLOC_PROVIDER: KernelProvider = KernelProvider::new(
@@ -239,10 +394,363 @@ pub mod kernel_providers {
         /// Represents the ALPC Kernel Provider
         pub static ref ALPC_PROVIDER: KernelProvider =
             KernelProvider::new(kernel_guids::ALPC_GUID, kernel_flags::EVENT_TRACE_FLAG_ALPC);
+        /// Represents the UDP-IP Kernel Provider, enabled by the same flag as the TCP-IP one
+        pub static ref UDP_IP_PROVIDER: KernelProvider = KernelProvider::new(
+            kernel_guids::UDP_IP_GUID,
+            kernel_flags::EVENT_TRACE_FLAG_NETWORK_TCPIP
+        );
+        /// Represents the Sampled Profile Kernel Provider, the same as [PROFILE_PROVIDER]
+        ///
+        /// The SampledProfile events are logged at every profiling interrupt, which requires the
+        /// `SeSystemProfilePrivilege`
+        pub static ref SAMPLED_PROFILE_PROVIDER: KernelProvider = KernelProvider::new(
+            kernel_guids::PERF_INFO_GUID,
+            kernel_flags::EVENT_TRACE_FLAG_PROFILE
+        );
+        /// Represents the Object Manager Kernel Provider
+        ///
+        /// Handle and object events are not enabled by flags, they are logged once `PERF_OB_HANDLE`
+        /// (mask 0x40 of group 4) is set with [KernelTrace::enable_group_mask]
+        ///
+        /// [KernelTrace::enable_group_mask]: crate::trace::KernelTrace::enable_group_mask
+        pub static ref OBJECT_MANAGER_PROVIDER: KernelProvider =
+            KernelProvider::new(kernel_guids::OB_TRACE_GUID, 0);
+        /// Represents the System Config Kernel Provider
+        ///
+        /// SystemConfig events describe the hardware of the machine, they are not enabled by
+        /// flags and are logged when the NT Kernel Logger stops
+        pub static ref SYSTEM_CONFIG_PROVIDER: KernelProvider =
+            KernelProvider::new(kernel_guids::EVENT_TRACE_CONFIG_GUID, 0);
+        /// Represents the Lost Event Kernel Provider
+        ///
+        /// RT_LostEvent events are not enabled by flags, they are logged whenever a real time
+        /// session drops events or buffers
+        pub static ref LOST_EVENT_PROVIDER: KernelProvider =
+            KernelProvider::new(kernel_guids::LOST_EVENT_GUID, 0);
+        /// Represents the StackWalk Kernel Provider
+        ///
+        /// StackWalk events are not enabled by flags, they are logged for the events selected
+        /// with [KernelTrace::enable_stack_walk]
+        ///
+        /// [KernelTrace::enable_stack_walk]: crate::trace::KernelTrace::enable_stack_walk
+        pub static ref STACK_WALK_PROVIDER: KernelProvider =
+            KernelProvider::new(kernel_guids::STACK_WALK_GUID, 0);
+        /// Represents every Kernel Provider at once, see [KernelProvider::all]
+        pub static ref KERNEL_ALL_PROVIDER: KernelProvider = KernelProvider::all();
+
+        static ref KERNEL_PROVIDERS: [&'static KernelProvider; 32] = [
+            &VIRTUAL_ALLOC_PROVIDER,
+            &VAMAP_PROVIDER,
+            &THREAD_PROVIDER,
+            &SPLIT_IO_PROVIDER,
+            &SYSTEM_CALL_PROVIDER,
+            &REGISTRY_PROVIDER,
+            &PROFILE_PROVIDER,
+            &PROCESS_COUNTER_PROVIDER,
+            &PROCESS_PROVIDER,
+            &TCP_IP_PROVIDER,
+            &MEMORY_PAGE_FAULT_PROVIDER,
+            &MEMORY_HARD_FAULT_PROVIDER,
+            &INTERRUPT_PROVIDER,
+            &DRIVER_PROVIDER,
+            &DPC_PROVIDER,
+            &IMAGE_LOAD_PROVIDER,
+            &THREAD_DISPATCHER_PROVIDER,
+            &FILE_INIT_IO_PROVIDER,
+            &FILE_IO_PROVIDER,
+            &DISK_IO_INIT_PROVIDER,
+            &DISK_IO_PROVIDER,
+            &DISK_FILE_IO_PROVIDER,
+            &DEBUG_PRINT_PROVIDER,
+            &CONTEXT_SWITCH_PROVIDER,
+            &ALPC_PROVIDER,
+            &UDP_IP_PROVIDER,
+            &SAMPLED_PROFILE_PROVIDER,
+            &OBJECT_MANAGER_PROVIDER,
+            &SYSTEM_CONFIG_PROVIDER,
+            &LOST_EVENT_PROVIDER,
+            &STACK_WALK_PROVIDER,
+            &KERNEL_ALL_PROVIDER,
+        ];
+    }
+
+    /// Use the `by_guid` function to find the first pre-created Kernel Provider bound to a GUID
+    ///
+    /// Several Kernel Providers share the same GUID and only differ on their flags, the first one
+    /// declared in this module is returned in that case
+    ///
+    /// # Arguments
+    /// * `guid` - Kernel Provider GUID to look for
+    ///
+    /// # Example
+    /// ```rust
+    /// let guid = Guid::from(kernel_guids::PROCESS_GUID);
+    /// assert!(kernel_providers::by_guid(&guid).is_some());
+    /// ```
+    pub fn by_guid(guid: &Guid) -> Option<&'static KernelProvider> {
+        KERNEL_PROVIDERS
+            .iter()
+            .copied()
+            .find(|provider| provider.guid == *guid)
+    }
+}
+
+/// Represents the Level of an Event
+///
+/// See: [EVENT_DESCRIPTOR->Level](https://docs.microsoft.com/en-us/windows/win32/api/evntprov/ns-evntprov-event_descriptor)
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TraceLevel {
+    LogAlways = 0,
+    Critical = 1,
+    Error = 2,
+    Warning = 3,
+    Information = 4,
+    Verbose = 5,
+}
+
+impl TraceLevel {
+    /// Use the `to_u8` function to obtain the raw value of the level, as used by ETW
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    // LogAlways events are never filtered out, otherwise higher values are more verbose
+    pub(crate) fn allows(self, level: u8) -> bool {
+        level == TraceLevel::LogAlways as u8 || level <= self as u8
+    }
+}
+
+/// Represents a set of Keywords, see [Provider::any_keyword] and [Provider::all_keyword]
+///
+/// Keywords are combined with `|`, the constants cover the standard keywords reserved by ETW and
+/// the keywords of Microsoft-Windows-Kernel-Process. Any other Provider keyword can be built from
+/// its raw value
+///
+/// # Example
+/// ```rust
+/// let keywords = Keyword::PROCESS | Keyword::THREAD | Keyword::from(0x8000_0000_0000_0000);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Keyword(pub u64);
+
+impl Keyword {
+    /// WINEVENT_KEYWORD_RESPONSE_TIME
+    pub const RESPONSE_TIME: Keyword = Keyword(0x0001_0000_0000_0000);
+    /// WINEVENT_KEYWORD_WDI_DIAG
+    pub const WDI_DIAG: Keyword = Keyword(0x0004_0000_0000_0000);
+    /// WINEVENT_KEYWORD_SQM
+    pub const SQM: Keyword = Keyword(0x0008_0000_0000_0000);
+    /// WINEVENT_KEYWORD_AUDIT_FAILURE
+    pub const AUDIT_FAILURE: Keyword = Keyword(0x0010_0000_0000_0000);
+    /// WINEVENT_KEYWORD_AUDIT_SUCCESS
+    pub const AUDIT_SUCCESS: Keyword = Keyword(0x0020_0000_0000_0000);
+    /// WINEVENT_KEYWORD_CORRELATION_HINT
+    pub const CORRELATION_HINT: Keyword = Keyword(0x0040_0000_0000_0000);
+    /// WINEVENT_KEYWORD_EVENTLOG_CLASSIC
+    pub const EVENTLOG_CLASSIC: Keyword = Keyword(0x0080_0000_0000_0000);
+
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_PROCESS
+    pub const PROCESS: Keyword = Keyword(0x10);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_THREAD
+    pub const THREAD: Keyword = Keyword(0x20);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_IMAGE
+    pub const IMAGE: Keyword = Keyword(0x40);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_CPU_PRIORITY
+    pub const CPU_PRIORITY: Keyword = Keyword(0x80);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_OTHER_PRIORITY
+    pub const OTHER_PRIORITY: Keyword = Keyword(0x100);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_PROCESS_FREEZE
+    pub const PROCESS_FREEZE: Keyword = Keyword(0x200);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_JOB
+    pub const JOB: Keyword = Keyword(0x400);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_ENABLE_PROCESS_TRACING_CALLBACKS
+    pub const ENABLE_PROCESS_TRACING_CALLBACKS: Keyword = Keyword(0x800);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_JOB_IO
+    pub const JOB_IO: Keyword = Keyword(0x1000);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_WORK_ON_BEHALF
+    pub const WORK_ON_BEHALF: Keyword = Keyword(0x2000);
+    /// Microsoft-Windows-Kernel-Process WINEVENT_KEYWORD_JOB_SILO
+    pub const JOB_SILO: Keyword = Keyword(0x4000);
+
+    /// Use the `bits` function to obtain the raw value of the Keywords
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+}
+
+impl std::ops::BitOr for Keyword {
+    type Output = Keyword;
+
+    fn bitor(self, rhs: Keyword) -> Keyword {
+        Keyword(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Keyword {
+    fn bitor_assign(&mut self, rhs: Keyword) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl From<u64> for Keyword {
+    fn from(bits: u64) -> Self {
+        Keyword(bits)
+    }
+}
+
+impl From<Keyword> for u64 {
+    fn from(keyword: Keyword) -> Self {
+        keyword.0
+    }
+}
+
+impl From<TraceLevel> for u8 {
+    fn from(level: TraceLevel) -> Self {
+        level.to_u8()
+    }
+}
+
+// {482C2DB2-C390-47C8-87F8-1A15BFC130FB} as big endian bytes
+const TRACELOGGING_NAMESPACE: [u8; 16] = [
+    0x48, 0x2C, 0x2D, 0xB2, 0xC3, 0x90, 0x47, 0xC8, 0x87, 0xF8, 0x1A, 0x15, 0xBF, 0xC1, 0x30, 0xFB,
+];
+
+fn tracelogging_guid(name: &str) -> Guid {
+    let mut data = TRACELOGGING_NAMESPACE.to_vec();
+    name.to_uppercase()
+        .encode_utf16()
+        .for_each(|c| data.extend_from_slice(&c.to_be_bytes()));
+
+    let hash = utils::sha1(&data);
+    let mut data4 = [0u8; 8];
+    data4.copy_from_slice(&hash[8..16]);
+    Guid::from_values(
+        u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]),
+        u16::from_le_bytes([hash[4], hash[5]]),
+        // Version 5, name-based GUID
+        u16::from_le_bytes([hash[6], (hash[7] & 0x0F) | 0x50]),
+        data4,
+    )
+}
+
+type EventCallback =
+    Box<dyn FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static>;
+type SchemaErrorSink = Box<dyn FnMut(&EventRecord, &schema::SchemaError) + Send + Sync + 'static>;
+
+/// Identifies a callback of a Provider, see [Provider::register_callback]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallbackId(u64);
+
+/// Provider GUID of the synthetic Events carrying a [DuplicateSummary], see [Provider::dedupe_window]
+pub const DUPLICATE_SUMMARY_GUID: &str = "5c4b8b1e-0f2a-4d7e-9a61-3e2d7c9b4f10";
+
+/// Summary of a run of duplicated Events suppressed by [Provider::dedupe_window]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateSummary {
+    /// Provider of the duplicated Event
+    pub provider_id: Guid,
+    /// EventId of the duplicated Event
+    pub event_id: u16,
+    /// Raw value of the key field shared by the duplicates
+    pub key: Vec<u8>,
+    /// Number of duplicates suppressed, the first Event of the run is not counted
+    pub count: usize,
+    /// TimeStamp of the first Event of the run, which was dispatched
+    pub first_timestamp: i64,
+    /// TimeStamp of the last duplicate suppressed
+    pub last_timestamp: i64,
+}
+
+thread_local! {
+    // Summary being delivered to the callbacks on this thread, the only UserData from_record reads
+    static DISPATCHED_SUMMARY: Cell<*const DuplicateSummary> = const { Cell::new(std::ptr::null()) };
+}
+
+// Restores DISPATCHED_SUMMARY once the dispatch is over, even if a callback panics
+struct DispatchedSummary(*const DuplicateSummary);
+
+impl DispatchedSummary {
+    fn set(summary: &DuplicateSummary) -> Self {
+        DispatchedSummary(DISPATCHED_SUMMARY.with(|dispatched| dispatched.replace(summary)))
+    }
+}
+
+impl Drop for DispatchedSummary {
+    fn drop(&mut self) {
+        DISPATCHED_SUMMARY.with(|dispatched| dispatched.set(self.0));
+    }
+}
+
+impl DuplicateSummary {
+    /// Use the `from_record` function to obtain the summary carried by an Event
+    ///
+    /// Returns `None` unless the Event is the summary [Provider::dedupe_window] is delivering to
+    /// the callbacks of the current thread, a record built by hand or kept past its callback never
+    /// yields a summary
+    ///
+    /// # Arguments
+    /// * `record` - The [EventRecord] received by the callback
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, _: &mut SchemaLocator| {
+    ///     if let Some(summary) = DuplicateSummary::from_record(&record) {
+    ///         println!("{} duplicates of event {}", summary.count, summary.event_id);
+    ///     }
+    /// };
+    /// ```
+    pub fn from_record(record: &EventRecord) -> Option<DuplicateSummary> {
+        let summary = record.UserData as *const DuplicateSummary;
+        if record.EventHeader.ProviderId != Guid::from(DUPLICATE_SUMMARY_GUID)
+            || summary.is_null()
+            || DISPATCHED_SUMMARY.with(|dispatched| dispatched.get()) != summary
+        {
+            return None;
+        }
+        // Points at the summary report_duplicates is dispatching, which outlives the callbacks
+        Some(unsafe { &*summary }.clone())
+    }
+}
+
+/// Settings of [Provider::dedupe_window]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DedupeSettings {
+    /// Length of the window, measured from the first Event of a run
+    pub window: Duration,
+    /// Name of the property that has to match for two Events to be duplicates
+    pub key_field: String,
+}
+
+// ProviderId, EventId, Version, Opcode, Task and Level of an Event
+type EventSignature = ([u8; 16], u16, u8, u8, u16, u8);
+
+struct DuplicateRun {
+    signature: EventSignature,
+    summary: DuplicateSummary,
+}
+
+impl DuplicateRun {
+    fn signature(record: &EventRecord) -> EventSignature {
+        let descriptor = &record.EventHeader.EventDescriptor;
+        (
+            utils::guid_bytes(&record.EventHeader.ProviderId),
+            descriptor.Id,
+            descriptor.Version,
+            descriptor.Opcode,
+            descriptor.Task,
+            descriptor.Level,
+        )
     }
 }
 
 /// Main Provider structure
+///
+/// Cloning a Provider copies its configuration and shares its callbacks, a callback added to or
+/// removed from a clone applies to every copy. This lets the same Provider be enabled on several
+/// Traces, in that case the callbacks are called from the thread of each Trace. The state of
+/// [Provider::dedupe_window] is not shared, each copy collapses the duplicates of its own Events
 pub struct Provider {
     /// Option that represents a Provider GUID
     pub guid: Option<Guid>,
@@ -256,18 +764,89 @@ pub struct Provider {
     pub trace_flags: u32,
     /// Provider kernel flags, only apply to KernelProvider
     pub flags: u32, // Only applies to KernelProviders
+    /// Source Id used when enabling the Provider, see [Provider::source_id]
+    pub source_id: Option<Guid>,
+    /// Minimum [TraceLevel] of the events dispatched to the callbacks, see [Provider::min_level]
+    pub min_level: Option<TraceLevel>,
+    /// Per EventId [TraceLevel] overrides, see [Provider::level_override]
+    pub level_overrides: HashMap<u16, TraceLevel>,
+    /// Only dispatch the events of the current process, see [Provider::self_only]
+    pub self_only: bool,
+    /// Time the enable waits for the Provider to process it, see [Provider::enable_timeout]
+    pub enable_timeout: Option<Duration>,
+    /// Collapse consecutive duplicated Events, see [Provider::dedupe_window]
+    pub dedupe: Option<DedupeSettings>,
+    /// Only receive the Events with these EventIds, see [Provider::with_event_ids]
+    pub event_ids: Vec<u16>,
+    /// Drop the Events flagged as private, see [Provider::exclude_private]
+    pub exclude_private: bool,
+    /// Capture a call stack with every Event, see [Provider::with_stack_trace]
+    pub stack_trace: bool,
     // perfinfo
-    callbacks: Arc<
-        RwLock<
-            Vec<Box<dyn FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static>>,
-        >,
-    >,
+    callbacks: Arc<RwLock<Vec<(CallbackId, EventCallback)>>>,
+    next_callback_id: Arc<AtomicU64>,
+    schema_error_sink: Arc<RwLock<Option<SchemaErrorSink>>>,
+    duplicate_run: Arc<RwLock<Option<DuplicateRun>>>,
+    // Malformed string passed to by_guid, reported by build
+    invalid_guid: Option<String>,
+    // Malformed string passed to source_id, reported by build
+    invalid_source_id: Option<String>,
     // filters: RwLock<Vec<F>>,
 }
 
+impl Clone for Provider {
+    fn clone(&self) -> Self {
+        Provider {
+            guid: self.guid,
+            any: self.any,
+            all: self.all,
+            level: self.level,
+            trace_flags: self.trace_flags,
+            flags: self.flags,
+            source_id: self.source_id,
+            min_level: self.min_level,
+            level_overrides: self.level_overrides.clone(),
+            self_only: self.self_only,
+            enable_timeout: self.enable_timeout,
+            dedupe: self.dedupe.clone(),
+            event_ids: self.event_ids.clone(),
+            exclude_private: self.exclude_private,
+            stack_trace: self.stack_trace,
+            callbacks: Arc::clone(&self.callbacks),
+            next_callback_id: Arc::clone(&self.next_callback_id),
+            schema_error_sink: Arc::clone(&self.schema_error_sink),
+            duplicate_run: Arc::new(RwLock::new(None)),
+            invalid_guid: self.invalid_guid.clone(),
+            invalid_source_id: self.invalid_source_id.clone(),
+        }
+    }
+}
+
 impl std::fmt::Debug for Provider {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The callbacks can't be formatted, only how many are registered
+        let callbacks = match self.callbacks.read() {
+            Ok(callbacks) => callbacks.len(),
+            Err(_) => 0,
+        };
+        f.debug_struct("Provider")
+            .field("guid", &self.guid)
+            .field("any", &format_args!("{:#x}", self.any))
+            .field("all", &format_args!("{:#x}", self.all))
+            .field("level", &self.level)
+            .field("trace_flags", &self.trace_flags)
+            .field("flags", &format_args!("{:#x}", self.flags))
+            .field("source_id", &self.source_id)
+            .field("min_level", &self.min_level)
+            .field("level_overrides", &self.level_overrides)
+            .field("self_only", &self.self_only)
+            .field("enable_timeout", &self.enable_timeout)
+            .field("dedupe", &self.dedupe)
+            .field("event_ids", &self.event_ids)
+            .field("exclude_private", &self.exclude_private)
+            .field("stack_trace", &self.stack_trace)
+            .field("callbacks", &callbacks)
+            .finish()
     }
 }
 
@@ -288,7 +867,21 @@ impl Provider {
             level: 5,
             trace_flags: 0,
             flags: 0,
+            source_id: None,
+            min_level: None,
+            level_overrides: HashMap::new(),
+            self_only: false,
+            enable_timeout: None,
+            dedupe: None,
+            event_ids: Vec::new(),
+            exclude_private: false,
+            stack_trace: false,
             callbacks: Arc::new(RwLock::new(Vec::new())),
+            next_callback_id: Arc::new(AtomicU64::new(0)),
+            schema_error_sink: Arc::new(RwLock::new(None)),
+            duplicate_run: Arc::new(RwLock::new(None)),
+            invalid_guid: None,
+            invalid_source_id: None,
         }
     }
 
@@ -309,21 +902,125 @@ impl Provider {
             level: 5,
             trace_flags: 0,
             flags: kernel_provider.flags,
+            source_id: None,
+            min_level: None,
+            level_overrides: HashMap::new(),
+            self_only: false,
+            enable_timeout: None,
+            dedupe: None,
+            event_ids: Vec::new(),
+            exclude_private: false,
+            stack_trace: false,
             callbacks: Arc::new(RwLock::new(Vec::new())),
+            next_callback_id: Arc::new(AtomicU64::new(0)),
+            schema_error_sink: Arc::new(RwLock::new(None)),
+            duplicate_run: Arc::new(RwLock::new(None)),
+            invalid_guid: None,
+            invalid_source_id: None,
         }
     }
 
+    /// Use the `kernel_flags` function to enable more Kernel flags along with the ones of the
+    /// Kernel Provider
+    ///
+    /// # Arguments
+    /// * `flags` - Flags from [kernel_flags](kernel_providers::kernel_flags) OR'd with the current ones
+    ///
+    /// # Remarks
+    /// The flags are session wide, the NT Kernel Logger logs the events of every flag set by any
+    /// of its Providers. The callbacks still only receive the events of the Provider GUID unless
+    /// it's bound to the `SYSTEM_TRACE_GUID`
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::kernel(&kernel_providers::PROCESS_PROVIDER)
+    ///     .kernel_flags(kernel_flags::EVENT_TRACE_FLAG_THREAD | kernel_flags::EVENT_TRACE_FLAG_IMAGE_LOAD);
+    /// ```
+    pub fn kernel_flags(mut self, flags: u32) -> Self {
+        self.flags |= flags;
+        self
+    }
+
     /// Use the `by_guid` function to bind a GUID with a Provider
     ///
     /// # Arguments
     /// * `guid` - A string representation of the GUID, without curly braces, that is being binded to the Provider
     ///
+    /// # Remarks
+    /// A malformed string, e.g. with curly braces, leaves the Provider without GUID and makes
+    /// [Provider::build] fail with a [ProviderError::InvalidGuid]. Use [Provider::try_by_guid] to
+    /// get the error right away
+    ///
     /// # Example
     /// ```rust
     /// let my_provider = Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
     /// ```
     pub fn by_guid(mut self, guid: &str) -> Self {
-        self.guid = Some(Guid::from(guid));
+        if utils::is_guid_str(guid) {
+            self.guid = Some(Guid::from(guid));
+        } else {
+            self.guid = None;
+            self.invalid_guid = Some(guid.to_string());
+        }
+        self
+    }
+
+    /// Use the `by_guid_value` function to bind an already parsed GUID with a Provider
+    ///
+    /// # Arguments
+    /// * `guid` - The GUID that is being binded to the Provider
+    ///
+    /// # Example
+    /// ```rust
+    /// let guid = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+    /// let my_provider = Provider::new().by_guid_value(guid);
+    /// ```
+    pub fn by_guid_value(mut self, guid: Guid) -> Self {
+        self.guid = Some(guid);
+        self
+    }
+
+    /// Use the `try_by_guid` function to bind a GUID with a Provider, failing if the string isn't
+    /// a GUID
+    ///
+    /// # Arguments
+    /// * `guid` - A string representation of the GUID, without curly braces, that is being binded to the Provider
+    ///
+    /// # Remarks
+    /// This function can fail, if the string isn't made of 32 hex digits in the 8-4-4-4-12 format
+    /// a [ProviderError::InvalidGuid] is returned
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().try_by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")?;
+    /// ```
+    pub fn try_by_guid(self, guid: &str) -> ProviderResult<Self> {
+        if !utils::is_guid_str(guid) {
+            return Err(ProviderError::InvalidGuid(guid.to_string()));
+        }
+        Ok(self.by_guid(guid))
+    }
+
+    /// Use the `by_tracelogging_name` function to bind the GUID of a TraceLogging Provider
+    ///
+    /// TraceLogging Providers registered without an explicit GUID use a GUID derived from their
+    /// name, this function computes it so the name used in the registration can be used directly
+    ///
+    /// # Arguments
+    /// * `name` - Name of the TraceLogging Provider
+    ///
+    /// # Remarks
+    /// The GUID is derived as described in the [TraceLogging docs](https://docs.microsoft.com/en-us/windows/win32/api/traceloggingprovider/nf-traceloggingprovider-tracelogging_define_provider):
+    /// SHA-1 over a fixed namespace and the uppercased UTF-16BE name, with the version nibble set
+    /// to 5. The names are case-insensitive
+    ///
+    /// # Example
+    /// ```rust
+    /// // ce5fa4ea-ab00-5402-8b76-9f76ac858fb5
+    /// let my_provider = Provider::new().by_tracelogging_name("MyCompany.MyComponent");
+    /// ```
+    pub fn by_tracelogging_name(mut self, name: &str) -> Self {
+        self.guid = Some(tracelogging_guid(name));
         self
     }
 
@@ -339,23 +1036,49 @@ impl Provider {
     /// * `name` - Provider name to find
     ///
     /// # Safety Note
-    /// This function won't fail if the Provider GUID can't be found, it will log the event and set the Guid field to None. This behavior might change in the future
+    /// The lookup error isn't returned, if the Provider GUID can't be found the Guid field is set to
+    /// None and [Provider::build] will return [ProviderError::NoGuid]. The error is logged when the
+    /// `log` feature is enabled, use [Provider::try_by_name] to get it instead
     ///
     /// # Example
     /// ```rust
     /// let my_provider = Provider::new().by_name(String::from("Microsoft-Windows-WinINet"));
     /// ```
     pub fn by_name(mut self, name: String) -> Self {
-        unsafe {
-            match pla::get_provider_guid(&name) {
-                Ok(res) => self.guid = Some(res),
-                Err(err) => {
-                    println!("{:?}", err);
-                    self.guid = None;
-                }
+        self.guid = None;
+        let unresolved = self.clone();
+        match self.try_by_name(&name) {
+            Ok(prov) => prov,
+            Err(_err) => {
+                #[cfg(feature = "log")]
+                log::warn!("Couldn't find the GUID of Provider {:?}: {}", name, _err);
+                unresolved
             }
         }
-        self
+    }
+
+    /// Use the `try_by_name` function to bind a GUID with a Provider, failing if the name can't be
+    /// resolved
+    ///
+    /// Works like [Provider::by_name] but returns the lookup error, which tells apart a Provider
+    /// name that doesn't exist from a Provider that was never given a GUID
+    ///
+    /// # Arguments
+    /// * `name` - Provider name to find
+    ///
+    /// # Remarks
+    /// This function can fail, if the Provider can't be found a [ProviderError::ComProvider] with
+    /// the underlying [PlaError] is returned
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().try_by_name("Microsoft-Windows-WinINet")?;
+    /// ```
+    ///
+    /// [PlaError]: crate::native::pla::PlaError
+    pub fn try_by_name(mut self, name: &str) -> ProviderResult<Self> {
+        self.guid = Some(unsafe { pla::get_provider_guid(name)? });
+        Ok(self)
     }
 
     /// Use the `any` function to set the `any` flag in the Provider instance
@@ -388,8 +1111,38 @@ impl Provider {
         self
     }
 
+    /// Use the `any_keyword` function to set the `any` flag in the Provider instance from a
+    /// [Keyword]
+    ///
+    /// # Arguments
+    /// * `any` - Keywords to set
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().any_keyword(Keyword::PROCESS | Keyword::THREAD);
+    /// ```
+    pub fn any_keyword(self, any: Keyword) -> Self {
+        self.any(any.into())
+    }
+
+    /// Use the `all_keyword` function to set the `all` flag in the Provider instance from a
+    /// [Keyword]
+    ///
+    /// # Arguments
+    /// * `all` - Keywords to set
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().all_keyword(Keyword::from(0x4000000000000000));
+    /// ```
+    pub fn all_keyword(self, all: Keyword) -> Self {
+        self.all(all.into())
+    }
+
     /// Use the `level` function to set the `level` flag in the Provider instance
     ///
+    /// Prefer [Provider::level_typed], which only accepts the defined levels
+    ///
     /// # Arguments
     /// * `level` - Level flag value to set
     ///
@@ -403,11 +1156,44 @@ impl Provider {
     /// // Verbose (0x5)
     /// let my_provider = Provider::new().level(0x5);
     /// ```
+    ///
+    /// # Remarks
+    /// A level of 0 ([TraceLevel::LogAlways]) doesn't turn the Provider off, ETW treats it as "every
+    /// level" and delivers the events regardless of their level, see [Provider::all_levels]
     pub fn level(mut self, level: u8) -> Self {
         self.level = level;
         self
     }
 
+    /// Use the `level_typed` function to set the `level` flag in the Provider instance from a
+    /// [TraceLevel]
+    ///
+    /// # Arguments
+    /// * `level` - [TraceLevel] to enable the Provider at
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().level_typed(TraceLevel::Warning);
+    /// ```
+    pub fn level_typed(self, level: TraceLevel) -> Self {
+        self.level(level.into())
+    }
+
+    /// Use the `all_levels` function to enable the Provider for the events of every level
+    ///
+    /// This is an alias of `level(0)`: when a Provider is enabled with [TraceLevel::LogAlways] the
+    /// check `Level <= EnableLevel || EnableLevel == 0` done on the provider side always succeeds
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().all_levels();
+    /// ```
+    ///
+    /// See: [EnableTraceEx2](https://docs.microsoft.com/en-us/windows/win32/api/evntrace/nf-evntrace-enabletraceex2)
+    pub fn all_levels(self) -> Self {
+        self.level_typed(TraceLevel::LogAlways)
+    }
+
     /// Use the `trace_flags` function to set the `trace_flags` flag in the Provider instance
     /// [More info](https://docs.microsoft.com/en-us/windows-hardware/drivers/devtest/trace-flags)
     ///
@@ -423,73 +1209,689 @@ impl Provider {
         self
     }
 
-    /// Use the `add_callback` function to add a callback function that will be called when the Provider generates an Event
+    /// Use the `min_level` function to drop the events more verbose than `min_level` before they
+    /// reach the callbacks
     ///
-    /// # Arguments
-    /// * `callback` - Callback to add
+    /// This is a client side filter independent of the `level` the Provider is enabled at, which
+    /// comes in handy when the same enable level serves multiple consumers. Events logged with
+    /// [TraceLevel::LogAlways] are never dropped
     ///
-    /// # Remarks
-    /// The [SchemaLocator] has to be mutable because whenever we obtain a new Schema it will be saved
-    /// into the [SchemaLocator] instance cache
+    /// # Arguments
+    /// * `min_level` - Most verbose [TraceLevel] dispatched to the callbacks
     ///
     /// # Example
     /// ```rust
-    /// Provider::new().add_callback(|record: EventRecord, schema_locator: &mut SchemaLocator| {
-    ///     // Handle Event
-    /// });
+    /// let my_provider = Provider::new().level(0x5).min_level(TraceLevel::Warning);
     /// ```
-    ///
-    /// [SchemaLocator]: crate::schema::SchemaLocator
-    pub fn add_callback<T>(self, callback: T) -> Self
-    where
-        T: FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static,
-    {
-        if let Ok(mut callbacks) = self.callbacks.write() {
-            callbacks.push(Box::new(callback));
-        }
+    pub fn min_level(mut self, min_level: TraceLevel) -> Self {
+        self.min_level = Some(min_level);
         self
     }
 
-    /*
-    pub fn add_filter(&mut self) -> ProviderResult<()> {
-        if let Ok(mut filters) = self.callbacks.write() {
-            self.filters.push(callback_info);
-
-            return Ok(());
-        }
-        Ok(())
-    }
-     */
-
-    /// Use the `build` function to build the provider
+    /// Use the `level_override` function to set the most verbose level dispatched for a single EventId
     ///
-    /// # Safety Note
-    /// This function might return an [ProviderError::NoGuid] if the GUID is not set in the Provider struct
+    /// Works like [Provider::min_level] but only applies to the events with the given EventId, which
+    /// allows quieting a chatty event without lowering the level of the whole Provider. The override
+    /// takes precedence over [Provider::min_level] for that EventId and setting it again for the same
+    /// EventId replaces the previous value
+    ///
+    /// # Arguments
+    /// * `id` - EventId the override applies to
+    /// * `max_level` - Most verbose [TraceLevel] dispatched to the callbacks for this EventId
     ///
     /// # Example
     /// ```rust
-    /// Provider::new()
-    ///   .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
-    ///   .add_callback(process_callback)
-    ///   .build()?
+    /// let my_provider = Provider::new()
+    ///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///     .level_override(5, TraceLevel::Warning);
     /// ```
-    // TODO: should we check if callbacks is empty ???
-    pub fn build(self) -> ProviderResult<Self> {
-        if self.guid.is_none() {
-            return Err(ProviderError::NoGuid);
-        }
-        Ok(self)
+    pub fn level_override(mut self, id: u16, max_level: TraceLevel) -> Self {
+        self.level_overrides.insert(id, max_level);
+        self
     }
 
-    pub(crate) fn on_event(&self, record: EventRecord, locator: &mut schema::SchemaLocator) {
-        // Has to be mutable because the SchemaLocator will be mutated when locating the schema
+    /// Use the `self_only` function to only receive the events emitted by the current process
+    ///
+    /// # Remarks
+    /// On Windows 8.1 and later the Provider is enabled with a PID filter
+    /// (`EVENT_FILTER_TYPE_PID`) so the events from other processes are dropped by ETW itself. On
+    /// older versions the filter is not available and the events are dropped before reaching the
+    /// callbacks by comparing the ProcessId of their header with the current process id, which is
+    /// also done on newer versions since providers are free to ignore the filter
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new()
+    ///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///     .self_only();
+    /// ```
+    pub fn self_only(mut self) -> Self {
+        self.self_only = true;
+        self
+    }
+
+    /// Use the `with_event_ids` function to only receive the events with the given EventIds
+    ///
+    /// # Arguments
+    /// * `ids` - EventIds to receive, up to 64
+    ///
+    /// # Remarks
+    /// On Windows 8.1 and later the Provider is enabled with an EventId filter
+    /// (`EVENT_FILTER_TYPE_EVENT_ID`) so the other events are dropped by ETW before reaching the
+    /// session. Like [Provider::self_only] the events are also checked before reaching the
+    /// callbacks, which covers older versions. More than 64 EventIds makes `build`, and opening
+    /// the Trace the Provider is enabled on, fail with a [ProviderError::TooManyEventIds]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new()
+    ///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///     .with_event_ids(vec![1, 2])
+    ///     .build()?;
+    /// ```
+    pub fn with_event_ids(mut self, ids: Vec<u16>) -> Self {
+        self.event_ids = ids;
+        self
+    }
+
+    /// Use the `exclude_private` function to drop the events the Provider marks as private
+    ///
+    /// Sets `EVENT_ENABLE_PROPERTY_EXCLUDE_INPRIVATE` in the enable properties, ETW then discards
+    /// the events the Provider logs while in private mode, e.g. the events of the InPrivate
+    /// browsing windows. Only the Providers that opt into marking such events are affected, it
+    /// isn't a generic PII filter
+    ///
+    /// # Arguments
+    /// * `exclude` - Whether the private events are dropped
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new()
+    ///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///     .exclude_private(true);
+    /// ```
+    ///
+    /// See: [ENABLE_TRACE_PARAMETERS](https://docs.microsoft.com/en-us/windows/win32/api/evntrace/ns-evntrace-enable_trace_parameters)
+    pub fn exclude_private(mut self, exclude: bool) -> Self {
+        self.exclude_private = exclude;
+        self
+    }
+
+    /// Use the `with_stack_trace` function to capture the call stack of every event the Provider
+    /// logs
+    ///
+    /// Sets `EVENT_ENABLE_PROPERTY_STACK_TRACE` in the enable properties, ETW then attaches the
+    /// stack as an extended data item which can be read with [EventRecordExt::extended_data_stack]
+    ///
+    /// # Remarks
+    /// Walking the stack has a cost for every event logged, combine it with the keywords and level
+    /// to only capture the events of interest
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new()
+    ///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///     .with_stack_trace()
+    ///     .add_callback(|record, _| {
+    ///         if let Some(stack) = record.extended_data_stack() {
+    ///             println!("{} frames", stack.len());
+    ///         }
+    ///     });
+    /// ```
+    ///
+    /// See: [ENABLE_TRACE_PARAMETERS](https://docs.microsoft.com/en-us/windows/win32/api/evntrace/ns-evntrace-enable_trace_parameters)
+    ///
+    /// [EventRecordExt::extended_data_stack]: crate::native::etw_types::EventRecordExt::extended_data_stack
+    pub fn with_stack_trace(mut self) -> Self {
+        self.stack_trace = true;
+        self
+    }
+
+    /// Use the `enable_timeout` function to enable the Provider synchronously
+    ///
+    /// By default the Provider is enabled asynchronously, `EnableTraceEx2` returns right away and
+    /// the Provider processes the enable on its own time. With a timeout the call waits, up to
+    /// `timeout`, for the Provider to process the enable before the Trace starts, which is needed
+    /// when the rundown events logged on enable have to be received before proceeding
+    ///
+    /// # Arguments
+    /// * `timeout` - Maximum time to wait, rounded down to milliseconds
+    ///
+    /// # Remarks
+    /// A timeout of 0 keeps the asynchronous behaviour and timeouts that don't fit in 32 bits wait
+    /// forever. If the timeout expires the enable fails with `ERROR_TIMEOUT`, the Provider may
+    /// still end up enabled
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new()
+    ///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///     .enable_timeout(Duration::from_secs(5));
+    /// ```
+    pub fn enable_timeout(mut self, timeout: Duration) -> Self {
+        self.enable_timeout = Some(timeout);
+        self
+    }
+
+    /// Use the `source_id` function to set the Source Id used when enabling the Provider
+    /// [More info](https://docs.microsoft.com/en-us/windows/win32/api/evntrace/ns-evntrace-enable_trace_parameters)
+    ///
+    /// # Arguments
+    /// * `source_id` - A string representation of the GUID, without curly braces, to use as Source Id
+    ///
+    /// # Remarks
+    /// ETW reference-counts the enablement of a Provider by Source Id, disabling a Provider only
+    /// drops the enablement made with the same Source Id. Using a dedicated Source Id prevents this
+    /// crate from clobbering the enablement made by other controllers of the same Provider. When
+    /// not set the Provider GUID is used as Source Id
+    ///
+    /// A malformed string leaves the Source Id unset and makes [Provider::build] fail with a
+    /// [ProviderError::InvalidSourceId]. Use [Provider::try_source_id] to get the error right away
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().source_id("6b1ee1a2-cb25-4bfc-9e05-4c8d4cb8bd42");
+    /// ```
+    pub fn source_id(mut self, source_id: &str) -> Self {
+        if utils::is_guid_str(source_id) {
+            self.source_id = Some(Guid::from(source_id));
+            self.invalid_source_id = None;
+        } else {
+            self.source_id = None;
+            self.invalid_source_id = Some(source_id.to_string());
+        }
+        self
+    }
+
+    /// Use the `try_source_id` function to set the Source Id used when enabling the Provider,
+    /// failing if the string isn't a GUID
+    ///
+    /// # Arguments
+    /// * `source_id` - A string representation of the GUID, without curly braces, to use as Source Id
+    ///
+    /// # Remarks
+    /// This function can fail, if the string isn't made of 32 hex digits in the 8-4-4-4-12 format
+    /// a [ProviderError::InvalidSourceId] is returned
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new().try_source_id("6b1ee1a2-cb25-4bfc-9e05-4c8d4cb8bd42")?;
+    /// ```
+    pub fn try_source_id(self, source_id: &str) -> ProviderResult<Self> {
+        if !utils::is_guid_str(source_id) {
+            return Err(ProviderError::InvalidSourceId(source_id.to_string()));
+        }
+        Ok(self.source_id(source_id))
+    }
+
+    /// Use the `add_callback` function to add a callback function that will be called when the Provider generates an Event
+    ///
+    /// # Arguments
+    /// * `callback` - Callback to add
+    ///
+    /// # Remarks
+    /// The [SchemaLocator] has to be mutable because whenever we obtain a new Schema it will be saved
+    /// into the [SchemaLocator] instance cache
+    ///
+    /// # Example
+    /// ```rust
+    /// Provider::new().add_callback(|record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     // Handle Event
+    /// });
+    /// ```
+    ///
+    /// [SchemaLocator]: crate::schema::SchemaLocator
+    pub fn add_callback<T>(self, callback: T) -> Self
+    where
+        T: FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static,
+    {
+        self.register_callback(callback);
+        self
+    }
+
+    /// Use the `register_callback` function to add a callback that can be removed later on
+    ///
+    /// # Arguments
+    /// * `callback` - Callback to add
+    ///
+    /// # Remarks
+    /// Unlike [Provider::add_callback] this only needs a reference, so callbacks can be added and
+    /// removed while the Trace runs through its `providers`. It must not be called from within a
+    /// callback of the same Provider
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new();
+    /// let id = my_provider.register_callback(|record: EventRecord, _: &mut SchemaLocator| {
+    ///     // Handle Event
+    /// });
+    /// my_provider.remove_callback(id);
+    /// ```
+    pub fn register_callback<T>(&self, callback: T) -> CallbackId
+    where
+        T: FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static,
+    {
+        let id = CallbackId(self.next_callback_id.fetch_add(1, Ordering::Relaxed));
+        if let Ok(mut callbacks) = self.callbacks.write() {
+            callbacks.push((id, Box::new(callback)));
+        }
+        id
+    }
+
+    /// Use the `remove_callback` function to remove a callback added with
+    /// [Provider::register_callback]
+    ///
+    /// # Arguments
+    /// * `id` - The [CallbackId] returned when the callback was registered
+    ///
+    /// # Remarks
+    /// Returns `false` if no callback with this id is registered, e.g. it was already removed
+    pub fn remove_callback(&self, id: CallbackId) -> bool {
+        match self.callbacks.write() {
+            Ok(mut callbacks) => {
+                let count = callbacks.len();
+                callbacks.retain(|(callback_id, _)| *callback_id != id);
+                callbacks.len() != count
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Use the `clear_callbacks` function to remove every callback of the Provider
+    ///
+    /// # Remarks
+    /// The Events keep being received, they are just not dispatched until a callback is added
+    pub fn clear_callbacks(&self) {
+        if let Ok(mut callbacks) = self.callbacks.write() {
+            callbacks.clear();
+        }
+    }
+
+    /// Use the `add_callback_with_context` function to add a callback that shares a context with
+    /// the caller
+    ///
+    /// # Arguments
+    /// * `context` - Context passed by reference to every call of the callback
+    /// * `callback` - Callback to add
+    ///
+    /// # Remarks
+    /// The callback is a `Fn` so the context can be read after the Trace is started through the
+    /// caller's clone of the [Arc], it has to handle its own interior mutability, e.g. with atomics
+    /// or a [Mutex](std::sync::Mutex), instead of capturing an `Arc<Mutex<_>>` in the closure
+    ///
+    /// # Example
+    /// ```rust
+    /// let counts = Arc::new(Mutex::new(HashMap::new()));
+    /// let my_provider = Provider::new().add_callback_with_context(
+    ///     Arc::clone(&counts),
+    ///     |counts: &Mutex<HashMap<u16, usize>>, record: EventRecord, _: &mut SchemaLocator| {
+    ///         let id = record.EventHeader.EventDescriptor.Id;
+    ///         *counts.lock().unwrap().entry(id).or_default() += 1;
+    ///     },
+    /// );
+    /// ```
+    pub fn add_callback_with_context<C, T>(self, context: Arc<C>, callback: T) -> Self
+    where
+        C: Send + Sync + 'static,
+        T: Fn(&C, EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static,
+    {
+        self.add_callback(
+            move |record: EventRecord, locator: &mut schema::SchemaLocator| {
+                callback(&context, record, locator)
+            },
+        )
+    }
+
+    /// Use the `add_callback_for_ids` function to add a callback only called for some EventIds
+    ///
+    /// # Arguments
+    /// * `ids` - EventIds the callback is called for
+    /// * `callback` - Callback to add
+    ///
+    /// # Remarks
+    /// The EventId is read from the header of the [EventRecord], the events with other EventIds
+    /// are skipped before any Schema lookup. The events are still delivered by ETW, use
+    /// [Provider::with_event_ids] to filter them in the session instead
+    ///
+    /// # Example
+    /// ```rust
+    /// Provider::new().add_callback_for_ids(&[3006, 3008], |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     // Handle DNS query events
+    /// });
+    /// ```
+    pub fn add_callback_for_ids<T>(self, ids: &[u16], mut callback: T) -> Self
+    where
+        T: FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static,
+    {
+        let ids = ids.to_vec();
+        self.add_callback(
+            move |record: EventRecord, locator: &mut schema::SchemaLocator| {
+                if ids.contains(&record.EventHeader.EventDescriptor.Id) {
+                    callback(record, locator)
+                }
+            },
+        )
+    }
+
+    /// Use the `on_parsed` function to add a callback that receives the Event already resolved
+    ///
+    /// The [Schema] of every Event is located and a [Parser] is created for it before calling the
+    /// closure, which only runs when the Schema could be found. Events whose Schema can't be located
+    /// are sent to the [Provider::on_missing_schema] callback instead, or silently dropped if none
+    /// has been set
+    ///
+    /// # Arguments
+    /// * `callback` - Closure receiving the [EventMetadata] and a [Parser] over the Event
+    ///
+    /// # Remarks
+    /// The Schemas are cached by the [SchemaLocator], but the Parser is not reused between Events:
+    /// its cache holds the offsets of the properties in the buffer of one Event, which depend on the
+    /// data of variable length properties. A new Parser is created for every Event
+    ///
+    /// # Example
+    /// ```rust
+    /// Provider::new().on_parsed(|meta: &EventMetadata, parser: &mut Parser| {
+    ///     if meta.event_id == 1 {
+    ///         let image_name: String = parser.try_parse("ImageName").unwrap_or_default();
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [Schema]: crate::schema::Schema
+    /// [EventMetadata]: crate::schema::EventMetadata
+    /// [SchemaLocator]: crate::schema::SchemaLocator
+    pub fn on_parsed<T>(self, mut callback: T) -> Self
+    where
+        T: FnMut(&schema::EventMetadata, &mut Parser) + Send + Sync + 'static,
+    {
+        let error_sink = Arc::clone(&self.schema_error_sink);
+        let summary_guid = Guid::from(DUPLICATE_SUMMARY_GUID);
+        self.add_callback(
+            move |record: EventRecord, locator: &mut schema::SchemaLocator| {
+                // Summaries of dedupe_window don't have a Schema
+                if record.EventHeader.ProviderId == summary_guid {
+                    return;
+                }
+                match locator.event_schema(record) {
+                    Ok(schema) => {
+                        let meta = schema::EventMetadata::from(&schema);
+                        let mut parser = Parser::create(&schema);
+                        callback(&meta, &mut parser);
+                    }
+                    Err(err) => {
+                        if let Ok(mut sink) = error_sink.write() {
+                            if let Some(sink) = sink.as_mut() {
+                                sink(&record, &err);
+                            }
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    /// Use the `on_missing_schema` function to handle the Events whose Schema can't be located
+    ///
+    /// Only applies to the callbacks added with [Provider::on_parsed], by default these Events are
+    /// dropped. Setting it again replaces the previous callback
+    ///
+    /// # Arguments
+    /// * `callback` - Closure receiving the [EventRecord] and the [SchemaError] returned by the locator
+    ///
+    /// # Example
+    /// ```rust
+    /// Provider::new().on_missing_schema(|record: &EventRecord, err: &SchemaError| {
+    ///     println!("No schema for event {}: {:?}", record.EventHeader.EventDescriptor.Id, err);
+    /// });
+    /// ```
+    ///
+    /// [SchemaError]: crate::schema::SchemaError
+    pub fn on_missing_schema<T>(self, callback: T) -> Self
+    where
+        T: FnMut(&EventRecord, &schema::SchemaError) + Send + Sync + 'static,
+    {
+        if let Ok(mut sink) = self.schema_error_sink.write() {
+            *sink = Some(Box::new(callback));
+        }
+        self
+    }
+
+    /// Use the `dedupe_window` function to collapse bursts of identical consecutive Events
+    ///
+    /// Two consecutive Events are duplicates when they share the same signature (ProviderId,
+    /// EventId, Version, Opcode, Task and Level) and the same value for the `key_field` property.
+    /// The first Event of a run is dispatched as usual, the duplicates that follow within `window`
+    /// are suppressed and counted. Once the run ends, because a different Event arrives or a
+    /// duplicate arrives after the window, a synthetic Event carrying the [DuplicateSummary] is
+    /// delivered to the callbacks. Its ProviderId is [DUPLICATE_SUMMARY_GUID] and its EventDescriptor
+    /// the one of the duplicated Event, use [DuplicateSummary::from_record] to read it
+    ///
+    /// # Arguments
+    /// * `window` - Maximum time between the first Event of a run and its duplicates
+    /// * `key_field` - Name of the property that has to match for two Events to be duplicates
+    ///
+    /// # Remarks
+    /// The window is measured on the TimeStamp of the Events, which are expected to be in 100ns
+    /// units, i.e. the Trace doesn't use raw timestamps. Events whose Schema can't be located or
+    /// that lack the key field are never collapsed. The run still open when the Trace is stopped is
+    /// reported on stop. The summaries are not delivered to the [Provider::on_parsed] callbacks,
+    /// there's no Schema to parse them with
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_provider = Provider::new()
+    ///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///     .dedupe_window(Duration::from_secs(1), "ProcessID")
+    ///     .add_callback(|record: EventRecord, _: &mut SchemaLocator| {
+    ///         if let Some(summary) = DuplicateSummary::from_record(&record) {
+    ///             println!("{} duplicates of event {}", summary.count, summary.event_id);
+    ///         }
+    ///     });
+    /// ```
+    pub fn dedupe_window(mut self, window: Duration, key_field: &str) -> Self {
+        self.dedupe = Some(DedupeSettings {
+            window,
+            key_field: key_field.to_string(),
+        });
+        self
+    }
+
+    /*
+    pub fn add_filter(&mut self) -> ProviderResult<()> {
+        if let Ok(mut filters) = self.callbacks.write() {
+            self.filters.push(callback_info);
+
+            return Ok(());
+        }
+        Ok(())
+    }
+     */
+
+    /// Use the `build` function to build the provider
+    ///
+    /// # Safety Note
+    /// This function might return an [ProviderError::NoGuid] if the GUID is not set in the Provider struct,
+    /// an [ProviderError::InvalidGuid] if the string given to [Provider::by_guid] isn't a GUID,
+    /// an [ProviderError::InvalidSourceId] if the string given to [Provider::source_id] isn't a
+    /// GUID or an [ProviderError::TooManyEventIds] if the EventId filter has too many entries
+    ///
+    /// # Example
+    /// ```rust
+    /// Provider::new()
+    ///   .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+    ///   .add_callback(process_callback)
+    ///   .build()?
+    /// ```
+    // TODO: should we check if callbacks is empty ???
+    pub fn build(mut self) -> ProviderResult<Self> {
+        if self.guid.is_none() {
+            return Err(match self.invalid_guid.take() {
+                Some(guid) => ProviderError::InvalidGuid(guid),
+                None => ProviderError::NoGuid,
+            });
+        }
+        if let Some(source_id) = self.invalid_source_id.take() {
+            return Err(ProviderError::InvalidSourceId(source_id));
+        }
+        self.check_event_ids()?;
+        Ok(self)
+    }
+
+    // The Trace checks it again on open, a Provider can be enabled without being built
+    pub(crate) fn check_event_ids(&self) -> ProviderResult<()> {
+        if self.event_ids.len() > MAX_EVENT_FILTER_EVENT_ID_COUNT {
+            return Err(ProviderError::TooManyEventIds(self.event_ids.len()));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn enable_parameters(&self) -> EnableTraceParameters {
+        // Should always be Some at this point, the Provider has been built
+        let source_id = self
+            .source_id
+            .unwrap_or_else(|| self.guid.unwrap_or_else(Guid::zeroed));
+        let mut enable_property = self.trace_flags;
+        if self.exclude_private {
+            enable_property |= EVENT_ENABLE_PROPERTY_EXCLUDE_INPRIVATE;
+        }
+        if self.stack_trace {
+            enable_property |= EVENT_ENABLE_PROPERTY_STACK_TRACE;
+        }
+        EnableTraceParameters::create(source_id, enable_property)
+    }
+
+    /// Timeout EnableTraceEx2 waits for the Provider to process the enable in ms, 0 returns
+    /// immediately
+    pub(crate) fn enable_timeout_ms(&self) -> u32 {
+        match self.enable_timeout {
+            // u32::MAX is INFINITE
+            Some(timeout) => timeout.as_millis().min(u128::from(u32::MAX)) as u32,
+            None => 0,
+        }
+    }
+
+    pub(crate) fn on_event(&self, record: EventRecord, locator: &mut schema::SchemaLocator) {
+        // Has to be mutable because the SchemaLocator will be mutated when locating the schema
         // within the cb creating a clone of the whole SchemaLocator HashMap doesn't
         // sound like a plan still needs to think more about this thou...
         // Could we locate the schema before calling the callback???
+        let descriptor = &record.EventHeader.EventDescriptor;
+        let max_level = self
+            .level_overrides
+            .get(&descriptor.Id)
+            .copied()
+            .or(self.min_level);
+        if let Some(max_level) = max_level {
+            if !max_level.allows(descriptor.Level) {
+                return;
+            }
+        }
+
+        if self.self_only && record.EventHeader.ProcessId != std::process::id() {
+            return;
+        }
+
+        if !self.event_ids.is_empty() && !self.event_ids.contains(&descriptor.Id) {
+            return;
+        }
+
+        if let Some(dedupe) = &self.dedupe {
+            if self.is_duplicate(dedupe, &record, locator) {
+                return;
+            }
+        }
+
+        self.dispatch(record, locator);
+    }
+
+    fn dispatch(&self, record: EventRecord, locator: &mut schema::SchemaLocator) {
         if let Ok(mut callbacks) = self.callbacks.write() {
-            callbacks.iter_mut().for_each(|cb| cb(record, locator))
+            callbacks.iter_mut().for_each(|(_, cb)| cb(record, locator))
         }
     }
+
+    fn is_duplicate(
+        &self,
+        dedupe: &DedupeSettings,
+        record: &EventRecord,
+        locator: &mut schema::SchemaLocator,
+    ) -> bool {
+        let key = locator.event_schema(*record).ok().and_then(|schema| {
+            let mut parser = Parser::create(&schema);
+            TryParse::<Vec<u8>>::try_parse(&mut parser, &dedupe.key_field).ok()
+        });
+        let signature = DuplicateRun::signature(record);
+        let timestamp = record.EventHeader.TimeStamp;
+
+        let mut run = match self.duplicate_run.write() {
+            Ok(run) => run,
+            Err(_) => return false,
+        };
+        if let (Some(current), Some(key)) = (run.as_mut(), key.as_ref()) {
+            let elapsed = timestamp.saturating_sub(current.summary.first_timestamp);
+            // TimeStamps are in 100ns units
+            if current.signature == signature
+                && current.summary.key == *key
+                && elapsed >= 0
+                && elapsed as u128 <= dedupe.window.as_nanos() / 100
+            {
+                current.summary.count += 1;
+                current.summary.last_timestamp = timestamp;
+                return true;
+            }
+        }
+
+        let finished = std::mem::replace(
+            &mut *run,
+            key.map(|key| DuplicateRun {
+                signature,
+                summary: DuplicateSummary {
+                    provider_id: record.EventHeader.ProviderId,
+                    event_id: record.EventHeader.EventDescriptor.Id,
+                    key,
+                    count: 0,
+                    first_timestamp: timestamp,
+                    last_timestamp: timestamp,
+                },
+            }),
+        );
+        drop(run);
+        self.report_duplicates(finished, locator);
+        false
+    }
+
+    fn report_duplicates(&self, run: Option<DuplicateRun>, locator: &mut schema::SchemaLocator) {
+        let run = match run {
+            Some(run) if run.summary.count > 0 => run,
+            _ => return,
+        };
+
+        // The summary outlives the dispatch, the callbacks are called synchronously
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        let (_, id, version, opcode, task, level) = run.signature;
+        let descriptor = &mut record.EventHeader.EventDescriptor;
+        descriptor.Id = id;
+        descriptor.Version = version;
+        descriptor.Opcode = opcode;
+        descriptor.Task = task;
+        descriptor.Level = level;
+        record.EventHeader.ProviderId = Guid::from(DUPLICATE_SUMMARY_GUID);
+        record.EventHeader.TimeStamp = run.summary.last_timestamp;
+        record.UserData = &run.summary as *const DuplicateSummary as *mut _;
+        record.UserDataLength = std::mem::size_of::<DuplicateSummary>() as u16;
+        let _dispatched = DispatchedSummary::set(&run.summary);
+        self.dispatch(record, locator);
+    }
+
+    pub(crate) fn flush_duplicates(&self) {
+        let run = match self.duplicate_run.write() {
+            Ok(mut run) => run.take(),
+            Err(_) => return,
+        };
+        // The summary can't be located, the Trace locator is left to the consumer thread
+        self.report_duplicates(run, &mut schema::SchemaLocator::new());
+    }
 }
 
 #[cfg(test)]
@@ -514,12 +1916,357 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_tracelogging_guid() {
+        let prov = Provider::new().by_tracelogging_name("MyCompany.MyComponent");
+        assert_eq!(
+            Guid::from("ce5fa4ea-ab00-5402-8b76-9f76ac858fb5"),
+            prov.guid.unwrap()
+        );
+
+        let prov = Provider::new().by_tracelogging_name("Microsoft-Windows-Example");
+        assert_eq!(
+            Guid::from("01348cd9-e85f-59c9-bc70-3304d80a3378"),
+            prov.guid.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tracelogging_guid_case_insensitive() {
+        assert_eq!(
+            tracelogging_guid("MyCompany.MyComponent"),
+            tracelogging_guid("mycompany.mycomponent")
+        );
+    }
+
     #[test]
     fn test_set_level() {
         let prov = Provider::new().level(1);
         assert_eq!(1, prov.level);
     }
 
+    #[test]
+    fn test_try_by_name_unknown_provider() {
+        let prov = Provider::new().try_by_name("Ferrisetw-Not-A-Provider");
+        assert!(matches!(
+            prov,
+            Err(ProviderError::ComProvider(pla::PlaError::NotFound))
+        ));
+    }
+
+    #[test]
+    fn test_with_event_ids() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .with_event_ids(vec![1, 5])
+            .build()
+            .unwrap();
+        assert_eq!(prov.event_ids, vec![1, 5]);
+
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .with_event_ids((0..65).collect())
+            .build();
+        assert!(matches!(prov, Err(ProviderError::TooManyEventIds(65))));
+    }
+
+    #[test]
+    fn test_debug_format() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .any(0x10)
+            .add_callback(|_, _| {})
+            .build()
+            .unwrap();
+
+        let formatted = format!("{:?}", prov);
+        assert!(formatted.contains("22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716"));
+        assert!(formatted.contains("any: 0x10"));
+        assert!(formatted.contains("callbacks: 1"));
+    }
+
+    #[test]
+    fn test_keyword_builders() {
+        let prov = Provider::new().any_keyword(Keyword::PROCESS);
+        assert_eq!(prov.any, 0x10);
+
+        let prov = Provider::new()
+            .any_keyword(Keyword::PROCESS | Keyword::THREAD | Keyword::IMAGE)
+            .all_keyword(Keyword::from(0x4000000000000000));
+        assert_eq!(prov.any, 0x70);
+        assert_eq!(prov.all, 0x4000000000000000);
+
+        let mut keywords = Keyword::from(0xf000_0000_0000_0000) | Keyword::RESPONSE_TIME;
+        keywords |= Keyword(0x3ff);
+        assert_eq!(keywords.bits(), 0xf0010000000003ff);
+    }
+
+    #[test]
+    fn test_level_typed() {
+        assert_eq!(Provider::new().level_typed(TraceLevel::Warning).level, 3);
+        assert_eq!(u8::from(TraceLevel::Verbose), 5);
+        assert_eq!(TraceLevel::Critical.to_u8(), 1);
+    }
+
+    #[test]
+    fn test_all_levels_enables_log_always() {
+        let prov = Provider::new().all_levels();
+        assert_eq!(prov.level, 0);
+        assert_eq!(
+            prov.level,
+            Provider::new().level(TraceLevel::LogAlways as u8).level
+        );
+    }
+
+    fn record_with_level(level: u8) -> EventRecord {
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.EventDescriptor.Level = level;
+        record
+    }
+
+    #[test]
+    fn test_min_level_suppresses_verbose_events() {
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let callback_count = Arc::clone(&count);
+        let prov = Provider::new()
+            .min_level(TraceLevel::Warning)
+            .add_callback(move |_, _| {
+                callback_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            });
+        let mut locator = schema::SchemaLocator::new();
+
+        for level in 0..=5 {
+            prov.on_event(record_with_level(level), &mut locator);
+        }
+
+        // LogAlways, Critical, Error and Warning
+        assert_eq!(4, count.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_level_override_only_applies_to_its_event_id() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_events = Arc::clone(&events);
+        let prov = Provider::new()
+            .level_override(7, TraceLevel::Warning)
+            .add_callback(move |record: EventRecord, _| {
+                let descriptor = record.EventHeader.EventDescriptor;
+                callback_events
+                    .lock()
+                    .unwrap()
+                    .push((descriptor.Id, descriptor.Level));
+            });
+        let mut locator = schema::SchemaLocator::new();
+
+        for id in [7, 8].iter() {
+            for level in [2, 5].iter() {
+                let mut record = record_with_level(*level);
+                record.EventHeader.EventDescriptor.Id = *id;
+                prov.on_event(record, &mut locator);
+            }
+        }
+
+        assert_eq!(vec![(7, 2), (8, 2), (8, 5)], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn test_self_only_drops_other_processes() {
+        let pids = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_pids = Arc::clone(&pids);
+        let prov = Provider::new()
+            .self_only()
+            .add_callback(move |record: EventRecord, _| {
+                callback_pids
+                    .lock()
+                    .unwrap()
+                    .push(record.EventHeader.ProcessId);
+            });
+        let mut locator = schema::SchemaLocator::new();
+
+        for pid in [std::process::id(), std::process::id() + 1, 4].iter() {
+            let mut record = record_with_level(4);
+            record.EventHeader.ProcessId = *pid;
+            prov.on_event(record, &mut locator);
+        }
+
+        assert_eq!(vec![std::process::id()], *pids.lock().unwrap());
+    }
+
+    #[test]
+    fn test_on_parsed_resolves_schema() {
+        use crate::native::tdh_types::TdhInType;
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+        use crate::parser::TryParse;
+
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1).property(
+            TestProperty::new("ProcessID", TdhInType::InTypeUInt32 as u16, 4),
+        );
+        let event = TestEvent::new(&test_schema, 1234u32.to_ne_bytes().to_vec());
+
+        let parsed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_parsed = Arc::clone(&parsed);
+        let prov = Provider::new().on_parsed(move |meta, parser| {
+            let pid: u32 = parser.try_parse("ProcessID").unwrap();
+            callback_parsed.lock().unwrap().push((meta.event_id, pid));
+        });
+        let mut locator = schema::SchemaLocator::new();
+        locator.insert_schema(&event.record, test_schema.build());
+
+        prov.on_event(event.record, &mut locator);
+        prov.on_event(event.record, &mut locator);
+
+        assert_eq!(vec![(1, 1234), (1, 1234)], *parsed.lock().unwrap());
+    }
+
+    #[test]
+    fn test_dedupe_window_collapses_bursts() {
+        use crate::native::tdh_types::TdhInType;
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1).property(
+            TestProperty::new("ProcessID", TdhInType::InTypeUInt32 as u16, 4),
+        );
+        let burst = TestEvent::new(&test_schema, 1234u32.to_ne_bytes().to_vec());
+        let other = TestEvent::new(&test_schema, 5678u32.to_ne_bytes().to_vec());
+
+        let dispatched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let summaries = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_dispatched = Arc::clone(&dispatched);
+        let callback_summaries = Arc::clone(&summaries);
+        let prov = Provider::new()
+            .dedupe_window(Duration::from_millis(1), "ProcessID")
+            .add_callback(move |record: EventRecord, _| {
+                match DuplicateSummary::from_record(&record) {
+                    Some(summary) => callback_summaries.lock().unwrap().push(summary),
+                    None => callback_dispatched
+                        .lock()
+                        .unwrap()
+                        .push(record.EventHeader.TimeStamp),
+                }
+            });
+        let mut locator = schema::SchemaLocator::new();
+        locator.insert_schema(&burst.record, test_schema.build());
+
+        // 1ms is 10_000 units of 100ns
+        for timestamp in [100, 200, 300, 400, 10_100, 20_200].iter() {
+            let mut record = burst.record;
+            record.EventHeader.TimeStamp = *timestamp;
+            prov.on_event(record, &mut locator);
+        }
+        let mut record = other.record;
+        record.EventHeader.TimeStamp = 20_300;
+        prov.on_event(record, &mut locator);
+        prov.flush_duplicates();
+
+        // The event after the window starts a new run, the different key ends it
+        assert_eq!(vec![100, 20_200, 20_300], *dispatched.lock().unwrap());
+        assert_eq!(
+            vec![DuplicateSummary {
+                provider_id: Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716"),
+                event_id: 1,
+                key: 1234u32.to_ne_bytes().to_vec(),
+                count: 4,
+                first_timestamp: 100,
+                last_timestamp: 10_100,
+            }],
+            *summaries.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_dedupe_flush_delivers_summary_to_callbacks() {
+        use crate::native::tdh_types::TdhInType;
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 3).property(
+            TestProperty::new("ProcessID", TdhInType::InTypeUInt32 as u16, 4),
+        );
+        let burst = TestEvent::new(&test_schema, 1234u32.to_ne_bytes().to_vec());
+
+        let summaries = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let parsed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let missing = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let callback_summaries = Arc::clone(&summaries);
+        let callback_parsed = Arc::clone(&parsed);
+        let callback_missing = Arc::clone(&missing);
+        let prov = Provider::new()
+            .dedupe_window(Duration::from_secs(1), "ProcessID")
+            .add_callback_for_ids(&[3], move |record: EventRecord, _| {
+                if let Some(summary) = DuplicateSummary::from_record(&record) {
+                    callback_summaries
+                        .lock()
+                        .unwrap()
+                        .push((record.EventHeader.EventDescriptor.Id, summary.count));
+                }
+            })
+            .on_parsed(move |_, _| {
+                callback_parsed.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_missing_schema(move |_, _| {
+                callback_missing.fetch_add(1, Ordering::SeqCst);
+            });
+        let mut locator = schema::SchemaLocator::new();
+        locator.insert_schema(&burst.record, test_schema.build());
+
+        for timestamp in [100, 200, 300].iter() {
+            let mut record = burst.record;
+            record.EventHeader.TimeStamp = *timestamp;
+            prov.on_event(record, &mut locator);
+        }
+        assert!(summaries.lock().unwrap().is_empty());
+
+        // The run still open is reported on stop
+        prov.flush_duplicates();
+        assert_eq!(vec![(3, 2)], *summaries.lock().unwrap());
+        assert_eq!(1, parsed.load(Ordering::SeqCst));
+        assert_eq!(0, missing.load(Ordering::SeqCst));
+        assert!(DuplicateSummary::from_record(&burst.record).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_summary_from_forged_record() {
+        let garbage = [0xffu8; std::mem::size_of::<DuplicateSummary>()];
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.ProviderId = Guid::from(DUPLICATE_SUMMARY_GUID);
+        record.UserData = garbage.as_ptr() as *mut _;
+        record.UserDataLength = garbage.len() as u16;
+
+        // Right ProviderId and length, but not the summary being dispatched
+        assert!(DuplicateSummary::from_record(&record).is_none());
+    }
+
+    #[test]
+    fn test_on_missing_schema_receives_errors() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        // No manifest is registered for this Provider, TDH can't locate the schema
+        let event = TestEvent::new(
+            &TestSchema::new("7f3d5a0e-56e2-4c9b-9d33-0c6a7e2f1b41", 2),
+            Vec::new(),
+        );
+
+        let parsed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let missing = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_parsed = Arc::clone(&parsed);
+        let callback_missing = Arc::clone(&missing);
+        let prov = Provider::new()
+            .on_parsed(move |_, _| {
+                callback_parsed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            })
+            .on_missing_schema(move |record, _| {
+                callback_missing
+                    .lock()
+                    .unwrap()
+                    .push(record.EventHeader.EventDescriptor.Id);
+            });
+        let mut locator = schema::SchemaLocator::new();
+
+        prov.on_event(event.record, &mut locator);
+
+        assert_eq!(0, parsed.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(vec![2], *missing.lock().unwrap());
+    }
+
     #[test]
     fn test_set_any() {
         let prov = Provider::new().any(0x1993);
@@ -532,6 +2279,88 @@ mod test {
         assert_eq!(0x1302, prov.all);
     }
 
+    #[test]
+    fn test_source_id_in_parameters() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .source_id("6b1ee1a2-cb25-4bfc-9e05-4c8d4cb8bd42");
+        let params = prov.enable_parameters();
+        assert_eq!(
+            Guid::from("6b1ee1a2-cb25-4bfc-9e05-4c8d4cb8bd42"),
+            params.SourceId
+        );
+    }
+
+    #[test]
+    fn test_invalid_source_id() {
+        let guid = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+        for malformed in ["", "{6b1ee1a2-cb25-4bfc-9e05-4c8d4cb8bd42}", "not-a-guid"] {
+            assert!(matches!(
+                Provider::new().by_guid(guid).try_source_id(malformed),
+                Err(ProviderError::InvalidSourceId(value)) if value == malformed
+            ));
+
+            let prov = Provider::new().by_guid(guid).source_id(malformed);
+            assert!(prov.source_id.is_none());
+            assert!(matches!(
+                prov.build(),
+                Err(ProviderError::InvalidSourceId(value)) if value == malformed
+            ));
+        }
+
+        // A valid Source Id set afterwards replaces the malformed one
+        let prov = Provider::new()
+            .by_guid(guid)
+            .source_id("not-a-guid")
+            .source_id("6b1ee1a2-cb25-4bfc-9e05-4c8d4cb8bd42")
+            .build();
+        assert!(prov.is_ok());
+    }
+
+    #[test]
+    fn test_default_source_id_in_parameters() {
+        let prov = Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        let params = prov.enable_parameters();
+        assert_eq!(
+            Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716"),
+            params.SourceId
+        );
+    }
+
+    #[test]
+    fn test_exclude_private_in_parameters() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .trace_flags(0x4);
+        assert_eq!(prov.enable_parameters().EnableProperty, 0x4);
+
+        let prov = prov.exclude_private(true);
+        // EVENT_ENABLE_PROPERTY_EXCLUDE_INPRIVATE
+        assert_eq!(prov.enable_parameters().EnableProperty, 0x200 | 0x4);
+    }
+
+    #[test]
+    fn test_stack_trace_in_parameters() {
+        let prov = Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        assert!(!prov.stack_trace);
+
+        let prov = prov.with_stack_trace().exclude_private(true);
+        // EVENT_ENABLE_PROPERTY_STACK_TRACE
+        assert_eq!(prov.enable_parameters().EnableProperty, 0x200 | 0x4);
+    }
+
+    #[test]
+    fn test_enable_timeout_ms() {
+        let prov = Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        assert_eq!(0, prov.enable_timeout_ms());
+
+        let prov = prov.enable_timeout(Duration::from_millis(2500));
+        assert_eq!(2500, prov.enable_timeout_ms());
+
+        let prov = prov.enable_timeout(Duration::from_secs(u64::MAX));
+        assert_eq!(u32::MAX, prov.enable_timeout_ms());
+    }
+
     #[test]
     fn test_set_trace_flags() {
         let prov = Provider::new().trace_flags(100);
@@ -553,6 +2382,141 @@ mod test {
         assert_eq!(3, prov.callbacks.read().unwrap().len());
     }
 
+    #[test]
+    fn test_callback_with_context() {
+        use std::collections::HashMap;
+        use std::sync::Mutex;
+
+        let counts = Arc::new(Mutex::new(HashMap::new()));
+        let prov = Provider::new().add_callback_with_context(
+            Arc::clone(&counts),
+            |counts: &Mutex<HashMap<u16, usize>>, record: EventRecord, _| {
+                let id = record.EventHeader.EventDescriptor.Id;
+                *counts.lock().unwrap().entry(id).or_default() += 1;
+            },
+        );
+        let mut locator = schema::SchemaLocator::new();
+
+        for id in [1, 2, 1, 1].iter() {
+            let mut record = record_with_level(4);
+            record.EventHeader.EventDescriptor.Id = *id;
+            prov.on_event(record, &mut locator);
+        }
+
+        let counts = counts.lock().unwrap();
+        assert_eq!(counts.get(&1), Some(&3));
+        assert_eq!(counts.get(&2), Some(&1));
+    }
+
+    #[test]
+    fn test_remove_and_clear_callbacks() {
+        let prov = Provider::new().add_callback(|_x, _y| {});
+        let first = prov.register_callback(|_x, _y| {});
+        let second = prov.register_callback(|_x, _y| {});
+        assert_ne!(first, second);
+        assert_eq!(3, prov.callbacks.read().unwrap().len());
+
+        assert!(prov.remove_callback(first));
+        assert!(!prov.remove_callback(first));
+        assert_eq!(2, prov.callbacks.read().unwrap().len());
+
+        prov.clear_callbacks();
+        assert_eq!(0, prov.callbacks.read().unwrap().len());
+        assert!(!prov.remove_callback(second));
+    }
+
+    #[test]
+    fn test_clone_shares_callbacks() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .any(0xf0)
+            .all(0x10)
+            .level(4)
+            .with_event_ids(vec![1, 2])
+            .add_callback(|_x, _y| {});
+        let copy = prov.clone();
+
+        assert_eq!(copy.guid, prov.guid);
+        assert_eq!((copy.any, copy.all, copy.level), (0xf0, 0x10, 4));
+        assert_eq!(copy.event_ids, vec![1, 2]);
+        assert_eq!(1, copy.callbacks.read().unwrap().len());
+
+        // Ids stay unique across the copies since the callbacks are shared
+        let id = copy.register_callback(|_x, _y| {});
+        assert_ne!(id, prov.register_callback(|_x, _y| {}));
+        assert_eq!(3, prov.callbacks.read().unwrap().len());
+        assert!(prov.remove_callback(id));
+        assert_eq!(2, copy.callbacks.read().unwrap().len());
+    }
+
+    #[test]
+    fn test_try_by_guid() {
+        let guid = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+        let prov = Provider::new().try_by_guid(guid).unwrap();
+        assert_eq!(prov.guid, Some(Guid::from(guid)));
+        let prov = Provider::new().by_guid_value(Guid::from(guid));
+        assert_eq!(prov.guid, Some(Guid::from(guid)));
+
+        for malformed in [
+            "",
+            "not-a-guid",
+            "{22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716}",
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e71g",
+            "22fb2cd60e7b422ba0c72fad1fd0e716",
+        ] {
+            assert!(matches!(
+                Provider::new().try_by_guid(malformed),
+                Err(ProviderError::InvalidGuid(value)) if value == malformed
+            ));
+        }
+    }
+
+    #[test]
+    fn test_builder_fail_invalid_guid() {
+        for malformed in [
+            "{22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716}",
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e71",
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e7166",
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e71z",
+            "22fb2cd6_0e7b_422b_a0c7_2fad1fd0e716",
+        ] {
+            let prov = Provider::new().by_guid(malformed);
+            assert!(prov.guid.is_none());
+            assert!(matches!(
+                prov.build(),
+                Err(ProviderError::InvalidGuid(value)) if value == malformed
+            ));
+        }
+
+        // A valid GUID bound afterwards replaces the malformed one
+        let prov = Provider::new()
+            .by_guid("not-a-guid")
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .build();
+        assert!(prov.is_ok());
+    }
+
+    #[test]
+    fn test_callback_for_ids() {
+        let ids = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_ids = Arc::clone(&ids);
+        let prov = Provider::new().add_callback_for_ids(&[2, 5], move |record: EventRecord, _| {
+            callback_ids
+                .lock()
+                .unwrap()
+                .push(record.EventHeader.EventDescriptor.Id)
+        });
+        let mut locator = schema::SchemaLocator::new();
+
+        for id in [1, 2, 3, 2, 5].iter() {
+            let mut record = record_with_level(4);
+            record.EventHeader.EventDescriptor.Id = *id;
+            prov.on_event(record, &mut locator);
+        }
+
+        assert_eq!(vec![2, 2, 5], *ids.lock().unwrap());
+    }
+
     #[test]
     fn test_builder_fail_no_guid() {
         let prov = Provider::new().build();
@@ -590,4 +2554,74 @@ mod test {
         assert_eq!(true, kernel_provider.guid.is_some());
         assert_eq!(Guid::from(IMAGE_LOAD_GUID), kernel_provider.guid.unwrap());
     }
+
+    #[test]
+    fn test_kernel_provider_all() {
+        let kernel_provider = KernelProvider::all();
+
+        assert_eq!(Guid::from(SYSTEM_TRACE_GUID), kernel_provider.guid);
+        assert_eq!(ALL_FLAGS, kernel_provider.flags);
+        assert_eq!(0x06b7_ffff, kernel_provider.flags);
+        assert_eq!(
+            EVENT_TRACE_FLAG_PROCESS | EVENT_TRACE_FLAG_THREAD,
+            kernel_provider.flags & (EVENT_TRACE_FLAG_PROCESS | EVENT_TRACE_FLAG_THREAD)
+        );
+        assert_ne!(0, kernel_provider.flags & EVENT_TRACE_FLAG_FILE_IO_INIT);
+        assert_eq!(0, kernel_provider.flags & EVENT_TRACE_FLAG_PROFILE);
+    }
+
+    #[test]
+    fn test_kernel_flags_combined() {
+        let prov = Provider::kernel(&PROCESS_PROVIDER).kernel_flags(EVENT_TRACE_FLAG_THREAD);
+        assert_eq!(
+            EVENT_TRACE_FLAG_PROCESS | EVENT_TRACE_FLAG_THREAD,
+            prov.flags
+        );
+        assert_eq!(0x3, prov.flags);
+        assert_eq!(Some(Guid::from(PROCESS_GUID)), prov.guid);
+
+        let process =
+            KernelProvider::combine(&[&PROCESS_PROVIDER, &PROCESS_COUNTER_PROVIDER]).unwrap();
+        assert_eq!(Guid::from(PROCESS_GUID), process.guid);
+        assert_eq!(
+            EVENT_TRACE_FLAG_PROCESS | EVENT_TRACE_FLAG_PROCESS_COUNTERS,
+            process.flags
+        );
+
+        assert!(matches!(
+            KernelProvider::combine(&[&PROCESS_PROVIDER, &THREAD_PROVIDER]),
+            Err(ProviderError::KernelGuidMismatch(guid)) if guid == Guid::from(THREAD_GUID)
+        ));
+        assert!(matches!(
+            KernelProvider::combine(&[]),
+            Err(ProviderError::NoGuid)
+        ));
+    }
+
+    #[test]
+    fn test_well_known_kernel_providers() {
+        let expected = [
+            (
+                &*UDP_IP_PROVIDER,
+                UDP_IP_GUID,
+                EVENT_TRACE_FLAG_NETWORK_TCPIP,
+            ),
+            (
+                &*SAMPLED_PROFILE_PROVIDER,
+                PERF_INFO_GUID,
+                EVENT_TRACE_FLAG_PROFILE,
+            ),
+            (&*OBJECT_MANAGER_PROVIDER, OB_TRACE_GUID, 0),
+            (&*SYSTEM_CONFIG_PROVIDER, EVENT_TRACE_CONFIG_GUID, 0),
+            (&*LOST_EVENT_PROVIDER, LOST_EVENT_GUID, 0),
+        ];
+        for (provider, guid, flags) in expected.iter() {
+            assert_eq!(Guid::from(*guid), provider.guid);
+            assert_eq!(*flags, provider.flags);
+        }
+
+        for guid in [OB_TRACE_GUID, EVENT_TRACE_CONFIG_GUID, LOST_EVENT_GUID] {
+            assert!(by_guid(&Guid::from(guid)).is_some());
+        }
+    }
 }
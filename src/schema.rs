@@ -1,9 +1,15 @@
 //! ETW Event Schema locator and handler
 //!
 //! This module contains the means needed to locate and interact with the Schema of an ETW event
-use crate::native::etw_types::{DecodingSource, EventRecord, TraceEventInfoRaw};
+use crate::native::etw_types::{
+    self, DecodingSource, EventRecord, EventRecordExt, TraceEventInfoRaw,
+    EVENT_HEADER_FLAG_32_BIT_HEADER,
+};
 use crate::native::tdh;
-use crate::native::tdh_types::Property;
+use crate::native::tdh_types::{Property, PropertyFlags, TdhContext};
+use crate::parser::{Parser, ParserError, PropertyCursor};
+use crate::property::PropertyInfo;
+use crate::utils;
 use std::collections::HashMap;
 use std::sync::Arc;
 use windows::Guid;
@@ -13,6 +19,8 @@ use windows::Guid;
 pub enum SchemaError {
     /// Represents a Parser error
     ParseError,
+    /// Represents an invalid [TdhContext] passed to [SchemaLocator::set_context]
+    InvalidContext(String),
     /// Represents an internal [TdhNativeError]
     ///
     /// [TdhNativeError]: tdh::TdhNativeError
@@ -29,9 +37,7 @@ type SchemaResult<T> = Result<T, SchemaError>;
 
 #[derive(Debug, Eq, PartialEq, Hash)]
 struct SchemaKey {
-    // For now, lazy to wrap Guid around an implement Hash
-    // TODO: wrap Guid and implement hash
-    provider: String,
+    provider: [u8; 16],
     id: u16,
     opcode: u8,
     version: u8,
@@ -40,9 +46,8 @@ struct SchemaKey {
 
 impl SchemaKey {
     pub fn new(event: &EventRecord) -> Self {
-        let provider = format!("{:?}", event.EventHeader.ProviderId);
         SchemaKey {
-            provider,
+            provider: utils::guid_bytes(&event.EventHeader.ProviderId),
             id: event.EventHeader.EventDescriptor.Id,
             opcode: event.EventHeader.EventDescriptor.Opcode,
             version: event.EventHeader.EventDescriptor.Version,
@@ -51,6 +56,48 @@ impl SchemaKey {
     }
 }
 
+/// Identifies the kind of an Event
+///
+/// An `EventSignature` is the combination of the Provider GUID and the EventId of an [EventRecord],
+/// it can be used to group or count events without having to locate their [Schema]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EventSignature {
+    /// Provider GUID of the Event
+    pub provider_id: Guid,
+    /// EventId of the Event
+    pub event_id: u16,
+}
+
+impl EventSignature {
+    /// Use the `new` function to create an EventSignature
+    ///
+    /// # Arguments
+    /// * `provider_id` - Provider GUID of the Event
+    /// * `event_id` - EventId of the Event
+    pub fn new(provider_id: Guid, event_id: u16) -> Self {
+        EventSignature {
+            provider_id,
+            event_id,
+        }
+    }
+}
+
+impl From<&EventRecord> for EventSignature {
+    fn from(record: &EventRecord) -> Self {
+        EventSignature::new(
+            record.EventHeader.ProviderId,
+            record.EventHeader.EventDescriptor.Id,
+        )
+    }
+}
+
+impl std::hash::Hash for EventSignature {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        utils::guid_bytes(&self.provider_id).hash(state);
+        self.event_id.hash(state);
+    }
+}
+
 /// Represents a cache of Schemas already located
 ///
 /// This cache is implemented as a [HashMap] where the key is a combination of the following elements
@@ -61,10 +108,61 @@ impl SchemaKey {
 /// * EventHeader.EventDescriptor.Version
 /// * EventHeader.EventDescriptor.Level
 ///
+/// Lookups that fail, e.g. for events of a Provider without a registered manifest, are cached
+/// with the same key so identical events don't query TDH again, see [SchemaLocator::clear_cache]
+///
 /// Credits: [KrabsETW::schema_locator](https://github.com/microsoft/krabsetw/blob/master/krabs/krabs/schema_locator.hpp)
-#[derive(Default)]
 pub struct SchemaLocator {
-    schemas: HashMap<SchemaKey, Arc<TraceEventInfoRaw>>,
+    schemas: HashMap<SchemaKey, CachedSchema>,
+    missing: HashMap<SchemaKey, tdh::TdhNativeError>,
+    contexts: Vec<TdhContext>,
+    lookup: SchemaLookup,
+}
+
+// Locates the TRACE_EVENT_INFO of an event, `tdh::schema_from_tdh` unless replaced by a test
+type SchemaLookup = fn(EventRecord, &[TdhContext]) -> tdh::TdhNativeResult<TraceEventInfoRaw>;
+
+impl Default for SchemaLocator {
+    fn default() -> Self {
+        SchemaLocator::new()
+    }
+}
+
+// A located TRACE_EVENT_INFO along with its names, resolved once per kind of event
+struct CachedSchema {
+    info: Arc<TraceEventInfoRaw>,
+    names: Arc<EventNames>,
+}
+
+impl CachedSchema {
+    fn new(info: TraceEventInfoRaw) -> Self {
+        CachedSchema {
+            names: Arc::new(EventNames::new(&info)),
+            info: Arc::new(info),
+        }
+    }
+}
+
+/// Names of an event, from its [TraceEventInfo]
+///
+/// [TraceEventInfo]: crate::native::etw_types::TraceEventInfo
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventNames {
+    provider_name: Option<String>,
+    task_name: Option<String>,
+    opcode_name: String,
+    level_name: String,
+}
+
+impl EventNames {
+    fn new(info: &TraceEventInfoRaw) -> Self {
+        EventNames {
+            provider_name: info.provider_name(),
+            task_name: info.task_name(),
+            opcode_name: info.opcode_name(),
+            level_name: info.level_name(),
+        }
+    }
 }
 
 impl std::fmt::Debug for SchemaLocator {
@@ -77,7 +175,97 @@ impl SchemaLocator {
     pub(crate) fn new() -> Self {
         SchemaLocator {
             schemas: HashMap::new(),
+            missing: HashMap::new(),
+            contexts: Vec::new(),
+            lookup: tdh::schema_from_tdh,
+        }
+    }
+
+    /// Use the `clear_cache` function to drop the Schemas already located
+    ///
+    /// # Remarks
+    /// The failed lookups are dropped too, this should be called when a Provider registers its
+    /// manifest after its first events were received so the next events are looked up again
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     if schema_locator.event_schema(record).is_err() {
+    ///         schema_locator.clear_cache();
+    ///     }
+    /// };
+    /// ```
+    pub fn clear_cache(&mut self) {
+        self.schemas.clear();
+        self.missing.clear();
+    }
+
+    /// Use the `set_context` function to set the [TdhContext] used to locate the Schemas
+    ///
+    /// WPP and some legacy Providers can't be decoded without extra information, like the TMF
+    /// file of a WPP Provider or the pointer size of the machine that logged the events
+    ///
+    /// # Arguments
+    /// * `contexts` - The [TdhContext] to use, each context type can only be set once
+    ///
+    /// # Remarks
+    /// The Schemas already located are dropped since they might decode differently with the new
+    /// contexts. A [TdhContext::PointerSize] also overrides the pointer size the [Parser] uses
+    ///
+    /// This function can fail, if a context is invalid it will return a [SchemaError::InvalidContext]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     schema_locator.set_context(vec![
+    ///         TdhContext::WppTmfFile(String::from("C:\\symbols\\my_driver.tmf")),
+    ///         TdhContext::PointerSize(8),
+    ///     ])?;
+    ///     let schema = schema_locator.event_schema(record)?;
+    /// };
+    /// ```
+    pub fn set_context(&mut self, contexts: Vec<TdhContext>) -> SchemaResult<()> {
+        for (i, context) in contexts.iter().enumerate() {
+            match context {
+                TdhContext::PointerSize(size) if *size != 4 && *size != 8 => {
+                    return Err(SchemaError::InvalidContext(format!(
+                        "Invalid pointer size {}",
+                        size
+                    )));
+                }
+                TdhContext::WppTmfFile(path)
+                | TdhContext::WppTmfSearchPath(path)
+                | TdhContext::PdbPath(path)
+                    if path.is_empty() =>
+                {
+                    return Err(SchemaError::InvalidContext(format!(
+                        "Empty path in {:?}",
+                        context
+                    )));
+                }
+                _ => {}
+            }
+            if contexts[..i]
+                .iter()
+                .any(|prev| prev.context_type() == context.context_type())
+            {
+                return Err(SchemaError::InvalidContext(format!(
+                    "Context set more than once: {:?}",
+                    context
+                )));
+            }
         }
+
+        self.contexts = contexts;
+        self.clear_cache();
+        Ok(())
+    }
+
+    fn pointer_size(&self) -> Option<u32> {
+        self.contexts.iter().find_map(|context| match context {
+            TdhContext::PointerSize(size) => Some(*size),
+            _ => None,
+        })
     }
 
     /// Use the `event_schema` function to retrieve the Schema of an ETW Event
@@ -90,7 +278,8 @@ impl SchemaLocator {
     /// works as expected this function will return a Result with the [Schema] that represents
     /// the ETW event that triggered the callback
     ///
-    /// This function can fail, if it does it will return a [SchemaError]
+    /// This function can fail, if it does it will return a [SchemaError]. The failure is cached,
+    /// the following events of the same kind return the same error without querying TDH
     ///
     /// # Example
     /// ```rust
@@ -100,20 +289,55 @@ impl SchemaLocator {
     /// ```
     pub fn event_schema(&mut self, event: EventRecord) -> SchemaResult<Schema> {
         let key = SchemaKey::new(&event);
-        let info: Arc<_>;
 
+        if let Some(err) = self.missing.get(&key) {
+            return Err(SchemaError::TdhNativeError(copy_tdh_error(err)));
+        }
         if !self.schemas.contains_key(&key) {
             // TODO: Cloning for now, should be a reference at some point...
-            info = Arc::from(tdh::schema_from_tdh(event.clone())?);
-            self.schemas.insert(key, Arc::clone(&info));
-        } else {
-            info = Arc::clone(self.schemas.get(&key).unwrap());
+            match (self.lookup)(event.clone(), &self.contexts) {
+                Ok(info) => {
+                    self.schemas
+                        .insert(SchemaKey::new(&event), CachedSchema::new(info));
+                }
+                Err(err) => {
+                    let copy = copy_tdh_error(&err);
+                    self.missing.insert(key, err);
+                    return Err(SchemaError::TdhNativeError(copy));
+                }
+            }
         }
+        let cached = self.schemas.get(&key).unwrap();
+
+        let mut schema = Schema::new(event, Arc::clone(&cached.info));
+        schema.names = Some(Arc::clone(&cached.names));
+        schema.pointer_size = self.pointer_size();
+        Ok(schema)
+    }
 
-        Ok(Schema::new(event, info))
+    // Lets tests feed a synthetic TRACE_EVENT_INFO instead of querying TDH
+    #[cfg(test)]
+    pub(crate) fn insert_schema(&mut self, event: &EventRecord, info: TraceEventInfoRaw) {
+        self.schemas
+            .insert(SchemaKey::new(event), CachedSchema::new(info));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_lookup(&mut self, lookup: SchemaLookup) {
+        self.lookup = lookup;
     }
 }
 
+// std::io::Error isn't Clone, the OS error code is all TDH reports anyway
+fn copy_tdh_error(err: &tdh::TdhNativeError) -> tdh::TdhNativeError {
+    let tdh::TdhNativeError::IoError(err) = err;
+    let copy = match err.raw_os_error() {
+        Some(code) => std::io::Error::from_raw_os_error(code),
+        None => std::io::Error::new(err.kind(), err.to_string()),
+    };
+    tdh::TdhNativeError::IoError(copy)
+}
+
 /// Represents a Schema
 ///
 /// This structure holds a [TraceEventInfo](https://docs.microsoft.com/en-us/windows/win32/api/tdh/ns-tdh-trace_event_info)
@@ -121,23 +345,34 @@ impl SchemaLocator {
 pub struct Schema {
     record: EventRecord,
     schema: Arc<TraceEventInfoRaw>,
+    pointer_size: Option<u32>,
+    // Set when located through the SchemaLocator, shared by the events of the same kind
+    names: Option<Arc<EventNames>>,
 }
 
 impl Schema {
     pub(crate) fn new(record: EventRecord, schema: Arc<TraceEventInfoRaw>) -> Self {
-        Schema { record, schema }
+        Schema {
+            record,
+            schema,
+            pointer_size: None,
+            names: None,
+        }
     }
 
-    pub(crate) fn user_buffer(&self) -> Vec<u8> {
-        unsafe {
-            std::slice::from_raw_parts(
-                self.record.UserData as *mut _,
-                self.record.UserDataLength.into(),
-            )
-            .to_vec()
+    // Size of a pointer on the machine that logged the event, unless overridden by a TdhContext
+    pub(crate) fn pointer_size(&self) -> u32 {
+        match self.pointer_size {
+            Some(pointer_size) => pointer_size,
+            None if (self.event_flags() & EVENT_HEADER_FLAG_32_BIT_HEADER) != 0 => 4,
+            None => 8,
         }
     }
 
+    pub(crate) fn user_buffer(&self) -> Vec<u8> {
+        etw_types::user_data(&self.record).to_vec()
+    }
+
     // Horrible getters FTW!! :D
     // TODO: Not a big fan of this, think a better way..
     pub(crate) fn record(&self) -> EventRecord {
@@ -204,6 +439,53 @@ impl Schema {
         self.record.EventHeader.EventDescriptor.Version
     }
 
+    /// Use the `task` function to obtain the Task of the [EventRecord]
+    ///
+    /// This getter returns the Task of the ETW Event that triggered the registered callback, along
+    /// with the [opcode](Schema::opcode) it tells apart the start and stop events of an operation
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let task = schema.task();
+    /// };
+    /// ```
+    pub fn task(&self) -> u16 {
+        self.record.EventHeader.EventDescriptor.Task
+    }
+
+    /// Use the `level` function to obtain the Level of the [EventRecord]
+    ///
+    /// This getter returns the Level of the ETW Event that triggered the registered callback
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let level = schema.level();
+    /// };
+    /// ```
+    pub fn level(&self) -> u8 {
+        self.record.EventHeader.EventDescriptor.Level
+    }
+
+    /// Use the `keyword` function to obtain the Keyword of the [EventRecord]
+    ///
+    /// This getter returns the Keyword bitmask of the ETW Event that triggered the registered
+    /// callback
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let keyword = schema.keyword();
+    /// };
+    /// ```
+    pub fn keyword(&self) -> u64 {
+        self.record.EventHeader.EventDescriptor.Keyword
+    }
+
     /// Use the `process_id` function to obtain the ProcessId of the [EventRecord]
     ///
     /// This getter returns the ProcessId of the process that triggered the ETW Event
@@ -216,7 +498,7 @@ impl Schema {
     /// };
     /// ```  
     pub fn process_id(&self) -> u32 {
-        self.record.EventHeader.ProcessId
+        self.record.process_id()
     }
 
     /// Use the `thread_id` function to obtain the ThreadId of the [EventRecord]
@@ -231,7 +513,7 @@ impl Schema {
     /// };
     /// ```  
     pub fn thread_id(&self) -> u32 {
-        self.record.EventHeader.ThreadId
+        self.record.thread_id()
     }
 
     /// Use the `timestamp` function to obtain the TimeStamp of the [EventRecord]
@@ -282,32 +564,95 @@ impl Schema {
         self.schema.decoding_source()
     }
 
+    /// Use the `provider_guid` function to obtain the GUID of the Provider that logged the Event
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let provider_guid = schema.provider_guid();
+    /// };
+    /// ```
+    pub fn provider_guid(&self) -> Guid {
+        self.record.EventHeader.ProviderId
+    }
+
+    /// Use the `properties` function to enumerate the top level properties of the Event
+    ///
+    /// The [Property] attributes are read from the [TraceEventInfo], in the order the properties
+    /// are logged, which lets the values be parsed without knowing their names in advance. The
+    /// members of a struct property are found with [Property::struct_members]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     for property in schema.properties() {
+    ///         if property.in_type() == TdhInType::InTypeUInt32 {
+    ///             let value: u32 = parser.try_parse(&property.name)?;
+    ///         }
+    ///     }
+    /// };
+    /// ```
+    /// [TraceEventInfo]: crate::native::etw_types::TraceEventInfo
+    pub fn properties(&self) -> impl Iterator<Item = Property> + '_ {
+        (0..self.top_level_property_count()).map(move |index| self.property(index))
+    }
+
+    /// Use the `cursor` function to read the properties of the Event one at a time
+    ///
+    /// Unlike [Parser::try_parse_all], which decodes every property upfront, the [PropertyCursor]
+    /// only decodes the properties the caller asks for and can be stopped early
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut cursor = schema.cursor();
+    ///     if let Some(first) = cursor.next_property() {
+    ///         let (info, value) = first?;
+    ///         println!("{}: {:?}", info.property.name, value()?);
+    ///     }
+    /// };
+    /// ```
+    pub fn cursor(&self) -> PropertyCursor<'_> {
+        PropertyCursor::new(self)
+    }
+
     /// Use the `provider_name` function to obtain the Provider name from the [TraceEventInfo]
     ///
+    /// # Remarks
+    /// `None` is returned when the [TraceEventInfo] holds no provider name
+    ///
     /// # Example
     /// ```rust
     /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
     ///     let schema = schema_locator.event_schema(record)?;
-    ///     let provider_name = schema.provider_name();
+    ///     let provider_name = schema.provider_name().unwrap_or_default();
     /// };
     /// ```
     /// [TraceEventInfo]: crate::native::etw_types::TraceEventInfo
-    pub fn provider_name(&self) -> String {
+    pub fn provider_name(&self) -> Option<String> {
         self.schema.provider_name()
     }
 
     /// Use the `task_name` function to obtain the Task name from the [TraceEventInfo]
     ///
     /// See: [TaskType](https://docs.microsoft.com/en-us/windows/win32/wes/eventmanifestschema-tasktype-complextype)
+    ///
+    /// # Remarks
+    /// `None` is returned when the [TraceEventInfo] holds no task name
+    ///
     /// # Example
     /// ```rust
     /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
     ///     let schema = schema_locator.event_schema(record)?;
-    ///     let task_name = schema.task_name();
+    ///     let task_name = schema.task_name().unwrap_or_default();
     /// };
     /// ```
     /// [TraceEventInfo]: crate::native::etw_types::TraceEventInfo
-    pub fn task_name(&self) -> String {
+    pub fn task_name(&self) -> Option<String> {
         self.schema.task_name()
     }
 
@@ -326,15 +671,290 @@ impl Schema {
         self.schema.opcode_name()
     }
 
+    /// Use the `level_name` function to obtain the Level name from the [TraceEventInfo]
+    ///
+    /// See: [LevelType](https://docs.microsoft.com/en-us/windows/win32/wes/eventmanifestschema-leveltype-complextype)
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let level_name = schema.level_name();
+    /// };
+    /// ```
+    /// [TraceEventInfo]: crate::native::etw_types::TraceEventInfo
+    pub fn level_name(&self) -> String {
+        self.schema.level_name()
+    }
+
+    /// Use the `cached_metadata` function to obtain the [EventMetadata] of the Event without
+    /// resolving its names again
+    ///
+    /// The provider, task, opcode and level names are resolved once per kind of event, when the
+    /// [SchemaLocator] first locates its Schema, and shared by the following events of the same
+    /// kind. Only the header fields are read from the Event itself, which makes name-inclusive
+    /// logging viable at high event rates
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let meta = schema.cached_metadata();
+    ///     println!("{:?} [{}] {:?}", meta.provider_name, meta.level_name, meta.task_name);
+    /// };
+    /// ```
+    pub fn cached_metadata(&self) -> EventMetadata {
+        match &self.names {
+            Some(names) => EventMetadata::new(self, names),
+            None => EventMetadata::new(self, &EventNames::new(&self.schema)),
+        }
+    }
+
+    /// Use the `format_all` function to obtain every property of the Event formatted by TDH
+    ///
+    /// Each property is returned as a (name, formatted value) pair, the values are formatted by
+    /// [TdhFormatProperty] so they match the output of the Windows tools, this includes value maps,
+    /// IP addresses, SIDs and hex values. Array properties are formatted as a comma separated list
+    /// and the members of struct properties are returned as `Struct.Member` pairs
+    ///
+    /// # Arguments
+    /// * `parser` - The [Parser] created for this Schema, used to split the user buffer
+    ///
+    /// # Remarks
+    /// A property that can't be formatted doesn't fail the whole Event, its value is replaced by
+    /// the error between angle brackets, e.g. `<InvalidType>`
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     for (name, value) in schema.format_all(&mut parser) {
+    ///         println!("{}: {}", name, value);
+    ///     }
+    /// };
+    /// ```
+    /// [TdhFormatProperty]: https://docs.microsoft.com/en-us/windows/win32/api/tdh/nf-tdh-tdhformatproperty
+    pub fn format_all(&self, parser: &mut Parser) -> Vec<(String, String)> {
+        let mut formatted = Vec::new();
+        for i in 0..self.top_level_property_count() {
+            let property = self.property(i);
+
+            if property.flags.contains(PropertyFlags::PROPERTY_STRUCT) {
+                match parser.try_parse_struct(&property.name) {
+                    Ok(members) => formatted.extend(members.iter().map(|member| {
+                        let value = self.format_property(parser, &member.info);
+                        (
+                            format!("{}.{}", property.name, member.name()),
+                            format_result(value),
+                        )
+                    })),
+                    Err(err) => formatted.push((property.name, format_result(Err(err)))),
+                }
+                continue;
+            }
+
+            let value = parser
+                .find_property(&property.name)
+                .and_then(|prop_info| self.format_property(parser, &prop_info));
+            formatted.push((property.name, format_result(value)));
+        }
+
+        formatted
+    }
+
+    // Length and count come from the Parser, they can be held by other properties
+    fn format_property(
+        &self,
+        parser: &Parser,
+        prop_info: &PropertyInfo,
+    ) -> Result<String, ParserError> {
+        let property = &prop_info.property;
+        let mut map_info = match property.map_name() {
+            Some(map_name) => Some(tdh::event_map_info(self.record, map_name)?),
+            None => None,
+        };
+
+        Ok(tdh::format_property(
+            &self.schema,
+            map_info.as_deref_mut(),
+            self.pointer_size(),
+            property,
+            parser.property_length(property)?,
+            parser.element_count(property)?,
+            &prop_info.buffer,
+        )?)
+    }
+
+    /// Use the `log_level` function to obtain the [log::Level] that matches the Level of the Event
+    ///
+    /// The mapping is done by means of [default_log_level], see [Schema::log_level_with] to
+    /// provide a custom mapping
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     log::log!(schema.log_level(), "{:?}", schema.task_name());
+    /// };
+    /// ```
+    #[cfg(feature = "log")]
+    pub fn log_level(&self) -> log::Level {
+        self.log_level_with(default_log_level)
+    }
+
+    /// Use the `log_level_with` function to map the Level of the Event to a [log::Level] using a
+    /// custom mapping function
+    ///
+    /// # Arguments
+    /// * `mapping` - Function that receives the ETW level and returns the [log::Level]
+    #[cfg(feature = "log")]
+    pub fn log_level_with<F>(&self, mapping: F) -> log::Level
+    where
+        F: Fn(u8) -> log::Level,
+    {
+        mapping(self.record.EventHeader.EventDescriptor.Level)
+    }
+
+    /// Use the `tracing_level` function to obtain the [tracing::Level] that matches the Level of
+    /// the Event
+    ///
+    /// The mapping is done by means of [default_tracing_level], see [Schema::tracing_level_with]
+    /// to provide a custom mapping
+    #[cfg(feature = "tracing")]
+    pub fn tracing_level(&self) -> tracing::Level {
+        self.tracing_level_with(default_tracing_level)
+    }
+
+    /// Use the `tracing_level_with` function to map the Level of the Event to a [tracing::Level]
+    /// using a custom mapping function
+    ///
+    /// # Arguments
+    /// * `mapping` - Function that receives the ETW level and returns the [tracing::Level]
+    #[cfg(feature = "tracing")]
+    pub fn tracing_level_with<F>(&self, mapping: F) -> tracing::Level
+    where
+        F: Fn(u8) -> tracing::Level,
+    {
+        mapping(self.record.EventHeader.EventDescriptor.Level)
+    }
+
     pub(crate) fn property_count(&self) -> u32 {
         self.schema.property_count()
     }
 
+    pub(crate) fn top_level_property_count(&self) -> u32 {
+        self.schema.top_level_property_count()
+    }
+
     pub(crate) fn property(&self, index: u32) -> Property {
         self.schema.property(index)
     }
 }
 
+// Properties that fail to format are marked with the error instead of failing the whole Event
+fn format_result(value: Result<String, ParserError>) -> String {
+    value.unwrap_or_else(|err| format!("<{:?}>", err))
+}
+
+/// Default mapping from an ETW level to a [log::Level]
+///
+/// | ETW Level             | log::Level |
+/// |-----------------------|------------|
+/// | LogAlways (0)         | Info       |
+/// | Critical (1)          | Error      |
+/// | Error (2)             | Error      |
+/// | Warning (3)           | Warn       |
+/// | Information (4)       | Info       |
+/// | Verbose (5)           | Debug      |
+/// | Provider defined (>5) | Trace      |
+#[cfg(feature = "log")]
+pub fn default_log_level(level: u8) -> log::Level {
+    match level {
+        1 | 2 => log::Level::Error,
+        3 => log::Level::Warn,
+        0 | 4 => log::Level::Info,
+        5 => log::Level::Debug,
+        _ => log::Level::Trace,
+    }
+}
+
+/// Default mapping from an ETW level to a [tracing::Level]
+///
+/// The mapping is the same as the one used by [default_log_level]
+#[cfg(feature = "tracing")]
+pub fn default_tracing_level(level: u8) -> tracing::Level {
+    match level {
+        1 | 2 => tracing::Level::ERROR,
+        3 => tracing::Level::WARN,
+        0 | 4 => tracing::Level::INFO,
+        5 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    }
+}
+
+/// Summary of an ETW event, handed to the [Provider::on_parsed] callbacks
+///
+/// Holds the header fields and names usually needed to dispatch an event, so the callback doesn't
+/// have to deal with the [Schema] itself
+///
+/// [Provider::on_parsed]: crate::provider::Provider::on_parsed
+#[derive(Debug, Clone)]
+pub struct EventMetadata {
+    /// GUID of the Provider that logged the event
+    pub provider_id: Guid,
+    /// EventId of the event
+    pub event_id: u16,
+    /// Opcode of the event
+    pub opcode: u8,
+    /// Version of the event
+    pub version: u8,
+    /// Level of the event
+    pub level: u8,
+    /// Keyword of the event
+    pub keyword: u64,
+    /// Id of the process that logged the event
+    pub process_id: u32,
+    /// Id of the thread that logged the event
+    pub thread_id: u32,
+    /// Raw timestamp of the event, see [Schema::timestamp]
+    pub timestamp: i64,
+    /// Provider name from the [Schema], if any
+    pub provider_name: Option<String>,
+    /// Task name from the [Schema], if any
+    pub task_name: Option<String>,
+    /// Opcode name from the [Schema]
+    pub opcode_name: String,
+    /// Level name from the [Schema]
+    pub level_name: String,
+}
+
+impl EventMetadata {
+    fn new(schema: &Schema, names: &EventNames) -> Self {
+        let descriptor = &schema.record.EventHeader.EventDescriptor;
+        EventMetadata {
+            provider_id: schema.record.EventHeader.ProviderId,
+            event_id: descriptor.Id,
+            opcode: descriptor.Opcode,
+            version: descriptor.Version,
+            level: descriptor.Level,
+            keyword: descriptor.Keyword,
+            process_id: schema.process_id(),
+            thread_id: schema.thread_id(),
+            timestamp: schema.timestamp(),
+            provider_name: names.provider_name.clone(),
+            task_name: names.task_name.clone(),
+            opcode_name: names.opcode_name.clone(),
+            level_name: names.level_name.clone(),
+        }
+    }
+}
+
+impl From<&Schema> for EventMetadata {
+    fn from(schema: &Schema) -> Self {
+        schema.cached_metadata()
+    }
+}
+
 impl PartialEq for Schema {
     fn eq(&self, other: &Self) -> bool {
         self.schema.event_id() == other.schema.event_id()
@@ -349,11 +969,457 @@ impl Eq for Schema {}
 mod test {
     use super::*;
 
+    #[test]
     fn test_getters() {
-        todo!()
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let mut test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 2);
+        test_schema.version = 3;
+        test_schema.opcode = 2;
+        test_schema.level = 4;
+        test_schema.task = 5;
+        test_schema.keyword = 0x8000_0000_0000_0010;
+        let event = TestEvent::new(&test_schema, Vec::new());
+        let schema = event.schema(&test_schema);
+
+        assert_eq!(schema.event_id(), 2);
+        assert_eq!(schema.event_version(), 3);
+        assert_eq!(schema.opcode(), 2);
+        assert_eq!(schema.level(), 4);
+        assert_eq!(schema.task(), 5);
+        assert_eq!(schema.keyword(), 0x8000_0000_0000_0010);
+        assert_eq!(
+            schema.provider_guid(),
+            Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+        );
+    }
+
+    #[test]
+    fn test_properties() {
+        use crate::native::tdh_types::{PropertyFlags, TdhInType, TdhOutType};
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+        let mut image_name =
+            TestProperty::new("ImageName", TdhInType::InTypeUnicodeString as u16, 0);
+        image_name.out_type = TdhOutType::OutTypeString as u16;
+        let mut ids = TestProperty::new("ThreadIds", TdhInType::InTypeUInt32 as u16, 4);
+        ids.flags = PropertyFlags::PROPERTY_PARAM_FIXED_COUNT.bits();
+        ids.count = 4;
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1)
+            .property(TestProperty::new(
+                "ProcessID",
+                TdhInType::InTypeUInt32 as u16,
+                4,
+            ))
+            .property(image_name)
+            .property(ids);
+        let event = TestEvent::new(&test_schema, Vec::new());
+        let schema = event.schema(&test_schema);
+
+        let properties: Vec<(String, TdhInType, TdhOutType, u16)> = schema
+            .properties()
+            .map(|prop| {
+                (
+                    prop.name.clone(),
+                    prop.in_type(),
+                    prop.out_type(),
+                    prop.count(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            properties,
+            vec![
+                (
+                    String::from("ProcessID"),
+                    TdhInType::InTypeUInt32,
+                    TdhOutType::OutTypeNull,
+                    1
+                ),
+                (
+                    String::from("ImageName"),
+                    TdhInType::InTypeUnicodeString,
+                    TdhOutType::OutTypeString,
+                    1
+                ),
+                (
+                    String::from("ThreadIds"),
+                    TdhInType::InTypeUInt32,
+                    TdhOutType::OutTypeNull,
+                    4
+                ),
+            ]
+        );
     }
 
     fn test_schema_key() {
         todo!()
     }
+
+    #[test]
+    fn test_format_all() {
+        use crate::native::tdh_types::{TdhInType, TdhOutType};
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+        let mut hex = TestProperty::new("Flags", TdhInType::InTypeUInt32 as u16, 4);
+        hex.out_type = TdhOutType::OutTypeHexInt32 as u16;
+        let mut address = TestProperty::new("Address", TdhInType::InTypeUInt32 as u16, 4);
+        address.out_type = TdhOutType::OutTypeIpv4 as u16;
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1)
+            .property(TestProperty::new(
+                "ProcessID",
+                TdhInType::InTypeUInt32 as u16,
+                4,
+            ))
+            .property(hex)
+            .property(address);
+
+        let mut user_data = Vec::new();
+        user_data.extend_from_slice(&1234u32.to_le_bytes());
+        user_data.extend_from_slice(&42u32.to_le_bytes());
+        user_data.extend_from_slice(&[127, 0, 0, 1]);
+        let event = TestEvent::new(&test_schema, user_data);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let formatted = schema.format_all(&mut parser);
+        assert_eq!(
+            formatted,
+            vec![
+                (String::from("ProcessID"), String::from("1234")),
+                (String::from("Flags"), String::from("0x2A")),
+                (String::from("Address"), String::from("127.0.0.1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_all_param_length_and_struct() {
+        use crate::native::tdh_types::{PropertyFlags, TdhInType};
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+        let u32_prop = |name: &str| TestProperty::new(name, TdhInType::InTypeUInt32 as u16, 4);
+        let mut name = TestProperty::new("Name", TdhInType::InTypeUnicodeString as u16, 0);
+        name.flags = PropertyFlags::PROPERTY_PARAM_LENGTH.bits();
+        let mut kind = u32_prop("Kind");
+        kind.member = true;
+        let mut size = u32_prop("Size");
+        size.member = true;
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1)
+            .property(TestProperty::new(
+                "NameLength",
+                TdhInType::InTypeUInt16 as u16,
+                2,
+            ))
+            .property(name)
+            .property(TestProperty::structure("Header", 3, 2))
+            .property(kind)
+            .property(size);
+
+        let mut user_data = 3u16.to_le_bytes().to_vec();
+        "abc"
+            .encode_utf16()
+            .for_each(|c| user_data.extend_from_slice(&c.to_le_bytes()));
+        user_data.extend_from_slice(&2u32.to_le_bytes());
+        user_data.extend_from_slice(&100u32.to_le_bytes());
+        let event = TestEvent::new(&test_schema, user_data);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        assert_eq!(
+            schema.format_all(&mut parser),
+            vec![
+                (String::from("NameLength"), String::from("3")),
+                (String::from("Name"), String::from("abc")),
+                (String::from("Header.Kind"), String::from("2")),
+                (String::from("Header.Size"), String::from("100")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_all_marks_failing_properties() {
+        use crate::native::tdh_types::TdhInType;
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1).property(
+            TestProperty::new("ProcessID", TdhInType::InTypeUInt32 as u16, 4),
+        );
+        // Truncated payload, the property can't be located
+        let event = TestEvent::new(&test_schema, vec![0, 0]);
+        let schema = event.schema(&test_schema);
+        let mut parser = Parser::create(&schema);
+
+        let formatted = schema.format_all(&mut parser);
+        assert_eq!(formatted.len(), 1);
+        assert_eq!(formatted[0].0, "ProcessID");
+        assert!(formatted[0].1.starts_with("<PropertyError"));
+    }
+
+    #[test]
+    fn test_cached_metadata_is_resolved_once() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let mut test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        test_schema.provider_name = Some(String::from("Microsoft-Windows-Kernel-Process"));
+        test_schema.task_name = Some(String::from("ProcessStart"));
+        test_schema.level_name = Some(String::from("Information"));
+        let mut first = TestEvent::new(&test_schema, Vec::new());
+        first.record.EventHeader.ProcessId = 4;
+        let mut second = TestEvent::new(&test_schema, Vec::new());
+        second.record.EventHeader.ProcessId = 1234;
+
+        let mut locator = SchemaLocator::new();
+        locator.insert_schema(&first.record, test_schema.build());
+        let first = locator.event_schema(first.record).unwrap();
+        let second = locator.event_schema(second.record).unwrap();
+
+        // The second event of the same kind reuses the names resolved for the first one
+        assert!(Arc::ptr_eq(
+            first.names.as_ref().unwrap(),
+            second.names.as_ref().unwrap()
+        ));
+        let meta = second.cached_metadata();
+        assert_eq!(
+            meta.provider_name.as_deref(),
+            Some("Microsoft-Windows-Kernel-Process")
+        );
+        assert_eq!(meta.task_name.as_deref(), Some("ProcessStart"));
+        assert_eq!(meta.opcode_name, "");
+        assert_eq!(meta.level_name, "Information");
+        assert_eq!(meta.process_id, 1234);
+        assert_eq!(first.cached_metadata().process_id, 4);
+
+        // A Schema built outside of the locator resolves its names on the spot
+        let uncached = TestEvent::new(&test_schema, Vec::new()).schema(&test_schema);
+        assert!(uncached.names.is_none());
+        assert_eq!(uncached.cached_metadata().level_name, "Information");
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_level_mapping() {
+        let expected = [
+            (0, log::Level::Info),
+            (1, log::Level::Error),
+            (2, log::Level::Error),
+            (3, log::Level::Warn),
+            (4, log::Level::Info),
+            (5, log::Level::Debug),
+            (6, log::Level::Trace),
+        ];
+
+        for (level, log_level) in expected.iter() {
+            assert_eq!(default_log_level(*level), *log_level);
+        }
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_schema_log_level() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let mut test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        test_schema.level = 3;
+        let event = TestEvent::new(&test_schema, Vec::new());
+        let schema = event.schema(&test_schema);
+
+        assert_eq!(schema.log_level(), log::Level::Warn);
+        assert_eq!(
+            schema.log_level_with(|_| log::Level::Trace),
+            log::Level::Trace
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_level_mapping() {
+        let expected = [
+            (0, tracing::Level::INFO),
+            (1, tracing::Level::ERROR),
+            (2, tracing::Level::ERROR),
+            (3, tracing::Level::WARN),
+            (4, tracing::Level::INFO),
+            (5, tracing::Level::DEBUG),
+            (6, tracing::Level::TRACE),
+        ];
+
+        for (level, tracing_level) in expected.iter() {
+            assert_eq!(default_tracing_level(*level), *tracing_level);
+        }
+    }
+
+    #[test]
+    fn test_pointer_size_context() {
+        use crate::native::tdh_types::TdhInType;
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+        use crate::parser::{Pointer, TryParse};
+
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1)
+            .property(TestProperty::new(
+                "Address",
+                TdhInType::InTypePointer as u16,
+                0,
+            ))
+            .property(TestProperty::new(
+                "Count",
+                TdhInType::InTypeUInt32 as u16,
+                4,
+            ));
+        // A 32-bit pointer logged without the 32-bit header flag
+        let mut user_data = 0xdeadu32.to_ne_bytes().to_vec();
+        user_data.extend_from_slice(&7u32.to_ne_bytes());
+        let event = TestEvent::new(&test_schema, user_data);
+
+        let mut locator = SchemaLocator::new();
+        locator.schemas.insert(
+            SchemaKey::new(&event.record),
+            CachedSchema::new(test_schema.build()),
+        );
+        let schema = locator.event_schema(event.record).unwrap();
+        let mut parser = Parser::create(&schema);
+        let address: Pointer = parser.try_parse("Address").unwrap();
+        assert_eq!(*address, 0x7_0000_dead);
+
+        locator
+            .set_context(vec![TdhContext::PointerSize(4)])
+            .unwrap();
+        locator.schemas.insert(
+            SchemaKey::new(&event.record),
+            CachedSchema::new(test_schema.build()),
+        );
+        let schema = locator.event_schema(event.record).unwrap();
+        let mut parser = Parser::create(&schema);
+        let address: Pointer = parser.try_parse("Address").unwrap();
+        let count: u32 = parser.try_parse("Count").unwrap();
+        assert_eq!(*address, 0xdead);
+        assert_eq!(count, 7);
+    }
+
+    #[test]
+    fn test_set_context_validation() {
+        let mut locator = SchemaLocator::new();
+
+        assert!(matches!(
+            locator.set_context(vec![TdhContext::PointerSize(2)]),
+            Err(SchemaError::InvalidContext(_))
+        ));
+        assert!(matches!(
+            locator.set_context(vec![TdhContext::WppTmfFile(String::new())]),
+            Err(SchemaError::InvalidContext(_))
+        ));
+        assert!(matches!(
+            locator.set_context(vec![TdhContext::PointerSize(4), TdhContext::PointerSize(8)]),
+            Err(SchemaError::InvalidContext(_))
+        ));
+        assert!(locator
+            .set_context(vec![
+                TdhContext::WppTmfFile(String::from("driver.tmf")),
+                TdhContext::PointerSize(8),
+            ])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_missing_schema_cached() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+        use std::cell::Cell;
+
+        // ERROR_NOT_FOUND, what TDH returns for a Provider without a manifest
+        const ERROR_NOT_FOUND: i32 = 1168;
+        thread_local!(static LOOKUPS: Cell<usize> = const { Cell::new(0) });
+        fn missing_lookup(
+            _: EventRecord,
+            _: &[TdhContext],
+        ) -> tdh::TdhNativeResult<TraceEventInfoRaw> {
+            LOOKUPS.with(|lookups| lookups.set(lookups.get() + 1));
+            Err(tdh::TdhNativeError::IoError(
+                std::io::Error::from_raw_os_error(ERROR_NOT_FOUND),
+            ))
+        }
+        let lookups = || LOOKUPS.with(|lookups| lookups.get());
+        let is_not_found = |result: SchemaResult<Schema>| {
+            matches!(
+                result,
+                Err(SchemaError::TdhNativeError(tdh::TdhNativeError::IoError(err)))
+                    if err.raw_os_error() == Some(ERROR_NOT_FOUND)
+            )
+        };
+
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1);
+        let event = TestEvent::new(&test_schema, Vec::new());
+        let other = TestEvent::new(
+            &TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 2),
+            Vec::new(),
+        );
+        let mut locator = SchemaLocator::new();
+        locator.set_lookup(missing_lookup);
+
+        assert!(is_not_found(locator.event_schema(event.record)));
+        assert!(is_not_found(locator.event_schema(event.record)));
+        assert_eq!(lookups(), 1);
+
+        // Other kinds of events are looked up on their own
+        assert!(is_not_found(locator.event_schema(other.record)));
+        assert_eq!(lookups(), 2);
+
+        locator.clear_cache();
+        assert!(is_not_found(locator.event_schema(event.record)));
+        assert_eq!(lookups(), 3);
+    }
+
+    #[test]
+    fn test_schema_key_versions() {
+        use crate::native::tdh_types::TdhInType;
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+        use crate::parser::TryParse;
+
+        // Version 1 logs a 32-bit Size, version 2 widened it and opcode 1 of version 2 adds a Flags
+        let mut layouts = Vec::new();
+        for (version, opcode, in_type, length) in [
+            (1, 0, TdhInType::InTypeUInt32, 4),
+            (2, 0, TdhInType::InTypeUInt64, 8),
+            (2, 1, TdhInType::InTypeUInt64, 8),
+        ] {
+            let mut test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 10)
+                .property(TestProperty::new("Size", in_type as u16, length));
+            if opcode == 1 {
+                test_schema = test_schema.property(TestProperty::new(
+                    "Flags",
+                    TdhInType::InTypeUInt16 as u16,
+                    2,
+                ));
+            }
+            test_schema.version = version;
+            test_schema.opcode = opcode;
+            layouts.push(test_schema);
+        }
+        let events = [
+            TestEvent::new(&layouts[0], 7u32.to_ne_bytes().to_vec()),
+            TestEvent::new(&layouts[1], 0x1_0000_0007u64.to_ne_bytes().to_vec()),
+            TestEvent::new(
+                &layouts[2],
+                [&8u64.to_ne_bytes()[..], &3u16.to_ne_bytes()[..]].concat(),
+            ),
+        ];
+
+        let mut locator = SchemaLocator::new();
+        for (event, layout) in events.iter().zip(layouts.iter()) {
+            locator.insert_schema(&event.record, layout.build());
+        }
+
+        let schema = locator.event_schema(events[0].record).unwrap();
+        let size: u32 = Parser::create(&schema).try_parse("Size").unwrap();
+        assert_eq!((schema.event_version(), size), (1, 7));
+
+        let schema = locator.event_schema(events[1].record).unwrap();
+        let size: u64 = Parser::create(&schema).try_parse("Size").unwrap();
+        assert_eq!((schema.event_version(), size), (2, 0x1_0000_0007));
+
+        let schema = locator.event_schema(events[2].record).unwrap();
+        let mut parser = Parser::create(&schema);
+        let size: u64 = parser.try_parse("Size").unwrap();
+        let flags: u16 = parser.try_parse("Flags").unwrap();
+        assert_eq!((schema.opcode(), size, flags), (1, 8, 3));
+    }
 }
@@ -0,0 +1,449 @@
+//! File sinks
+//!
+//! The `sink` module, available with the `sink` feature, writes the events of a Trace to a file
+//! as NDJSON (one JSON object per line) or CSV, see [UserTrace::sink_to]. Writes are buffered and
+//! the file can be rotated once it reaches a given size
+//!
+//! [UserTrace::sink_to]: crate::trace::UserTrace::sink_to
+use crate::parser::{Parser, ParserError, PropertyValue};
+use crate::schema::{Schema, SchemaError};
+use crate::{fmt, trace};
+use serde_json::{Number, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Sink module errors
+#[derive(Debug)]
+pub enum SinkError {
+    /// Wrapper over an IO error raised while writing the file
+    IoError(std::io::Error),
+    /// Wrapper over a [ParserError] raised while parsing the event properties
+    ParserError(ParserError),
+    /// Wrapper over a [SchemaError] raised while locating the Schema of the event
+    SchemaError(SchemaError),
+}
+
+impl From<std::io::Error> for SinkError {
+    fn from(err: std::io::Error) -> Self {
+        SinkError::IoError(err)
+    }
+}
+
+impl From<ParserError> for SinkError {
+    fn from(err: ParserError) -> Self {
+        SinkError::ParserError(err)
+    }
+}
+
+impl From<SchemaError> for SinkError {
+    fn from(err: SchemaError) -> Self {
+        SinkError::SchemaError(err)
+    }
+}
+
+type SinkResult<T> = Result<T, SinkError>;
+
+/// Format of the file written by a [FileSink]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line, holding the header fields and a `properties` object
+    Ndjson,
+    /// Comma separated values, the header fields followed by one column per property
+    ///
+    /// Only the events with `event_id` are written so the columns are stable, they are taken
+    /// from the first event written
+    Csv {
+        /// EventId of the events written
+        event_id: u16,
+    },
+}
+
+// Header fields written before the properties, in both formats
+const HEADER_COLUMNS: [&str; 9] = [
+    "provider_id",
+    "provider_name",
+    "event_id",
+    "version",
+    "opcode",
+    "level",
+    "timestamp",
+    "process_id",
+    "thread_id",
+];
+
+/// Writes events to a file
+///
+/// Properties are decoded with [Parser::try_parse_all], time values are written as microseconds
+/// since the Unix epoch, GUIDs in the [fmt::format_guid] format and binary values in hex
+///
+/// # Example
+/// ```rust
+/// let sink = FileSink::create(Path::new("capture.ndjson"), Format::Ndjson)?
+///     .rotate_at(64 * 1024 * 1024);
+/// let my_trace = UserTrace::new().enable(provider).sink(sink).start()?;
+/// ```
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    format: Format,
+    writer: BufWriter<File>,
+    written: u64,
+    max_file_size: Option<u64>,
+    rotations: u32,
+    columns: Option<Vec<String>>,
+}
+
+impl FileSink {
+    /// Use the `create` function to create a FileSink writing to `path`
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file, truncated if it exists
+    /// * `format` - [Format] of the file
+    ///
+    /// # Remarks
+    /// This function can fail, if the file can't be created the IO error will be returned
+    pub fn create(path: &Path, format: Format) -> std::io::Result<Self> {
+        Ok(FileSink {
+            path: path.to_path_buf(),
+            format,
+            writer: BufWriter::new(File::create(path)?),
+            written: 0,
+            max_file_size: None,
+            rotations: 0,
+            columns: None,
+        })
+    }
+
+    /// Use the `rotate_at` function to rotate the file once it reaches `max_file_size` bytes
+    ///
+    /// The full file is renamed by inserting a sequence number before its extension, e.g.
+    /// `capture.1.ndjson`, `capture.2.ndjson`..., and a new file is started at the original path.
+    /// A CSV header is repeated at the top of every file
+    ///
+    /// # Arguments
+    /// * `max_file_size` - Maximum size of a file in bytes, an event is never split across files
+    pub fn rotate_at(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// Use the `write_event` function to append an event to the file
+    ///
+    /// Returns `false` if the event was ignored, i.e. a CSV file and the EventId or properties
+    /// don't match its columns
+    ///
+    /// # Arguments
+    /// * `schema` - The [Schema] of the event
+    ///
+    /// # Remarks
+    /// This function can fail, if the properties can't be parsed or the file can't be written
+    /// the [SinkError] will be returned accordingly
+    pub fn write_event(&mut self, schema: &Schema) -> SinkResult<bool> {
+        let values = Parser::create(schema).try_parse_all()?;
+        let pointer_size = schema.pointer_size();
+
+        let line = match self.format {
+            Format::Ndjson => {
+                let properties = values
+                    .iter()
+                    .map(|(name, value)| (name.clone(), json_value(value, pointer_size)));
+                trace::event_json_line(schema, properties)
+            }
+            Format::Csv { event_id } => {
+                if schema.event_id() != event_id {
+                    return Ok(false);
+                }
+                let names: Vec<String> = values.iter().map(|(name, _)| name.clone()).collect();
+                match &self.columns {
+                    Some(columns) if *columns != names => return Ok(false),
+                    Some(_) => {}
+                    None => {
+                        self.columns = Some(names);
+                        self.write_header()?;
+                    }
+                }
+                csv_line(schema, &values, pointer_size)
+            }
+        };
+
+        self.write_line(&line)?;
+        Ok(true)
+    }
+
+    /// Use the `flush` function to write the buffered events to the file
+    pub fn flush(&mut self) -> SinkResult<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Use the `rotations` function to obtain how many times the file has been rotated
+    pub fn rotations(&self) -> u32 {
+        self.rotations
+    }
+
+    fn write_header(&mut self) -> SinkResult<()> {
+        let properties = self.columns.iter().flatten().map(|name| csv_field(name));
+        let header = HEADER_COLUMNS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(properties)
+            .collect::<Vec<String>>()
+            .join(",");
+        self.write_line(&header)
+    }
+
+    fn write_line(&mut self, line: &str) -> SinkResult<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(max_file_size) = self.max_file_size {
+            if self.written > 0 && self.written + len > max_file_size {
+                self.rotate()?;
+            }
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.written += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> SinkResult<()> {
+        self.writer.flush()?;
+        self.rotations += 1;
+        std::fs::rename(&self.path, rotated_path(&self.path, self.rotations))?;
+
+        self.writer = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        if self.columns.is_some() {
+            self.write_header()?;
+        }
+        Ok(())
+    }
+}
+
+// capture.ndjson -> capture.1.ndjson
+fn rotated_path(path: &Path, sequence: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, sequence, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, sequence),
+    };
+    path.with_file_name(name)
+}
+
+fn value_text(value: &PropertyValue, pointer_size: u32) -> String {
+    match value {
+        PropertyValue::Null => String::new(),
+        PropertyValue::I8(v) => v.to_string(),
+        PropertyValue::U8(v) => v.to_string(),
+        PropertyValue::I16(v) => v.to_string(),
+        PropertyValue::U16(v) => v.to_string(),
+        PropertyValue::I32(v) => v.to_string(),
+        PropertyValue::U32(v) => v.to_string(),
+        PropertyValue::I64(v) => v.to_string(),
+        PropertyValue::U64(v) => v.to_string(),
+        PropertyValue::F32(v) => v.to_string(),
+        PropertyValue::F64(v) => v.to_string(),
+        PropertyValue::Bool(v) => v.to_string(),
+        PropertyValue::String(v) => v.clone(),
+        PropertyValue::Guid(v) => fmt::format_guid(v),
+        PropertyValue::Pointer(v) => fmt::format_ptr(*v as u64, pointer_size as usize),
+        PropertyValue::Time(v) => match v.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_micros().to_string(),
+            Err(_) => String::new(),
+        },
+        PropertyValue::Binary(v) => fmt::format_hex(v),
+    }
+}
+
+// Numbers and booleans are written as such, NaN, infinities and times before the epoch as null
+fn json_value(value: &PropertyValue, pointer_size: u32) -> Value {
+    match value {
+        PropertyValue::Null => Value::Null,
+        PropertyValue::I8(v) => Value::from(*v),
+        PropertyValue::U8(v) => Value::from(*v),
+        PropertyValue::I16(v) => Value::from(*v),
+        PropertyValue::U16(v) => Value::from(*v),
+        PropertyValue::I32(v) => Value::from(*v),
+        PropertyValue::U32(v) => Value::from(*v),
+        PropertyValue::I64(v) => Value::from(*v),
+        PropertyValue::U64(v) => Value::from(*v),
+        PropertyValue::F32(v) => Number::from_f64(*v as f64).map_or(Value::Null, Value::Number),
+        PropertyValue::F64(v) => Number::from_f64(*v).map_or(Value::Null, Value::Number),
+        PropertyValue::Bool(v) => Value::Bool(*v),
+        PropertyValue::Time(v) => match v.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => Value::from(since_epoch.as_micros() as u64),
+            Err(_) => Value::Null,
+        },
+        _ => Value::String(value_text(value, pointer_size)),
+    }
+}
+
+// Fields holding a separator, a quote or a line break are quoted, quotes are doubled
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_line(schema: &Schema, values: &[(String, PropertyValue)], pointer_size: u32) -> String {
+    let record = schema.record();
+    let header = vec![
+        fmt::format_guid(&record.EventHeader.ProviderId),
+        schema.provider_name().unwrap_or_default(),
+        schema.event_id().to_string(),
+        schema.event_version().to_string(),
+        schema.opcode().to_string(),
+        record.EventHeader.EventDescriptor.Level.to_string(),
+        schema.timestamp().to_string(),
+        schema.process_id().to_string(),
+        schema.thread_id().to_string(),
+    ];
+
+    header
+        .into_iter()
+        .chain(
+            values
+                .iter()
+                .map(|(_, value)| value_text(value, pointer_size)),
+        )
+        .map(|field| csv_field(&field))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::native::tdh_types::TdhInType;
+    use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+    const PROVIDER_GUID: &str = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ferrisetw-{}-{}", std::process::id(), name))
+    }
+
+    fn test_schema(event_id: u16) -> TestSchema {
+        let mut test_schema = TestSchema::new(PROVIDER_GUID, event_id)
+            .property(TestProperty::new(
+                "ProcessID",
+                TdhInType::InTypeUInt32 as u16,
+                4,
+            ))
+            .property(TestProperty::new(
+                "Flags",
+                TdhInType::InTypeUInt16 as u16,
+                2,
+            ));
+        test_schema.provider_name = Some(String::from("Kernel, \"Process\""));
+        test_schema
+    }
+
+    fn event(test_schema: &TestSchema, pid: u32) -> TestEvent {
+        let mut data = pid.to_ne_bytes().to_vec();
+        data.extend_from_slice(&7u16.to_ne_bytes());
+        TestEvent::new(test_schema, data)
+    }
+
+    #[test]
+    fn test_ndjson_sink() {
+        let path = temp_path("sink.ndjson");
+        let test_schema = test_schema(1);
+        let mut sink = FileSink::create(&path, Format::Ndjson).unwrap();
+        for pid in [4u32, 1234, 42].iter() {
+            let event = event(&test_schema, *pid);
+            assert!(sink.write_event(&event.schema(&test_schema)).unwrap());
+        }
+        sink.flush().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "{\"provider_id\":\"22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716\",\
+             \"provider_name\":\"Kernel, \\\"Process\\\"\",\"event_id\":1,\"version\":0,\
+             \"opcode\":0,\"level\":0,\"timestamp\":0,\"process_id\":0,\"thread_id\":0,\
+             \"properties\":{\"ProcessID\":1234,\"Flags\":7}}"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_sink_keeps_columns_stable() {
+        let path = temp_path("sink.csv");
+        let first = test_schema(1);
+        let other_id = test_schema(2);
+        let other_properties = TestSchema::new(PROVIDER_GUID, 1).property(TestProperty::new(
+            "Other",
+            TdhInType::InTypeUInt32 as u16,
+            4,
+        ));
+        let mut sink = FileSink::create(&path, Format::Csv { event_id: 1 }).unwrap();
+
+        assert!(sink.write_event(&event(&first, 4).schema(&first)).unwrap());
+        assert!(!sink
+            .write_event(&event(&other_id, 5).schema(&other_id))
+            .unwrap());
+        let other = TestEvent::new(&other_properties, 6u32.to_ne_bytes().to_vec());
+        assert!(!sink.write_event(&other.schema(&other_properties)).unwrap());
+        assert!(sink
+            .write_event(&event(&first, 1234).schema(&first))
+            .unwrap());
+        sink.flush().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "provider_id,provider_name,event_id,version,opcode,level,timestamp,process_id,\
+             thread_id,ProcessID,Flags\n\
+             22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716,\"Kernel, \"\"Process\"\"\",1,0,0,0,0,0,0,4,7\n\
+             22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716,\"Kernel, \"\"Process\"\"\",1,0,0,0,0,0,0,1234,7\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sink_rotation() {
+        let path = temp_path("rotate.csv");
+        let test_schema = test_schema(1);
+        let line_len = csv_line(
+            &event(&test_schema, 1000).schema(&test_schema),
+            &[
+                (String::from("ProcessID"), PropertyValue::U32(1000)),
+                (String::from("Flags"), PropertyValue::U16(7)),
+            ],
+            8,
+        )
+        .len() as u64
+            + 1;
+        // Room for the header and two events per file
+        let header_len = HEADER_COLUMNS.join(",").len() as u64 + ",ProcessID,Flags\n".len() as u64;
+        let mut sink = FileSink::create(&path, Format::Csv { event_id: 1 })
+            .unwrap()
+            .rotate_at(header_len + 2 * line_len);
+
+        for pid in 1000..1005u32 {
+            let event = event(&test_schema, pid);
+            sink.write_event(&event.schema(&test_schema)).unwrap();
+        }
+        sink.flush().unwrap();
+        assert_eq!(sink.rotations(), 2);
+
+        let rotated = [rotated_path(&path, 1), rotated_path(&path, 2)];
+        assert!(rotated[0]
+            .to_string_lossy()
+            .ends_with(&format!("{}-rotate.1.csv", std::process::id())));
+        let lines = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(&rotated[0]), 3);
+        assert_eq!(lines(&rotated[1]), 3);
+        assert_eq!(lines(&path), 2);
+
+        for path in rotated.iter().chain(std::iter::once(&path)) {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
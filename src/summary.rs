@@ -0,0 +1,180 @@
+//! ETW Event summary collector
+//!
+//! The `summary` module provides a simple collector that tallies the events seen per Provider and
+//! per [EventSignature], this comes in handy to quickly find out which providers are generating
+//! most of the events in a Trace
+use crate::native::etw_types::EventRecord;
+use crate::schema::{EventSignature, SchemaLocator};
+use crate::utils;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use windows::Guid;
+
+/// Event count by Provider collector
+///
+/// The collector can be fed events manually by means of the `record` function or it can be
+/// attached to a [Provider] as a callback. Cloning a `ProviderSummary` returns a handle to the same
+/// tallies, so a clone can be moved into the callback while the original is kept to build the report
+///
+/// # Example
+/// ```rust
+/// let summary = ProviderSummary::new();
+/// let provider = Provider::new()
+///     .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+///     .add_callback(summary.callback())
+///     .build()?;
+/// // ... start and stop the trace
+/// for (signature, count) in summary.report() {
+///     println!("{:?}: {}", signature, count);
+/// }
+/// ```
+///
+/// [Provider]: crate::provider::Provider
+#[derive(Debug, Clone, Default)]
+pub struct ProviderSummary {
+    counts: Arc<Mutex<HashMap<EventSignature, u64>>>,
+}
+
+impl ProviderSummary {
+    /// Use the `new` function to create an empty ProviderSummary
+    pub fn new() -> Self {
+        ProviderSummary {
+            counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Use the `record` function to tally an Event
+    ///
+    /// # Arguments
+    /// * `record` - The [EventRecord] to count
+    pub fn record(&self, record: &EventRecord) {
+        if let Ok(mut counts) = self.counts.lock() {
+            *counts.entry(EventSignature::from(record)).or_insert(0) += 1;
+        }
+    }
+
+    /// Use the `callback` function to obtain a callback that can be added to a Provider, every
+    /// Event the callback receives is tallied into this summary
+    pub fn callback(&self) -> impl FnMut(EventRecord, &mut SchemaLocator) + Send + Sync + 'static {
+        let summary = self.clone();
+        move |record: EventRecord, _locator: &mut SchemaLocator| summary.record(&record)
+    }
+
+    /// Use the `report` function to obtain the event count per (Provider, EventId)
+    ///
+    /// The report is sorted by count, the most frequent [EventSignature] comes first
+    pub fn report(&self) -> Vec<(EventSignature, u64)> {
+        let mut report: Vec<(EventSignature, u64)> = match self.counts.lock() {
+            Ok(counts) => counts.iter().map(|(sig, count)| (*sig, *count)).collect(),
+            Err(_) => Vec::new(),
+        };
+
+        report.sort_by(|(a_sig, a_count), (b_sig, b_count)| {
+            b_count
+                .cmp(a_count)
+                .then_with(|| {
+                    utils::guid_bytes(&a_sig.provider_id)
+                        .cmp(&utils::guid_bytes(&b_sig.provider_id))
+                })
+                .then_with(|| a_sig.event_id.cmp(&b_sig.event_id))
+        });
+        report
+    }
+
+    /// Use the `provider_report` function to obtain the event count per Provider
+    ///
+    /// The report is sorted by count, the most verbose Provider comes first
+    pub fn provider_report(&self) -> Vec<(Guid, u64)> {
+        let mut totals: Vec<(Guid, u64)> = Vec::new();
+        for (sig, count) in self.report() {
+            match totals.iter_mut().find(|(guid, _)| *guid == sig.provider_id) {
+                Some((_, total)) => *total += count,
+                None => totals.push((sig.provider_id, count)),
+            }
+        }
+
+        totals.sort_by(|(a_guid, a_count), (b_guid, b_count)| {
+            b_count
+                .cmp(a_count)
+                .then_with(|| utils::guid_bytes(a_guid).cmp(&utils::guid_bytes(b_guid)))
+        });
+        totals
+    }
+
+    /// Use the `total` function to obtain the number of events tallied
+    pub fn total(&self) -> u64 {
+        match self.counts.lock() {
+            Ok(counts) => counts.values().sum(),
+            Err(_) => 0,
+        }
+    }
+
+    /// Use the `clear` function to reset all the tallies
+    pub fn clear(&self) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROCESS_GUID: &str = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+    const REGISTRY_GUID: &str = "70eb4f03-c1de-4f73-a051-33d13d5413bd";
+
+    fn synthetic_record(provider: &str, event_id: u16) -> EventRecord {
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.ProviderId = Guid::from(provider);
+        record.EventHeader.EventDescriptor.Id = event_id;
+        record
+    }
+
+    #[test]
+    fn test_report_tallies() {
+        let summary = ProviderSummary::new();
+        for _ in 0..3 {
+            summary.record(&synthetic_record(PROCESS_GUID, 1));
+        }
+        summary.record(&synthetic_record(PROCESS_GUID, 2));
+        for _ in 0..2 {
+            summary.record(&synthetic_record(REGISTRY_GUID, 7));
+        }
+
+        let report = summary.report();
+        assert_eq!(report.len(), 3);
+        assert_eq!(
+            report[0],
+            (EventSignature::new(Guid::from(PROCESS_GUID), 1), 3)
+        );
+        assert_eq!(
+            report[1],
+            (EventSignature::new(Guid::from(REGISTRY_GUID), 7), 2)
+        );
+        assert_eq!(
+            report[2],
+            (EventSignature::new(Guid::from(PROCESS_GUID), 2), 1)
+        );
+        assert_eq!(summary.total(), 6);
+    }
+
+    #[test]
+    fn test_provider_report_tallies() {
+        let summary = ProviderSummary::new();
+        let mut callback = summary.callback();
+        let mut locator = SchemaLocator::new();
+
+        callback(synthetic_record(REGISTRY_GUID, 7), &mut locator);
+        callback(synthetic_record(PROCESS_GUID, 1), &mut locator);
+        callback(synthetic_record(PROCESS_GUID, 2), &mut locator);
+
+        let report = summary.provider_report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0], (Guid::from(PROCESS_GUID), 2));
+        assert_eq!(report[1], (Guid::from(REGISTRY_GUID), 1));
+
+        summary.clear();
+        assert_eq!(summary.total(), 0);
+    }
+}
@@ -2,16 +2,39 @@
 //!
 //! Provides both a Kernel and User trace that allows to start an ETW session
 use super::traits::*;
-use crate::native::etw_types::{EnableTraceParameters, EventRecord, INVALID_TRACE_HANDLE};
+use crate::native::etw_types::{
+    event_id_filter, is_real_time_mode, EventFilterDescriptor, EventRecord, EventRecordExt,
+    TraceClock,
+};
 use crate::native::{evntrace, version_helper};
+#[cfg(feature = "serde_json")]
+use crate::parser::Parser;
 use crate::provider::Provider;
+#[cfg(feature = "sink")]
+use crate::sink::{FileSink, Format, SinkError};
 use crate::{provider, schema, utils};
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use windows::Guid;
 
+/// Prefix of the random name given to the Traces that aren't named, see [TraceTrait::named]
+pub const TRACE_NAME_PREFIX: &str = "n4r1b-trace-";
 const KERNEL_LOGGER_NAME: &str = "NT Kernel Logger";
 const SYSTEM_TRACE_CONTROL_GUID: &str = "9e814aad-3204-11d2-9a82-006008a86939";
 const EVENT_TRACE_SYSTEM_LOGGER_MODE: u32 = 0x02000000;
+const EVENT_TRACE_USE_PAGED_MEMORY: u32 = 0x01000000;
+// MAX_PATH, the path is stored null terminated within the session properties
+const MAX_LOG_FILE_PATH: usize = 260;
+// MAX_PATH, the name is stored null terminated within the session properties as well
+const MAX_TRACE_NAME: usize = 260;
+
+lazy_static! {
+    static ref SYSTEM_TRACE_CONTROL: Guid = Guid::from(SYSTEM_TRACE_CONTROL_GUID);
+    static ref LOST_EVENT: Guid =
+        Guid::from(provider::kernel_providers::kernel_guids::LOST_EVENT_GUID);
+}
 
 /// Trace module errors
 #[derive(Debug)]
@@ -20,6 +43,24 @@ pub enum TraceError {
     ///
     /// [EvntraceNativeError]: crate::native::evntrace::EvntraceNativeError
     EtwNativeError(evntrace::EvntraceNativeError),
+    /// ETW ran out of resources while enabling a Provider (ERROR_NO_SYSTEM_RESOURCES)
+    ///
+    /// Usually caused by too many active sessions in the system, too many providers enabled in
+    /// the session or the session buffers being exhausted. Stopping unused sessions or enabling
+    /// less providers usually helps, see [TraceBaseTrait::retry_enable] to retry the enable
+    OutOfResources,
+    /// Represents an invalid [LogFile] configuration
+    InvalidLogFile(String),
+    /// Represents a session name that can't be used, see [TraceTrait::named]
+    InvalidTraceName(String),
+    /// Represents a combination of Trace options not supported by the session
+    InvalidOptions(String),
+    /// Wrapper over an internal [ProviderError]
+    ///
+    /// [ProviderError]: crate::provider::ProviderError
+    ProviderError(provider::ProviderError),
+    /// Represents a panic of the thread replaying a [FileTrace], holds the panic message
+    ReplayPanicked(String),
     /// Wrapper over an standard IO Error
     IoError(std::io::Error),
 }
@@ -32,20 +73,115 @@ impl From<std::io::Error> for TraceError {
     }
 }
 
+impl From<provider::ProviderError> for TraceError {
+    fn from(err: provider::ProviderError) -> Self {
+        TraceError::ProviderError(err)
+    }
+}
+
 impl From<evntrace::EvntraceNativeError> for TraceError {
     fn from(err: evntrace::EvntraceNativeError) -> Self {
-        TraceError::EtwNativeError(err)
+        match err {
+            evntrace::EvntraceNativeError::NoSystemResources => TraceError::OutOfResources,
+            _ => TraceError::EtwNativeError(err),
+        }
+    }
+}
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceError::EtwNativeError(err) => write!(f, "ETW error: {}", err),
+            TraceError::OutOfResources => {
+                f.write_str("ETW ran out of resources while enabling a provider")
+            }
+            TraceError::InvalidLogFile(reason) => write!(f, "invalid log file: {}", reason),
+            TraceError::InvalidTraceName(reason) => write!(f, "invalid trace name: {}", reason),
+            TraceError::InvalidOptions(reason) => write!(f, "invalid trace options: {}", reason),
+            TraceError::ProviderError(err) => write!(f, "provider error: {}", err),
+            TraceError::ReplayPanicked(msg) => write!(f, "file replay panicked: {}", msg),
+            TraceError::IoError(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TraceError::EtwNativeError(err) => Some(err),
+            TraceError::ProviderError(err) => Some(err),
+            TraceError::IoError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl TraceError {
+    fn io_error_kind(&self) -> std::io::ErrorKind {
+        match self {
+            TraceError::EtwNativeError(err) => err.io_error_kind(),
+            TraceError::OutOfResources | TraceError::ReplayPanicked(_) => std::io::ErrorKind::Other,
+            TraceError::InvalidLogFile(_)
+            | TraceError::InvalidTraceName(_)
+            | TraceError::InvalidOptions(_) => std::io::ErrorKind::InvalidInput,
+            TraceError::ProviderError(err) => err.io_error_kind(),
+            TraceError::IoError(err) => err.kind(),
+        }
+    }
+}
+
+/// Maps the error to its [std::io::ErrorKind], the [TraceError] is kept as the inner error and
+/// can be retrieved with [std::io::Error::get_ref] or [std::io::Error::into_inner]
+impl From<TraceError> for std::io::Error {
+    fn from(err: TraceError) -> Self {
+        std::io::Error::new(err.io_error_kind(), err)
     }
 }
 
 type TraceResult<T> = Result<T, TraceError>;
 
+const ENABLE_RETRIES: u32 = 3;
+const ENABLE_RETRY_BACKOFF_MS: u64 = 100;
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of groups in the kernel PERFINFO_GROUPMASK, see [KernelTrace::enable_group_mask]
+pub const GROUP_MASK_COUNT: usize = 8;
+
+// Retries the enable with an exponential backoff while ETW is out of resources
+fn enable_with_retry<F>(retry: bool, mut enable: F) -> TraceResult<()>
+where
+    F: FnMut() -> evntrace::EvntraceNativeResult<()>,
+{
+    let mut attempt = 0;
+    loop {
+        match enable() {
+            Err(evntrace::EvntraceNativeError::NoSystemResources)
+                if retry && attempt < ENABLE_RETRIES =>
+            {
+                std::thread::sleep(Duration::from_millis(ENABLE_RETRY_BACKOFF_MS << attempt));
+                attempt += 1;
+            }
+            res => return Ok(res?),
+        }
+    }
+}
+
 /// Trace Properties struct
 ///
 /// Keeps the ETW session configuration settings
 ///
+/// Not every field is honoured on every OS version, fields that don't apply to the running system
+/// are zeroed when the session is registered:
+/// * `age_limit` - Only applies to systems older than Win8, newer versions reuse the field as the
+///   flush threshold so the value is ignored
+///
+/// There is no real-time buffers setting on any version, the buffers handed to the real-time
+/// consumer come from the `min_buffer`/`max_buffer` pool. `RealTimeBuffersLost` is only filled by
+/// the system, see [TraceStats::real_time_buffers_lost]
+///
 /// [More info](https://docs.microsoft.com/en-us/message-analyzer/specifying-advanced-etw-session-configuration-settings#configuring-the-etw-session)
 #[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct TraceProperties {
     /// Represents the ETW Session in KB
     pub buffer_size: u32,
@@ -57,6 +193,107 @@ pub struct TraceProperties {
     pub flush_timer: u32,
     /// Represents the ETW Session [Logging Mode](https://docs.microsoft.com/en-us/windows/win32/etw/logging-mode-constants)
     pub log_file_mode: u32,
+    /// Represents the ETW Session buffer aging time in minutes, ignored on Win8 or greater
+    pub age_limit: u32,
+}
+
+/// Log File settings
+///
+/// Describes the `.etl` file a Trace writes its events to, see [TraceBaseTrait::log_file]. Events
+/// are still delivered to the Provider callbacks while being logged to the file
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LogFile {
+    /// Represents the path of the `.etl` file
+    pub path: String,
+    /// Represents the maximum size of the file in MB
+    pub max_file_size: u32,
+    /// Represents whether the file space is preallocated when the session starts
+    pub preallocate: bool,
+}
+
+impl LogFile {
+    /// Use the `new` function to create a LogFile builder
+    ///
+    /// # Arguments
+    /// * `path` - Path of the `.etl` file the events will be written to
+    ///
+    /// # Example
+    /// ```rust
+    /// let log_file = LogFile::new("C:\\traces\\my_trace.etl");
+    /// ```
+    pub fn new(path: &str) -> Self {
+        LogFile {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Use the `max_file_size` function to set the maximum size of the file
+    ///
+    /// # Arguments
+    /// * `max_file_size` - Maximum size of the file in MB
+    ///
+    /// # Example
+    /// ```rust
+    /// let log_file = LogFile::new("my_trace.etl").max_file_size(512);
+    /// ```
+    pub fn max_file_size(mut self, max_file_size: u32) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Use the `preallocate` function to set whether the file space is preallocated
+    ///
+    /// Preallocating the file (`EVENT_TRACE_FILE_MODE_PREALLOCATE`) reduces the fragmentation and
+    /// avoids allocation stalls while capturing
+    ///
+    /// # Arguments
+    /// * `preallocate` - Preallocate the whole `max_file_size` when the session starts
+    ///
+    /// # Remarks
+    /// Preallocating requires the `max_file_size` to be set, otherwise `build` will fail
+    ///
+    /// # Example
+    /// ```rust
+    /// let log_file = LogFile::new("my_trace.etl").max_file_size(512).preallocate(true);
+    /// ```
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Use the `build` function to build the LogFile
+    ///
+    /// # Safety Note
+    /// This function might return an [TraceError::InvalidLogFile] if the path is empty or too long,
+    /// or if the file is preallocated without a `max_file_size`
+    ///
+    /// # Example
+    /// ```rust
+    /// let log_file = LogFile::new("my_trace.etl")
+    ///     .max_file_size(512)
+    ///     .preallocate(true)
+    ///     .build()?;
+    /// ```
+    pub fn build(self) -> TraceResult<Self> {
+        self.check()?;
+        Ok(self)
+    }
+
+    fn check(&self) -> TraceResult<()> {
+        if self.path.is_empty() || self.path.len() >= MAX_LOG_FILE_PATH {
+            return Err(TraceError::InvalidLogFile(
+                "Log file path is empty or too long".to_owned(),
+            ));
+        }
+        if self.preallocate && self.max_file_size == 0 {
+            return Err(TraceError::InvalidLogFile(
+                "Preallocating the log file requires a maximum file size".to_owned(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 /// Struct which holds the Trace data
@@ -72,19 +309,191 @@ pub struct TraceData {
     pub events_handled: isize,
     /// List of Providers associated with the Trace
     pub providers: RwLock<Vec<provider::Provider>>,
+    /// Represents the [LogFile] the Trace writes to, if any
+    pub log_file: Option<LogFile>,
     schema_locator: schema::SchemaLocator,
+    retry_enable: bool,
+    min_level: Option<provider::TraceLevel>,
+    last_timestamp: i64,
+    // Events delivered with the last_timestamp, several events can share a TimeStamp
+    last_timestamp_count: usize,
+    // Position reached by the previous pass over a followed file and how many events at that
+    // TimeStamp have been skipped on the current pass
+    skip_until: Option<(i64, usize)>,
+    skip_seen: usize,
+    raw_timestamps: bool,
+    clock: TraceClock,
+    group_mask: [u32; GROUP_MASK_COUNT],
+    paged_memory: bool,
+    stack_walk: Vec<(Guid, u8)>,
+    profile_interval: Option<u32>,
+    consumer_thread_name: Option<String>,
+    process_error_hook: Option<ProcessErrorHook>,
+    capture_state: Vec<Guid>,
+    // Mirror of events_handled that can be read while the Trace runs
+    live_events: Arc<AtomicIsize>,
+    started: Option<Instant>,
+    heartbeat: Option<Heartbeat>,
+    // EventsLost of the session when the last RT_LostEvent was received
+    events_lost: u32,
+    events_lost_hook: Option<EventsLostHook>,
+    events_lost_query: EventsLostQuery,
+    #[cfg(feature = "sink")]
+    sink: Option<Mutex<FileSink>>,
+    #[cfg(feature = "sink")]
+    sink_error_hook: Option<SinkErrorHook>,
     // buffers_read : isize
 }
 
 impl TraceData {
     fn new() -> Self {
-        let name = format!("n4r1b-trace-{}", utils::rand_string());
+        let name = format!("{}{}", TRACE_NAME_PREFIX, utils::rand_string());
         TraceData {
             name,
             events_handled: 0,
             properties: TraceProperties::default(),
             providers: RwLock::new(Vec::new()),
+            log_file: None,
             schema_locator: schema::SchemaLocator::new(),
+            retry_enable: false,
+            min_level: None,
+            last_timestamp: 0,
+            last_timestamp_count: 0,
+            skip_until: None,
+            skip_seen: 0,
+            raw_timestamps: false,
+            clock: TraceClock::FileTime,
+            group_mask: [0; GROUP_MASK_COUNT],
+            paged_memory: false,
+            stack_walk: Vec::new(),
+            profile_interval: None,
+            consumer_thread_name: None,
+            process_error_hook: None,
+            capture_state: Vec::new(),
+            live_events: Arc::new(AtomicIsize::new(0)),
+            started: None,
+            heartbeat: None,
+            events_lost: 0,
+            events_lost_hook: None,
+            events_lost_query: EventsLostQuery::default(),
+            #[cfg(feature = "sink")]
+            sink: None,
+            #[cfg(feature = "sink")]
+            sink_error_hook: None,
+        }
+    }
+
+    pub(crate) fn consumer_thread_name(&self) -> String {
+        match &self.consumer_thread_name {
+            Some(name) => name.clone(),
+            None => format!("etw-consumer:{}", self.name),
+        }
+    }
+
+    pub(crate) fn raw_timestamps(&self) -> bool {
+        self.raw_timestamps
+    }
+
+    fn reset_events(&mut self) {
+        self.events_handled = 0;
+        self.events_lost = 0;
+        self.live_events.store(0, Ordering::Relaxed);
+    }
+
+    // The BufferCallback doesn't report EventsLost to real-time consumers, ETW delivers an
+    // RT_LostEvent instead, the count is then queried from the session
+    fn on_lost_event(&mut self) {
+        if self.events_lost_hook.is_none() {
+            return;
+        }
+        if let Some(events_lost) = (self.events_lost_query.0)(&self.name) {
+            self.report_events_lost(events_lost);
+        }
+    }
+
+    fn report_events_lost(&mut self, events_lost: u32) {
+        let delta = events_lost_delta(self.events_lost, events_lost);
+        self.events_lost = events_lost;
+        if delta > 0 {
+            if let Some(hook) = &self.events_lost_hook {
+                (hook.0)(delta);
+            }
+        }
+    }
+
+    // Called once the events are being processed
+    fn on_started(&mut self) {
+        let started = Instant::now();
+        self.started = Some(started);
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            let name = self.name.clone();
+            let live_events = Arc::clone(&self.live_events);
+            heartbeat.spawn(move || {
+                session_stats(
+                    &name,
+                    live_events.load(Ordering::Relaxed),
+                    started.elapsed(),
+                )
+                .ok()
+            });
+        }
+    }
+
+    fn query_stats(&self) -> TraceResult<TraceStats> {
+        let started = match self.started {
+            Some(started) => started,
+            None => {
+                return Err(TraceError::EtwNativeError(
+                    evntrace::EvntraceNativeError::InvalidHandle,
+                ))
+            }
+        };
+        session_stats(
+            &self.name,
+            self.live_events.load(Ordering::Relaxed),
+            started.elapsed(),
+        )
+    }
+
+    fn stop_heartbeat(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            heartbeat.join();
+        }
+    }
+
+    #[cfg(feature = "sink")]
+    fn write_sink(&mut self, record: EventRecord) {
+        if let Some(sink) = self.sink.as_ref() {
+            let written = self
+                .schema_locator
+                .event_schema(record)
+                .map_err(SinkError::from)
+                .and_then(|schema| match sink.lock() {
+                    Ok(mut sink) => sink.write_event(&schema).map(|_| ()),
+                    Err(_) => Ok(()),
+                });
+            if let Err(err) = written {
+                self.on_sink_error(&err);
+            }
+        }
+    }
+
+    fn flush_sink(&self) {
+        #[cfg(feature = "sink")]
+        if let Some(sink) = self.sink.as_ref() {
+            if let Ok(mut sink) = sink.lock() {
+                if let Err(err) = sink.flush() {
+                    self.on_sink_error(&err);
+                }
+            }
+        }
+    }
+
+    // Events the sink can't decode or write are left out of the file
+    #[cfg(feature = "sink")]
+    fn on_sink_error(&self, err: &SinkError) {
+        if let Some(hook) = &self.sink_error_hook {
+            (hook.0)(err);
         }
     }
 
@@ -101,19 +510,415 @@ impl TraceData {
     }
 
     pub(crate) fn on_event(&mut self, record: EventRecord) {
+        // Events already delivered on a previous pass over a followed file
+        let timestamp = record.EventHeader.TimeStamp;
+        if let Some((skip_until, skip_count)) = self.skip_until {
+            if timestamp < skip_until {
+                return;
+            }
+            if timestamp == skip_until && self.skip_seen < skip_count {
+                self.skip_seen += 1;
+                return;
+            }
+        }
+        if timestamp > self.last_timestamp || self.last_timestamp_count == 0 {
+            self.last_timestamp = timestamp;
+            self.last_timestamp_count = 1;
+        } else if timestamp == self.last_timestamp {
+            self.last_timestamp_count += 1;
+        }
+
+        if record.EventHeader.ProviderId == *LOST_EVENT {
+            self.on_lost_event();
+        }
+
+        if let Some(min_level) = self.min_level {
+            if !min_level.allows(record.EventHeader.EventDescriptor.Level) {
+                return;
+            }
+        }
+
         self.events_handled = self.events_handled + 1;
+        self.live_events.fetch_add(1, Ordering::Relaxed);
         let locator = &mut self.schema_locator;
         // We need a mutable reference to be able to modify the data it refers, which is actually
         // done within the Callback (The schema locator is modified)
         if let Ok(providers) = self.providers.read() {
+            let claimed = providers
+                .iter()
+                .any(|prov| prov.guid == Some(record.EventHeader.ProviderId));
             providers.iter().for_each(|prov| {
                 // We can unwrap safely, provider builder wouldn't accept a provider without guid
                 // so we must have Some(Guid)
-                if prov.guid.unwrap() == record.EventHeader.ProviderId {
+                let guid = prov.guid.unwrap();
+                if guid == record.EventHeader.ProviderId {
+                    prov.on_event(record, locator);
+                } else if guid == *SYSTEM_TRACE_CONTROL && !claimed && record.is_kernel_event() {
+                    // A Provider bound to the SystemTraceControlGuid receives the kernel events
+                    // no other Provider of the Trace is bound to
                     prov.on_event(record, locator);
                 }
             });
         };
+
+        #[cfg(feature = "sink")]
+        self.write_sink(record);
+    }
+}
+
+/// Settings of a Provider enabled in a Trace, see [TraceDescription]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProviderDescription {
+    /// Provider GUID
+    #[cfg_attr(feature = "serde", serde(serialize_with = "utils::serialize_guid"))]
+    pub guid: Guid,
+    /// Level the Provider is enabled at
+    pub level: u8,
+    /// Provider Any keyword
+    pub any: u64,
+    /// Provider All keyword
+    pub all: u64,
+    /// Provider trace flags
+    pub trace_flags: u32,
+    /// Provider kernel flags, only apply to KernelProvider
+    pub kernel_flags: u32,
+    /// Source Id used when enabling the Provider, if other than the Provider GUID
+    #[cfg_attr(feature = "serde", serde(serialize_with = "utils::serialize_opt_guid"))]
+    pub source_id: Option<Guid>,
+    /// Client side minimum [TraceLevel](provider::TraceLevel) of the Provider
+    pub min_level: Option<provider::TraceLevel>,
+    /// Whether only the events of the current process are dispatched
+    pub self_only: bool,
+}
+
+impl From<&Provider> for ProviderDescription {
+    fn from(provider: &Provider) -> Self {
+        ProviderDescription {
+            guid: provider.guid.unwrap_or_else(Guid::zeroed),
+            level: provider.level,
+            any: provider.any,
+            all: provider.all,
+            trace_flags: provider.trace_flags,
+            kernel_flags: provider.flags,
+            source_id: provider.source_id,
+            min_level: provider.min_level,
+            self_only: provider.self_only,
+        }
+    }
+}
+
+/// Summary of the configuration of a Trace, see [TraceBaseTrait::describe]
+///
+/// The `Display` implementation prints the summary in a plain text format meant to be pasted in
+/// bug reports. With the `serde` feature it can also be serialized
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TraceDescription {
+    /// Trace name
+    pub name: String,
+    /// [TraceClock] needed to convert the event timestamps, see [TraceBaseTrait::clock]
+    pub clock: TraceClock,
+    /// Session [TraceProperties]
+    pub properties: TraceProperties,
+    /// [LogFile] the Trace writes to, if any
+    pub log_file: Option<LogFile>,
+    /// Client side minimum [TraceLevel](provider::TraceLevel) of the Trace
+    pub min_level: Option<provider::TraceLevel>,
+    /// Providers enabled in the Trace
+    pub providers: Vec<ProviderDescription>,
+}
+
+impl std::fmt::Display for TraceDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "trace: {}", self.name)?;
+        writeln!(f, "clock: {:?}", self.clock)?;
+        writeln!(
+            f,
+            "buffers: size={}KB min={} max={} flush_timer={}s log_file_mode={:#x} age_limit={}",
+            self.properties.buffer_size,
+            self.properties.min_buffer,
+            self.properties.max_buffer,
+            self.properties.flush_timer,
+            self.properties.log_file_mode,
+            self.properties.age_limit
+        )?;
+        if let Some(log_file) = &self.log_file {
+            writeln!(
+                f,
+                "log_file: {} max_file_size={}MB preallocate={}",
+                log_file.path, log_file.max_file_size, log_file.preallocate
+            )?;
+        }
+        if let Some(min_level) = self.min_level {
+            writeln!(f, "min_level: {:?}", min_level)?;
+        }
+        for prov in self.providers.iter() {
+            write!(
+                f,
+                "provider: {:?} level={} any={:#x} all={:#x} trace_flags={:#x} kernel_flags={:#x}",
+                prov.guid, prov.level, prov.any, prov.all, prov.trace_flags, prov.kernel_flags
+            )?;
+            if let Some(source_id) = prov.source_id {
+                write!(f, " source_id={:?}", source_id)?;
+            }
+            if let Some(min_level) = prov.min_level {
+                write!(f, " min_level={:?}", min_level)?;
+            }
+            if prov.self_only {
+                write!(f, " self_only")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Statistics of a Trace, see [UserTrace::run_for] and [TraceBaseTrait::query_stats]
+///
+/// The session counters are only filled when queried from a running session, they are left to 0
+/// by [UserTrace::run_for]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceStats {
+    /// Number of events handled by the Trace
+    pub events_handled: isize,
+    /// Time the Trace was running
+    pub elapsed: Duration,
+    /// Number of buffers allocated for the session
+    pub buffers: u32,
+    /// Number of allocated buffers currently unused
+    pub free_buffers: u32,
+    /// Number of buffers flushed by the session
+    pub buffers_written: u32,
+    /// Number of events that couldn't be written to the session buffers
+    pub events_lost: u32,
+    /// Number of buffers that couldn't be delivered to the real-time consumer
+    pub real_time_buffers_lost: u32,
+    /// Number of buffers that couldn't be written to the log file
+    pub log_buffers_lost: u32,
+}
+
+/// Queries the counters of the running session named `name`
+fn session_stats(name: &str, events_handled: isize, elapsed: Duration) -> TraceResult<TraceStats> {
+    let info = evntrace::query_trace(name)?;
+    let properties = &info.properties;
+    Ok(TraceStats {
+        events_handled,
+        elapsed,
+        buffers: properties.NumberOfBuffers,
+        free_buffers: properties.FreeBuffers,
+        buffers_written: properties.BuffersWritten,
+        events_lost: properties.EventsLost,
+        real_time_buffers_lost: properties.RealTimeBuffersLost,
+        log_buffers_lost: properties.LogBuffersLost,
+    })
+}
+
+type HeartbeatCallback = dyn Fn(&TraceStats) + Send + Sync + 'static;
+type EventsLostCallback = dyn Fn(u32) + Send + Sync + 'static;
+#[cfg(feature = "sink")]
+type SinkErrorCallback = dyn Fn(&SinkError) + Send + Sync + 'static;
+
+// Callback set with UserTrace::on_events_lost
+pub(crate) struct EventsLostHook(Box<EventsLostCallback>);
+
+impl std::fmt::Debug for EventsLostHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventsLostHook")
+    }
+}
+
+// Callback set with UserTrace::on_sink_error
+#[cfg(feature = "sink")]
+pub(crate) struct SinkErrorHook(Box<SinkErrorCallback>);
+
+#[cfg(feature = "sink")]
+impl std::fmt::Debug for SinkErrorHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SinkErrorHook")
+    }
+}
+
+// Queries the EventsLost count of the session named `name`, replaced by the tests
+#[derive(Clone, Copy)]
+struct EventsLostQuery(fn(&str) -> Option<u32>);
+
+impl Default for EventsLostQuery {
+    fn default() -> Self {
+        EventsLostQuery(|name| {
+            evntrace::query_trace(name)
+                .ok()
+                .map(|info| info.properties.EventsLost)
+        })
+    }
+}
+
+impl std::fmt::Debug for EventsLostQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventsLostQuery")
+    }
+}
+
+// Events lost since the previous report, a count lower than the previous one means the counter
+// restarted
+fn events_lost_delta(previous: u32, current: u32) -> u32 {
+    current.checked_sub(previous).unwrap_or(current)
+}
+// Stop flag of the timer thread alongside its handle
+type HeartbeatWorker = (Arc<(Mutex<bool>, Condvar)>, JoinHandle<()>);
+
+/// Timer set with [UserTrace::heartbeat], ticking on its own thread while the Trace runs
+pub(crate) struct Heartbeat {
+    interval: Duration,
+    callback: Arc<HeartbeatCallback>,
+    worker: Option<HeartbeatWorker>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new<F>(interval: Duration, callback: F) -> Self
+    where
+        F: Fn(&TraceStats) + Send + Sync + 'static,
+    {
+        Heartbeat {
+            interval,
+            callback: Arc::new(callback),
+            worker: None,
+        }
+    }
+
+    /// Spawns the timer thread, on every tick the callback receives the [TraceStats] returned by
+    /// `query`, ticks where `query` returns `None` are skipped
+    pub(crate) fn spawn<Q>(&mut self, query: Q)
+    where
+        Q: Fn() -> Option<TraceStats> + Send + 'static,
+    {
+        self.join();
+
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stopped = Arc::clone(&stopped);
+        let interval = self.interval;
+        let callback = Arc::clone(&self.callback);
+        let thread = std::thread::spawn(move || {
+            let (lock, condvar) = &*thread_stopped;
+            let mut stopped = match lock.lock() {
+                Ok(stopped) => stopped,
+                Err(_) => return,
+            };
+            let mut next_tick = Instant::now() + interval;
+            while !*stopped {
+                let now = Instant::now();
+                if now >= next_tick {
+                    if let Some(stats) = query() {
+                        callback(&stats);
+                    }
+                    next_tick = Instant::now() + interval;
+                    continue;
+                }
+                stopped = match condvar.wait_timeout(stopped, next_tick - now) {
+                    Ok((stopped, _)) => stopped,
+                    Err(_) => return,
+                };
+            }
+        });
+        self.worker = Some((stopped, thread));
+    }
+
+    /// Stops the timer thread and waits for it to exit
+    pub(crate) fn join(&mut self) {
+        if let Some((stopped, thread)) = self.worker.take() {
+            let (lock, condvar) = &*stopped;
+            if let Ok(mut stopped) = lock.lock() {
+                *stopped = true;
+                condvar.notify_all();
+            }
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+impl std::fmt::Debug for Heartbeat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Heartbeat")
+            .field("interval", &self.interval)
+            .field("running", &self.worker.is_some())
+            .finish()
+    }
+}
+
+/// Where the events of a Trace come from, see [TraceBaseTrait::source]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceSource {
+    /// Events are consumed live from an ETW session
+    RealTime,
+    /// Events are written to, or replayed from, an `.etl` file
+    File,
+}
+
+/// What to do when `ProcessTrace` fails, see [TraceBaseTrait::on_process_error]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessErrorAction {
+    /// Call `ProcessTrace` again on the same session
+    Retry,
+    /// Stop processing the events, the session is left as is
+    Abort,
+}
+
+type ProcessErrorCallback = dyn FnMut(u32, u32) -> ProcessErrorAction + Send + 'static;
+
+/// Shared handle over the closure set with [TraceBaseTrait::on_process_error]
+#[derive(Clone)]
+pub(crate) struct ProcessErrorHook(Arc<Mutex<Box<ProcessErrorCallback>>>);
+
+impl ProcessErrorHook {
+    pub(crate) fn new<F>(hook: F) -> Self
+    where
+        F: FnMut(u32, u32) -> ProcessErrorAction + Send + 'static,
+    {
+        ProcessErrorHook(Arc::new(Mutex::new(Box::new(hook))))
+    }
+
+    pub(crate) fn action(&self, status: u32, attempt: u32) -> ProcessErrorAction {
+        match self.0.lock() {
+            Ok(mut hook) => hook(status, attempt),
+            Err(_) => ProcessErrorAction::Abort,
+        }
+    }
+}
+
+impl std::fmt::Debug for ProcessErrorHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProcessErrorHook")
+    }
+}
+
+impl TraceData {
+    fn source(&self) -> TraceSource {
+        if is_real_time_mode(self.properties.log_file_mode) {
+            TraceSource::RealTime
+        } else {
+            TraceSource::File
+        }
+    }
+
+    fn describe(&self) -> TraceDescription {
+        let providers = match self.providers.read() {
+            Ok(providers) => providers.iter().map(ProviderDescription::from).collect(),
+            Err(_) => Vec::new(),
+        };
+        TraceDescription {
+            name: self.name.clone(),
+            clock: self.clock,
+            properties: self.properties,
+            log_file: self.log_file.clone(),
+            min_level: self.min_level,
+            providers,
+        }
     }
 }
 
@@ -123,6 +928,12 @@ impl TraceData {
 pub trait TraceBaseTrait {
     /// Internal function to set TraceName. See [TraceTrait::named]
     fn set_trace_name(&mut self, name: &str);
+    /// Use the `trace_name` function to obtain the name of the session
+    ///
+    /// Unless set with [TraceTrait::named], the name is [TRACE_NAME_PREFIX] followed by a random
+    /// string generated for every Trace. Name the Trace to be able to find the session again, e.g.
+    /// to stop it with [UserTrace::stop_existing] after a crash
+    fn trace_name(&self) -> &str;
     /// The `set_trace_properties` function sets the ETW session configuration properties
     ///
     /// # Arguments
@@ -135,52 +946,329 @@ pub trait TraceBaseTrait {
     /// let my_trace = UserTrace::new().set_trace_properties(props);
     /// ```
     fn set_trace_properties(self, props: TraceProperties) -> Self;
-    /// The `enable` function enables a [Provider] for the Trace
+    /// The `age_limit` function sets the time in minutes unused buffers are kept before being freed
     ///
     /// # Arguments
-    /// * `provider` - [Provider] to enable
+    /// * `minutes` - Buffer aging time
     ///
     /// # Remarks
-    /// Multiple providers can be enabled for the same trace, as long as they are from the same CPU privilege
+    /// The value only applies to Windows versions older than Win8, on newer versions it's ignored
     ///
     /// # Example
     /// ```rust
-    /// let provider = Provider::new()
-    ///     .by_name("Microsoft-Windows-DistributedCOM")
-    ///     .add_callback(|record, schema| { println!("{}", record.EventHeader.ProcessId); })
-    ///     .build()?;
-    /// let my_trace = UserTrace::new().enable(provider);
+    /// let my_trace = KernelTrace::new().age_limit(15);
     /// ```
-    fn enable(self, provider: provider::Provider) -> Self;
-    /// The `open` function opens a Trace session
+    fn age_limit(self, minutes: u32) -> Self;
+    /// The `buffer_size` function sets the size, in KB, of each session buffer
     ///
-    /// # Remark
-    /// This function can fail, if it does it will return a [TraceError] accordingly
+    /// # Arguments
+    /// * `kb` - Size of a buffer, ETW picks the size when left to 0
     ///
     /// # Example
     /// ```rust
-    /// let my_trace = UserTrace::new().open()?;
+    /// let my_trace = KernelTrace::new().buffer_size(1024);
     /// ```
-    fn open(self) -> TraceResult<Self>
-    where
-        Self: Sized;
-    /// The `start` function starts a Trace session, this includes open and process the trace
-    ///
-    /// # Safety Note
-    /// This function will spawn a new thread, ETW blocks the thread listening to events, so we need
-    /// a new thread to which delegate this process.
+    fn buffer_size(self, kb: u32) -> Self;
+    /// The `min_buffers` function sets the number of buffers allocated when the session starts
     ///
+    /// # Arguments
+    /// * `count` - Minimum number of buffers
     ///
-    /// This function can fail, if it does it will return a [TraceError]
+    /// # Remarks
+    /// Opening the Trace fails with [TraceError::InvalidOptions] if the minimum is greater than
+    /// the maximum set with [TraceBaseTrait::max_buffers]
     ///
     /// # Example
     /// ```rust
-    /// let my_trace = UserTrace::new().start()?;
+    /// let my_trace = KernelTrace::new().min_buffers(64).max_buffers(256);
     /// ```
-    fn start(self) -> TraceResult<Self>
-    where
-        Self: Sized;
-    /// The `process` function will start processing a Trace session
+    fn min_buffers(self, count: u32) -> Self;
+    /// The `max_buffers` function sets the maximum number of buffers the session can allocate
+    ///
+    /// Raising it lets the session absorb bursts of events instead of losing them, see the
+    /// `events_lost` of [TraceBaseTrait::query_stats]
+    ///
+    /// # Arguments
+    /// * `count` - Maximum number of buffers, ETW picks the maximum when left to 0
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = KernelTrace::new().buffer_size(1024).max_buffers(256);
+    /// ```
+    fn max_buffers(self, count: u32) -> Self;
+    /// The `flush_timer` function sets how often, in seconds, the buffers are flushed
+    ///
+    /// # Arguments
+    /// * `secs` - Flush interval, when left to 0 the buffers are only flushed once full
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().flush_timer(1);
+    /// ```
+    fn flush_timer(self, secs: u32) -> Self;
+    /// The `retry_enable` function sets whether enabling a Provider should be retried when ETW
+    /// is out of resources
+    ///
+    /// # Arguments
+    /// * `retry` - Retry the enable a few times, with a short backoff, on [TraceError::OutOfResources]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().retry_enable(true);
+    /// ```
+    fn retry_enable(self, retry: bool) -> Self;
+    /// The `log_file` function sets the `.etl` file the Trace writes its events to
+    ///
+    /// # Arguments
+    /// * `log_file` - [LogFile] to write to
+    ///
+    /// # Remarks
+    /// The LogFile is checked again when the Trace is opened, which fails with a
+    /// [TraceError::InvalidLogFile] if it wouldn't pass [LogFile::build]
+    ///
+    /// # Example
+    /// ```rust
+    /// let log_file = LogFile::new("my_trace.etl").max_file_size(512).build()?;
+    /// let my_trace = UserTrace::new().log_file(log_file);
+    /// ```
+    fn log_file(self, log_file: LogFile) -> Self;
+    /// The `min_level` function drops, for every Provider in the Trace, the events more verbose
+    /// than `min_level` before they reach the callbacks
+    ///
+    /// # Arguments
+    /// * `min_level` - Most verbose [TraceLevel] dispatched to the callbacks
+    ///
+    /// # Remarks
+    /// This is a client side filter, see [Provider::min_level] for the per Provider equivalent
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().min_level(TraceLevel::Error);
+    /// ```
+    ///
+    /// [TraceLevel]: crate::provider::TraceLevel
+    /// [Provider::min_level]: crate::provider::Provider::min_level
+    fn min_level(self, min_level: provider::TraceLevel) -> Self;
+    /// The `raw_timestamps` function sets whether the events are delivered with raw QPC
+    /// timestamps instead of timestamps converted to FILETIME by ETW
+    ///
+    /// # Arguments
+    /// * `raw` - Deliver the raw QPC timestamps
+    ///
+    /// # Remarks
+    /// Skipping the conversion saves some work per event, the [RawTimestamp] returned by
+    /// [EventRecordExt::timestamp] can still be converted when needed with the [TraceBaseTrait::clock]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().raw_timestamps(true).enable(provider).start()?;
+    /// let clock = my_trace.clock();
+    /// ```
+    ///
+    /// [RawTimestamp]: crate::native::etw_types::RawTimestamp
+    /// [EventRecordExt::timestamp]: crate::native::etw_types::EventRecordExt::timestamp
+    fn raw_timestamps(self, raw: bool) -> Self;
+    /// The `use_paged_memory` function sets whether the session buffers are allocated from paged
+    /// memory (`EVENT_TRACE_USE_PAGED_MEMORY`) instead of the non-paged pool
+    ///
+    /// # Arguments
+    /// * `paged` - Allocate the session buffers from paged memory
+    ///
+    /// # Remarks
+    /// Relieves the non-paged pool on systems running many sessions. Kernel sessions can't use
+    /// paged buffers, opening a [KernelTrace] with this option set fails with
+    /// [TraceError::InvalidOptions]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().use_paged_memory(true).enable(provider).start()?;
+    /// ```
+    fn use_paged_memory(self, paged: bool) -> Self;
+    /// The `consumer_thread_name` function sets the name of the thread processing the events
+    ///
+    /// By default the thread is named `etw-consumer:` followed by the name of the Trace, so it can
+    /// be told apart in debuggers and profilers
+    ///
+    /// # Arguments
+    /// * `name` - Name of the thread
+    ///
+    /// # Remarks
+    /// The name is set with `SetThreadDescription`, which is only available from Windows 10 1607,
+    /// on older versions the thread is left unnamed
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().consumer_thread_name("my-consumer").enable(provider).start()?;
+    /// ```
+    fn consumer_thread_name(self, name: &str) -> Self;
+    /// The `on_process_error` function sets the closure deciding what to do when `ProcessTrace`
+    /// fails
+    ///
+    /// `ProcessTrace` returns `ERROR_SUCCESS` once the session is stopped and `ERROR_CANCELLED`
+    /// when the processing is cancelled, both are the normal end of the Trace and never reach the
+    /// closure. Any other status is an error, the closure receives it alongside the number of
+    /// consecutive failures and picks whether `ProcessTrace` is called again. By default the
+    /// processing is aborted on the first error
+    ///
+    /// # Arguments
+    /// * `hook` - Closure receiving the Win32 error code and the attempt number, starting at 1
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new()
+    ///     .on_process_error(|status, attempt| {
+    ///         if attempt <= 3 { ProcessErrorAction::Retry } else { ProcessErrorAction::Abort }
+    ///     })
+    ///     .enable(provider)
+    ///     .start()?;
+    /// ```
+    fn on_process_error<F>(self, hook: F) -> Self
+    where
+        F: FnMut(u32, u32) -> ProcessErrorAction + Send + 'static;
+    /// The `clock` function returns the [TraceClock] needed to convert the event timestamps
+    ///
+    /// # Remarks
+    /// The clock is captured when the Trace is opened, before that [TraceClock::FileTime] is
+    /// returned
+    fn clock(&self) -> TraceClock;
+    /// The `source` function returns where the events of the Trace come from
+    ///
+    /// # Remarks
+    /// A session is consumed in real time unless its [TraceProperties] set a `log_file_mode`
+    /// without `EVENT_TRACE_REAL_TIME_MODE`, the events are then only written to its [LogFile].
+    /// A [FileTrace] always replays an `.etl` file
+    fn source(&self) -> TraceSource;
+    /// The `is_realtime` function returns whether the Trace consumes a live session
+    ///
+    /// Functions such as [TraceBaseTrait::query_stats] only apply to live sessions
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().enable(provider).start()?;
+    /// if my_trace.is_realtime() {
+    ///     println!("{:?}", my_trace.query_stats()?);
+    /// }
+    /// ```
+    fn is_realtime(&self) -> bool {
+        self.source() == TraceSource::RealTime
+    }
+    /// The `describe` function summarizes the configuration of the Trace and its Providers
+    ///
+    /// # Remarks
+    /// Can be called both before and after the Trace is started, the [TraceDescription] is a
+    /// snapshot of the configuration at the time of the call
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().enable(provider);
+    /// println!("{}", my_trace.describe());
+    /// ```
+    fn describe(&self) -> TraceDescription;
+    /// The `estimated_memory_bytes` function computes the memory the session buffers can take
+    ///
+    /// The estimate is `buffer_size * max_buffer` from the [TraceProperties], multiplied by the
+    /// number of processors for a [KernelTrace] since the kernel logger allocates its buffers per
+    /// CPU. Unless [TraceBaseTrait::use_paged_memory] is set this is non-paged pool, so the value
+    /// can be validated against the system limits before starting the Trace
+    ///
+    /// # Remarks
+    /// Properties left to 0 are picked by ETW when the session starts, they count as 0 in the
+    /// estimate
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().set_trace_properties(props);
+    /// assert!(my_trace.estimated_memory_bytes() < 64 * 1024 * 1024);
+    /// ```
+    fn estimated_memory_bytes(&self) -> u64;
+    /// The `logger_thread_id` function returns the Id of the thread the session logger runs on
+    ///
+    /// The value is filled by the system in the `LoggerThreadId` of the EVENT_TRACE_PROPERTIES when
+    /// the session is started. It allows telling apart the logger activity when tracing the current
+    /// process, or looking at that thread when a session falls behind
+    ///
+    /// # Remarks
+    /// Returns `None` until the Trace is started
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().enable(provider).start()?;
+    /// println!("{:?}", my_trace.logger_thread_id());
+    /// ```
+    fn logger_thread_id(&self) -> Option<u32>;
+    /// The `query_stats` function returns the [TraceStats] of the running session
+    ///
+    /// The session counters are queried from ETW (`ControlTrace(EVENT_TRACE_CONTROL_QUERY)`), the
+    /// elapsed time is counted from the moment the Trace started processing events. A growing
+    /// `events_lost` or `real_time_buffers_lost` means the session drops events, widening the
+    /// buffers with [TraceBaseTrait::set_trace_properties] usually helps
+    ///
+    /// # Remarks
+    /// This function can fail, if the Trace hasn't been started yet or the query fails the
+    /// [TraceError] will be returned accordingly
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = KernelTrace::new().enable(provider).start()?;
+    /// let stats = my_trace.query_stats()?;
+    /// if stats.events_lost > 0 {
+    ///     println!("{} events lost", stats.events_lost);
+    /// }
+    /// ```
+    fn query_stats(&self) -> TraceResult<TraceStats>;
+    /// The `enable` function enables a [Provider] for the Trace
+    ///
+    /// # Arguments
+    /// * `provider` - [Provider] to enable
+    ///
+    /// # Remarks
+    /// Multiple providers can be enabled for the same trace, as long as they are from the same CPU privilege
+    ///
+    /// Each event is dispatched to the callbacks of the Providers whose GUID matches the
+    /// ProviderId of the event, a Provider never sees the events of the other Providers enabled
+    /// in the Trace. The only exception is a Provider bound to the `SYSTEM_TRACE_GUID`, such as
+    /// [KernelProvider::all], which receives the kernel events no other Provider is bound to
+    ///
+    /// [KernelProvider::all]: crate::provider::KernelProvider::all
+    ///
+    /// # Example
+    /// ```rust
+    /// let provider = Provider::new()
+    ///     .by_name("Microsoft-Windows-DistributedCOM")
+    ///     .add_callback(|record, schema| { println!("{}", record.EventHeader.ProcessId); })
+    ///     .build()?;
+    /// let my_trace = UserTrace::new().enable(provider);
+    /// ```
+    fn enable(self, provider: provider::Provider) -> Self;
+    /// The `open` function opens a Trace session
+    ///
+    /// # Remark
+    /// This function can fail, if it does it will return a [TraceError] accordingly
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().open()?;
+    /// ```
+    fn open(self) -> TraceResult<Self>
+    where
+        Self: Sized;
+    /// The `start` function starts a Trace session, this includes open and process the trace
+    ///
+    /// # Safety Note
+    /// This function will spawn a new thread, ETW blocks the thread listening to events, so we need
+    /// a new thread to which delegate this process.
+    ///
+    ///
+    /// This function can fail, if it does it will return a [TraceError]
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().start()?;
+    /// ```
+    fn start(self) -> TraceResult<Self>
+    where
+        Self: Sized;
+    /// The `process` function will start processing a Trace session
     ///
     /// # Safety Note
     /// This function will spawn the new thread which starts listening for events.
@@ -199,13 +1287,18 @@ pub trait TraceBaseTrait {
         Self: Sized;
     /// The `stop` function stops a Trace session
     ///
+    /// Stopping an already stopped, or never started, Trace does nothing and succeeds
+    ///
     /// # Safety Note
     /// Since a call to `start` will block thread and in case we want to execute it within a thread
     /// we would -- for now -- have to move it to the context of the new thread, this function is
-    /// called from the [Drop] implementation.
+    /// called from the [Drop] implementation. The [Drop] implementation ignores the errors, call
+    /// this function explicitly to handle them
     ///
-    /// This function will log if it fails
-    fn stop(&mut self);
+    /// # Remarks
+    /// This function can fail, if stopping the session or closing the consumer handle fails the
+    /// [TraceError] will be returned accordingly
+    fn stop(&mut self) -> TraceResult<()>;
 }
 
 // Hyper Macro to create an impl of the BaseTrace for the Kernel and User Trace
@@ -216,11 +1309,103 @@ macro_rules! impl_base_trace {
                 self.data.name = name.to_string();
             }
 
+            fn trace_name(&self) -> &str {
+                &self.data.name
+            }
+
             fn set_trace_properties(mut self, props: TraceProperties) -> Self {
                 self.data.properties = props;
                 self
             }
 
+            fn age_limit(mut self, minutes: u32) -> Self {
+                self.data.properties.age_limit = minutes;
+                self
+            }
+
+            fn buffer_size(mut self, kb: u32) -> Self {
+                self.data.properties.buffer_size = kb;
+                self
+            }
+
+            fn min_buffers(mut self, count: u32) -> Self {
+                self.data.properties.min_buffer = count;
+                self
+            }
+
+            fn max_buffers(mut self, count: u32) -> Self {
+                self.data.properties.max_buffer = count;
+                self
+            }
+
+            fn flush_timer(mut self, secs: u32) -> Self {
+                self.data.properties.flush_timer = secs;
+                self
+            }
+
+            fn retry_enable(mut self, retry: bool) -> Self {
+                self.data.retry_enable = retry;
+                self
+            }
+
+            fn log_file(mut self, log_file: LogFile) -> Self {
+                self.data.log_file = Some(log_file);
+                self
+            }
+
+            fn min_level(mut self, min_level: provider::TraceLevel) -> Self {
+                self.data.min_level = Some(min_level);
+                self
+            }
+
+            fn raw_timestamps(mut self, raw: bool) -> Self {
+                self.data.raw_timestamps = raw;
+                self
+            }
+
+            fn use_paged_memory(mut self, paged: bool) -> Self {
+                self.data.paged_memory = paged;
+                self
+            }
+
+            fn consumer_thread_name(mut self, name: &str) -> Self {
+                self.data.consumer_thread_name = Some(name.to_string());
+                self
+            }
+
+            fn on_process_error<F>(mut self, hook: F) -> Self
+            where
+                F: FnMut(u32, u32) -> ProcessErrorAction + Send + 'static,
+            {
+                self.data.process_error_hook = Some(ProcessErrorHook::new(hook));
+                self
+            }
+
+            fn clock(&self) -> TraceClock {
+                self.data.clock
+            }
+
+            fn source(&self) -> TraceSource {
+                self.data.source()
+            }
+
+            fn describe(&self) -> TraceDescription {
+                self.data.describe()
+            }
+
+            fn logger_thread_id(&self) -> Option<u32> {
+                self.etw.logger_thread_id()
+            }
+
+            fn query_stats(&self) -> TraceResult<TraceStats> {
+                self.data.query_stats()
+            }
+
+            fn estimated_memory_bytes(&self) -> u64 {
+                let cpus = if <$t>::per_cpu_buffers() { processor_count() } else { 1 };
+                estimated_memory_bytes(&self.data.properties, cpus)
+            }
+
             // TODO: Check if provider is built before inserting
             fn enable(mut self, provider: provider::Provider) -> Self {
                 if provider.guid.is_none() {
@@ -231,19 +1416,41 @@ macro_rules! impl_base_trace {
             }
 
             fn open(mut self) -> TraceResult<Self> {
-                self.data.events_handled = 0;
+                self.data.reset_events();
+                check_trace_name(&self.data.name)?;
+                check_buffer_counts(&self.data.properties)?;
+                check_event_ids(&self.data.providers)?;
+                check_log_file(&self.data.log_file)?;
+                let memory_mode = buffer_memory_mode(
+                    self.data.paged_memory,
+                    <$t>::supports_paged_memory(),
+                )?;
 
-                self.etw.fill_info::<$t>(&self.data.name, &self.data.properties, &self.data.providers);
+                self.etw.fill_info::<$t>(
+                    &self.data.name,
+                    &self.data.properties,
+                    self.data.log_file.as_ref(),
+                    &self.data.providers,
+                    memory_mode,
+                );
                 self.etw.register_trace(&self.data)?;
-                <$t>::enable_provider(&self);
+                if let Err(err) = <$t>::enable_provider(&self) {
+                    // The session is already started, don't leave it running behind the error
+                    let _ = self.etw.stop(&self.data);
+                    return Err(err);
+                }
                 self.etw.open(&self.data)?;
+                self.data.clock = evntrace::trace_clock(self.data.raw_timestamps);
 
                 Ok(self)
             }
 
             fn start(mut self) -> TraceResult<Self> {
-                self.data.events_handled = 0;
-                if let Err(err) = self.etw.start() {
+                self.data.reset_events();
+                if let Err(err) = self.etw.start(
+                    &self.data.consumer_thread_name(),
+                    self.data.process_error_hook.clone(),
+                ) {
                     match err {
                         evntrace::EvntraceNativeError::InvalidHandle => {
                             return Ok(self.open()?.process()?);
@@ -251,23 +1458,31 @@ macro_rules! impl_base_trace {
                         _=> return Err(TraceError::EtwNativeError(err)),
                     };
                 };
+                self.data.on_started();
                 Ok(self)
             }
 
-            fn stop(&mut self)  {
-                if let Err(err) = self.etw.stop(&self.data) {
-                    println!("Error stopping trace: {:?}", err);
+            fn stop(&mut self) -> TraceResult<()> {
+                self.data.stop_heartbeat();
+                let stopped = self.etw.stop(&self.data);
+                if let Ok(providers) = self.data.providers.read() {
+                    providers.iter().for_each(|prov| prov.flush_duplicates());
                 }
+                self.data.flush_sink();
+                Ok(stopped?)
             }
 
             fn process(mut self) -> TraceResult<Self> {
-                self.data.events_handled = 0;
-                self.etw.process()?;
+                self.data.reset_events();
+                self.etw.process(
+                    &self.data.consumer_thread_name(),
+                    self.data.process_error_hook.clone(),
+                )?;
+                self.data.on_started();
 
                 Ok(self)
             }
 
-            // query_stats
             // set_default_event_callback
             // buffers_processed
         })*
@@ -300,15 +1515,26 @@ pub trait TraceTrait: TraceBaseTrait {
     /// # Remarks
     /// If this function is not called during the process of building the trace a random name will be generated
     ///
+    /// The name is passed as the LoggerName of the session, it must be shorter than MAX_PATH (260
+    /// bytes) otherwise opening the Trace fails with [TraceError::InvalidTraceName]
+    ///
     /// # Example
     /// ```rust
-    /// let my_trace = UserTrace::new().named("TestTrace".to_string());
+    /// let my_trace = UserTrace::new().named("TestTrace");
     /// ```
-    fn named(self, name: String) -> Self;
-    fn enable_provider(&self) {}
+    fn named<S: Into<String>>(self, name: S) -> Self;
+    fn enable_provider(&self) -> TraceResult<()> {
+        Ok(())
+    }
     fn augmented_file_mode() -> u32 {
         0
     }
+    fn supports_paged_memory() -> bool {
+        true
+    }
+    fn per_cpu_buffers() -> bool {
+        false
+    }
     fn enable_flags(_providers: &RwLock<Vec<Provider>>) -> u32 {
         0
     }
@@ -333,76 +1559,620 @@ impl UserTrace {
     }
 }
 
-impl KernelTrace {
-    /// Use the `new` function to create a KernelTrace builder
+impl UserTrace {
+    /// Use the `run_for` function to run the Trace for a fixed amount of time
+    ///
+    /// The Trace is started, left running for `duration` and then stopped. The function blocks
+    /// until the processing thread has delivered the last events
+    ///
+    /// # Arguments
+    /// * `duration` - Time the Trace will be running
+    ///
+    /// # Remarks
+    /// This function can fail, if starting the Trace fails the [TraceError] will be returned
+    /// accordingly. The session is always stopped before returning, even on error
     ///
     /// # Example
     /// ```rust
-    /// let user_trace = KernelTrace::new();
+    /// let stats = UserTrace::new()
+    ///     .enable(provider)
+    ///     .run_for(Duration::from_secs(20))?;
+    /// println!("{} events", stats.events_handled);
     /// ```
-    pub fn new() -> Self {
-        let data = TraceData::new();
-
-        let mut kt = KernelTrace {
-            data,
-            etw: evntrace::NativeEtw::new(),
-        };
-
-        if !version_helper::is_win8_or_greater() {
-            kt.set_trace_name(KERNEL_LOGGER_NAME);
-        }
+    pub fn run_for(self, duration: Duration) -> TraceResult<TraceStats> {
+        let started = std::time::Instant::now();
+        // On error the trace is dropped, which stops the session
+        let mut trace = self.start()?;
+        std::thread::sleep(duration);
+        trace.stop()?;
+        trace.etw.join();
 
-        kt
+        Ok(TraceStats {
+            events_handled: trace.data.events_handled,
+            elapsed: started.elapsed(),
+            ..Default::default()
+        })
     }
-}
-
-impl TraceTrait for UserTrace {
-    /// See [TraceTrait::named]
-    fn named(mut self, name: String) -> Self {
-        if !name.is_empty() {
-            self.set_trace_name(&name);
-        }
 
+    /// Use the `heartbeat` function to receive the [TraceStats] of the session at a fixed interval
+    ///
+    /// Once the Trace is started a timer thread queries the session with
+    /// [TraceBaseTrait::query_stats] every `interval` and hands the result to the callback, which
+    /// allows watching for lost events or buffers while the Trace runs. The thread is stopped and
+    /// joined by [TraceBaseTrait::stop], hence also when the Trace is dropped
+    ///
+    /// # Arguments
+    /// * `interval` - Time between two calls of the callback
+    /// * `callback` - Closure receiving the fresh [TraceStats]
+    ///
+    /// # Remarks
+    /// The callback runs on the timer thread, ticks where the query fails are skipped
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new()
+    ///     .enable(provider)
+    ///     .heartbeat(Duration::from_secs(5), |stats| {
+    ///         println!("{} events, {} lost", stats.events_handled, stats.events_lost);
+    ///     })
+    ///     .start()?;
+    /// ```
+    pub fn heartbeat<F>(mut self, interval: Duration, callback: F) -> Self
+    where
+        F: Fn(&TraceStats) + Send + Sync + 'static,
+    {
+        self.data.heartbeat = Some(Heartbeat::new(interval, callback));
         self
     }
 
-    // TODO: Should this fail???
-    // TODO: Add option to enable same provider twice with different flags
-    #[allow(unused_must_use)]
-    fn enable_provider(&self) {
-        if let Ok(providers) = self.data.providers.read() {
-            providers.iter().for_each(|prov| {
-                // Should always be Some but just in case
-                if prov.guid.is_some() {
-                    let parameters =
-                        EnableTraceParameters::create(prov.guid.unwrap(), prov.trace_flags);
-                    // Fixme: return error if this fails
-                    self.etw.enable_trace(
-                        prov.guid.unwrap().clone(),
-                        prov.any,
-                        prov.all,
-                        prov.level,
-                        parameters,
-                    );
+    /// Use the `on_events_lost` function to be notified when the session drops events
+    ///
+    /// ETW delivers an `RT_LostEvent` to real-time consumers when events were dropped, the
+    /// `EventsLost` count of the session is then queried and, when it has grown since the previous
+    /// `RT_LostEvent`, the callback receives the number of newly lost events
+    ///
+    /// # Arguments
+    /// * `callback` - Closure receiving the number of events lost since the previous report
+    ///
+    /// # Remarks
+    /// The callback runs on the thread processing the events, when the `RT_LostEvent` is
+    /// processed. Use [UserTrace::heartbeat] to watch the lost buffers too
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new()
+    ///     .enable(provider)
+    ///     .on_events_lost(|lost| println!("{} events lost", lost))
+    ///     .start()?;
+    /// ```
+    pub fn on_events_lost<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(u32) + Send + Sync + 'static,
+    {
+        self.data.events_lost_hook = Some(EventsLostHook(Box::new(callback)));
+        self
+    }
+
+    /// Use the `stop_existing` function to stop a running session by its name
+    ///
+    /// A session that isn't stopped, e.g. because the process that started it crashed, outlives
+    /// its process and keeps its name taken. Calling this function before starting a named Trace
+    /// makes sure the name is free
+    ///
+    /// # Arguments
+    /// * `name` - Name of the session, see [TraceBaseTrait::trace_name]
+    ///
+    /// # Remarks
+    /// A session that doesn't exist is not an error. This function can fail, if the session can't
+    /// be stopped, e.g. without the rights to control it, the [TraceError] will be returned
+    ///
+    /// # Example
+    /// ```rust
+    /// UserTrace::stop_existing("my-agent")?;
+    /// let my_trace = UserTrace::new()
+    ///     .named(String::from("my-agent"))
+    ///     .enable(provider)
+    ///     .start()?;
+    /// ```
+    pub fn stop_existing(name: &str) -> TraceResult<()> {
+        check_trace_name(name)?;
+        Ok(evntrace::stop_trace_by_name(name)?)
+    }
+
+    /// Use the `sink_to` function to write every event of the Trace to a file
+    ///
+    /// Each event is decoded and appended to the file in the given [Format], see [FileSink] for
+    /// the details. The file is flushed when the Trace is stopped
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file, truncated if it exists
+    /// * `format` - [Format] of the file, CSV only writes the events with its EventId
+    ///
+    /// # Remarks
+    /// This function can fail, if the file can't be created the [TraceError::IoError] will be
+    /// returned. Use [UserTrace::sink] to rotate the file
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new()
+    ///     .enable(provider)
+    ///     .sink_to(Path::new("capture.ndjson"), Format::Ndjson)?
+    ///     .start()?;
+    /// ```
+    #[cfg(feature = "sink")]
+    pub fn sink_to(self, path: &std::path::Path, format: Format) -> TraceResult<Self> {
+        Ok(self.sink(FileSink::create(path, format)?))
+    }
+
+    /// Use the `sink` function to write every event of the Trace to a configured [FileSink]
+    ///
+    /// # Arguments
+    /// * `sink` - The [FileSink] the events are written to
+    #[cfg(feature = "sink")]
+    pub fn sink(mut self, sink: FileSink) -> Self {
+        self.data.sink = Some(Mutex::new(sink));
+        self
+    }
+
+    /// Use the `on_sink_error` function to be notified of the events the sink couldn't write
+    ///
+    /// An event is left out of the file when its Schema can't be located, its properties can't
+    /// be parsed or the file can't be written, the Trace keeps running
+    ///
+    /// # Arguments
+    /// * `callback` - Closure receiving the [SinkError] of the event
+    ///
+    /// # Remarks
+    /// The callback runs on the thread processing the events, it's also called if the final flush
+    /// of the file fails when the Trace is stopped
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new()
+    ///     .enable(provider)
+    ///     .sink_to(Path::new("capture.ndjson"), Format::Ndjson)?
+    ///     .on_sink_error(|err| println!("Event not written: {:?}", err))
+    ///     .start()?;
+    /// ```
+    #[cfg(feature = "sink")]
+    pub fn on_sink_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&SinkError) + Send + Sync + 'static,
+    {
+        self.data.sink_error_hook = Some(SinkErrorHook(Box::new(callback)));
+        self
+    }
+
+    /// Use the `is_provider_enabled` function to check whether a Provider is enabled on the Trace
+    ///
+    /// The enablement is queried from ETW (`EnumerateTraceGuidsEx(TraceGuidQueryInfo)`), which
+    /// catches the enables silently ignored by the system instead of assuming they took effect
+    ///
+    /// # Arguments
+    /// * `guid` - A string representation of the Provider GUID, without curly braces
+    ///
+    /// # Remarks
+    /// This function can fail, if the Trace hasn't been started yet or the query fails the
+    /// [TraceError] will be returned accordingly
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = UserTrace::new().enable(provider).start()?;
+    /// assert!(my_trace.is_provider_enabled("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")?);
+    /// ```
+    pub fn is_provider_enabled(&self, guid: &str) -> TraceResult<bool> {
+        Ok(self.etw.is_provider_enabled(&Guid::from(guid))?)
+    }
+
+    /// Use the `capture_state_on_start` function to have Providers log their current state when
+    /// the Trace starts
+    ///
+    /// Once the Providers are enabled a capture state request (`EVENT_CONTROL_CODE_CAPTURE_STATE`)
+    /// is sent to each of them, the rundown events they log in response, e.g. the processes
+    /// already running, end up at the beginning of the session. Combined with a [LogFile] this
+    /// makes the `.etl` self-contained for offline analysis
+    ///
+    /// # Arguments
+    /// * `providers` - GUIDs of the Providers whose state is captured
+    ///
+    /// # Remarks
+    /// The Providers have to be enabled in the Trace, otherwise starting the Trace fails with a
+    /// [TraceError::InvalidOptions]. The state is captured with the keywords and level the
+    /// Provider is enabled with
+    ///
+    /// # Example
+    /// ```rust
+    /// let kernel_process = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+    /// let my_trace = UserTrace::new()
+    ///     .log_file(LogFile::new("my_trace.etl").build()?)
+    ///     .enable(Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716").any(0x10))
+    ///     .capture_state_on_start(&[kernel_process])
+    ///     .start()?;
+    /// ```
+    pub fn capture_state_on_start(mut self, providers: &[Guid]) -> Self {
+        self.data.capture_state.extend_from_slice(providers);
+        self
+    }
+
+    // A Provider without GUID or enabled twice, within `providers` or in the Trace, is an error
+    fn check_new_providers(&self, providers: &[Provider]) -> TraceResult<()> {
+        let mut guids: Vec<Guid> = match self.data.providers.read() {
+            Ok(enabled) => enabled.iter().filter_map(|prov| prov.guid).collect(),
+            Err(_) => Vec::new(),
+        };
+        for prov in providers.iter() {
+            let guid = prov.guid.ok_or(provider::ProviderError::NoGuid)?;
+            prov.check_event_ids()?;
+            if guids.contains(&guid) {
+                return Err(TraceError::InvalidOptions(format!(
+                    "Provider {} enabled more than once",
+                    crate::fmt::format_guid(&guid)
+                )));
+            }
+            guids.push(guid);
+        }
+        Ok(())
+    }
+
+    /// Use the `enable_all` function to enable a list of Providers sharing the same callback
+    ///
+    /// The callback is added to every Provider, on top of their own callbacks, so it receives the
+    /// events of all of them. Use [Schema::provider_guid] or the ProviderId of the header to tell
+    /// them apart
+    ///
+    /// # Arguments
+    /// * `providers` - The [Provider]s to enable
+    /// * `callback` - Callback shared by the Providers
+    ///
+    /// # Remarks
+    /// This function can fail, a Provider without GUID or enabled twice, either within `providers`
+    /// or already enabled in the Trace, results in a [TraceError] and nothing is enabled
+    ///
+    /// # Example
+    /// ```rust
+    /// let providers = vec![
+    ///     Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716").build()?,
+    ///     Provider::new().by_name("Microsoft-Windows-DistributedCOM".to_string()).build()?,
+    /// ];
+    /// let my_trace = UserTrace::new().enable_all(providers, |record, schema_locator| {
+    ///     println!("{:?}", record.EventHeader.ProviderId);
+    /// })?;
+    /// ```
+    ///
+    /// [Schema::provider_guid]: crate::schema::Schema::provider_guid
+    pub fn enable_all<T>(mut self, providers: Vec<Provider>, callback: T) -> TraceResult<Self>
+    where
+        T: FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static,
+    {
+        self.check_new_providers(&providers)?;
+
+        let callback = Arc::new(Mutex::new(callback));
+        for prov in providers {
+            let shared = Arc::clone(&callback);
+            self.data.insert_provider(prov.add_callback(
+                move |record: EventRecord, locator: &mut schema::SchemaLocator| {
+                    if let Ok(mut callback) = shared.lock() {
+                        callback(record, locator);
+                    }
+                },
+            ));
+        }
+        Ok(self)
+    }
+}
+
+impl KernelTrace {
+    /// Use the `enable_group_mask` function to enable kernel event classes through the
+    /// PERFINFO_GROUPMASK
+    ///
+    /// Some kernel event classes, like the PMC counters or the profiling subclasses, can't be
+    /// expressed with the legacy 32-bit [kernel_flags] and are enabled through one of the 8 groups
+    /// of the mask instead. The mask is set with `TraceSetInformation(TraceSystemTraceEnableFlagsInfo)`
+    /// once the session is started
+    ///
+    /// # Arguments
+    /// * `group` - Index of the group, from 0 to 7
+    /// * `mask` - Flags to enable in the group, OR'ed with the flags already enabled
+    ///
+    /// # Remarks
+    /// Group 0 holds the legacy `EnableFlags`, the flags of the kernel Providers enabled in the
+    /// Trace are added to it. Groups 1 to 7 hold the `PERF_*` masks defined in ntwmi.h, those
+    /// constants encode their group in their top 3 bits (`PERF_X = (group << 29) | flag`) so the
+    /// `mask` is the constant without those bits
+    ///
+    /// The mask is only set when at least one of the groups 1 to 7 is used, it's not supported on
+    /// versions older than Win8
+    ///
+    /// This function can fail, if `group` is not lower than [GROUP_MASK_COUNT] a
+    /// [TraceError::InvalidOptions] is returned
+    ///
+    /// # Example
+    /// ```rust
+    /// // Flag 0x400 of the second group
+    /// let my_trace = KernelTrace::new().enable_group_mask(1, 0x00000400)?;
+    /// ```
+    ///
+    /// [kernel_flags]: crate::provider::kernel_providers::kernel_flags
+    pub fn enable_group_mask(mut self, group: usize, mask: u32) -> TraceResult<Self> {
+        if group >= GROUP_MASK_COUNT {
+            return Err(TraceError::InvalidOptions(format!(
+                "Group mask index out of range: {}",
+                group
+            )));
+        }
+        self.data.group_mask[group] |= mask;
+        Ok(self)
+    }
+
+    /// Use the `enable_stack_walk` function to log the call stack of a kernel event
+    ///
+    /// Once enabled, every event matching `event_guid` and `opcode` is followed by a StackWalk
+    /// event ([STACK_WALK_PROVIDER]) holding its call stack, the stacks are enabled with
+    /// `TraceSetInformation(TraceStackTracingInfo)` once the session is started. See
+    /// [StackWalkCorrelator] to attach the stacks to their events
+    ///
+    /// # Arguments
+    /// * `event_guid` - A string representation of the kernel Provider GUID, without curly braces
+    /// * `opcode` - Opcode of the event to walk the stack of
+    ///
+    /// # Remarks
+    /// The Provider of the event still has to be enabled in the Trace, along with the
+    /// [STACK_WALK_PROVIDER] to receive the StackWalk events in the callbacks
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_trace = KernelTrace::new()
+    ///     .enable(Provider::kernel(&kernel_providers::FILE_IO_PROVIDER).build()?)
+    ///     .enable(Provider::kernel(&kernel_providers::STACK_WALK_PROVIDER).build()?)
+    ///     .enable_stack_walk(kernel_guids::FILE_IO_GUID, 64) // FileIo Create
+    ///     .start()?;
+    /// ```
+    ///
+    /// [STACK_WALK_PROVIDER]: crate::provider::kernel_providers::STACK_WALK_PROVIDER
+    /// [StackWalkCorrelator]: crate::events::kernel::StackWalkCorrelator
+    pub fn enable_stack_walk(mut self, event_guid: &str, opcode: u8) -> Self {
+        self.data.stack_walk.push((Guid::from(event_guid), opcode));
+        self
+    }
+
+    /// Use the `set_profile_interval` function to set the interval of the sampled profile
+    ///
+    /// The SampledProfile events of the [PROFILE_PROVIDER] are logged every `interval`, instead of
+    /// the 1ms default. The interval is set with `TraceSetInformation(TraceSampledProfileIntervalInfo)`
+    /// once the session is started
+    ///
+    /// # Arguments
+    /// * `interval` - Time between two samples, in 100ns units
+    ///
+    /// # Remarks
+    /// The interval applies to the whole system, not only this session, and requires the
+    /// `SeSystemProfilePrivilege`. Windows clamps it between 1221 (0.1221ms) and 10000000 (1s)
+    ///
+    /// # Example
+    /// ```rust
+    /// // Sample at 8kHz
+    /// let my_trace = KernelTrace::new()
+    ///     .enable(Provider::kernel(&kernel_providers::PROFILE_PROVIDER).build()?)
+    ///     .set_profile_interval(1250)
+    ///     .start()?;
+    /// ```
+    ///
+    /// [PROFILE_PROVIDER]: crate::provider::kernel_providers::PROFILE_PROVIDER
+    pub fn set_profile_interval(mut self, interval: u32) -> Self {
+        self.data.profile_interval = Some(interval);
+        self
+    }
+
+    /// Use the `new` function to create a KernelTrace builder
+    ///
+    /// # Example
+    /// ```rust
+    /// let user_trace = KernelTrace::new();
+    /// ```
+    pub fn new() -> Self {
+        let data = TraceData::new();
+
+        let mut kt = KernelTrace {
+            data,
+            etw: evntrace::NativeEtw::new(),
+        };
+
+        if !version_helper::is_win8_or_greater() {
+            kt.set_trace_name(KERNEL_LOGGER_NAME);
+        }
+
+        kt
+    }
+}
+
+impl TraceTrait for UserTrace {
+    /// See [TraceTrait::named]
+    fn named<S: Into<String>>(mut self, name: S) -> Self {
+        let name = name.into();
+        if !name.is_empty() {
+            self.set_trace_name(&name);
+        }
+
+        self
+    }
+
+    // TODO: Add option to enable same provider twice with different flags
+    fn enable_provider(&self) -> TraceResult<()> {
+        if let Ok(providers) = self.data.providers.read() {
+            for prov in providers.iter() {
+                // Should always be Some but just in case
+                if let Some(guid) = prov.guid {
+                    let mut parameters = prov.enable_parameters();
+                    // The filters have to outlive the call to EnableTraceEx2
+                    let pids = [std::process::id()];
+                    let event_ids = event_id_filter(&prov.event_ids);
+                    let mut filters = Vec::new();
+                    if version_helper::is_win8_1_or_greater() {
+                        if prov.self_only {
+                            filters.push(EventFilterDescriptor::pid(&pids));
+                        }
+                        if !prov.event_ids.is_empty() {
+                            filters.push(EventFilterDescriptor::event_ids(&event_ids));
+                        }
+                    }
+                    if !filters.is_empty() {
+                        parameters.set_filters(&mut filters);
+                    }
+                    enable_with_retry(self.data.retry_enable, || {
+                        self.etw.enable_trace(
+                            guid,
+                            prov.any,
+                            prov.all,
+                            prov.level,
+                            prov.enable_timeout_ms(),
+                            parameters,
+                        )
+                    })?;
                 }
-            });
+            }
+
+            for (guid, any, all, level) in
+                capture_state_targets(&providers, &self.data.capture_state)?
+            {
+                self.etw.capture_state(guid, any, all, level)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Providers have to be enabled on the session to capture their state, with the same keywords
+fn capture_state_targets(
+    providers: &[Provider],
+    guids: &[Guid],
+) -> TraceResult<Vec<(Guid, u64, u64, u8)>> {
+    guids
+        .iter()
+        .map(|guid| {
+            providers
+                .iter()
+                .find(|prov| prov.guid == Some(*guid))
+                .map(|prov| (*guid, prov.any, prov.all, prov.level))
+                .ok_or_else(|| {
+                    TraceError::InvalidOptions(format!(
+                        "Provider {} has to be enabled to capture its state",
+                        crate::fmt::format_guid(guid)
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn estimated_memory_bytes(properties: &TraceProperties, cpus: u64) -> u64 {
+    // buffer_size is in KB
+    u64::from(properties.buffer_size) * 1024 * u64::from(properties.max_buffer) * cpus
+}
+
+fn processor_count() -> u64 {
+    std::thread::available_parallelism().map_or(1, |count| count.get() as u64)
+}
+
+// The name is copied, null terminated, to a MAX_PATH buffer of the session properties
+fn check_trace_name(name: &str) -> TraceResult<()> {
+    if name.is_empty() || name.len() >= MAX_TRACE_NAME {
+        return Err(TraceError::InvalidTraceName(format!(
+            "Trace name must be between 1 and {} bytes long",
+            MAX_TRACE_NAME - 1
+        )));
+    }
+    Ok(())
+}
+
+// A maximum of 0 is picked by ETW, there's nothing to check it against
+fn check_buffer_counts(properties: &TraceProperties) -> TraceResult<()> {
+    if properties.max_buffer != 0 && properties.min_buffer > properties.max_buffer {
+        return Err(TraceError::InvalidOptions(format!(
+            "Minimum number of buffers ({}) greater than the maximum ({})",
+            properties.min_buffer, properties.max_buffer
+        )));
+    }
+    Ok(())
+}
+
+// Providers enabled without being built could carry more EventIds than the filter supports
+fn check_event_ids(providers: &RwLock<Vec<Provider>>) -> TraceResult<()> {
+    if let Ok(providers) = providers.read() {
+        for prov in providers.iter() {
+            prov.check_event_ids()?;
         }
     }
+    Ok(())
+}
+
+// A LogFile set without being built could overflow the session's file name buffer
+fn check_log_file(log_file: &Option<LogFile>) -> TraceResult<()> {
+    match log_file {
+        Some(log_file) => log_file.check(),
+        None => Ok(()),
+    }
+}
+
+// LogFileMode bits selecting the pool the session buffers are allocated from
+fn buffer_memory_mode(paged_memory: bool, supports_paged_memory: bool) -> TraceResult<u32> {
+    match (paged_memory, supports_paged_memory) {
+        (false, _) => Ok(0),
+        (true, true) => Ok(EVENT_TRACE_USE_PAGED_MEMORY),
+        (true, false) => Err(TraceError::InvalidOptions(
+            "Kernel sessions can't use paged memory buffers".to_owned(),
+        )),
+    }
+}
+
+// The group mask replaces the legacy EnableFlags, which become its first group. None when no
+// extension group is set so the session keeps using the legacy flags
+fn group_mask_array(
+    group_mask: &[u32; GROUP_MASK_COUNT],
+    legacy_flags: u32,
+) -> Option<[u32; GROUP_MASK_COUNT]> {
+    if group_mask[1..].iter().all(|&mask| mask == 0) {
+        return None;
+    }
+
+    let mut masks = *group_mask;
+    masks[0] |= legacy_flags;
+    Some(masks)
 }
 
-// TODO: Implement enable_provider function for providers that require call to TraceSetInformation with extended PERFINFO_GROUPMASK
 impl TraceTrait for KernelTrace {
     /// See [TraceTrait::named]
     ///
     /// # Remarks
     /// On Windows Versions older than Win8 this method won't change the trace name. In those versions the trace name need to be set to "NT Kernel Logger", that's handled by the module
-    fn named(mut self, name: String) -> Self {
+    fn named<S: Into<String>>(mut self, name: S) -> Self {
+        let name = name.into();
         if !name.is_empty() && version_helper::is_win8_or_greater() {
             self.set_trace_name(&name);
         }
         self
     }
 
+    fn enable_provider(&self) -> TraceResult<()> {
+        let legacy_flags = <KernelTrace as TraceTrait>::enable_flags(&self.data.providers);
+        if let Some(mut masks) = group_mask_array(&self.data.group_mask, legacy_flags) {
+            self.etw.set_group_mask(&mut masks)?;
+        }
+        if !self.data.stack_walk.is_empty() {
+            self.etw.set_stack_tracing(&self.data.stack_walk)?;
+        }
+        if let Some(interval) = self.data.profile_interval {
+            self.etw.set_profile_interval(interval)?;
+        }
+        Ok(())
+    }
+
+    fn supports_paged_memory() -> bool {
+        false
+    }
+
+    fn per_cpu_buffers() -> bool {
+        true
+    }
+
     fn augmented_file_mode() -> u32 {
         if version_helper::is_win8_or_greater() {
             EVENT_TRACE_SYSTEM_LOGGER_MODE
@@ -428,47 +2198,1604 @@ impl TraceTrait for KernelTrace {
     }
 }
 
-/// On drop the ETW session will be stopped if not stopped before
-// TODO: log if it fails??
-#[allow(unused_must_use)]
-impl Drop for UserTrace {
-    fn drop(&mut self) {
-        if self.etw.session_handle() != INVALID_TRACE_HANDLE {
-            self.stop();
+// Replays the file until stopped, each pass picks up the events appended since the previous one
+fn follow_file<F>(
+    data: &mut TraceData,
+    stop: &AtomicBool,
+    poll_interval: Duration,
+    mut process_pass: F,
+) -> TraceResult<()>
+where
+    F: FnMut(&mut TraceData) -> TraceResult<()>,
+{
+    loop {
+        process_pass(data)?;
+        data.skip_until = Some((data.last_timestamp, data.last_timestamp_count));
+        data.skip_seen = 0;
+
+        if stop.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        std::thread::sleep(poll_interval);
+        if stop.load(Ordering::SeqCst) {
+            return Ok(());
         }
     }
 }
 
-/// On drop the ETW session will be stopped if not stopped before
-#[allow(unused_must_use)]
-impl Drop for KernelTrace {
-    fn drop(&mut self) {
-        if self.etw.session_handle() != INVALID_TRACE_HANDLE {
-            self.stop();
-        }
-    }
+/// File Trace struct
+///
+/// Replays the events stored in an `.etl` file to the enabled Providers. In follow mode the file
+/// is polled for new events while it grows, like `tail -f`, until the trace is stopped
+pub struct FileTrace {
+    data: Option<Box<TraceData>>,
+    path: String,
+    follow: bool,
+    poll_interval: Duration,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<TraceResult<()>>>,
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+impl FileTrace {
+    /// Use the `new` function to create a FileTrace builder that replays the file once
+    ///
+    /// # Arguments
+    /// * `path` - Path of the `.etl` file to replay
+    ///
+    /// # Example
+    /// ```rust
+    /// let file_trace = FileTrace::new("my_trace.etl");
+    /// ```
+    pub fn new(path: &str) -> Self {
+        FileTrace {
+            data: Some(Box::new(TraceData::new())),
+            path: path.to_string(),
+            follow: false,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            stop: Arc::new(AtomicBool::new(false)),
+            worker: None,
+        }
+    }
 
-    #[test]
-    fn test_set_properties() {
-        let prop = TraceProperties {
-            buffer_size: 10,
-            min_buffer: 1,
-            max_buffer: 20,
-            flush_timer: 60,
-            log_file_mode: 5,
-        };
-        let trace = UserTrace::new().set_trace_properties(prop);
+    /// Use the `open` function to create a FileTrace builder that replays the file once
+    ///
+    /// Same as [FileTrace::new], for paths built with [std::path]. Any `.etl` file can be
+    /// replayed, including the captures of `wpr` or `xperf`, the enabled Providers receive the
+    /// events just like in a live session
+    ///
+    /// # Arguments
+    /// * `path` - Path of the `.etl` file to replay
+    ///
+    /// # Example
+    /// ```rust
+    /// let stats = FileTrace::open(Path::new("C:\\traces\\boot.etl"))
+    ///     .enable(provider)
+    ///     .run()?;
+    /// ```
+    pub fn open(path: &std::path::Path) -> Self {
+        FileTrace::new(&path.to_string_lossy())
+    }
 
-        assert_eq!(trace.data.properties.buffer_size, 10);
-        assert_eq!(trace.data.properties.min_buffer, 1);
-        assert_eq!(trace.data.properties.max_buffer, 20);
-        assert_eq!(trace.data.properties.flush_timer, 60);
+    /// Use the `follow` function to create a FileTrace builder that follows a growing file
+    ///
+    /// Once the end of the current data is reached the file is polled again every `poll_interval`
+    /// (1 second by default) and only the events appended since the previous pass are delivered.
+    /// The trace keeps following the file until `stop` is called
+    ///
+    /// # Arguments
+    /// * `path` - Path of the `.etl` file to follow
+    ///
+    /// # Remarks
+    /// Events are only visible once the writer session flushes its buffers to the file, the
+    /// flush timer of the writer bounds how far behind the follower is
+    ///
+    /// # Example
+    /// ```rust
+    /// let file_trace = FileTrace::follow("my_trace.etl").enable(provider).process()?;
+    /// ```
+    pub fn follow(path: &str) -> Self {
+        let mut file_trace = FileTrace::new(path);
+        file_trace.follow = true;
+        file_trace
+    }
+
+    /// Use the `poll_interval` function to set how often a followed file is polled for new events
+    ///
+    /// # Arguments
+    /// * `poll_interval` - Time to wait after reaching the end of the current data
+    ///
+    /// # Example
+    /// ```rust
+    /// let file_trace = FileTrace::follow("my_trace.etl").poll_interval(Duration::from_millis(200));
+    /// ```
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Use the `enable` function to receive the events of a [Provider] stored in the file
+    ///
+    /// # Arguments
+    /// * `provider` - [Provider] to enable
+    ///
+    /// # Example
+    /// ```rust
+    /// let file_trace = FileTrace::new("my_trace.etl").enable(provider);
+    /// ```
+    pub fn enable(mut self, provider: provider::Provider) -> Self {
+        if provider.guid.is_none() {
+            panic!("Can't enable Provider with no GUID");
+        }
+        if let Some(data) = self.data.as_mut() {
+            data.insert_provider(provider);
+        }
+        self
+    }
+
+    /// Use the `process` function to start replaying the file
+    ///
+    /// # Safety Note
+    /// This function will spawn a new thread, the events are delivered to the Providers callbacks
+    /// from that thread
+    ///
+    /// # Example
+    /// ```rust
+    /// let file_trace = FileTrace::new("my_trace.etl").enable(provider).process()?;
+    /// ```
+    pub fn process(mut self) -> TraceResult<Self> {
+        let mut data = match self.data.take() {
+            Some(data) => data,
+            None => return Ok(self),
+        };
+
+        let path = self.path.clone();
+        let follow = self.follow;
+        let poll_interval = self.poll_interval;
+        let stop = Arc::clone(&self.stop);
+        self.worker = Some(std::thread::spawn(move || {
+            let process_pass = |data: &mut TraceData| -> TraceResult<()> {
+                let start_time = data.last_timestamp;
+                Ok(evntrace::process_file(data, &path, start_time)?)
+            };
+
+            if follow {
+                follow_file(&mut data, &stop, poll_interval, process_pass)
+            } else {
+                process_pass(&mut data)
+            }
+        }));
+
+        Ok(self)
+    }
+
+    /// The `source` function returns where the events of the Trace come from, always
+    /// [TraceSource::File] for a FileTrace
+    pub fn source(&self) -> TraceSource {
+        TraceSource::File
+    }
+
+    /// The `is_realtime` function returns whether the Trace consumes a live session, always
+    /// `false` for a FileTrace
+    pub fn is_realtime(&self) -> bool {
+        false
+    }
+
+    /// Use the `run` function to replay the whole file on the calling thread
+    ///
+    /// Unlike [FileTrace::process] no thread is spawned, the function returns once every event
+    /// of the file has been delivered to the Providers callbacks
+    ///
+    /// # Remarks
+    /// This function can fail, a followed file never ends and results in
+    /// [TraceError::InvalidOptions], if the replay fails the [TraceError] will be returned
+    /// accordingly
+    ///
+    /// # Example
+    /// ```rust
+    /// let stats = FileTrace::new("my_trace.etl").enable(provider).run()?;
+    /// println!("{} events", stats.events_handled);
+    /// ```
+    pub fn run(mut self) -> TraceResult<TraceStats> {
+        if self.follow {
+            return Err(TraceError::InvalidOptions(
+                "A followed file can't be run to completion".to_string(),
+            ));
+        }
+        let mut data = match self.data.take() {
+            Some(data) => data,
+            None => {
+                return Err(TraceError::InvalidOptions(
+                    "The file is already being processed".to_string(),
+                ))
+            }
+        };
+
+        let started = Instant::now();
+        data.reset_events();
+        evntrace::process_file(&mut data, &self.path, 0)?;
+
+        Ok(TraceStats {
+            events_handled: data.events_handled,
+            elapsed: started.elapsed(),
+            ..Default::default()
+        })
+    }
+
+    /// Use the `stop` function to stop following the file
+    ///
+    /// The function waits for the current pass over the file to finish, when not following the
+    /// file this waits for the whole replay
+    ///
+    /// # Remarks
+    /// This function can fail, if the replay failed the [TraceError] will be returned accordingly,
+    /// a panic of a callback results in [TraceError::ReplayPanicked]
+    pub fn stop(&mut self) -> TraceResult<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.worker.take() {
+            Some(worker) => worker.join().unwrap_or_else(|panic| {
+                let msg = match panic.downcast_ref::<&str>() {
+                    Some(msg) => msg.to_string(),
+                    None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+                };
+                Err(TraceError::ReplayPanicked(msg))
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// On drop the replay will be stopped if not stopped before
+#[allow(unused_must_use)]
+impl Drop for FileTrace {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Builder of a [UserTrace] enabling a set of Providers, each with its own callback
+///
+/// Collects the session configuration and the `(Provider, callback)` pairs so a monitoring setup
+/// can be started in one expression. It's a thin layer over [UserTrace], the Trace it returns
+/// can still be configured with the rest of the [TraceBaseTrait] functions before being started
+///
+/// # Example
+/// ```rust
+/// let my_trace = TraceBuilder::new()
+///     .named("my-monitor")
+///     .buffer_size(512)
+///     .with(Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716"), process_callback)
+///     .with(Provider::new().by_name("Microsoft-Windows-DNS-Client"), dns_callback)
+///     .start()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct TraceBuilder {
+    name: Option<String>,
+    properties: TraceProperties,
+    providers: Vec<Provider>,
+}
+
+impl TraceBuilder {
+    /// Use the `new` function to create a TraceBuilder without Providers
+    ///
+    /// # Example
+    /// ```rust
+    /// let builder = TraceBuilder::new();
+    /// ```
+    pub fn new() -> Self {
+        TraceBuilder {
+            name: None,
+            properties: TraceProperties::default(),
+            providers: Vec::new(),
+        }
+    }
+
+    /// Use the `named` function to set the name of the Trace, see [TraceTrait::named]
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Use the `buffer_size` function to set the size, in KB, of the session buffers
+    pub fn buffer_size(mut self, buffer_size: u32) -> Self {
+        self.properties.buffer_size = buffer_size;
+        self
+    }
+
+    /// Use the `min_buffers` function to set the minimum number of session buffers
+    pub fn min_buffers(mut self, count: u32) -> Self {
+        self.properties.min_buffer = count;
+        self
+    }
+
+    /// Use the `max_buffers` function to set the maximum number of session buffers
+    pub fn max_buffers(mut self, count: u32) -> Self {
+        self.properties.max_buffer = count;
+        self
+    }
+
+    /// Use the `flush_timer` function to set how often, in seconds, the buffers are flushed
+    pub fn flush_timer(mut self, flush_timer: u32) -> Self {
+        self.properties.flush_timer = flush_timer;
+        self
+    }
+
+    /// Use the `properties` function to set the whole [TraceProperties] at once
+    ///
+    /// Overrides the values set with the other buffer functions
+    pub fn properties(mut self, properties: TraceProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// Use the `with` function to add a Provider along with the callback receiving its events
+    ///
+    /// # Arguments
+    /// * `provider` - The [Provider] to enable, it doesn't need to be built beforehand
+    /// * `callback` - Callback added to the Provider, see [Provider::add_callback]
+    pub fn with<T>(mut self, provider: Provider, callback: T) -> Self
+    where
+        T: FnMut(EventRecord, &mut schema::SchemaLocator) + Send + Sync + 'static,
+    {
+        self.providers.push(provider.add_callback(callback));
+        self
+    }
+
+    /// Use the `build` function to create the [UserTrace] with every Provider enabled
+    ///
+    /// # Remarks
+    /// This function can fail, a Provider that doesn't build or enabled twice, or more minimum
+    /// buffers than the maximum, results in a [TraceError]
+    pub fn build(self) -> TraceResult<UserTrace> {
+        check_buffer_counts(&self.properties)?;
+        let providers = self
+            .providers
+            .into_iter()
+            .map(Provider::build)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut trace = UserTrace::new().set_trace_properties(self.properties);
+        if let Some(name) = self.name {
+            trace = trace.named(name);
+        }
+        trace.check_new_providers(&providers)?;
+        Ok(providers
+            .into_iter()
+            .fold(trace, |trace, provider| trace.enable(provider)))
+    }
+
+    /// Use the `start` function to build and start the [UserTrace]
+    ///
+    /// # Remarks
+    /// This function can fail, see [TraceBuilder::build] and [TraceBaseTrait::start]
+    pub fn start(self) -> TraceResult<UserTrace> {
+        self.build()?.start()
+    }
+}
+
+/// Maximum number of events returned by [explore]
+#[cfg(feature = "serde_json")]
+pub const EXPLORE_MAX_EVENTS: usize = 10_000;
+
+/// Use the `explore` function to find out which events a Provider emits
+///
+/// The Provider is enabled at Verbose level with every keyword, the events received during
+/// `duration` are returned as JSON lines. Each line holds the event header info alongside every
+/// property formatted by TDH, see [Schema::format_all]. Available with the `serde_json` feature
+///
+/// # Arguments
+/// * `provider` - GUID, without curly braces, or name of the Provider
+/// * `duration` - Time to capture events for
+///
+/// # Remarks
+/// Only the first [EXPLORE_MAX_EVENTS] events are kept, chatty providers would exhaust the memory
+/// otherwise. Events whose schema can't be found are skipped
+///
+/// # Example
+/// ```rust
+/// for line in explore("Microsoft-Windows-Kernel-Process", Duration::from_secs(5))? {
+///     println!("{}", line);
+/// }
+/// ```
+///
+/// [Schema::format_all]: crate::schema::Schema::format_all
+#[cfg(feature = "serde_json")]
+pub fn explore(provider: &str, duration: Duration) -> TraceResult<Vec<String>> {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let callback_lines = Arc::clone(&lines);
+
+    let provider = if utils::is_guid_str(provider) {
+        Provider::new().by_guid(provider)
+    } else {
+        Provider::new().by_name(provider.to_string())
+    };
+    let provider = provider
+        .level(5)
+        .any(u64::MAX)
+        .add_callback(
+            move |record: EventRecord, locator: &mut schema::SchemaLocator| {
+                let mut lines = match callback_lines.lock() {
+                    Ok(lines) => lines,
+                    Err(_) => return,
+                };
+                if lines.len() >= EXPLORE_MAX_EVENTS {
+                    return;
+                }
+
+                if let Ok(schema) = locator.event_schema(record) {
+                    let mut parser = Parser::create(&schema);
+                    let properties = schema.format_all(&mut parser);
+                    lines.push(event_json(&schema, &properties));
+                }
+            },
+        )
+        .build()?;
+
+    let mut trace = UserTrace::new().enable(provider).start()?;
+    std::thread::sleep(duration);
+    trace.stop()?;
+    // The events still buffered are delivered before ProcessTrace returns
+    trace.etw.join();
+
+    let lines = match lines.lock() {
+        Ok(lines) => lines.clone(),
+        Err(_) => Vec::new(),
+    };
+    Ok(lines)
+}
+
+#[cfg(feature = "serde_json")]
+fn event_json(schema: &schema::Schema, properties: &[(String, String)]) -> String {
+    let properties = properties
+        .iter()
+        .map(|(name, value)| (name.clone(), serde_json::Value::from(value.as_str())));
+    event_json_line(schema, properties)
+}
+
+// The header fields of an event followed by its properties, in the order of the schema
+#[cfg(feature = "serde_json")]
+pub(crate) fn event_json_line<I>(schema: &schema::Schema, properties: I) -> String
+where
+    I: Iterator<Item = (String, serde_json::Value)>,
+{
+    let record = schema.record();
+    let properties: serde_json::Map<String, serde_json::Value> = properties.collect();
+
+    serde_json::json!({
+        "provider_id": crate::fmt::format_guid(&record.EventHeader.ProviderId),
+        "provider_name": schema.provider_name(),
+        "event_id": schema.event_id(),
+        "version": schema.event_version(),
+        "opcode": schema.opcode(),
+        "level": record.EventHeader.EventDescriptor.Level,
+        "timestamp": schema.timestamp(),
+        "process_id": schema.process_id(),
+        "thread_id": schema.thread_id(),
+        "properties": properties,
+    })
+    .to_string()
+}
+
+/// On drop the ETW session will be stopped if not stopped before, so it doesn't outlive the
+/// process. Errors are ignored, use [TraceBaseTrait::stop] to handle them
+impl Drop for UserTrace {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// On drop the ETW session will be stopped if not stopped before, so it doesn't outlive the
+/// process. Errors are ignored, use [TraceBaseTrait::stop] to handle them
+impl Drop for KernelTrace {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_properties() {
+        let prop = TraceProperties {
+            buffer_size: 10,
+            min_buffer: 1,
+            max_buffer: 20,
+            flush_timer: 60,
+            log_file_mode: 5,
+            age_limit: 15,
+        };
+        let trace = UserTrace::new().set_trace_properties(prop);
+
+        assert_eq!(trace.data.properties.buffer_size, 10);
+        assert_eq!(trace.data.properties.min_buffer, 1);
+        assert_eq!(trace.data.properties.max_buffer, 20);
+        assert_eq!(trace.data.properties.flush_timer, 60);
         assert_eq!(trace.data.properties.log_file_mode, 5);
+        assert_eq!(trace.data.properties.age_limit, 15);
+    }
+
+    #[test]
+    fn test_set_age_limit() {
+        let trace = UserTrace::new().age_limit(30);
+
+        assert_eq!(trace.data.properties.age_limit, 30);
+    }
+
+    #[test]
+    fn test_no_system_resources_mapping() {
+        let err = evntrace::enable_status(1450).unwrap_err();
+        assert!(matches!(TraceError::from(err), TraceError::OutOfResources));
+    }
+
+    #[test]
+    fn test_enable_retry_on_no_resources() {
+        let mut attempts = 0;
+        let res = enable_with_retry(true, || {
+            attempts += 1;
+            if attempts < 3 {
+                return Err(evntrace::EvntraceNativeError::NoSystemResources);
+            }
+            Ok(())
+        });
+
+        assert!(res.is_ok());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_enable_no_retry_on_no_resources() {
+        let mut attempts = 0;
+        let res = enable_with_retry(false, || {
+            attempts += 1;
+            Err(evntrace::EvntraceNativeError::NoSystemResources)
+        });
+
+        assert!(matches!(res, Err(TraceError::OutOfResources)));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_log_file_preallocate() {
+        let log_file = LogFile::new("trace.etl")
+            .max_file_size(512)
+            .preallocate(true)
+            .build()
+            .unwrap();
+        let trace = UserTrace::new().log_file(log_file);
+
+        let log_file = trace.data.log_file.as_ref().unwrap();
+        assert!(log_file.preallocate);
+        assert_eq!(log_file.max_file_size, 512);
+    }
+
+    #[test]
+    fn test_describe_enabled_providers() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .level(4)
+            .any(0x10)
+            .self_only();
+        let prov1 = Provider::new()
+            .by_guid("A0C1853B-5C40-4B15-8766-3CF1C58F985A")
+            .min_level(provider::TraceLevel::Error);
+        let trace = UserTrace::new()
+            .named(String::from("DescribeTrace"))
+            .age_limit(15)
+            .enable(prov)
+            .enable(prov1);
+
+        let description = trace.describe();
+        assert_eq!(description.name, "DescribeTrace");
+        assert_eq!(description.properties.age_limit, 15);
+        assert_eq!(description.providers.len(), 2);
+        assert_eq!(
+            description.providers[0].guid,
+            Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+        );
+        assert_eq!(description.providers[0].level, 4);
+        assert_eq!(description.providers[0].any, 0x10);
+        assert!(description.providers[0].self_only);
+        assert_eq!(
+            description.providers[1].min_level,
+            Some(provider::TraceLevel::Error)
+        );
+
+        let text = description.to_string();
+        assert!(text.contains("trace: DescribeTrace"));
+        assert_eq!(text.matches("provider: ").count(), 2);
+    }
+
+    #[test]
+    fn test_describe_reports_trace_clock() {
+        let mut trace = UserTrace::new().raw_timestamps(true);
+        assert_eq!(trace.describe().clock, TraceClock::FileTime);
+
+        let clock = TraceClock::Qpc {
+            frequency: 10_000_000,
+            sync_qpc: 1000,
+            sync_filetime: 2000,
+        };
+        trace.data.clock = clock;
+        let description = trace.describe();
+        assert_eq!(description.clock, clock);
+        assert!(description.to_string().contains("clock: Qpc"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    fn test_describe_serialize() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .min_level(provider::TraceLevel::Error);
+        let trace = UserTrace::new()
+            .named(String::from("SerializeTrace"))
+            .enable(prov);
+
+        let value = serde_json::to_value(trace.describe()).unwrap();
+        assert_eq!(value["name"], "SerializeTrace");
+        assert_eq!(value["clock"], "FileTime");
+        assert_eq!(
+            value["providers"][0]["guid"],
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716"
+        );
+        assert_eq!(value["providers"][0]["source_id"], serde_json::Value::Null);
+        assert_eq!(value["providers"][0]["min_level"], "Error");
+    }
+
+    #[test]
+    fn test_log_file_preallocate_without_size() {
+        let res = LogFile::new("trace.etl").preallocate(true).build();
+
+        assert!(matches!(res, Err(TraceError::InvalidLogFile(_))));
+    }
+
+    #[test]
+    fn test_open_checks_unbuilt_log_file() {
+        let res = UserTrace::new()
+            .log_file(LogFile::new(&"a".repeat(MAX_LOG_FILE_PATH)))
+            .open();
+        assert!(matches!(res, Err(TraceError::InvalidLogFile(_))));
+
+        let res = UserTrace::new()
+            .log_file(LogFile::new("trace.etl").preallocate(true))
+            .open();
+        assert!(matches!(res, Err(TraceError::InvalidLogFile(_))));
+    }
+
+    #[test]
+    fn test_follow_picks_up_appended_events() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_seen = Arc::clone(&seen);
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .add_callback(move |record: EventRecord, _| {
+                callback_seen
+                    .lock()
+                    .unwrap()
+                    .push(record.EventHeader.TimeStamp);
+            });
+        let mut data = TraceData::new();
+        data.insert_provider(prov);
+
+        let mut file: Vec<i64> = vec![1, 2, 3];
+        let stop = AtomicBool::new(false);
+        let mut passes = 0;
+        let res = follow_file(&mut data, &stop, Duration::from_millis(1), |data| {
+            // Every pass replays the whole file, as ProcessTrace does from the start time
+            for timestamp in file.iter() {
+                let mut record: EventRecord = unsafe { std::mem::zeroed() };
+                record.EventHeader.ProviderId = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+                record.EventHeader.TimeStamp = *timestamp;
+                data.on_event(record);
+            }
+
+            passes += 1;
+            match passes {
+                1 => file.extend_from_slice(&[4, 5]),
+                _ => stop.store(true, Ordering::SeqCst),
+            }
+            Ok(())
+        });
+
+        assert!(res.is_ok());
+        assert_eq!(passes, 2);
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_follow_keeps_appended_events_sharing_a_timestamp() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let callback_seen = Arc::clone(&seen);
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .add_callback(move |record: EventRecord, _| {
+                callback_seen
+                    .lock()
+                    .unwrap()
+                    .push(record.EventHeader.EventDescriptor.Id);
+            });
+        let mut data = TraceData::new();
+        data.insert_provider(prov);
+
+        // (TimeStamp, Id), the events appended after the first pass share its last TimeStamp
+        let mut file: Vec<(i64, u16)> = vec![(1, 1), (2, 2), (2, 3)];
+        let stop = AtomicBool::new(false);
+        let mut passes = 0;
+        let res = follow_file(&mut data, &stop, Duration::from_millis(1), |data| {
+            for (timestamp, id) in file.iter() {
+                let mut record: EventRecord = unsafe { std::mem::zeroed() };
+                record.EventHeader.ProviderId = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+                record.EventHeader.TimeStamp = *timestamp;
+                record.EventHeader.EventDescriptor.Id = *id;
+                data.on_event(record);
+            }
+
+            passes += 1;
+            match passes {
+                1 => file.extend_from_slice(&[(2, 4), (3, 5)]),
+                2 => file.push((3, 6)),
+                _ => stop.store(true, Ordering::SeqCst),
+            }
+            Ok(())
+        });
+
+        assert!(res.is_ok());
+        assert_eq!(passes, 3);
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_first_pass_delivers_zero_timestamps() {
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let callback_count = Arc::clone(&count);
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .add_callback(move |_, _| {
+                callback_count.fetch_add(1, Ordering::SeqCst);
+            });
+        let mut data = TraceData::new();
+        data.insert_provider(prov);
+
+        // Nothing has been delivered yet, a 0 TimeStamp must not be taken as already seen
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.ProviderId = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        data.on_event(record);
+
+        assert_eq!(1, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn test_event_json() {
+        use crate::native::test_utils::{TestEvent, TestSchema};
+
+        let mut test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 2);
+        test_schema.level = 4;
+        test_schema.provider_name = Some(String::from("Microsoft-Windows-Kernel-Process"));
+        let event = TestEvent::new(&test_schema, Vec::new());
+        let schema = event.schema(&test_schema);
+        let properties = vec![
+            (String::from("ProcessID"), String::from("1234")),
+            (String::from("ImageName"), String::from("C:\\a \"b\"")),
+        ];
+
+        assert_eq!(
+            event_json(&schema, &properties),
+            "{\"provider_id\":\"22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716\",\
+             \"provider_name\":\"Microsoft-Windows-Kernel-Process\",\"event_id\":2,\
+             \"version\":0,\"opcode\":0,\"level\":4,\"timestamp\":0,\"process_id\":0,\
+             \"thread_id\":0,\"properties\":{\"ProcessID\":\"1234\",\
+             \"ImageName\":\"C:\\\\a \\\"b\\\"\"}}"
+        );
+
+        // No provider name in the TraceEventInfo
+        let unnamed = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 2);
+        let event = TestEvent::new(&unnamed, Vec::new());
+        assert!(event_json(&event.schema(&unnamed), &[]).contains("\"provider_name\":null,"));
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn test_explore_kernel_process() {
+        // Microsoft-Windows-Kernel-Process is low volume, the capture might even be empty
+        let lines = explore(
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716",
+            Duration::from_secs(2),
+        )
+        .unwrap();
+
+        assert!(lines.len() <= EXPLORE_MAX_EVENTS);
+        assert!(lines
+            .iter()
+            .all(|line| line.starts_with('{') && line.ends_with('}')));
+    }
+
+    #[test]
+    fn test_run_for_stops_after_duration() {
+        let prov = Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        let started = std::time::Instant::now();
+        let stats = UserTrace::new()
+            .named(String::from("RunForTrace"))
+            .enable(prov)
+            .run_for(Duration::from_millis(500))
+            .unwrap();
+
+        assert!(stats.elapsed >= Duration::from_millis(500));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(feature = "sink")]
+    #[test]
+    fn test_sink_to_writes_every_event() {
+        use std::sync::atomic::AtomicUsize;
+
+        let path = std::env::temp_dir().join("ferrisetw-sink-trace.ndjson");
+        let events = Arc::new(AtomicUsize::new(0));
+        let callback_events = Arc::clone(&events);
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .add_callback(move |_record, _locator| {
+                callback_events.fetch_add(1, Ordering::Relaxed);
+            })
+            .build()
+            .unwrap();
+
+        UserTrace::new()
+            .named(String::from("SinkTrace"))
+            .enable(prov)
+            .sink_to(&path, Format::Ndjson)
+            .unwrap()
+            .run_for(Duration::from_secs(1))
+            .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), events.load(Ordering::Relaxed));
+        assert!(content
+            .lines()
+            .all(|line| line.starts_with("{\"provider_id\":")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sink")]
+    #[test]
+    fn test_sink_errors_are_reported() {
+        use crate::native::tdh_types::TdhInType;
+        use crate::native::test_utils::{TestEvent, TestProperty, TestSchema};
+
+        let path = std::env::temp_dir().join(format!(
+            "ferrisetw-{}-sink-errors.ndjson",
+            std::process::id()
+        ));
+        let test_schema = TestSchema::new("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716", 1).property(
+            TestProperty::new("ProcessID", TdhInType::InTypeUInt32 as u16, 4),
+        );
+        let valid = TestEvent::new(&test_schema, 4u32.to_ne_bytes().to_vec());
+        // Too short to hold the ProcessID
+        let truncated = TestEvent::new(&test_schema, vec![0x04, 0x00]);
+
+        let errors = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let hook_errors = Arc::clone(&errors);
+        let mut trace = UserTrace::new()
+            .sink_to(&path, Format::Ndjson)
+            .unwrap()
+            .on_sink_error(move |err| {
+                assert!(matches!(err, SinkError::ParserError(_)));
+                hook_errors.fetch_add(1, Ordering::SeqCst);
+            });
+        trace
+            .data
+            .schema_locator
+            .insert_schema(&valid.record, test_schema.build());
+
+        trace.data.write_sink(valid.record);
+        trace.data.write_sink(truncated.record);
+        trace.data.flush_sink();
+
+        assert_eq!(errors.load(Ordering::SeqCst), 1);
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_trace_name() {
+        let trace = UserTrace::new();
+        assert!(trace.trace_name().starts_with(TRACE_NAME_PREFIX));
+        assert_ne!(trace.trace_name(), UserTrace::new().trace_name());
+
+        let trace = UserTrace::new().named(String::from("NamedTrace"));
+        assert_eq!(trace.trace_name(), "NamedTrace");
+    }
+
+    #[test]
+    fn test_named_rejects_long_name() {
+        let name = "a".repeat(MAX_TRACE_NAME - 1);
+        let trace = UserTrace::new().named(name.as_str());
+        assert_eq!(trace.trace_name(), name);
+        assert!(check_trace_name(trace.trace_name()).is_ok());
+
+        let res = UserTrace::new().named("a".repeat(MAX_TRACE_NAME)).open();
+        assert!(matches!(res, Err(TraceError::InvalidTraceName(_))));
+        assert!(matches!(
+            UserTrace::stop_existing(""),
+            Err(TraceError::InvalidTraceName(_))
+        ));
+    }
+
+    #[test]
+    fn test_stop_existing_missing_session() {
+        assert!(UserTrace::stop_existing("ferrisetw-missing-session").is_ok());
+    }
+
+    #[test]
+    fn test_stop_is_idempotent() {
+        let mut trace = UserTrace::new();
+        assert!(trace.stop().is_ok());
+        assert!(trace.stop().is_ok());
+    }
+
+    #[test]
+    fn test_start_after_drop_reuses_name() {
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .build()
+            .unwrap();
+        let trace = UserTrace::new()
+            .named(String::from("DropTrace"))
+            .enable(prov)
+            .start()
+            .unwrap();
+        drop(trace);
+
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .build()
+            .unwrap();
+        let mut trace = UserTrace::new()
+            .named(String::from("DropTrace"))
+            .enable(prov)
+            .start()
+            .unwrap();
+        trace.stop().unwrap();
+        trace.stop().unwrap();
+    }
+
+    #[test]
+    fn test_query_stats_before_start() {
+        let trace = UserTrace::new();
+        assert!(matches!(
+            trace.query_stats(),
+            Err(TraceError::EtwNativeError(
+                evntrace::EvntraceNativeError::InvalidHandle
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_heartbeat_ticks_until_joined() {
+        use std::sync::atomic::AtomicUsize;
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let callback_ticks = Arc::clone(&ticks);
+        let mut heartbeat = Heartbeat::new(Duration::from_millis(10), move |stats| {
+            assert_eq!(stats.events_handled, 7);
+            callback_ticks.fetch_add(1, Ordering::SeqCst);
+        });
+        heartbeat.spawn(|| {
+            Some(TraceStats {
+                events_handled: 7,
+                ..Default::default()
+            })
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        heartbeat.join();
+
+        let fired = ticks.load(Ordering::SeqCst);
+        assert!(fired >= 1);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(ticks.load(Ordering::SeqCst), fired);
+    }
+
+    #[test]
+    fn test_heartbeat_fires_during_run() {
+        use std::sync::atomic::AtomicUsize;
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let callback_ticks = Arc::clone(&ticks);
+        let prov = Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        UserTrace::new()
+            .named(String::from("HeartbeatTrace"))
+            .enable(prov)
+            .heartbeat(Duration::from_millis(50), move |stats| {
+                assert!(stats.buffers > 0);
+                callback_ticks.fetch_add(1, Ordering::SeqCst);
+            })
+            .run_for(Duration::from_millis(500))
+            .unwrap();
+
+        assert!(ticks.load(Ordering::SeqCst) >= 1);
+    }
+
+    #[test]
+    fn test_io_error_kind_mapping() {
+        use crate::native::pla::{HResult, PlaError};
+        use std::error::Error;
+        use std::io::ErrorKind;
+
+        let err = std::io::Error::from(TraceError::from(
+            evntrace::EvntraceNativeError::AlreadyExist,
+        ));
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let inner = err.get_ref().unwrap().downcast_ref::<TraceError>();
+        assert!(matches!(inner, Some(TraceError::EtwNativeError(_))));
+        assert!(inner.unwrap().source().is_some());
+
+        let err = std::io::Error::from(TraceError::from(provider::ProviderError::ComProvider(
+            PlaError::NotFound,
+        )));
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        let err = std::io::Error::from(PlaError::ComHResultError(HResult::HrAccessDenied));
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        let err = std::io::Error::from(TraceError::IoError(std::io::Error::from(
+            ErrorKind::PermissionDenied,
+        )));
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        let err = std::io::Error::from(provider::ProviderError::NoGuid);
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_capture_state_targets() {
+        let guid = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        let providers = vec![Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .any(0x10)
+            .level(4)];
+
+        let targets = capture_state_targets(&providers, &[guid]).unwrap();
+        assert_eq!(targets, vec![(guid, 0x10, 0, 4)]);
+
+        let missing = Guid::from("7f3d5a0e-56e2-4c9b-9d33-0c6a7e2f1b41");
+        assert!(matches!(
+            capture_state_targets(&providers, &[guid, missing]),
+            Err(TraceError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_capture_state_on_start_is_at_file_start() {
+        const KERNEL_PROCESS: &str = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+        // Microsoft-Windows-Kernel-Process ProcessRundown
+        const PROCESS_RUNDOWN: u16 = 15;
+        let path = std::env::temp_dir().join("ferrisetw-capture-state.etl");
+        let path = path.to_string_lossy();
+
+        UserTrace::new()
+            .named(String::from("CaptureStateTrace"))
+            .log_file(LogFile::new(&path).build().unwrap())
+            .enable(Provider::new().by_guid(KERNEL_PROCESS).any(0x10))
+            .capture_state_on_start(&[Guid::from(KERNEL_PROCESS)])
+            .run_for(Duration::from_millis(500))
+            .unwrap();
+
+        let ids = Arc::new(Mutex::new(Vec::new()));
+        let callback_ids = Arc::clone(&ids);
+        let prov = Provider::new()
+            .by_guid(KERNEL_PROCESS)
+            .add_callback(move |record, _| {
+                callback_ids
+                    .lock()
+                    .unwrap()
+                    .push(record.EventHeader.EventDescriptor.Id)
+            });
+        let mut replay = FileTrace::new(&path).enable(prov).process().unwrap();
+        replay.stop().unwrap();
+
+        let ids = ids.lock().unwrap();
+        assert_eq!(ids.first(), Some(&PROCESS_RUNDOWN));
+    }
+
+    #[test]
+    fn test_file_trace_run_rejects_follow() {
+        assert!(matches!(
+            FileTrace::follow("missing.etl").run(),
+            Err(TraceError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_trace_stop_reports_panic() {
+        let mut file_trace = FileTrace::new("missing.etl");
+        file_trace.worker = Some(std::thread::spawn(|| panic!("callback failed")));
+
+        match file_trace.stop() {
+            Err(TraceError::ReplayPanicked(msg)) => assert_eq!(msg, "callback failed"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert!(file_trace.stop().is_ok());
+    }
+
+    #[test]
+    fn test_file_trace_run_counts_events() {
+        // Small x64 log holding the logfile header and three Kernel-Process events
+        let path = std::path::Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/file_trace_run.etl"
+        ));
+
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let callback_count = Arc::clone(&count);
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .add_callback(move |_, _| {
+                callback_count.fetch_add(1, Ordering::SeqCst);
+            });
+        let stats = FileTrace::open(path).enable(prov).run().unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        // The logfile header is handled as well, but no Provider claims it
+        assert_eq!(stats.events_handled, 4);
+    }
+
+    #[test]
+    fn test_is_provider_enabled() {
+        let prov = Provider::new().by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        let trace = UserTrace::new()
+            .named(String::from("IsProviderEnabledTrace"))
+            .enable(prov)
+            .start()
+            .unwrap();
+
+        assert!(trace
+            .is_provider_enabled("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .unwrap());
+        assert!(!trace
+            .is_provider_enabled("7f3d5a0e-56e2-4c9b-9d33-0c6a7e2f1b41")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_events_lost_delta() {
+        assert_eq!(events_lost_delta(0, 0), 0);
+        assert_eq!(events_lost_delta(0, 3), 3);
+        assert_eq!(events_lost_delta(3, 10), 7);
+        // The counter restarted
+        assert_eq!(events_lost_delta(10, 2), 2);
+
+        let deltas = Arc::new(Mutex::new(Vec::new()));
+        let callback_deltas = Arc::clone(&deltas);
+        let mut data = TraceData::new();
+        data.events_lost_hook = Some(EventsLostHook(Box::new(move |lost| {
+            callback_deltas.lock().unwrap().push(lost)
+        })));
+        for events_lost in [0, 3, 3, 10, 2, 2].iter() {
+            data.report_events_lost(*events_lost);
+        }
+        assert_eq!(*deltas.lock().unwrap(), vec![3, 7, 2]);
+
+        data.reset_events();
+        data.report_events_lost(2);
+        assert_eq!(*deltas.lock().unwrap(), vec![3, 7, 2, 2]);
+    }
+
+    #[test]
+    fn test_lost_event_queries_events_lost() {
+        use std::sync::atomic::AtomicU32;
+        static SESSION_EVENTS_LOST: AtomicU32 = AtomicU32::new(0);
+
+        let deltas = Arc::new(Mutex::new(Vec::new()));
+        let callback_deltas = Arc::clone(&deltas);
+        let mut data = TraceData::new();
+        data.events_lost_query =
+            EventsLostQuery(|_| Some(SESSION_EVENTS_LOST.load(Ordering::SeqCst)));
+        data.events_lost_hook = Some(EventsLostHook(Box::new(move |lost| {
+            callback_deltas.lock().unwrap().push(lost)
+        })));
+
+        let mut record: EventRecord = unsafe { std::mem::zeroed() };
+        record.EventHeader.ProviderId = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+        let mut lost_event: EventRecord = unsafe { std::mem::zeroed() };
+        lost_event.EventHeader.ProviderId =
+            Guid::from(provider::kernel_providers::kernel_guids::LOST_EVENT_GUID);
+        lost_event.EventHeader.EventDescriptor.Opcode = 32;
+
+        // Only an RT_LostEvent triggers the query
+        SESSION_EVENTS_LOST.store(5, Ordering::SeqCst);
+        data.on_event(record);
+        assert!(deltas.lock().unwrap().is_empty());
+        data.on_event(lost_event);
+        SESSION_EVENTS_LOST.store(12, Ordering::SeqCst);
+        data.on_event(lost_event);
+        assert_eq!(*deltas.lock().unwrap(), vec![5, 7]);
+    }
+
+    #[test]
+    fn test_group_mask_array() {
+        let mut data = TraceData::new();
+        data.group_mask[1] |= 0x400;
+        data.group_mask[1] |= 0x2;
+        data.group_mask[4] |= 0x10;
+
+        let masks = group_mask_array(&data.group_mask, 0x3).unwrap();
+        assert_eq!(masks, [0x3, 0x402, 0, 0, 0x10, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_enable_group_mask_out_of_range() {
+        let trace = KernelTrace::new()
+            .enable_group_mask(1, 0x400)
+            .unwrap()
+            .enable_group_mask(GROUP_MASK_COUNT - 1, 0x2)
+            .unwrap();
+        assert_eq!(trace.data.group_mask[1], 0x400);
+        assert_eq!(trace.data.group_mask[GROUP_MASK_COUNT - 1], 0x2);
+
+        assert!(matches!(
+            trace.enable_group_mask(GROUP_MASK_COUNT, 0x1),
+            Err(TraceError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_group_mask_array_legacy_only() {
+        let mut group_mask = [0; GROUP_MASK_COUNT];
+        group_mask[0] = 0x1;
+
+        assert_eq!(group_mask_array(&group_mask, 0x2), None);
+    }
+
+    #[test]
+    fn test_consumer_thread_name() {
+        let trace = UserTrace::new().named(String::from("TestName"));
+        assert_eq!(trace.data.consumer_thread_name(), "etw-consumer:TestName");
+
+        let trace = trace.consumer_thread_name("my-consumer");
+        assert_eq!(trace.data.consumer_thread_name(), "my-consumer");
+    }
+
+    #[test]
+    fn test_estimated_memory_bytes() {
+        let prop = TraceProperties {
+            buffer_size: 64,
+            max_buffer: 32,
+            ..Default::default()
+        };
+        let trace = UserTrace::new().set_trace_properties(prop);
+        assert_eq!(trace.estimated_memory_bytes(), 64 * 1024 * 32);
+
+        // The kernel logger buffers are per CPU
+        assert!(KernelTrace::per_cpu_buffers());
+        assert!(!UserTrace::per_cpu_buffers());
+        assert_eq!(estimated_memory_bytes(&prop, 4), 4 * 64 * 1024 * 32);
+        assert_eq!(UserTrace::new().estimated_memory_bytes(), 0);
+    }
+
+    #[test]
+    fn test_trace_source() {
+        let user_trace = UserTrace::new();
+        assert!(user_trace.is_realtime());
+        assert_eq!(user_trace.source(), TraceSource::RealTime);
+
+        let file_trace = FileTrace::new("my_trace.etl");
+        assert!(!file_trace.is_realtime());
+        assert_eq!(file_trace.source(), TraceSource::File);
+
+        // EVENT_TRACE_FILE_MODE_SEQUENTIAL only, the events go to the log file
+        let props = TraceProperties {
+            log_file_mode: 0x1,
+            ..Default::default()
+        };
+        let file_only = UserTrace::new()
+            .log_file(LogFile::new("trace.etl").build().unwrap())
+            .set_trace_properties(props);
+        assert!(!file_only.is_realtime());
+        assert_eq!(file_only.source(), TraceSource::File);
+
+        // Along with EVENT_TRACE_REAL_TIME_MODE
+        let props = TraceProperties {
+            log_file_mode: 0x101,
+            ..Default::default()
+        };
+        let both = UserTrace::new().set_trace_properties(props);
+        assert_eq!(both.source(), TraceSource::RealTime);
+    }
+
+    #[test]
+    fn test_logger_thread_id_before_start() {
+        assert_eq!(UserTrace::new().logger_thread_id(), None);
+    }
+
+    #[test]
+    fn test_paged_memory_mode() {
+        assert_eq!(
+            buffer_memory_mode(true, UserTrace::supports_paged_memory()).unwrap(),
+            0x01000000
+        );
+        assert_eq!(
+            buffer_memory_mode(false, UserTrace::supports_paged_memory()).unwrap(),
+            0
+        );
+        assert_eq!(
+            buffer_memory_mode(false, KernelTrace::supports_paged_memory()).unwrap(),
+            0
+        );
+        assert!(matches!(
+            buffer_memory_mode(true, KernelTrace::supports_paged_memory()),
+            Err(TraceError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_trace_min_level_suppresses_verbose_events() {
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let callback_count = Arc::clone(&count);
+        let prov = Provider::new()
+            .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+            .add_callback(move |_, _| {
+                callback_count.fetch_add(1, Ordering::SeqCst);
+            });
+        let mut trace = UserTrace::new()
+            .enable(prov)
+            .min_level(provider::TraceLevel::Error);
+
+        for level in 0..=5 {
+            let mut record: EventRecord = unsafe { std::mem::zeroed() };
+            record.EventHeader.ProviderId = Guid::from("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716");
+            record.EventHeader.EventDescriptor.Level = level;
+            trace.data.on_event(record);
+        }
+
+        // LogAlways, Critical and Error
+        assert_eq!(3, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_enable_all_shares_callback() {
+        let guids = [
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716",
+            "1418ef04-b0b4-4623-bf7e-d74ab47bbdaa",
+            "7dd42a49-5329-4832-8dfd-43d979153a88",
+        ];
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let callback_seen = Arc::clone(&seen);
+        let providers = guids
+            .iter()
+            .map(|guid| Provider::new().by_guid(guid).build().unwrap())
+            .collect();
+        let mut trace = UserTrace::new()
+            .enable_all(providers, move |record: EventRecord, _| {
+                callback_seen
+                    .lock()
+                    .unwrap()
+                    .push(record.EventHeader.ProviderId);
+            })
+            .unwrap();
+
+        for guid in guids.iter().rev() {
+            let mut record: EventRecord = unsafe { std::mem::zeroed() };
+            record.EventHeader.ProviderId = Guid::from(*guid);
+            trace.data.on_event(record);
+        }
+
+        let expected: Vec<Guid> = guids.iter().rev().map(|guid| Guid::from(*guid)).collect();
+        assert_eq!(expected, *seen.lock().unwrap());
+        assert_eq!(trace.data.providers.read().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_buffer_builders() {
+        let trace = UserTrace::new()
+            .buffer_size(1024)
+            .min_buffers(64)
+            .max_buffers(256)
+            .flush_timer(1);
+
+        assert_eq!(trace.data.properties.buffer_size, 1024);
+        assert_eq!(trace.data.properties.min_buffer, 64);
+        assert_eq!(trace.data.properties.max_buffer, 256);
+        assert_eq!(trace.data.properties.flush_timer, 1);
+        assert!(check_buffer_counts(&trace.data.properties).is_ok());
+
+        // The builders only touch their own property
+        let trace = trace.set_trace_properties(TraceProperties {
+            age_limit: 15,
+            ..Default::default()
+        });
+        let trace = trace.max_buffers(32);
+        assert_eq!(trace.data.properties.age_limit, 15);
+        assert_eq!(trace.data.properties.buffer_size, 0);
+        assert_eq!(trace.data.properties.max_buffer, 32);
+
+        let trace = trace.min_buffers(64);
+        assert!(matches!(
+            check_buffer_counts(&trace.data.properties),
+            Err(TraceError::InvalidOptions(_))
+        ));
+        // Without maximum ETW picks one
+        let trace = trace.max_buffers(0);
+        assert!(check_buffer_counts(&trace.data.properties).is_ok());
+
+        let builder = TraceBuilder::new().min_buffers(8).max_buffers(4).build();
+        assert!(matches!(builder, Err(TraceError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn test_trace_builder_routes_callbacks() {
+        let guids = [
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716",
+            "1418ef04-b0b4-4623-bf7e-d74ab47bbdaa",
+        ];
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (first_seen, second_seen) = (Arc::clone(&seen), Arc::clone(&seen));
+        let mut trace = TraceBuilder::new()
+            .named("BuilderTrace")
+            .buffer_size(512)
+            .max_buffers(64)
+            .with(Provider::new().by_guid(guids[0]), move |_, _| {
+                first_seen.lock().unwrap().push(0)
+            })
+            .with(Provider::new().by_guid(guids[1]), move |_, _| {
+                second_seen.lock().unwrap().push(1)
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(trace.data.name, "BuilderTrace");
+        assert_eq!(trace.data.properties.buffer_size, 512);
+        assert_eq!(trace.data.properties.max_buffer, 64);
+        assert_eq!(trace.data.providers.read().unwrap().len(), 2);
+
+        for guid in [guids[1], guids[0], guids[1]].iter() {
+            let mut record: EventRecord = unsafe { std::mem::zeroed() };
+            record.EventHeader.ProviderId = Guid::from(*guid);
+            trace.data.on_event(record);
+        }
+        assert_eq!(*seen.lock().unwrap(), vec![1, 0, 1]);
+
+        let duplicated = TraceBuilder::new()
+            .with(Provider::new().by_guid(guids[0]), |_, _| {})
+            .with(Provider::new().by_guid(guids[0]), |_, _| {})
+            .build();
+        assert!(matches!(duplicated, Err(TraceError::InvalidOptions(_))));
+        let no_guid = TraceBuilder::new().with(Provider::new(), |_, _| {}).build();
+        assert!(matches!(
+            no_guid,
+            Err(TraceError::ProviderError(provider::ProviderError::NoGuid))
+        ));
+    }
+
+    #[test]
+    fn test_enable_dispatches_by_provider_guid() {
+        let guids = [
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716",
+            "1418ef04-b0b4-4623-bf7e-d74ab47bbdaa",
+        ];
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let providers = guids.iter().enumerate().map(|(idx, guid)| {
+            let seen = Arc::clone(&seen);
+            Provider::new()
+                .by_guid(guid)
+                .add_callback(move |record, _| {
+                    let guid = record.EventHeader.ProviderId;
+                    seen.lock().unwrap().push((idx, guid));
+                })
+                .build()
+                .unwrap()
+        });
+        let mut trace = providers.fold(UserTrace::new(), |trace, prov| trace.enable(prov));
+        assert_eq!(trace.data.providers.read().unwrap().len(), 2);
+
+        let unknown = "a0c1853b-5c40-4b15-8766-3cf1c58f985a";
+        for guid in [guids[0], unknown, guids[1], guids[1]].iter() {
+            let mut record: EventRecord = unsafe { std::mem::zeroed() };
+            record.EventHeader.ProviderId = Guid::from(*guid);
+            trace.data.on_event(record);
+        }
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0], (0, Guid::from(guids[0])));
+        assert_eq!(seen[1], (1, Guid::from(guids[1])));
+        assert_eq!(seen[2], (1, Guid::from(guids[1])));
+    }
+
+    #[test]
+    fn test_system_trace_provider_gets_unclaimed_kernel_events() {
+        use crate::provider::kernel_providers::kernel_guids;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let providers = [kernel_guids::SYSTEM_TRACE_GUID, kernel_guids::PROCESS_GUID]
+            .iter()
+            .map(|guid| {
+                let seen = Arc::clone(&seen);
+                let guid = guid.to_string();
+                Provider::new()
+                    .by_guid(&guid)
+                    .add_callback(move |record: EventRecord, _| {
+                        let event_guid = record.EventHeader.ProviderId;
+                        seen.lock()
+                            .unwrap()
+                            .push((Guid::from(guid.as_str()), event_guid));
+                    })
+            })
+            .collect::<Vec<_>>();
+        let mut trace = providers
+            .into_iter()
+            .fold(UserTrace::new(), |trace, prov| trace.enable(prov));
+
+        let user_guid = "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716";
+        for guid in [
+            kernel_guids::PROCESS_GUID,
+            kernel_guids::THREAD_GUID,
+            user_guid,
+        ]
+        .iter()
+        {
+            let mut record: EventRecord = unsafe { std::mem::zeroed() };
+            record.EventHeader.ProviderId = Guid::from(*guid);
+            trace.data.on_event(record);
+        }
+
+        let process = Guid::from(kernel_guids::PROCESS_GUID);
+        let thread = Guid::from(kernel_guids::THREAD_GUID);
+        let system = Guid::from(kernel_guids::SYSTEM_TRACE_GUID);
+        // The process event only reaches its own Provider, the user event reaches none
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(process, process), (system, thread)]
+        );
+    }
+
+    #[test]
+    fn test_enable_all_rejects_duplicated_guids() {
+        let provider = || {
+            Provider::new()
+                .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+                .build()
+                .unwrap()
+        };
+
+        let trace = UserTrace::new().enable_all(vec![provider(), provider()], |_, _| {});
+        assert!(matches!(trace, Err(TraceError::InvalidOptions(_))));
+
+        let trace = UserTrace::new()
+            .enable(provider())
+            .enable_all(vec![provider()], |_, _| {});
+        assert!(matches!(trace, Err(TraceError::InvalidOptions(_))));
+
+        let trace = UserTrace::new().enable_all(vec![Provider::new()], |_, _| {});
+        assert!(matches!(trace, Err(TraceError::ProviderError(_))));
+    }
+
+    #[test]
+    fn test_too_many_event_ids_fail_open() {
+        let ids: Vec<u16> = (0..65).collect();
+        // Enabled without being built, the limit is checked again by the Trace
+        let provider = || {
+            Provider::new()
+                .by_guid("22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716")
+                .with_event_ids(ids.clone())
+        };
+
+        let trace = UserTrace::new().enable(provider()).open();
+        assert!(matches!(
+            trace,
+            Err(TraceError::ProviderError(
+                provider::ProviderError::TooManyEventIds(65)
+            ))
+        ));
+
+        let trace = UserTrace::new().enable_all(vec![provider()], |_, _| {});
+        assert!(matches!(
+            trace,
+            Err(TraceError::ProviderError(
+                provider::ProviderError::TooManyEventIds(65)
+            ))
+        ));
     }
 
     #[test]
@@ -1,5 +1,7 @@
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use windows::Guid;
 
 pub fn rand_string() -> String {
     thread_rng()
@@ -47,3 +49,134 @@ pub fn parse_utf16_guid(v: &[u8]) -> String {
     .trim_matches('}')
     .to_string()
 }
+
+// Seconds between 1601-01-01 (FILETIME epoch) and 1970-01-01 (UNIX epoch)
+const FILETIME_UNIX_EPOCH_SECS: u64 = 11_644_473_600;
+
+pub fn filetime_to_system_time(filetime: u64) -> SystemTime {
+    let since_1601 = Duration::new(filetime / 10_000_000, (filetime % 10_000_000) as u32 * 100);
+    let unix_epoch = Duration::from_secs(FILETIME_UNIX_EPOCH_SECS);
+    if since_1601 >= unix_epoch {
+        UNIX_EPOCH + (since_1601 - unix_epoch)
+    } else {
+        UNIX_EPOCH - (unix_epoch - since_1601)
+    }
+}
+
+// SYSTEMTIME fields are wYear, wMonth, wDayOfWeek, wDay, wHour, wMinute, wSecond, wMilliseconds,
+// the day of the week is ignored. Returns `None` for out of range fields
+pub fn systemtime_to_system_time(fields: [u16; 8]) -> Option<SystemTime> {
+    let [year, month, _, day, hour, minute, second, millis] = fields;
+    if !(1601..=30827).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+        || millis > 999
+    {
+        return None;
+    }
+
+    // Days since 1970-01-01 of the civil date, with years starting in March
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let millis = ((days * 24 + i64::from(hour)) * 60 + i64::from(minute)) * 60_000
+        + i64::from(second) * 1_000
+        + i64::from(millis);
+    let since_epoch = Duration::from_millis(millis.unsigned_abs());
+    if millis >= 0 {
+        Some(UNIX_EPOCH + since_epoch)
+    } else {
+        Some(UNIX_EPOCH - since_epoch)
+    }
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+pub fn guid_bytes(guid: &Guid) -> [u8; 16] {
+    // Guid is a repr(C) struct of 16 bytes without padding
+    unsafe { std::mem::transmute::<Guid, [u8; 16]>(*guid) }
+}
+
+pub fn is_guid_str(v: &str) -> bool {
+    v.len() == 36
+        && v.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+#[cfg(feature = "serde")]
+pub fn serialize_guid<S: serde::Serializer>(guid: &Guid, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&crate::fmt::format_guid(guid))
+}
+
+#[cfg(feature = "serde")]
+pub fn serialize_opt_guid<S: serde::Serializer>(
+    guid: &Option<Guid>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match guid {
+        Some(guid) => serialize_guid(guid, serializer),
+        None => serializer.serialize_none(),
+    }
+}